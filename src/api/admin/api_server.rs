@@ -114,6 +114,9 @@ impl AdminApiServer {
 	}
 
 	async fn check_domain(&self, domain: &str) -> Result<bool, Error> {
+		// Domain names are case-insensitive, and bucket names are lowercase
+		let domain = &domain.to_lowercase();
+
 		// Resolve bucket from domain name, inferring if the website must be activated for the
 		// domain to be valid.
 		let (bucket_name, must_check_website) = if let Some(bname) = self
@@ -122,7 +125,7 @@ impl AdminApiServer {
 			.s3_api
 			.root_domain
 			.as_ref()
-			.and_then(|rd| host_to_bucket(domain, rd))
+			.and_then(|rd| host_to_bucket(domain, rd)?.ok())
 		{
 			(bname.to_string(), false)
 		} else if let Some(bname) = self
@@ -130,7 +133,7 @@ impl AdminApiServer {
 			.config
 			.s3_web
 			.as_ref()
-			.and_then(|sw| host_to_bucket(domain, sw.root_domain.as_str()))
+			.and_then(|sw| host_to_bucket(domain, sw.root_domain.as_str())?.ok())
 		{
 			(bname.to_string(), true)
 		} else {
@@ -180,10 +183,77 @@ impl AdminApiServer {
 				"Quorum is not available for some/all partitions, reads and writes will fail",
 			),
 		};
-		let status_str = format!(
+		let mut status_str = format!(
 			"{}\nConsult the full health check API endpoint at /v1/health for more details\n",
 			status_str
 		);
+		for (dir, count) in self.garage.block_manager.corruption_alarms() {
+			status_str.push_str(&format!(
+				"WARNING: {} corrupted blocks were found recently in data directory {}, the disk might be failing\n",
+				count,
+				dir.display()
+			));
+		}
+
+		Ok(Response::builder()
+			.status(status)
+			.header(http::header::CONTENT_TYPE, "text/plain")
+			.body(string_body(status_str))?)
+	}
+
+	async fn handle_ready(&self) -> Result<Response<ResBody>, Error> {
+		let system = &self.garage.system;
+		let mut reasons = vec![];
+
+		// Check that this node has been assigned a role in the cluster layout
+		if system
+			.cluster_layout()
+			.current()
+			.node_role(&system.id)
+			.is_none()
+		{
+			reasons.push("node has no role in the current cluster layout".to_string());
+		}
+
+		// Check that all data directories can be written to
+		if let Err(e) = self.garage.block_manager.check_data_dirs_writable().await {
+			reasons.push(e.to_string());
+		}
+
+		// Check that the resync backlog is not too deep
+		let resync_queue_len = self.garage.block_manager.resync.queue_len()?;
+		if let Some(max) = self.garage.config.admin.readiness_max_resync_queue {
+			if resync_queue_len > max {
+				reasons.push(format!(
+					"resync queue has {} items (maximum: {})",
+					resync_queue_len, max
+				));
+			}
+		}
+
+		// Check that reads and writes can reach a quorum
+		if system.health().status == ClusterHealthStatus::Unavailable {
+			reasons.push(
+				"quorum is not available for some/all partitions, reads and writes will fail"
+					.to_string(),
+			);
+		}
+
+		let (status, status_str) = if reasons.is_empty() {
+			(
+				StatusCode::OK,
+				format!("Node is ready\nResync queue length: {}\n", resync_queue_len),
+			)
+		} else {
+			(
+				StatusCode::SERVICE_UNAVAILABLE,
+				format!(
+					"Node is not ready:\n- {}\nResync queue length: {}\n",
+					reasons.join("\n- "),
+					resync_queue_len
+				),
+			)
+		};
 
 		Ok(Response::builder()
 			.status(status)
@@ -266,6 +336,7 @@ impl ApiHandler for AdminApiServer {
 			Endpoint::Options => self.handle_options(&req),
 			Endpoint::CheckDomain => self.handle_check_domain(req).await,
 			Endpoint::Health => self.handle_health(),
+			Endpoint::Ready => self.handle_ready().await,
 			Endpoint::Metrics => self.handle_metrics(),
 			Endpoint::GetClusterStatus => handle_get_cluster_status(&self.garage).await,
 			Endpoint::GetClusterHealth => handle_get_cluster_health(&self.garage).await,
@@ -197,18 +197,15 @@ async fn bucket_info_results(
 				.into_values()
 				.map(|key| {
 					let p = key.state.as_option().unwrap();
+					let perm = state.key_permissions(&key.key_id);
 					GetBucketInfoKey {
 						access_key_id: key.key_id,
 						name: p.name.get().to_string(),
-						permissions: p
-							.authorized_buckets
-							.get(&bucket.id)
-							.map(|p| ApiBucketKeyPerm {
-								read: p.allow_read,
-								write: p.allow_write,
-								owner: p.allow_owner,
-							})
-							.unwrap_or_default(),
+						permissions: ApiBucketKeyPerm {
+							read: perm.allow_read,
+							write: perm.allow_write,
+							owner: perm.allow_owner,
+						},
 						bucket_local_aliases: p
 							.local_aliases
 							.items()
@@ -371,12 +368,10 @@ pub async fn handle_delete_bucket(
 
 	// --- done checking, now commit ---
 	// 1. delete authorization from keys that had access
-	for (key_id, perm) in bucket.authorized_keys() {
-		if perm.is_any() {
-			helper
-				.set_bucket_key_permissions(bucket.id, key_id, BucketKeyPerm::NO_PERMISSIONS)
-				.await?;
-		}
+	for (key_id, _) in bucket.authorized_keys() {
+		helper
+			.set_bucket_key_permissions(bucket.id, &key_id, BucketKeyPerm::NO_PERMISSIONS)
+			.await?;
 	}
 	// 2. delete all local aliases
 	for ((key_id, alias), _, active) in state.local_aliases.items().iter() {
@@ -480,11 +475,7 @@ pub async fn handle_bucket_change_key_perm(
 
 	let key = helper.key().get_existing_key(&req.access_key_id).await?;
 
-	let mut perm = state
-		.authorized_keys
-		.get(&key.key_id)
-		.cloned()
-		.unwrap_or(BucketKeyPerm::NO_PERMISSIONS);
+	let mut perm = state.key_permissions(&key.key_id);
 
 	if req.permissions.read {
 		perm.allow_read = new_perm_flag;
@@ -208,6 +208,7 @@ async fn key_info_results(
 			.into_values()
 			.map(|bucket| {
 				let state = bucket.state.as_option().unwrap();
+				let perm = state.key_permissions(&key.key_id);
 				KeyInfoBucketResult {
 					id: hex::encode(bucket.id),
 					global_aliases: state
@@ -224,15 +225,11 @@ async fn key_info_results(
 						.filter(|((k, _), _, a)| *a && *k == key.key_id)
 						.map(|((_, n), _, _)| n.to_string())
 						.collect::<Vec<_>>(),
-					permissions: key_state
-						.authorized_buckets
-						.get(&bucket.id)
-						.map(|p| ApiBucketKeyPerm {
-							read: p.allow_read,
-							write: p.allow_write,
-							owner: p.allow_owner,
-						})
-						.unwrap_or_default(),
+					permissions: ApiBucketKeyPerm {
+						read: perm.allow_read,
+						write: perm.allow_write,
+						owner: perm.allow_owner,
+					},
 				}
 			})
 			.collect::<Vec<_>>(),
@@ -21,6 +21,7 @@ pub enum Endpoint {
 	Options,
 	CheckDomain,
 	Health,
+	Ready,
 	Metrics,
 	GetClusterStatus,
 	GetClusterHealth,
@@ -97,6 +98,7 @@ impl Endpoint {
 			OPTIONS _ => Options,
 			GET "/check" => CheckDomain,
 			GET "/health" => Health,
+			GET "/ready" => Ready,
 			GET "/metrics" => Metrics,
 			GET "/v1/status" => GetClusterStatus,
 			GET "/v1/health" => GetClusterHealth,
@@ -215,6 +217,7 @@ impl Endpoint {
 	pub fn authorization_type(&self) -> Authorization {
 		match self {
 			Self::Health => Authorization::None,
+			Self::Ready => Authorization::None,
 			Self::CheckDomain => Authorization::None,
 			Self::Metrics => Authorization::MetricsToken,
 			_ => Authorization::AdminToken,
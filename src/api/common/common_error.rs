@@ -89,12 +89,8 @@ pub use commonErrorDerivative;
 impl CommonError {
 	pub fn http_status_code(&self) -> StatusCode {
 		match self {
-			CommonError::InternalError(
-				GarageError::Timeout | GarageError::RemoteError(_) | GarageError::Quorum(..),
-			) => StatusCode::SERVICE_UNAVAILABLE,
-			CommonError::InternalError(_) | CommonError::Hyper(_) | CommonError::Http(_) => {
-				StatusCode::INTERNAL_SERVER_ERROR
-			}
+			CommonError::InternalError(e) => e.http_status_code(),
+			CommonError::Hyper(_) | CommonError::Http(_) => StatusCode::INTERNAL_SERVER_ERROR,
 			CommonError::BadRequest(_) => StatusCode::BAD_REQUEST,
 			CommonError::Forbidden(_) => StatusCode::FORBIDDEN,
 			CommonError::NoSuchBucket(_) => StatusCode::NOT_FOUND,
@@ -108,12 +104,13 @@ impl CommonError {
 	pub fn aws_code(&self) -> &'static str {
 		match self {
 			CommonError::Forbidden(_) => "AccessDenied",
-			CommonError::InternalError(
-				GarageError::Timeout | GarageError::RemoteError(_) | GarageError::Quorum(..),
-			) => "ServiceUnavailable",
-			CommonError::InternalError(_) | CommonError::Hyper(_) | CommonError::Http(_) => {
-				"InternalError"
-			}
+			CommonError::InternalError(e) => match e.http_status_code() {
+				StatusCode::SERVICE_UNAVAILABLE => "ServiceUnavailable",
+				StatusCode::BAD_REQUEST => "InvalidRequest",
+				StatusCode::PAYLOAD_TOO_LARGE => "EntityTooLarge",
+				_ => "InternalError",
+			},
+			CommonError::Hyper(_) | CommonError::Http(_) => "InternalError",
 			CommonError::BadRequest(_) => "InvalidRequest",
 			CommonError::NoSuchBucket(_) => "NoSuchBucket",
 			CommonError::BucketAlreadyExists => "BucketAlreadyExists",
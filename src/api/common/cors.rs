@@ -2,7 +2,8 @@ use std::sync::Arc;
 
 use http::header::{
 	ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
-	ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+	ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+	ACCESS_CONTROL_REQUEST_METHOD,
 };
 use hyper::{body::Body, body::Incoming as IncomingBody, Request, Response, StatusCode};
 
@@ -73,6 +74,9 @@ pub fn add_cors_headers(
 		ACCESS_CONTROL_EXPOSE_HEADERS,
 		rule.expose_headers.join(", ").parse()?,
 	);
+	if let Some(max_age) = rule.max_age_seconds {
+		h.insert(ACCESS_CONTROL_MAX_AGE, max_age.into());
+	}
 	Ok(())
 }
 
@@ -168,3 +172,75 @@ pub fn handle_options_for_bucket<B>(
 		"This CORS request is not allowed.".into(),
 	))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rule(origins: &[&str]) -> GarageCorsRule {
+		GarageCorsRule {
+			id: None,
+			max_age_seconds: Some(3600),
+			allow_origins: origins.iter().map(|x| x.to_string()).collect(),
+			allow_methods: vec!["GET".into(), "HEAD".into()],
+			allow_headers: vec!["Range".into()],
+			expose_headers: vec![],
+		}
+	}
+
+	#[test]
+	fn test_cors_rule_matches() {
+		let none = std::iter::empty::<&str>();
+
+		let explicit = rule(&["https://a.example.com", "https://b.example.com"]);
+		assert!(cors_rule_matches(
+			&explicit,
+			"https://b.example.com",
+			"GET",
+			none.clone()
+		));
+		assert!(!cors_rule_matches(
+			&explicit,
+			"https://c.example.com",
+			"GET",
+			none.clone()
+		));
+		assert!(!cors_rule_matches(
+			&explicit,
+			"https://a.example.com",
+			"PUT",
+			none.clone()
+		));
+		assert!(cors_rule_matches(
+			&explicit,
+			"https://a.example.com",
+			"GET",
+			["Range"].iter()
+		));
+		assert!(!cors_rule_matches(
+			&explicit,
+			"https://a.example.com",
+			"GET",
+			["Range", "Authorization"].iter()
+		));
+
+		let wildcard = rule(&["*"]);
+		assert!(cors_rule_matches(
+			&wildcard,
+			"https://c.example.com",
+			"HEAD",
+			none.clone()
+		));
+		assert!(!cors_rule_matches(&wildcard, "null", "DELETE", none));
+	}
+
+	#[test]
+	fn test_add_cors_headers() {
+		let mut resp = Response::new(http_body_util::Empty::<bytes::Bytes>::new());
+		add_cors_headers(&mut resp, &rule(&["*"])).unwrap();
+		let h = resp.headers();
+		assert_eq!(h.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+		assert_eq!(h.get(ACCESS_CONTROL_ALLOW_METHODS).unwrap(), "GET, HEAD");
+		assert_eq!(h.get(ACCESS_CONTROL_MAX_AGE).unwrap(), "3600");
+	}
+}
@@ -27,6 +27,8 @@ use opentelemetry::{
 	Context, KeyValue,
 };
 
+use garage_net::request_id::RequestId;
+
 use garage_util::error::Error as GarageError;
 use garage_util::forwarded_headers;
 use garage_util::metrics::{gen_trace_id, RecordDuration};
@@ -34,6 +36,10 @@ use garage_util::socket_address::UnixOrTCPSocketAddress;
 
 use crate::helpers::{BoxBody, ErrorBody};
 
+/// Time given to open connections of the API servers to finish when exiting,
+/// after which they are closed
+const API_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
 pub trait ApiEndpoint: Send + Sync + 'static {
 	fn name(&self) -> &'static str;
 	fn add_span_attributes(&self, span: SpanRef<'_>);
@@ -114,7 +120,14 @@ impl<A: ApiHandler> ApiServer<A> {
 				let listener = TcpListener::bind(addr).await?;
 
 				let handler = move |request, socketaddr| self.clone().handler(request, socketaddr);
-				server_loop(server_name, listener, handler, must_exit).await
+				server_loop(
+					server_name,
+					listener,
+					handler,
+					must_exit,
+					API_SHUTDOWN_GRACE_PERIOD,
+				)
+				.await
 			}
 			UnixOrTCPSocketAddress::UnixSocket(ref path) => {
 				if path.exists() {
@@ -130,7 +143,14 @@ impl<A: ApiHandler> ApiServer<A> {
 				)?;
 
 				let handler = move |request, socketaddr| self.clone().handler(request, socketaddr);
-				server_loop(server_name, listener, handler, must_exit).await
+				server_loop(
+					server_name,
+					listener,
+					handler,
+					must_exit,
+					API_SHUTDOWN_GRACE_PERIOD,
+				)
+				.await
 			}
 		}
 	}
@@ -139,6 +159,20 @@ impl<A: ApiHandler> ApiServer<A> {
 		self: Arc<Self>,
 		req: Request<IncomingBody>,
 		addr: String,
+	) -> Result<Response<BoxBody<A::Error>>, http::Error> {
+		let request_id = RequestId::new();
+		let mut res = request_id.scope(self.handler_stage1(req, addr)).await?;
+		res.headers_mut().insert(
+			"x-amz-request-id",
+			HeaderValue::from_str(&request_id.to_string()).expect("request id is valid ascii"),
+		);
+		Ok(res)
+	}
+
+	async fn handler_stage1(
+		&self,
+		req: Request<IncomingBody>,
+		addr: String,
 	) -> Result<Response<BoxBody<A::Error>>, http::Error> {
 		let uri = req.uri().clone();
 
@@ -271,11 +305,15 @@ impl Accept for UnixListenerOn {
 	}
 }
 
+/// Accept connections on `listener` and serve HTTP requests on them with `handler`,
+/// until `must_exit` is set. Connections that are still open `grace_period` after
+/// that are closed forcibly.
 pub async fn server_loop<A, H, F, E>(
 	server_name: String,
 	listener: A,
 	handler: H,
 	mut must_exit: watch::Receiver<bool>,
+	grace_period: Duration,
 ) -> Result<(), GarageError>
 where
 	A: Accept,
@@ -308,7 +346,7 @@ where
 					}
 				}
 			}
-			let deadline = Instant::now() + Duration::from_secs(10);
+			let deadline = Instant::now() + grace_period;
 			while !connections.is_empty() {
 				info!(
 					"{} server: {} connections still open, deadline in {:.2}s",
@@ -370,3 +408,47 @@ where
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::AsyncWriteExt;
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_stuck_connections_are_closed_after_grace_period() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let (exit_tx, exit_rx) = watch::channel(false);
+
+		let (started_tx, mut started_rx) = tokio::sync::mpsc::unbounded_channel();
+		let handler = move |_req: Request<IncomingBody>, _addr: String| {
+			started_tx.send(()).unwrap();
+			// A handler that never completes, e.g. a stuck download
+			futures::future::pending::<Result<Response<BoxBody<GarageError>>, http::Error>>()
+		};
+		let server = tokio::spawn(server_loop(
+			"Test".into(),
+			listener,
+			handler,
+			exit_rx,
+			Duration::from_millis(200),
+		));
+
+		let mut client = TcpStream::connect(addr).await.unwrap();
+		client
+			.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+			.await
+			.unwrap();
+		started_rx.recv().await.unwrap();
+
+		let start = Instant::now();
+		exit_tx.send(true).unwrap();
+		tokio::time::timeout(Duration::from_secs(5), server)
+			.await
+			.expect("server did not exit after the grace period")
+			.unwrap()
+			.unwrap();
+		assert!(start.elapsed() >= Duration::from_millis(200));
+	}
+}
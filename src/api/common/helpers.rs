@@ -1,4 +1,5 @@
 use std::convert::Infallible;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use futures::{Stream, StreamExt, TryStreamExt};
@@ -11,6 +12,7 @@ use hyper::{
 use idna::domain_to_unicode;
 use serde::{Deserialize, Serialize};
 
+use garage_model::bucket_alias_table::is_valid_bucket_name;
 use garage_model::bucket_table::BucketParams;
 use garage_model::garage::Garage;
 use garage_model::key_table::Key;
@@ -41,29 +43,129 @@ pub struct ReqCtx {
 	pub api_key: Key,
 }
 
+/// Maximum length of an object key in bytes, as in AWS S3
+pub const MAX_OBJECT_KEY_LEN: usize = 1024;
+
+/// The name of a bucket, as found in the host or the path of a request.
+/// A `BucketName` is always a valid bucket name according to `is_valid_bucket_name`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BucketName(String);
+
+impl BucketName {
+	pub fn new(name: &str) -> Result<Self, Error> {
+		if !is_valid_bucket_name(name) {
+			return Err(Error::InvalidBucketName(name.to_string()));
+		}
+		Ok(Self(name.to_string()))
+	}
+
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+
+	pub fn into_string(self) -> String {
+		self.0
+	}
+}
+
+impl AsRef<str> for BucketName {
+	fn as_ref(&self) -> &str {
+		&self.0
+	}
+}
+
+impl std::fmt::Display for BucketName {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+/// The key of an object in a bucket: a non-empty string of at most
+/// `MAX_OBJECT_KEY_LEN` bytes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ObjectKey(String);
+
+impl ObjectKey {
+	pub fn new(key: impl Into<String>) -> Result<Self, Error> {
+		let key = key.into();
+		if key.is_empty() {
+			return Err(Error::bad_request("Object key must not be empty"));
+		}
+		if key.len() > MAX_OBJECT_KEY_LEN {
+			return Err(Error::bad_request(format!(
+				"Object key is {} bytes long, the maximum is {} bytes",
+				key.len(),
+				MAX_OBJECT_KEY_LEN
+			)));
+		}
+		Ok(Self(key))
+	}
+
+	/// Key from a percent-encoded segment of an URL path, such as `dir/file%20name.html`
+	pub fn from_percent_encoded(encoded: &str) -> Result<Self, Error> {
+		let key = percent_encoding::percent_decode_str(encoded)
+			.decode_utf8()
+			.map_err(|_| Error::bad_request("Object key is not valid UTF-8"))?;
+		Self::new(key.into_owned())
+	}
+
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+
+	pub fn into_string(self) -> String {
+		self.0
+	}
+}
+
+impl AsRef<str> for ObjectKey {
+	fn as_ref(&self) -> &str {
+		&self.0
+	}
+}
+
+impl std::fmt::Display for ObjectKey {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
 /// Host to bucket
 ///
 /// Convert a host, like "bucket.garage-site.tld" to the corresponding bucket "bucket",
 /// considering that ".garage-site.tld" is the "root domain". For domains not matching
 /// the provided root domain, no bucket is returned
 /// This behavior has been chosen to follow AWS S3 semantic.
-pub fn host_to_bucket<'a>(host: &'a str, root: &str) -> Option<&'a str> {
+/// A trailing dot in a fully-qualified host ("bucket.garage-site.tld.") is ignored,
+/// and IP addresses (e.g. "127.0.0.1" or "[::1]") never designate a bucket.
+/// The root domain is matched case-insensitively. The returned bucket name keeps the
+/// case of `host`: since bucket names are always lowercase, `host` should have been
+/// normalized by `authority_to_host` first.
+/// An error is returned if `host` is in the root domain but its bucket part is not
+/// a valid bucket name.
+pub fn host_to_bucket(host: &str, root: &str) -> Option<Result<BucketName, Error>> {
+	if host.starts_with('[') || host.parse::<IpAddr>().is_ok() {
+		return None;
+	}
+	let host = host.strip_suffix('.').unwrap_or(host);
 	let root = root.trim_start_matches('.');
+	let root = root.strip_suffix('.').unwrap_or(root);
 	let label_root = root.chars().filter(|c| c == &'.').count() + 1;
 	let root = root.rsplit('.');
 	let mut host = host.rsplitn(label_root + 1, '.');
 	for root_part in root {
 		let host_part = host.next()?;
-		if root_part != host_part {
+		if !root_part.eq_ignore_ascii_case(host_part) {
 			return None;
 		}
 	}
-	host.next()
+	host.next().map(BucketName::new)
 }
 
 /// Extract host from the authority section given by the HTTP host header
 ///
 /// The HTTP host contains both a host and a port.
+/// The returned host is lowercased, as domain names are case-insensitive.
 /// Extracting the port is more complex than just finding the colon (:) symbol due to IPv6
 /// We do not use the collect pattern as there is no way in std rust to collect over a stack allocated value
 /// check here: <https://docs.rs/collect_slice/1.2.0/collect_slice/>
@@ -97,7 +199,7 @@ pub fn authority_to_host(authority: &str) -> Result<String, Error> {
 			authority
 		))),
 	};
-	authority.map(|h| domain_to_unicode(h).0)
+	authority.map(|h| domain_to_unicode(h).0.to_lowercase())
 }
 
 /// Extract the bucket name and the key name from an HTTP path and possibly a bucket provided in
@@ -297,6 +399,8 @@ mod tests {
 		assert_eq!(domain2, "garage.tld");
 		let domain3 = authority_to_host("127.0.0.1")?;
 		assert_eq!(domain3, "127.0.0.1");
+		assert_eq!(authority_to_host("Bucket.Garage.TLD")?, "bucket.garage.tld");
+		assert_eq!(authority_to_host("[::FFFF:7f00:1]")?, "[::ffff:7f00:1]");
 		assert!(authority_to_host("[").is_err());
 		assert!(authority_to_host("[hello").is_err());
 		Ok(())
@@ -304,26 +408,140 @@ mod tests {
 
 	#[test]
 	fn host_to_bucket_test() {
+		let bucket_of =
+			|host: &str, root: &str| host_to_bucket(host, root).map(|b| b.unwrap().into_string());
+
 		assert_eq!(
-			host_to_bucket("john.doe.garage.tld", ".garage.tld").unwrap(),
-			"john.doe"
+			bucket_of("john.doe.garage.tld", ".garage.tld"),
+			Some("john.doe".to_string())
 		);
 
 		assert_eq!(
-			host_to_bucket("john.doe.garage.tld", "garage.tld").unwrap(),
-			"john.doe"
+			bucket_of("john.doe.garage.tld", "garage.tld"),
+			Some("john.doe".to_string())
 		);
 
-		assert_eq!(host_to_bucket("john.doe.com", "garage.tld"), None);
+		assert_eq!(bucket_of("john.doe.com", "garage.tld"), None);
+
+		assert_eq!(bucket_of("john.doe.com", ".garage.tld"), None);
+
+		assert_eq!(bucket_of("garage.tld", "garage.tld"), None);
+
+		assert_eq!(bucket_of("garage.tld", ".garage.tld"), None);
+
+		assert_eq!(bucket_of("not-garage.tld", "garage.tld"), None);
+		assert_eq!(bucket_of("not-garage.tld", ".garage.tld"), None);
+
+		// Mixed-case hosts and root domains
+		assert_eq!(
+			bucket_of("john.doe.Garage.TLD", "garage.tld"),
+			Some("john.doe".to_string())
+		);
+		assert_eq!(
+			bucket_of("john.doe.garage.tld", ".Garage.Tld"),
+			Some("john.doe".to_string())
+		);
+		assert_eq!(
+			bucket_of(
+				&authority_to_host("John.Doe.GARAGE.tld:3902").unwrap(),
+				"garage.tld"
+			),
+			Some("john.doe".to_string())
+		);
+		assert_eq!(bucket_of("john.doe.Garage.com", "garage.tld"), None);
 
-		assert_eq!(host_to_bucket("john.doe.com", ".garage.tld"), None);
+		// Fully-qualified hosts with a trailing dot
+		assert_eq!(
+			bucket_of("john.doe.garage.tld.", ".garage.tld"),
+			Some("john.doe".to_string())
+		);
+		assert_eq!(
+			bucket_of("john.doe.garage.tld.", "garage.tld."),
+			Some("john.doe".to_string())
+		);
+		assert_eq!(bucket_of("garage.tld.", "garage.tld"), None);
+		assert_eq!(bucket_of("john.doe.com.", "garage.tld"), None);
+
+		// Hosts in the root domain that don't designate a valid bucket name
+		assert!(host_to_bucket("My_Bucket.garage.tld", "garage.tld")
+			.unwrap()
+			.is_err());
+		assert!(host_to_bucket("xn--bucket.garage.tld", "garage.tld")
+			.unwrap()
+			.is_err());
+		assert!(host_to_bucket(".garage.tld", "garage.tld")
+			.unwrap()
+			.is_err());
+
+		// IP literals, as returned by authority_to_host, are never buckets
+		assert_eq!(bucket_of("127.0.0.1", "0.1"), None);
+		assert_eq!(bucket_of("127.0.0.1", "garage.tld"), None);
+		assert_eq!(bucket_of("[::1]", "garage.tld"), None);
+		assert_eq!(
+			bucket_of(&authority_to_host("[::ffff:1.2.3.4]:3902").unwrap(), "3.4]"),
+			None
+		);
+	}
 
-		assert_eq!(host_to_bucket("garage.tld", "garage.tld"), None);
+	#[test]
+	fn bucket_name_test() {
+		for name in [
+			"my-bucket",
+			"john.doe",
+			"abc",
+			"a1b2c3",
+			"a".repeat(63).as_str(),
+		] {
+			assert_eq!(BucketName::new(name).unwrap().as_str(), name);
+		}
+		for name in [
+			"",
+			"ab",
+			"a".repeat(64).as_str(),
+			"My-Bucket",
+			"my_bucket",
+			"-bucket",
+			"bucket.",
+			"192.168.1.1",
+			"xn--bucket",
+			"bucket-s3alias",
+		] {
+			assert!(
+				matches!(BucketName::new(name), Err(Error::InvalidBucketName(_))),
+				"{:?} should be an invalid bucket name",
+				name
+			);
+		}
+	}
 
-		assert_eq!(host_to_bucket("garage.tld", ".garage.tld"), None);
+	#[test]
+	fn object_key_test() {
+		assert_eq!(ObjectKey::new("a/b/c.txt").unwrap().as_str(), "a/b/c.txt");
+		assert_eq!(ObjectKey::new("/").unwrap().as_str(), "/");
+		assert!(ObjectKey::new("").is_err());
+		assert!(ObjectKey::new("a".repeat(MAX_OBJECT_KEY_LEN)).is_ok());
+		assert!(ObjectKey::new("a".repeat(MAX_OBJECT_KEY_LEN + 1)).is_err());
+		// The maximum length is in bytes, not in characters
+		assert!(ObjectKey::new("é".repeat(MAX_OBJECT_KEY_LEN / 2 + 1)).is_err());
 
-		assert_eq!(host_to_bucket("not-garage.tld", "garage.tld"), None);
-		assert_eq!(host_to_bucket("not-garage.tld", ".garage.tld"), None);
+		assert_eq!(
+			ObjectKey::from_percent_encoded("dir/file%20name.html")
+				.unwrap()
+				.as_str(),
+			"dir/file name.html"
+		);
+		assert_eq!(
+			ObjectKey::from_percent_encoded("caf%C3%A9")
+				.unwrap()
+				.as_str(),
+			"café"
+		);
+		assert_eq!(
+			ObjectKey::from_percent_encoded("%2F").unwrap().as_str(),
+			"/"
+		);
+		assert!(ObjectKey::from_percent_encoded("").is_err());
+		assert!(ObjectKey::from_percent_encoded("%FF%FE").is_err());
 	}
 
 	#[test]
@@ -97,10 +97,11 @@ impl ApiHandler for K2VApiServer {
 			.map_err(helper_error_as_internal)?;
 		let bucket_params = bucket.state.into_option().unwrap();
 
+		let key_perm = bucket_params.key_permissions(&api_key.key_id);
 		let allowed = match endpoint.authorization_type() {
-			Authorization::Read => api_key.allow_read(&bucket_id),
-			Authorization::Write => api_key.allow_write(&bucket_id),
-			Authorization::Owner => api_key.allow_owner(&bucket_id),
+			Authorization::Read => key_perm.allow_read,
+			Authorization::Write => key_perm.allow_write,
+			Authorization::Owner => key_perm.allow_owner,
 			_ => unreachable!(),
 		};
 
@@ -155,8 +156,26 @@ impl ApiHandler for K2VApiServer {
 				start,
 				end,
 				limit,
+				delimiter,
 				reverse,
-			} => handle_read_index(ctx, prefix, start, end, limit, reverse).await,
+				min_entries,
+				min_values,
+				totals,
+			} => {
+				handle_read_index(
+					ctx,
+					prefix,
+					start,
+					end,
+					limit,
+					delimiter,
+					reverse,
+					min_entries,
+					min_values,
+					totals,
+				)
+				.await
+			}
 			Endpoint::InsertBatch {} => handle_insert_batch(ctx, req).await,
 			Endpoint::ReadBatch {} => handle_read_batch(ctx, req).await,
 			Endpoint::DeleteBatch {} => handle_delete_batch(ctx, req).await,
@@ -11,6 +11,8 @@ use garage_api_common::generic_server::ApiError;
 use garage_api_common::helpers::*;
 use garage_api_common::signature::error::Error as SignatureError;
 
+use crate::item::X_GARAGE_CAUSALITY_TOKEN;
+
 /// Errors of this crate
 #[derive(Debug, Error)]
 pub enum Error {
@@ -39,6 +41,11 @@ pub enum Error {
 	#[error(display = "Invalid causality token")]
 	InvalidCausalityToken,
 
+	/// The item was written since the causality token given in `If-Match`
+	/// was read; contains the current causality token of the item
+	#[error(display = "The item has been modified since the given causality token was read")]
+	CausalityConflict(String),
+
 	/// The client asked for an invalid return format (invalid Accept header)
 	#[error(display = "Not acceptable: {}", _0)]
 	NotAcceptable(String),
@@ -76,6 +83,7 @@ impl Error {
 			Error::InvalidBase64(_) => "InvalidBase64",
 			Error::InvalidUtf8Str(_) => "InvalidUtf8String",
 			Error::InvalidCausalityToken => "CausalityToken",
+			Error::CausalityConflict(_) => "CausalityConflict",
 			Error::InvalidDigest(_) => "InvalidDigest",
 		}
 	}
@@ -88,6 +96,7 @@ impl ApiError for Error {
 			Error::Common(c) => c.http_status_code(),
 			Error::NoSuchKey => StatusCode::NOT_FOUND,
 			Error::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+			Error::CausalityConflict(_) => StatusCode::CONFLICT,
 			Error::AuthorizationHeaderMalformed(_)
 			| Error::InvalidBase64(_)
 			| Error::InvalidUtf8Str(_)
@@ -99,6 +108,12 @@ impl ApiError for Error {
 	fn add_http_headers(&self, header_map: &mut HeaderMap<HeaderValue>) {
 		use hyper::header;
 		header_map.append(header::CONTENT_TYPE, "application/json".parse().unwrap());
+		if let Error::CausalityConflict(ct) = self {
+			let name = header::HeaderName::from_bytes(X_GARAGE_CAUSALITY_TOKEN.as_bytes());
+			if let (Ok(name), Ok(ct)) = (name, HeaderValue::from_str(ct)) {
+				header_map.append(name, ct);
+			}
+		}
 	}
 
 	fn http_body(&self, garage_region: &str, path: &str) -> ErrorBody {
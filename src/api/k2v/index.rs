@@ -9,15 +9,20 @@ use garage_api_common::helpers::*;
 
 use crate::api_server::ResBody;
 use crate::error::*;
-use crate::range::read_range;
+use crate::range::read_range_delimited;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_read_index(
 	ctx: ReqCtx,
 	prefix: Option<String>,
 	start: Option<String>,
 	end: Option<String>,
 	limit: Option<u64>,
+	delimiter: Option<String>,
 	reverse: Option<bool>,
+	min_entries: Option<i64>,
+	min_values: Option<i64>,
+	totals: Option<bool>,
 ) -> Result<Response<ResBody>, Error> {
 	let ReqCtx {
 		garage, bucket_id, ..
@@ -31,13 +36,14 @@ pub async fn handle_read_index(
 		.all_nongateway_nodes()
 		.to_vec();
 
-	let (partition_keys, more, next_start) = read_range(
+	let (partition_keys, common_prefixes, more, next_start) = read_range_delimited(
 		&garage.k2v.counter_table.table,
 		&bucket_id,
 		&prefix,
 		&start,
 		&end,
 		limit,
+		delimiter.as_deref(),
 		Some((DeletedFilter::NotDeleted, node_id_vec)),
 		EnumerationOrder::from_reverse(reverse),
 	)
@@ -48,25 +54,44 @@ pub async fn handle_read_index(
 	let s_values = VALUES.to_string();
 	let s_bytes = BYTES.to_string();
 
+	let partition_keys = partition_keys
+		.into_iter()
+		.map(|part| {
+			let vals = part.filtered_values(&garage.system.cluster_layout());
+			ReadIndexResponseEntry {
+				pk: part.sk,
+				entries: *vals.get(&s_entries).unwrap_or(&0),
+				conflicts: *vals.get(&s_conflicts).unwrap_or(&0),
+				values: *vals.get(&s_values).unwrap_or(&0),
+				bytes: *vals.get(&s_bytes).unwrap_or(&0),
+			}
+		})
+		// Rows are filtered out after the range has been read, so that `more`
+		// and `nextStart` still allow to continue the listing after them
+		.filter(|ent| {
+			ent.entries >= min_entries.unwrap_or(i64::MIN)
+				&& ent.values >= min_values.unwrap_or(i64::MIN)
+		})
+		.collect::<Vec<_>>();
+
+	let totals = match totals {
+		Some(true) => Some(ReadIndexTotals::sum(&partition_keys)),
+		_ => None,
+	};
+
+	// Only present in the response if a delimiter was given
+	let common_prefixes = delimiter.as_ref().map(|_| common_prefixes);
+
 	let resp = ReadIndexResponse {
 		prefix,
 		start,
 		end,
 		limit,
+		delimiter,
 		reverse,
-		partition_keys: partition_keys
-			.into_iter()
-			.map(|part| {
-				let vals = part.filtered_values(&garage.system.cluster_layout());
-				ReadIndexResponseEntry {
-					pk: part.sk,
-					entries: *vals.get(&s_entries).unwrap_or(&0),
-					conflicts: *vals.get(&s_conflicts).unwrap_or(&0),
-					values: *vals.get(&s_values).unwrap_or(&0),
-					bytes: *vals.get(&s_bytes).unwrap_or(&0),
-				}
-			})
-			.collect::<Vec<_>>(),
+		partition_keys,
+		common_prefixes,
+		totals,
 		more,
 		next_start,
 	};
@@ -80,10 +105,16 @@ struct ReadIndexResponse {
 	start: Option<String>,
 	end: Option<String>,
 	limit: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	delimiter: Option<String>,
 	reverse: bool,
 
 	#[serde(rename = "partitionKeys")]
 	partition_keys: Vec<ReadIndexResponseEntry>,
+	#[serde(rename = "commonPrefixes", skip_serializing_if = "Option::is_none")]
+	common_prefixes: Option<Vec<String>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	totals: Option<ReadIndexTotals>,
 
 	more: bool,
 	#[serde(rename = "nextStart")]
@@ -98,3 +129,24 @@ struct ReadIndexResponseEntry {
 	values: i64,
 	bytes: i64,
 }
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+struct ReadIndexTotals {
+	entries: i64,
+	conflicts: i64,
+	values: i64,
+	bytes: i64,
+}
+
+impl ReadIndexTotals {
+	fn sum(partition_keys: &[ReadIndexResponseEntry]) -> Self {
+		partition_keys
+			.iter()
+			.fold(Self::default(), |acc, pk| ReadIndexTotals {
+				entries: acc.entries + pk.entries,
+				conflicts: acc.conflicts + pk.conflicts,
+				values: acc.values + pk.values,
+				bytes: acc.bytes + pk.bytes,
+			})
+	}
+}
@@ -23,6 +23,73 @@ pub(crate) fn parse_causality_token(s: &str) -> Result<CausalContext, Error> {
 	CausalContext::parse(s).ok_or(Error::InvalidCausalityToken)
 }
 
+/// Get the causality token of a write to a single item, given either in the
+/// `X-Garage-Causality-Token` header, or in the `If-Match` header for a conditional
+/// write. In the latter case, check that the item has not been written since the
+/// token was read, so that concurrent read-modify-write cycles are detected
+/// instead of creating concurrent values.
+///
+/// This check is done before the write and is not atomic with it: two conditional
+/// writes racing with each other can both succeed, in which case their values are
+/// kept as concurrent values, as for unconditional writes.
+async fn write_causal_context(
+	ctx: &ReqCtx,
+	req: &Request<ReqBody>,
+	partition_key: &str,
+	sort_key: &str,
+) -> Result<Option<CausalContext>, Error> {
+	let token_header = |name: &str| -> Result<Option<CausalContext>, Error> {
+		req.headers()
+			.get(name)
+			.map(|s| s.to_str())
+			.transpose()?
+			// If-Match contains an entity tag, which is a quoted string
+			.map(|s| parse_causality_token(s.trim_matches('"')))
+			.transpose()
+	};
+	let causal_context = token_header(X_GARAGE_CAUSALITY_TOKEN)?;
+	let if_match = match token_header(header::IF_MATCH.as_str())? {
+		None => return Ok(causal_context),
+		Some(ct) => ct,
+	};
+	if causal_context.as_ref().is_some_and(|ct| *ct != if_match) {
+		return Err(Error::bad_request(
+			"If-Match and X-Garage-Causality-Token headers contain different tokens",
+		));
+	}
+
+	let current = ctx
+		.garage
+		.k2v
+		.item_table
+		.get(
+			&K2VItemPartition {
+				bucket_id: ctx.bucket_id,
+				partition_key: partition_key.to_string(),
+			},
+			&sort_key.to_string(),
+		)
+		.await?
+		.map(|item| item.causal_context())
+		.unwrap_or_default();
+	check_causality_precondition(&current, &if_match)?;
+
+	Ok(Some(if_match))
+}
+
+/// Fails with `Error::CausalityConflict` if an item with causal context `current`
+/// contains values that were written after causality token `expected` was read
+fn check_causality_precondition(
+	current: &CausalContext,
+	expected: &CausalContext,
+) -> Result<(), Error> {
+	if current.is_newer_than(expected) {
+		Err(Error::CausalityConflict(current.serialize()))
+	} else {
+		Ok(())
+	}
+}
+
 impl ReturnFormat {
 	pub fn from(req: &Request<ReqBody>) -> Result<Self, Error> {
 		let accept = match req.headers().get(header::ACCEPT) {
@@ -133,16 +200,10 @@ pub async fn handle_insert_item(
 	partition_key: &str,
 	sort_key: &str,
 ) -> Result<Response<ResBody>, Error> {
+	let causal_context = write_causal_context(&ctx, &req, partition_key, sort_key).await?;
 	let ReqCtx {
 		garage, bucket_id, ..
 	} = &ctx;
-	let causal_context = req
-		.headers()
-		.get(X_GARAGE_CAUSALITY_TOKEN)
-		.map(|s| s.to_str())
-		.transpose()?
-		.map(parse_causality_token)
-		.transpose()?;
 
 	let body = req.into_body().collect().await?;
 
@@ -171,16 +232,10 @@ pub async fn handle_delete_item(
 	partition_key: &str,
 	sort_key: &str,
 ) -> Result<Response<ResBody>, Error> {
+	let causal_context = write_causal_context(&ctx, &req, partition_key, sort_key).await?;
 	let ReqCtx {
 		garage, bucket_id, ..
 	} = &ctx;
-	let causal_context = req
-		.headers()
-		.get(X_GARAGE_CAUSALITY_TOKEN)
-		.map(|s| s.to_str())
-		.transpose()?
-		.map(parse_causality_token)
-		.transpose()?;
 
 	let value = DvvsValue::Deleted;
 
@@ -241,3 +296,39 @@ pub async fn handle_poll_item(
 			.body(empty_body())?)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ct(clock: &[(K2VNodeId, u64)]) -> CausalContext {
+		CausalContext {
+			vector_clock: clock.iter().cloned().collect(),
+		}
+	}
+
+	#[test]
+	fn test_causality_precondition() {
+		let read = ct(&[(1, 10), (2, 5)]);
+
+		// Nothing was written since the token was read
+		assert!(check_causality_precondition(&read, &read).is_ok());
+		// The item does not exist (yet or anymore)
+		assert!(check_causality_precondition(&CausalContext::new(), &read).is_ok());
+		// A stale token of the client's own is fine as long as the item has not advanced
+		assert!(check_causality_precondition(&ct(&[(1, 9)]), &read).is_ok());
+
+		// Another write was done on one of the nodes
+		let current = ct(&[(1, 11), (2, 5)]);
+		match check_causality_precondition(&current, &read) {
+			Err(Error::CausalityConflict(token)) => {
+				assert_eq!(parse_causality_token(&token).unwrap(), current)
+			}
+			_ => panic!("expected a conflict"),
+		}
+		// A write was done through a node that was not in the token
+		assert!(check_causality_precondition(&ct(&[(1, 10), (2, 5), (3, 1)]), &read).is_err());
+		// Writing with an empty token requires that the item does not exist
+		assert!(check_causality_precondition(&read, &CausalContext::new()).is_err());
+	}
+}
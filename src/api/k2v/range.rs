@@ -13,6 +13,10 @@ use crate::error::*;
 
 /// Read range in a Garage table.
 /// Returns (entries, more?, nextStart)
+///
+/// `nextStart` is the first key that was not returned because of `limit`.
+/// As it is included when it is passed as `start`, no key is returned twice
+/// when a listing is continued from it.
 #[allow(clippy::too_many_arguments)]
 pub(crate) async fn read_range<F>(
 	table: &Arc<Table<F, TableShardedReplication>>,
@@ -27,6 +31,45 @@ pub(crate) async fn read_range<F>(
 where
 	F: TableSchema<S = String> + 'static,
 {
+	let (entries, _, more, next_start) = read_range_delimited(
+		table,
+		partition_key,
+		prefix,
+		start,
+		end,
+		limit,
+		None,
+		filter,
+		enumeration_order,
+	)
+	.await?;
+	Ok((entries, more, next_start))
+}
+
+/// Read range in a Garage table, rolling up the keys that contain `delimiter`
+/// after the prefix into common prefixes, as in S3 listings.
+/// Returns (entries, common prefixes, more?, nextStart)
+///
+/// Each common prefix is returned once and counts as one item for `limit`.
+/// Once a common prefix has been returned, the listing jumps over all keys that
+/// start with it, including when it is continued from `nextStart`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn read_range_delimited<F>(
+	table: &Arc<Table<F, TableShardedReplication>>,
+	partition_key: &F::P,
+	prefix: &Option<String>,
+	start: &Option<String>,
+	end: &Option<String>,
+	limit: Option<u64>,
+	delimiter: Option<&str>,
+	filter: Option<F::Filter>,
+	enumeration_order: EnumerationOrder,
+) -> Result<(Vec<F::E>, Vec<String>, bool, Option<String>), Error>
+where
+	F: TableSchema<S = String> + 'static,
+{
+	let delimiter = delimiter.filter(|d| !d.is_empty());
+
 	let (mut start, mut start_ignore) = match (prefix, start) {
 		(None, None) => (None, false),
 		(None, Some(s)) => (Some(s.clone()), false),
@@ -48,10 +91,13 @@ where
 	};
 
 	let mut entries = vec![];
+	let mut common_prefixes = vec![];
 	loop {
 		let n_get = std::cmp::min(
 			1000,
-			limit.map(|x| x as usize).unwrap_or(usize::MAX - 10) - entries.len() + 2,
+			limit.map(|x| x as usize).unwrap_or(usize::MAX - 10)
+				- (entries.len() + common_prefixes.len())
+				+ 2,
 		);
 		let get_ret = table
 			.get_range(
@@ -64,6 +110,8 @@ where
 			.await?;
 
 		let get_ret_len = get_ret.len();
+		let mut last_seen = None;
+		let mut rolled_up = None;
 
 		for entry in get_ret {
 			if start_ignore && Some(entry.sort_key()) == start.as_ref() {
@@ -71,7 +119,7 @@ where
 			}
 			if let Some(p) = prefix {
 				if !entry.sort_key().starts_with(p) {
-					return Ok((entries, false, None));
+					return Ok((entries, common_prefixes, false, None));
 				}
 			}
 			if let Some(e) = end {
@@ -80,22 +128,88 @@ where
 					EnumerationOrder::Reverse => entry.sort_key() <= e,
 				};
 				if is_finished {
-					return Ok((entries, false, None));
+					return Ok((entries, common_prefixes, false, None));
 				}
 			}
 			if let Some(l) = limit {
-				if entries.len() >= l as usize {
-					return Ok((entries, true, Some(entry.sort_key().clone())));
+				if entries.len() + common_prefixes.len() >= l as usize {
+					// After a common prefix, the keys under it have been jumped over,
+					// so this entry is never under the last common prefix returned
+					let next_start = entry.sort_key().clone();
+					return Ok((entries, common_prefixes, true, Some(next_start)));
 				}
 			}
-			entries.push(entry);
+
+			let common_prefix = delimiter
+				.and_then(|d| common_prefix(entry.sort_key(), prefix.as_deref().unwrap_or(""), d));
+			match common_prefix {
+				Some(cp) => {
+					common_prefixes.push(cp.clone());
+					rolled_up = Some(cp);
+					break;
+				}
+				None => {
+					last_seen = Some(entry.sort_key().clone());
+					entries.push(entry);
+				}
+			}
+		}
+
+		if let Some(cp) = rolled_up {
+			// Jump over all the other keys under this common prefix
+			match enumeration_order {
+				EnumerationOrder::Forward => match key_after_prefix(&cp) {
+					Some(next) => {
+						start = Some(next);
+						start_ignore = false;
+					}
+					None => return Ok((entries, common_prefixes, false, None)),
+				},
+				EnumerationOrder::Reverse => {
+					start = Some(cp);
+					start_ignore = true;
+				}
+			}
+			continue;
 		}
 
 		if get_ret_len < n_get {
-			return Ok((entries, false, None));
+			return Ok((entries, common_prefixes, false, None));
 		}
 
-		start = Some(entries.last().unwrap().sort_key().clone());
+		start = Some(last_seen.unwrap());
 		start_ignore = true;
 	}
 }
+
+/// If `key` contains `delimiter` after `prefix`, returns the part of `key`
+/// up to and including the first such delimiter
+fn common_prefix(key: &str, prefix: &str, delimiter: &str) -> Option<String> {
+	let rest = key.strip_prefix(prefix)?;
+	let pos = rest.find(delimiter)?;
+	Some(key[..prefix.len() + pos + delimiter.len()].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_common_prefix() {
+		assert_eq!(common_prefix("a", "", "/"), None);
+		assert_eq!(common_prefix("a/b", "", "/").as_deref(), Some("a/"));
+		assert_eq!(common_prefix("a/", "", "/").as_deref(), Some("a/"));
+		// Nested delimiters are rolled up at the first level after the prefix
+		assert_eq!(common_prefix("a/b/c", "", "/").as_deref(), Some("a/"));
+		assert_eq!(common_prefix("a/b/c", "a/", "/").as_deref(), Some("a/b/"));
+		assert_eq!(common_prefix("a/b/c", "a/b/", "/"), None);
+		// The delimiter in the prefix itself is not taken into account
+		assert_eq!(common_prefix("a/b", "a/", "/"), None);
+		assert_eq!(common_prefix("a/b", "a", "/").as_deref(), Some("a/"));
+		// Multi-character delimiters
+		assert_eq!(
+			common_prefix("users::1::inbox", "users::", "::").as_deref(),
+			Some("users::1::")
+		);
+	}
+}
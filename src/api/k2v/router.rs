@@ -42,7 +42,11 @@ pub enum Endpoint {
 		start: Option<String>,
 		end: Option<String>,
 		limit: Option<u64>,
+		delimiter: Option<String>,
 		reverse: Option<bool>,
+		min_entries: Option<i64>,
+		min_values: Option<i64>,
+		totals: Option<bool>,
 	},
 	ReadItem {
 		partition_key: String,
@@ -105,7 +109,7 @@ impl Endpoint {
 				EMPTY => ReadItem (query::sort_key),
 			],
 			no_key: [
-				EMPTY => ReadIndex (query_opt::prefix, query_opt::start, query_opt::end, opt_parse::limit, opt_parse::reverse),
+				EMPTY => ReadIndex (query_opt::prefix, query_opt::start, query_opt::end, opt_parse::limit, query_opt::delimiter, opt_parse::reverse, opt_parse::min_entries, opt_parse::min_values, opt_parse::totals),
 			]
 		}
 	}
@@ -246,10 +250,14 @@ generateQueryParameters! {
 		"prefix" => prefix,
 		"start" => start,
 		"causality_token" => causality_token,
+		"delimiter" => delimiter,
 		"end" => end,
 		"limit" => limit,
+		"min_entries" => min_entries,
+		"min_values" => min_values,
 		"reverse" => reverse,
 		"sort_key" => sort_key,
-		"timeout" => timeout
+		"timeout" => timeout,
+		"totals" => totals
 	]
 }
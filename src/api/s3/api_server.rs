@@ -90,10 +90,11 @@ impl ApiHandler for S3ApiServer {
 			.s3_api
 			.root_domain
 			.as_ref()
-			.and_then(|root_domain| host_to_bucket(&host, root_domain));
+			.and_then(|root_domain| host_to_bucket(&host, root_domain))
+			.transpose()?;
 
 		let (endpoint, bucket_name) =
-			Endpoint::from_request(req, bucket_name.map(ToOwned::to_owned))?;
+			Endpoint::from_request(req, bucket_name.map(BucketName::into_string))?;
 
 		Ok(S3ApiEndpoint {
 			bucket_name,
@@ -150,10 +151,11 @@ impl ApiHandler for S3ApiServer {
 			.await?;
 		let bucket_params = bucket.state.into_option().unwrap();
 
+		let key_perm = bucket_params.key_permissions(&api_key.key_id);
 		let allowed = match endpoint.authorization_type() {
-			Authorization::Read => api_key.allow_read(&bucket_id),
-			Authorization::Write => api_key.allow_write(&bucket_id),
-			Authorization::Owner => api_key.allow_owner(&bucket_id),
+			Authorization::Read => key_perm.allow_read,
+			Authorization::Write => key_perm.allow_write,
+			Authorization::Owner => key_perm.allow_owner,
 			_ => unreachable!(),
 		};
 
@@ -52,7 +52,9 @@ pub async fn handle_list_buckets(
 		"Key should not be in deleted state at this point (in handle_list_buckets)",
 	)?;
 
-	// Collect buckets user has access to
+	// Collect buckets user has access to. The authorized buckets of the key
+	// are only an index of these buckets: the permissions of the key are
+	// those in the authorized key set of each bucket.
 	let ids = api_key
 		.state
 		.as_option()
@@ -60,7 +62,6 @@ pub async fn handle_list_buckets(
 		.authorized_buckets
 		.items()
 		.iter()
-		.filter(|(_, perms)| perms.is_any())
 		.map(|(id, _)| *id)
 		.collect::<Vec<_>>();
 
@@ -69,19 +70,26 @@ pub async fn handle_list_buckets(
 
 	for bucket_id in ids.iter() {
 		let bucket = garage.bucket_table.get(&EmptyKey, bucket_id).await?;
-		if let Some(bucket) = bucket {
-			for (alias, _, _active) in bucket.aliases().iter().filter(|(_, _, active)| *active) {
-				let alias_opt = garage.bucket_alias_table.get(&EmptyKey, alias).await?;
-				if let Some(alias_ent) = alias_opt {
-					if *alias_ent.state.get() == Some(*bucket_id) {
-						aliases.insert(alias_ent.name().to_string(), *bucket_id);
-					}
-				}
+		let param = match bucket.map(|b| b.state) {
+			Some(Deletable::Present(param)) if param.key_permissions(&api_key.key_id).is_any() => {
+				param
 			}
-			if let Deletable::Present(param) = bucket.state {
-				buckets_by_id.insert(bucket_id, param);
+			_ => continue,
+		};
+		for (alias, _, _active) in param
+			.aliases
+			.items()
+			.iter()
+			.filter(|(_, _, active)| *active)
+		{
+			let alias_opt = garage.bucket_alias_table.get(&EmptyKey, alias).await?;
+			if let Some(alias_ent) = alias_opt {
+				if *alias_ent.state.get() == Some(*bucket_id) {
+					aliases.insert(alias_ent.name().to_string(), *bucket_id);
+				}
 			}
 		}
+		buckets_by_id.insert(bucket_id, param);
 	}
 
 	for (alias, _, id_opt) in key_p.local_aliases.items() {
@@ -157,7 +165,14 @@ pub async fn handle_create_bucket(
 		// Check we have write or owner permission on the bucket,
 		// in that case it's fine, return 200 OK, bucket exists;
 		// otherwise return a forbidden error.
-		let kp = api_key.bucket_permissions(&bucket_id);
+		let kp = helper
+			.bucket()
+			.get_existing_bucket(bucket_id)
+			.await?
+			.state
+			.into_option()
+			.unwrap()
+			.key_permissions(&api_key.key_id);
 		if !(kp.allow_write || kp.allow_owner) {
 			return Err(CommonError::BucketAlreadyExists.into());
 		}
@@ -602,11 +602,16 @@ pub async fn handle_upload_part_copy(
 				if let Some(final_data) = data_to_upload {
 					garage
 						.block_manager
-						.rpc_put_block(final_hash, final_data, dest_encryption.is_encrypted(), None)
-						.await
-				} else {
-					Ok(())
+						.rpc_put_block(
+							final_hash,
+							final_data,
+							dest_encryption.is_encrypted(),
+							None,
+							None,
+						)
+						.await?;
 				}
+				Ok::<_, GarageError>(())
 			},
 			// Thing 2: we need to insert the block in the version
 			garage.version_table.insert(&dest_version),
@@ -668,7 +673,18 @@ async fn get_copy_source(ctx: &ReqCtx, req: &Request<ReqBody>) -> Result<Object,
 		.await
 		.map_err(pass_helper_error)?;
 
-	if !api_key.allow_read(&source_bucket_id) {
+	let source_bucket_params = garage
+		.bucket_helper()
+		.get_existing_bucket(source_bucket_id)
+		.await?
+		.state
+		.into_option()
+		.unwrap();
+
+	if !source_bucket_params
+		.key_permissions(&api_key.key_id)
+		.allow_read
+	{
 		return Err(Error::forbidden(format!(
 			"Reading from bucket {} not allowed for this key",
 			source_bucket
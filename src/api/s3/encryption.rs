@@ -274,7 +274,7 @@ impl EncryptionParams {
 	) -> Result<ByteStream, GarageError> {
 		let raw_block = garage
 			.block_manager
-			.rpc_get_block_streaming(hash, order)
+			.rpc_get_block_streaming(hash, order, None)
 			.await?;
 		Ok(self.decrypt_block_stream(raw_block))
 	}
@@ -5,6 +5,7 @@ use hyper::header::HeaderValue;
 use hyper::{HeaderMap, StatusCode};
 
 use garage_model::helper::error::Error as HelperError;
+use garage_net::request_id::RequestId;
 
 pub(crate) use garage_api_common::common_error::pass_helper_error;
 
@@ -59,6 +60,13 @@ pub enum Error {
 	#[error(display = "Proposed upload is smaller than the minimum allowed object size")]
 	EntityTooSmall,
 
+	/// The object is larger than the maximum object size set in the configuration
+	#[error(
+		display = "Proposed upload exceeds the maximum allowed object size ({} bytes)",
+		_0
+	)]
+	EntityTooLarge(usize),
+
 	// Category: bad request
 	/// The request contained an invalid UTF-8 sequence in its path or in other parameters
 	#[error(display = "Invalid UTF-8: {}", _0)]
@@ -140,6 +148,7 @@ impl Error {
 			Error::InvalidPart => "InvalidPart",
 			Error::InvalidPartOrder => "InvalidPartOrder",
 			Error::EntityTooSmall => "EntityTooSmall",
+			Error::EntityTooLarge(_) => "EntityTooLarge",
 			Error::AuthorizationHeaderMalformed(_) => "AuthorizationHeaderMalformed",
 			Error::NotImplemented(_) => "NotImplemented",
 			Error::InvalidXml(_) => "MalformedXML",
@@ -158,6 +167,7 @@ impl ApiError for Error {
 			Error::Common(c) => c.http_status_code(),
 			Error::NoSuchKey | Error::NoSuchUpload => StatusCode::NOT_FOUND,
 			Error::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+			Error::EntityTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
 			Error::InvalidRange(_) => StatusCode::RANGE_NOT_SATISFIABLE,
 			Error::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
 			Error::AuthorizationHeaderMalformed(_)
@@ -197,6 +207,7 @@ impl ApiError for Error {
 			message: s3_xml::Value(format!("{}", self)),
 			resource: Some(s3_xml::Value(path.to_string())),
 			region: Some(s3_xml::Value(garage_region.to_string())),
+			request_id: RequestId::current().map(|id| s3_xml::Value(id.to_string())),
 		};
 		let error_str = s3_xml::to_xml_with_header(&error).unwrap_or_else(|_| {
 			r#"
@@ -431,10 +431,18 @@ pub fn full_object_byte_stream(
 						.ok_or_message("channel closed")?;
 
 					let version = version_fut.await.unwrap()?.ok_or(Error::NoSuchKey)?;
-					for (i, (_, vb)) in version.blocks.items().iter().enumerate().skip(1) {
-						let stream_block_i = encryption
-							.get_block(&garage, &vb.hash, Some(order_stream.order(i as u64)))
-							.await?;
+					let hashes = version
+						.blocks
+						.items()
+						.iter()
+						.map(|(_, vb)| vb.hash)
+						.collect::<Vec<_>>();
+					let mut block_streams =
+						garage
+							.block_manager
+							.rpc_get_blocks_read_ahead(hashes, 1, order_stream);
+					while let Some(block_stream) = block_streams.next().await {
+						let stream_block_i = encryption.decrypt_block_stream(block_stream?);
 						tx.send(stream_block_i)
 							.await
 							.ok_or_message("channel closed")?;
@@ -651,10 +659,16 @@ fn body_from_blocks_range(
 
 	tokio::spawn(async move {
 		match async {
-			for (i, (block, block_offset)) in blocks.iter().enumerate() {
-				let block_stream = encryption
-					.get_block(&garage, &block.hash, Some(order_stream.order(i as u64)))
-					.await?;
+			let hashes = blocks.iter().map(|(b, _)| b.hash).collect::<Vec<_>>();
+			let mut block_streams =
+				garage
+					.block_manager
+					.rpc_get_blocks_read_ahead(hashes, 0, order_stream);
+			for (_, block_offset) in blocks.iter() {
+				let block_stream = match block_streams.next().await {
+					Some(block_stream) => encryption.decrypt_block_stream(block_stream?),
+					None => break,
+				};
 				let block_stream = block_stream
 					.scan(*block_offset, move |chunk_offset, chunk| {
 						let r = match chunk {
@@ -823,7 +837,7 @@ impl PreconditionHeaders {
 
 		if let Some(im) = &self.if_match {
 			// Step 1: if-match is present
-			if !im.iter().any(|x| x == etag || x == "*") {
+			if !etag_matches(im, etag) {
 				return Ok(Some(StatusCode::PRECONDITION_FAILED));
 			}
 		} else if let Some(ius) = &self.if_unmodified_since {
@@ -835,7 +849,7 @@ impl PreconditionHeaders {
 
 		if let Some(inm) = &self.if_none_match {
 			// Step 3: if-none-match is present
-			if inm.iter().any(|x| x == etag || x == "*") {
+			if etag_matches(inm, etag) {
 				return Ok(Some(StatusCode::NOT_MODIFIED));
 			}
 		} else if let Some(ims) = &self.if_modified_since {
@@ -855,3 +869,42 @@ impl PreconditionHeaders {
 		}
 	}
 }
+
+/// Returns true if one of the entity tags of an if-match or if-none-match
+/// header (with quotes already removed) matches the ETag of the object
+fn etag_matches(tags: &[String], etag: &str) -> bool {
+	tags.iter().any(|x| x == etag || x == "*")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_etag_matches() {
+		let etag = "9b2cf535f27731c974343645a3985328";
+		let parse = |h: &str| {
+			let mut headers = HeaderMap::new();
+			headers.insert(IF_NONE_MATCH, h.parse().unwrap());
+			PreconditionHeaders::parse_with(
+				&headers,
+				&IF_MATCH,
+				&IF_NONE_MATCH,
+				&IF_MODIFIED_SINCE,
+				&IF_UNMODIFIED_SINCE,
+			)
+			.unwrap()
+			.if_none_match
+			.unwrap()
+		};
+
+		assert!(etag_matches(&parse(&format!("\"{}\"", etag)), etag));
+		assert!(etag_matches(
+			&parse(&format!("\"abcd\", \"{}\"", etag)),
+			etag
+		));
+		assert!(etag_matches(&parse("*"), etag));
+		assert!(!etag_matches(&parse("\"abcd\""), etag));
+		assert!(!etag_matches(&[], etag));
+	}
+}
@@ -131,7 +131,11 @@ pub async fn handle_put_part(
 	let (stream, stream_checksums) = req_body.streaming_with_checksums();
 	let stream = stream.map_err(Error::from);
 
-	let mut chunker = StreamChunker::new(stream, garage.config.block_size);
+	let mut chunker = StreamChunker::new(
+		stream,
+		garage.config.block_size,
+		garage.config.max_object_size,
+	);
 
 	// Read first chuck, and at the same time try to get object to see if it exists
 	let ((_, object_version, mut mpu), first_block) =
@@ -405,6 +409,12 @@ pub async fn handle_complete_multipart_upload(
 	// Calculate total size of final object
 	let total_size = parts.iter().map(|x| x.size.unwrap()).sum();
 
+	// The upload is kept, so that it can be completed with fewer parts
+	let max_object_size = garage.config.max_object_size;
+	if max_object_size != 0 && total_size > max_object_size as u64 {
+		return Err(Error::EntityTooLarge(max_object_size));
+	}
+
 	if let Err(e) = check_quotas(&ctx, total_size, Some(&object)).await {
 		object_version.state = ObjectVersionState::Aborted;
 		let final_object = Object::new(*bucket_id, key.clone(), vec![object_version]);
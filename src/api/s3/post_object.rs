@@ -111,15 +111,15 @@ pub async fn handle_post_object(
 		.await
 		.map_err(pass_helper_error)?;
 
-	if !api_key.allow_write(&bucket_id) {
-		return Err(Error::forbidden("Operation is not allowed for this key."));
-	}
-
 	let bucket = garage
 		.bucket_helper()
 		.get_existing_bucket(bucket_id)
 		.await?;
 	let bucket_params = bucket.state.into_option().unwrap();
+
+	if !bucket_params.key_permissions(&api_key.key_id).allow_write {
+		return Err(Error::forbidden("Operation is not allowed for this key."));
+	}
 	let matching_cors_rule = find_matching_cors_rule(
 		&bucket_params,
 		&Request::from_parts(head.clone(), empty_body::<Infallible>()),
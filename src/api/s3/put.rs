@@ -131,7 +131,11 @@ pub(crate) async fn save_stream<S: Stream<Item = Result<Bytes, Error>> + Unpin>(
 		garage, bucket_id, ..
 	} = ctx;
 
-	let mut chunker = StreamChunker::new(body, garage.config.block_size);
+	let mut chunker = StreamChunker::new(
+		body,
+		garage.config.block_size,
+		garage.config.max_object_size,
+	);
 	let (first_block_opt, existing_object) = try_join!(
 		chunker.next(),
 		garage.object_table.get(bucket_id, key).map_err(Error::from),
@@ -573,7 +577,7 @@ async fn put_block_and_meta(
 	futures::try_join!(
 		garage
 			.block_manager
-			.rpc_put_block(hash, block, is_encrypted, Some(order_tag)),
+			.rpc_put_block(hash, block, is_encrypted, Some(order_tag), None),
 		garage.version_table.insert(&version),
 		garage.block_ref_table.insert(&block_ref),
 	)?;
@@ -584,15 +588,23 @@ pub(crate) struct StreamChunker<S: Stream<Item = Result<Bytes, Error>>> {
 	stream: S,
 	read_all: bool,
 	block_size: usize,
+	/// Maximum number of bytes that can be read from the stream, 0 for no limit
+	max_size: usize,
+	total_read: usize,
 	buf: BytesBuf,
 }
 
 impl<S: Stream<Item = Result<Bytes, Error>> + Unpin> StreamChunker<S> {
-	pub(crate) fn new(stream: S, block_size: usize) -> Self {
+	/// Split `stream` in chunks of `block_size` bytes. Reading fails with
+	/// `Error::EntityTooLarge` once more than `max_size` bytes have been read,
+	/// unless it is 0.
+	pub(crate) fn new(stream: S, block_size: usize, max_size: usize) -> Self {
 		Self {
 			stream,
 			read_all: false,
 			block_size,
+			max_size,
+			total_read: 0,
 			buf: BytesBuf::new(),
 		}
 	}
@@ -602,6 +614,10 @@ impl<S: Stream<Item = Result<Bytes, Error>> + Unpin> StreamChunker<S> {
 			if let Some(block) = self.stream.next().await {
 				let bytes = block?;
 				trace!("Body next: {} bytes", bytes.len());
+				self.total_read += bytes.len();
+				if self.max_size != 0 && self.total_read > self.max_size {
+					return Err(Error::EntityTooLarge(self.max_size));
+				}
 				self.buf.extend(bytes);
 			} else {
 				self.read_all = true;
@@ -702,3 +718,47 @@ pub(crate) fn next_timestamp(existing_object: Option<&Object>) -> u64 {
 		.map(|t| std::cmp::max(t + 1, now_msec()))
 		.unwrap_or_else(now_msec)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn body(
+		n_chunks: usize,
+		chunk_size: usize,
+	) -> impl Stream<Item = Result<Bytes, Error>> + Unpin {
+		futures::stream::iter((0..n_chunks).map(move |_| Ok(Bytes::from(vec![0u8; chunk_size]))))
+	}
+
+	async fn chunk_sizes<S>(mut chunker: StreamChunker<S>) -> Result<Vec<usize>, Error>
+	where
+		S: Stream<Item = Result<Bytes, Error>> + Unpin,
+	{
+		let mut sizes = vec![];
+		while let Some(block) = chunker.next().await? {
+			sizes.push(block.len());
+		}
+		Ok(sizes)
+	}
+
+	#[tokio::test]
+	async fn test_chunker_max_size() {
+		let sizes = chunk_sizes(StreamChunker::new(body(4, 100), 150, 0)).await;
+		assert_eq!(sizes.unwrap(), [150, 150, 100]);
+
+		// Objects of exactly the maximum size are accepted
+		let sizes = chunk_sizes(StreamChunker::new(body(4, 100), 150, 400)).await;
+		assert_eq!(sizes.unwrap(), [150, 150, 100]);
+
+		let res = chunk_sizes(StreamChunker::new(body(5, 100), 150, 400)).await;
+		assert!(matches!(res, Err(Error::EntityTooLarge(400))));
+
+		// The error is returned as soon as the limit is exceeded,
+		// without reading the rest of the stream
+		let mut chunker = StreamChunker::new(body(1000, 100), 150, 400);
+		assert_eq!(chunker.next().await.unwrap().unwrap().len(), 150);
+		assert_eq!(chunker.next().await.unwrap().unwrap().len(), 150);
+		assert!(chunker.next().await.is_err());
+		assert!(chunker.total_read <= 500);
+	}
+}
@@ -83,6 +83,8 @@ pub struct Error {
 	pub resource: Option<Value>,
 	#[serde(rename = "Region")]
 	pub region: Option<Value>,
+	#[serde(rename = "RequestId")]
+	pub request_id: Option<Value>,
 }
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
@@ -332,6 +334,7 @@ mod tests {
 			message: Value("A dummy error message".to_string()),
 			resource: Some(Value("/bucket/a/plop".to_string())),
 			region: Some(Value("garage".to_string())),
+			request_id: Some(Value("0123456789ABCDEF".to_string())),
 		};
 		assert_eq!(
 			to_xml_with_header(&error)?,
@@ -341,6 +344,7 @@ mod tests {
 	<Message>A dummy error message</Message>\
 	<Resource>/bucket/a/plop</Resource>\
 	<Region>garage</Region>\
+	<RequestId>0123456789ABCDEF</RequestId>\
 </Error>"
 		);
 		Ok(())
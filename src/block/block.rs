@@ -2,6 +2,7 @@ use std::path::PathBuf;
 
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use zstd::stream::Encoder;
 
 use garage_util::data::*;
@@ -15,7 +16,7 @@ pub enum DataBlockHeader {
 	Compressed,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DataBlockElem<T> {
 	header: DataBlockHeader,
 	elem: T,
@@ -82,11 +83,24 @@ impl DataBlock {
 		}
 	}
 
+	/// Check that the block is not larger than `max_size` bytes as it is stored
+	/// (i.e. after compression), 0 meaning no limit
+	pub fn check_max_size(&self, hash: &Hash, max_size: usize) -> Result<(), Error> {
+		if max_size != 0 && self.elem.len() > max_size {
+			return Err(Error::BlockTooLarge(*hash, self.elem.len(), max_size));
+		}
+		Ok(())
+	}
+
+	/// Build a data block from its content, compressing it if a compression level is given.
+	/// The block is stored uncompressed if compression would not make it smaller.
 	pub async fn from_buffer(data: Bytes, level: Option<i32>) -> DataBlock {
 		tokio::task::spawn_blocking(move || {
 			if let Some(level) = level {
 				if let Ok(data_compressed) = zstd_encode(&data[..], level) {
-					return DataBlock::compressed(data_compressed.into());
+					if data_compressed.len() < data.len() {
+						return DataBlock::compressed(data_compressed.into());
+					}
 				}
 			}
 			DataBlock::plain(data.into())
@@ -96,6 +110,161 @@ impl DataBlock {
 	}
 }
 
+/// Incremental verification of the integrity of a data block,
+/// for when the block is read in several chunks
+pub(crate) struct DataBlockVerifier {
+	state: VerifierState,
+}
+
+enum VerifierState {
+	Plain(Blake2Hasher),
+	Compressed(zstd::stream::write::Decoder<'static, HashWriter>),
+	Failed,
+}
+
+struct HashWriter(Blake2Hasher);
+
+impl std::io::Write for HashWriter {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.0.update(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+impl DataBlockVerifier {
+	pub(crate) fn new(header: DataBlockHeader) -> Self {
+		let state = match header {
+			DataBlockHeader::Plain => VerifierState::Plain(Blake2Hasher::new()),
+			DataBlockHeader::Compressed => {
+				match zstd::stream::write::Decoder::new(HashWriter(Blake2Hasher::new())) {
+					Ok(decoder) => VerifierState::Compressed(decoder),
+					Err(_) => VerifierState::Failed,
+				}
+			}
+		};
+		Self { state }
+	}
+
+	pub(crate) fn update(&mut self, data: &[u8]) {
+		use std::io::Write;
+
+		match &mut self.state {
+			VerifierState::Plain(hasher) => hasher.update(data),
+			VerifierState::Compressed(decoder) => {
+				if decoder.write_all(data).is_err() {
+					self.state = VerifierState::Failed;
+				}
+			}
+			VerifierState::Failed => (),
+		}
+	}
+
+	/// Check that the data given so far is the complete content of the block
+	/// with the given hash. For compressed blocks, the hash is that of the
+	/// decompressed content.
+	pub(crate) fn verify(self, hash: Hash) -> Result<(), Error> {
+		use std::io::Write;
+
+		let computed = match self.state {
+			VerifierState::Plain(hasher) => hasher.finalize(),
+			VerifierState::Compressed(mut decoder) => match decoder.flush() {
+				Ok(()) => decoder.into_inner().0.finalize(),
+				Err(_) => return Err(Error::CorruptData(hash)),
+			},
+			VerifierState::Failed => return Err(Error::CorruptData(hash)),
+		};
+		if computed == hash {
+			Ok(())
+		} else {
+			Err(Error::CorruptData(hash))
+		}
+	}
+}
+
+/// Reader for a block stored on disk, that returns its content in chunks
+/// and checks its integrity once all of it has been read
+pub(crate) struct DataBlockChunkReader<R> {
+	reader: R,
+	header: DataBlockHeader,
+	hash: Hash,
+	chunk_size: usize,
+	/// None once the end of the block or an error has been reached
+	verifier: Option<DataBlockVerifier>,
+	/// Data read so far, if the complete block is to be kept
+	kept: Option<Vec<u8>>,
+	/// Maximum size of the block for it to be kept
+	keep_max_size: usize,
+}
+
+impl<R: AsyncRead + Unpin> DataBlockChunkReader<R> {
+	/// If `keep_max_size` is set, the content of the block is also kept
+	/// so that it can be returned by `into_block`, unless the block turns
+	/// out to be larger than `keep_max_size`
+	pub(crate) fn new(
+		reader: R,
+		header: DataBlockHeader,
+		hash: Hash,
+		chunk_size: usize,
+		keep_max_size: Option<usize>,
+	) -> Self {
+		Self {
+			reader,
+			header,
+			hash,
+			chunk_size,
+			verifier: Some(DataBlockVerifier::new(header)),
+			kept: keep_max_size.map(|_| vec![]),
+			keep_max_size: keep_max_size.unwrap_or(0),
+		}
+	}
+
+	/// Read the next chunk of the block. At the end of the block, returns
+	/// `None` if the block is valid, or `Error::CorruptData` once otherwise.
+	pub(crate) async fn next_chunk(&mut self) -> Option<Result<Bytes, Error>> {
+		let verifier = self.verifier.as_mut()?;
+		let mut buf = vec![0u8; self.chunk_size];
+		match self.reader.read(&mut buf).await {
+			Ok(0) => match self.verifier.take().unwrap().verify(self.hash) {
+				Ok(()) => None,
+				Err(e) => {
+					self.kept = None;
+					Some(Err(e))
+				}
+			},
+			Ok(n) => {
+				buf.truncate(n);
+				verifier.update(&buf);
+				if let Some(kept) = &mut self.kept {
+					if kept.len() + n <= self.keep_max_size {
+						kept.extend_from_slice(&buf);
+					} else {
+						self.kept = None;
+					}
+				}
+				Some(Ok(Bytes::from(buf)))
+			}
+			Err(e) => {
+				self.verifier = None;
+				self.kept = None;
+				Some(Err(e.into()))
+			}
+		}
+	}
+
+	/// The complete block, if it has been kept and was read entirely
+	/// without errors
+	pub(crate) fn into_block(self) -> Option<DataBlock> {
+		match (self.verifier, self.kept) {
+			(None, Some(kept)) => Some(DataBlock::from_parts(self.header, Bytes::from(kept))),
+			_ => None,
+		}
+	}
+}
+
 pub fn zstd_encode<R: std::io::Read>(mut source: R, level: i32) -> std::io::Result<Vec<u8>> {
 	let mut result = Vec::<u8>::new();
 	let mut encoder = Encoder::new(&mut result, level)?;
@@ -104,3 +273,191 @@ pub fn zstd_encode<R: std::io::Read>(mut source: R, level: i32) -> std::io::Resu
 	encoder.finish()?;
 	Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn decode(block: &DataBlock) -> Vec<u8> {
+		match block.header {
+			DataBlockHeader::Plain => block.elem.to_vec(),
+			DataBlockHeader::Compressed => zstd::stream::decode_all(&block.elem[..]).unwrap(),
+		}
+	}
+
+	#[tokio::test]
+	async fn test_compression_roundtrip() {
+		let compressible = Bytes::from("hello garage ".repeat(10000));
+		let hash = blake2sum(&compressible[..]);
+		let block = DataBlock::from_buffer(compressible.clone(), Some(3)).await;
+		assert!(block.header.is_compressed());
+		assert!(block.elem.len() < compressible.len());
+		assert!(block.verify(hash).is_ok());
+		assert_eq!(decode(&block), compressible.to_vec());
+
+		let incompressible = Bytes::from(
+			(0..10000u32)
+				.map(|i| blake2sum(&i.to_le_bytes()).as_slice()[0])
+				.collect::<Vec<u8>>(),
+		);
+		let hash = blake2sum(&incompressible[..]);
+		let block = DataBlock::from_buffer(incompressible.clone(), Some(3)).await;
+		assert!(!block.header.is_compressed());
+		assert!(block.verify(hash).is_ok());
+		assert_eq!(decode(&block), incompressible.to_vec());
+	}
+
+	#[tokio::test]
+	async fn test_corrupted_compressed_block() {
+		let data = Bytes::from("hello garage ".repeat(10000));
+		let hash = blake2sum(&data[..]);
+		let block = DataBlock::from_buffer(data, Some(3)).await;
+		assert!(block.header.is_compressed());
+
+		let mut corrupted = block.elem.to_vec();
+		let i = corrupted.len() / 2;
+		corrupted[i] ^= 0xff;
+		let corrupted = DataBlock::compressed(Bytes::from(corrupted));
+		assert!(matches!(
+			corrupted.verify(hash),
+			Err(Error::CorruptData(h)) if h == hash
+		));
+	}
+
+	#[tokio::test]
+	async fn test_check_max_size() {
+		let data = Bytes::from("hello garage ".repeat(10000));
+		let hash = blake2sum(&data[..]);
+
+		let plain = DataBlock::plain(data.clone());
+		assert!(plain.check_max_size(&hash, 0).is_ok());
+		assert!(plain.check_max_size(&hash, data.len()).is_ok());
+		assert!(matches!(
+			plain.check_max_size(&hash, data.len() - 1),
+			Err(Error::BlockTooLarge(h, size, max)) if h == hash && size == data.len() && max == data.len() - 1
+		));
+
+		// The limit applies to the size of the block as it is stored
+		let compressed = DataBlock::from_buffer(data.clone(), Some(3)).await;
+		assert!(compressed.check_max_size(&hash, data.len() - 1).is_ok());
+	}
+
+	#[tokio::test]
+	async fn test_block_verifier() {
+		let data = Bytes::from("hello garage ".repeat(10000));
+		let hash = blake2sum(&data[..]);
+		let plain = DataBlock::plain(data.clone());
+		let compressed = DataBlock::from_buffer(data, Some(3)).await;
+		assert!(compressed.header.is_compressed());
+
+		for block in [plain, compressed] {
+			for chunk_size in [1, 7, 1000, block.elem.len()] {
+				let mut verifier = DataBlockVerifier::new(block.header);
+				for chunk in block.elem.chunks(chunk_size) {
+					verifier.update(chunk);
+				}
+				assert!(verifier.verify(hash).is_ok());
+			}
+
+			// Wrong hash
+			let mut verifier = DataBlockVerifier::new(block.header);
+			verifier.update(&block.elem[..]);
+			assert!(verifier.verify(blake2sum(b"other")).is_err());
+
+			// Truncated data
+			let mut verifier = DataBlockVerifier::new(block.header);
+			verifier.update(&block.elem[..block.elem.len() / 2]);
+			assert!(verifier.verify(hash).is_err());
+
+			// Corrupted byte
+			let mut corrupted = block.elem.to_vec();
+			let i = corrupted.len() / 2;
+			corrupted[i] ^= 0xff;
+			let mut verifier = DataBlockVerifier::new(block.header);
+			for chunk in corrupted.chunks(1000) {
+				verifier.update(chunk);
+			}
+			assert!(matches!(
+				verifier.verify(hash),
+				Err(Error::CorruptData(h)) if h == hash
+			));
+		}
+	}
+
+	async fn read_chunks(
+		block: &DataBlock,
+		hash: Hash,
+		chunk_size: usize,
+	) -> (Vec<Result<Bytes, Error>>, Option<DataBlock>) {
+		read_chunks_keeping(block, hash, chunk_size, block.elem.len()).await
+	}
+
+	async fn read_chunks_keeping(
+		block: &DataBlock,
+		hash: Hash,
+		chunk_size: usize,
+		keep_max_size: usize,
+	) -> (Vec<Result<Bytes, Error>>, Option<DataBlock>) {
+		let mut reader = DataBlockChunkReader::new(
+			&block.elem[..],
+			block.header,
+			hash,
+			chunk_size,
+			Some(keep_max_size),
+		);
+		let mut chunks = vec![];
+		while let Some(chunk) = reader.next_chunk().await {
+			chunks.push(chunk);
+		}
+		(chunks, reader.into_block())
+	}
+
+	#[tokio::test]
+	async fn test_chunk_reader() {
+		let data = Bytes::from(
+			(0..200_000u32)
+				.map(|i| blake2sum(&i.to_le_bytes()).as_slice()[0])
+				.collect::<Vec<u8>>(),
+		);
+		let hash = blake2sum(&data[..]);
+		let block = DataBlock::plain(data.clone());
+
+		let (chunks, kept) = read_chunks(&block, hash, 64 * 1024).await;
+		assert_eq!(chunks.len(), 4);
+		let chunks = chunks.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+		assert!(chunks.iter().all(|c| c.len() <= 64 * 1024));
+		assert_eq!(chunks.concat(), data.to_vec());
+		assert_eq!(kept.unwrap().elem, data);
+
+		let compressed =
+			DataBlock::from_buffer(Bytes::from("garage ".repeat(100_000)), Some(3)).await;
+		let hash = blake2sum("garage ".repeat(100_000).as_bytes());
+		let (chunks, kept) = read_chunks(&compressed, hash, 16).await;
+		assert!(chunks.len() > 1);
+		assert!(chunks.iter().all(|c| c.is_ok()));
+		assert_eq!(kept.unwrap().elem, compressed.elem);
+
+		// Blocks larger than the maximum size are not kept, but still read entirely
+		let hash = blake2sum(&data[..]);
+		let (chunks, kept) = read_chunks_keeping(&block, hash, 64 * 1024, 100_000).await;
+		assert_eq!(chunks.len(), 4);
+		assert!(chunks.iter().all(|c| c.is_ok()));
+		assert!(kept.is_none());
+	}
+
+	#[tokio::test]
+	async fn test_chunk_reader_corrupted() {
+		let data = Bytes::from("hello garage ".repeat(10000));
+		let hash = blake2sum(&data[..]);
+		let mut corrupted = data.to_vec();
+		corrupted[70_000] ^= 0xff;
+		let block = DataBlock::plain(Bytes::from(corrupted));
+
+		// All chunks are returned, then the error, which ends the stream
+		let (chunks, kept) = read_chunks(&block, hash, 64 * 1024).await;
+		assert_eq!(chunks.len(), 3);
+		assert!(chunks[..2].iter().all(|c| c.is_ok()));
+		assert!(matches!(&chunks[2], Err(Error::CorruptData(h)) if *h == hash));
+		assert!(kept.is_none());
+	}
+}
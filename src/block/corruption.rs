@@ -0,0 +1,95 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Tracks the number of corrupted blocks found (and repaired by resyncing
+/// them from other nodes) on each data directory over a sliding time window,
+/// to detect disks that are failing.
+///
+/// A data directory is in alarm state when the number of corruptions found
+/// in it during the last `window` reaches `threshold`.
+pub(crate) struct CorruptionTracker {
+	window: Duration,
+	threshold: usize,
+	events: Mutex<HashMap<PathBuf, VecDeque<Instant>>>,
+}
+
+impl CorruptionTracker {
+	pub(crate) fn new(window: Duration, threshold: usize) -> Self {
+		Self {
+			window,
+			threshold,
+			events: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Record a corruption found in data directory `dir`.
+	/// Returns the number of corruptions found in that directory during the window
+	/// if this corruption makes it reach the alarm threshold, `None` otherwise.
+	pub(crate) fn record(&self, dir: &Path) -> Option<usize> {
+		let now = Instant::now();
+		let mut events = self.events.lock().unwrap();
+		let dir_events = events.entry(dir.to_path_buf()).or_default();
+		dir_events.push_back(now);
+		self.expire(dir_events, now);
+
+		if dir_events.len() >= self.threshold {
+			Some(dir_events.len())
+		} else {
+			None
+		}
+	}
+
+	/// Returns the data directories that are in alarm state,
+	/// with the number of corruptions found in each of them during the window
+	pub(crate) fn alarms(&self) -> Vec<(PathBuf, usize)> {
+		let now = Instant::now();
+		let mut events = self.events.lock().unwrap();
+		let mut ret = vec![];
+		for (dir, dir_events) in events.iter_mut() {
+			self.expire(dir_events, now);
+			if dir_events.len() >= self.threshold {
+				ret.push((dir.clone(), dir_events.len()));
+			}
+		}
+		events.retain(|_, dir_events| !dir_events.is_empty());
+		ret.sort();
+		ret
+	}
+
+	fn expire(&self, dir_events: &mut VecDeque<Instant>, now: Instant) {
+		while let Some(t) = dir_events.front() {
+			if now.saturating_duration_since(*t) > self.window {
+				dir_events.pop_front();
+			} else {
+				break;
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_corruption_alarm() {
+		let tracker = CorruptionTracker::new(Duration::from_secs(3600), 3);
+		let disk1 = Path::new("/mnt/disk1");
+		let disk2 = Path::new("/mnt/disk2");
+
+		assert_eq!(tracker.record(disk1), None);
+		assert_eq!(tracker.record(disk2), None);
+		assert_eq!(tracker.record(disk1), None);
+		assert!(tracker.alarms().is_empty());
+
+		assert_eq!(tracker.record(disk1), Some(3));
+		assert_eq!(tracker.alarms(), vec![(disk1.to_path_buf(), 3)]);
+
+		let tracker = CorruptionTracker::new(Duration::from_secs(0), 1);
+		assert_eq!(tracker.record(disk1), Some(1));
+		std::thread::sleep(Duration::from_millis(10));
+		assert!(tracker.alarms().is_empty());
+	}
+}
@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use garage_util::data::*;
+
+use crate::block::DataBlock;
+
+/// Bounded cache of the contents of blocks recently read from disk, so that
+/// frequently read small blocks are served from memory.
+///
+/// Blocks are cached as they are stored, i.e. possibly compressed, and only
+/// once their integrity has been verified. This is the form in which blocks
+/// are sent to the node that requested them, which decompresses them itself:
+/// caching decompressed blocks would require compressing them again for each
+/// request, and would take more memory. As blocks are immutable, entries
+/// never need to be updated: they are only removed when they are evicted,
+/// or when the block is deleted from this node.
+pub(crate) struct BlockDataCache {
+	/// Maximum total size of the cached blocks, in bytes
+	capacity: usize,
+	/// Maximum size of a single cached block, in bytes
+	max_entry_size: usize,
+	inner: Mutex<CacheInner>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+	/// Cached blocks, with the time they were last used
+	entries: HashMap<Hash, (DataBlock, u64)>,
+	/// Blocks by time they were last used, to evict the least recently used
+	lru: BTreeMap<u64, Hash>,
+	/// Total size of the cached blocks
+	size: usize,
+	clock: u64,
+}
+
+impl BlockDataCache {
+	/// Create a cache of at most `capacity` bytes, of blocks of at most `max_entry_size`
+	/// bytes; a capacity of 0 disables it
+	pub(crate) fn new(capacity: usize, max_entry_size: usize) -> Self {
+		Self {
+			capacity,
+			max_entry_size: std::cmp::min(max_entry_size, capacity),
+			inner: Mutex::new(CacheInner::default()),
+		}
+	}
+
+	pub(crate) fn get(&self, hash: &Hash) -> Option<DataBlock> {
+		if self.capacity == 0 {
+			return None;
+		}
+		let mut inner = self.inner.lock().unwrap();
+		let inner = &mut *inner;
+		inner.clock += 1;
+		let (block, last_used) = inner.entries.get_mut(hash)?;
+		inner.lru.remove(last_used);
+		*last_used = inner.clock;
+		inner.lru.insert(inner.clock, *hash);
+		Some(block.clone())
+	}
+
+	/// Whether a block of this size can be cached
+	pub(crate) fn accepts(&self, size: usize) -> bool {
+		self.capacity > 0 && size <= self.max_entry_size
+	}
+
+	/// Maximum size of the blocks that can be cached, `None` if the cache is disabled
+	pub(crate) fn max_entry_size(&self) -> Option<usize> {
+		Some(self.max_entry_size).filter(|_| self.capacity > 0)
+	}
+
+	/// Add a block to the cache, if it is small enough. The block must have
+	/// been verified by the caller, as it is not checked again.
+	pub(crate) fn insert(&self, hash: Hash, block: DataBlock) {
+		let size = block.as_parts_ref().1.len();
+		if !self.accepts(size) {
+			return;
+		}
+		let mut inner = self.inner.lock().unwrap();
+		inner.clock += 1;
+		let now = inner.clock;
+		if let Some((_, last_used)) = inner.entries.insert(hash, (block, now)) {
+			inner.lru.remove(&last_used);
+		} else {
+			inner.size += size;
+		}
+		inner.lru.insert(now, hash);
+
+		while inner.size > self.capacity {
+			let (_, evicted) = inner.lru.pop_first().unwrap();
+			let (block, _) = inner.entries.remove(&evicted).unwrap();
+			inner.size -= block.as_parts_ref().1.len();
+		}
+	}
+
+	pub(crate) fn remove(&self, hash: &Hash) {
+		let mut inner = self.inner.lock().unwrap();
+		if let Some((block, last_used)) = inner.entries.remove(hash) {
+			inner.lru.remove(&last_used);
+			inner.size -= block.as_parts_ref().1.len();
+		}
+	}
+
+	#[cfg(test)]
+	fn size(&self) -> usize {
+		self.inner.lock().unwrap().size
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bytes::Bytes;
+
+	use super::*;
+
+	fn block(i: u8, size: usize) -> (Hash, DataBlock) {
+		let data = Bytes::from(vec![i; size]);
+		(blake2sum(&data), DataBlock::plain(data))
+	}
+
+	#[test]
+	fn test_hit_and_miss() {
+		let cache = BlockDataCache::new(1024, 512);
+		let (h1, b1) = block(1, 100);
+		let (h2, _) = block(2, 100);
+
+		assert!(cache.get(&h1).is_none());
+		cache.insert(h1, b1.clone());
+		assert_eq!(
+			cache.get(&h1).unwrap().as_parts_ref().1,
+			b1.as_parts_ref().1
+		);
+		assert!(cache.get(&h2).is_none());
+		assert_eq!(cache.size(), 100);
+
+		// Inserting the same block again does not count it twice
+		cache.insert(h1, b1.clone());
+		assert_eq!(cache.size(), 100);
+
+		cache.remove(&h1);
+		assert!(cache.get(&h1).is_none());
+		assert_eq!(cache.size(), 0);
+	}
+
+	#[test]
+	fn test_least_recently_used_is_evicted() {
+		let cache = BlockDataCache::new(300, 300);
+		let (h1, b1) = block(1, 100);
+		let (h2, b2) = block(2, 100);
+		let (h3, b3) = block(3, 150);
+
+		cache.insert(h1, b1.clone());
+		cache.insert(h2, b2.clone());
+		// Using h1 makes h2 the least recently used entry
+		assert!(cache.get(&h1).is_some());
+		cache.insert(h3, b3.clone());
+
+		assert_eq!(cache.size(), 250);
+		assert!(cache.get(&h1).is_some());
+		assert!(cache.get(&h2).is_none());
+		assert!(cache.get(&h3).is_some());
+	}
+
+	#[test]
+	fn test_large_blocks_and_disabled_cache() {
+		let cache = BlockDataCache::new(1024, 200);
+		let (h1, b1) = block(1, 201);
+		cache.insert(h1, b1.clone());
+		assert!(cache.get(&h1).is_none());
+		assert_eq!(cache.size(), 0);
+
+		// Entries are never larger than the cache itself
+		let small = BlockDataCache::new(100, 200);
+		let (h2, b2) = block(2, 150);
+		small.insert(h2, b2.clone());
+		assert!(small.get(&h2).is_none());
+
+		let disabled = BlockDataCache::new(0, 200);
+		let (h3, b3) = block(3, 10);
+		disabled.insert(h3, b3.clone());
+		assert!(disabled.get(&h3).is_none());
+	}
+}
@@ -0,0 +1,221 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use garage_util::data::*;
+use garage_util::error::{Error, OkOrMessage};
+use garage_util::migrate::*;
+
+/// Magic bytes at the beginning of block files that are encrypted
+const ENCRYPTED_BLOCK_MAGIC: &[u8; 8] = b"GRGBENC1";
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = ENCRYPTED_BLOCK_MAGIC.len() + NONCE_LEN;
+
+/// Encryption of data blocks at rest, using AES-256-GCM with a key
+/// provided in the configuration file.
+///
+/// Encrypted block files are made of a magic string, a random nonce
+/// and the ciphertext (including the authentication tag). The hash of the
+/// block is passed as associated data, so that a valid encrypted file cannot
+/// be passed off as the content of another block. The hash of a block
+/// is still computed over its plaintext.
+pub(crate) struct BlockEncryption {
+	cipher: Aes256Gcm,
+	key_id: Hash,
+}
+
+impl BlockEncryption {
+	/// Build the cipher from a 32-byte key, hex-encoded
+	pub(crate) fn from_hex_key(key: &str) -> Result<Self, Error> {
+		let key = hex::decode(key.trim())
+			.ok()
+			.filter(|k| k.len() == 32)
+			.ok_or_message("data_encryption_key must be 32 bytes, hex encoded")?;
+		let key_id = blake2sum(&[&b"garage-data-encryption-key-id"[..], &key[..]].concat());
+		Ok(Self {
+			cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+			key_id,
+		})
+	}
+
+	/// A value derived from the key, that identifies it without revealing it
+	pub(crate) fn key_id(&self) -> Hash {
+		self.key_id
+	}
+
+	/// Check whether the content of a block file looks like an encrypted block
+	pub(crate) fn is_encrypted(data: &[u8]) -> bool {
+		data.len() >= HEADER_LEN && data.starts_with(ENCRYPTED_BLOCK_MAGIC)
+	}
+
+	pub(crate) fn encrypt(&self, hash: &Hash, data: &[u8]) -> Vec<u8> {
+		let nonce = thread_rng().gen::<[u8; NONCE_LEN]>();
+		let ciphertext = self
+			.cipher
+			.encrypt(
+				Nonce::from_slice(&nonce),
+				Payload {
+					msg: data,
+					aad: hash.as_slice(),
+				},
+			)
+			.expect("AES-GCM encryption cannot fail on a buffer");
+
+		let mut ret = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+		ret.extend_from_slice(ENCRYPTED_BLOCK_MAGIC);
+		ret.extend_from_slice(&nonce);
+		ret.extend_from_slice(&ciphertext);
+		ret
+	}
+
+	/// Decrypt the content of a block file. Fails with `Error::CorruptData` if the
+	/// file is not an encrypted block, was modified, or belongs to another block.
+	pub(crate) fn decrypt(&self, hash: &Hash, data: &[u8]) -> Result<Vec<u8>, Error> {
+		if !Self::is_encrypted(data) {
+			return Err(Error::CorruptData(*hash));
+		}
+		let (nonce, ciphertext) = data[ENCRYPTED_BLOCK_MAGIC.len()..].split_at(NONCE_LEN);
+		self.cipher
+			.decrypt(
+				Nonce::from_slice(nonce),
+				Payload {
+					msg: ciphertext,
+					aad: hash.as_slice(),
+				},
+			)
+			.map_err(|_| Error::CorruptData(*hash))
+	}
+}
+
+/// Whether blocks stored on this node are encrypted, and with which key.
+/// This is persisted in the metadata directory so that we can refuse to start
+/// if encryption is toggled or the key is changed on an existing datastore.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DataEncryptionState {
+	/// Identifier of the encryption key, `None` if blocks are stored in plaintext
+	pub(crate) key_id: Option<Hash>,
+}
+
+impl InitialFormat for DataEncryptionState {
+	const VERSION_MARKER: &'static [u8] = b"G11bkenc";
+}
+
+impl DataEncryptionState {
+	/// Check that the data directories, last used with encryption state `self`,
+	/// can be used with the encryption state `new`.
+	pub(crate) fn check_transition(&self, new: &Self, migrate: bool) -> Result<(), Error> {
+		match (self.key_id, new.key_id) {
+			(None, None) => Ok(()),
+			(Some(a), Some(b)) if a == b => Ok(()),
+			(None, Some(_)) if migrate => Ok(()),
+			(None, Some(_)) => Err(Error::Message(
+				"data_encryption_key is set, but this node already stores unencrypted data blocks. \
+				Set data_encryption_migrate = true to enable encryption anyway: existing blocks \
+				will stay readable and will be encrypted when they are next read."
+					.into(),
+			)),
+			(Some(_), None) => Err(Error::Message(
+				"This node stores encrypted data blocks, but data_encryption_key is not set.".into(),
+			)),
+			(Some(_), Some(_)) => Err(Error::Message(
+				"data_encryption_key does not match the key that was used to encrypt the data blocks stored on this node.".into(),
+			)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+	const OTHER_KEY: &str = "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+	#[test]
+	fn test_encrypt_decrypt_roundtrip() {
+		let enc = BlockEncryption::from_hex_key(KEY).unwrap();
+		let data = b"hello garage ".repeat(1000);
+		let hash = blake2sum(&data);
+
+		let encrypted = enc.encrypt(&hash, &data);
+		assert!(BlockEncryption::is_encrypted(&encrypted));
+		assert!(!encrypted.windows(13).any(|w| w == b"hello garage "));
+		assert_eq!(enc.decrypt(&hash, &encrypted).unwrap(), data);
+
+		// Nonces are random, encrypting twice gives different files
+		assert_ne!(enc.encrypt(&hash, &data), encrypted);
+
+		// Empty blocks work as well
+		let empty_hash = blake2sum(b"");
+		let encrypted = enc.encrypt(&empty_hash, b"");
+		assert!(enc.decrypt(&empty_hash, &encrypted).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_tampering_is_detected() {
+		let enc = BlockEncryption::from_hex_key(KEY).unwrap();
+		let data = b"hello garage ".repeat(1000);
+		let hash = blake2sum(&data);
+		let encrypted = enc.encrypt(&hash, &data);
+
+		for i in [
+			HEADER_LEN - 1,
+			HEADER_LEN,
+			encrypted.len() / 2,
+			encrypted.len() - 1,
+		] {
+			let mut tampered = encrypted.clone();
+			tampered[i] ^= 0x01;
+			assert!(matches!(
+				enc.decrypt(&hash, &tampered),
+				Err(Error::CorruptData(h)) if h == hash
+			));
+		}
+
+		let truncated = &encrypted[..encrypted.len() - 1];
+		assert!(enc.decrypt(&hash, truncated).is_err());
+
+		// A valid file for another block is rejected
+		let other_hash = blake2sum(b"other block");
+		assert!(enc.decrypt(&other_hash, &encrypted).is_err());
+
+		// A file encrypted with another key is rejected
+		let other = BlockEncryption::from_hex_key(OTHER_KEY).unwrap();
+		assert!(other.decrypt(&hash, &encrypted).is_err());
+
+		// A plaintext file is rejected
+		assert!(enc.decrypt(&hash, &data).is_err());
+	}
+
+	#[test]
+	fn test_invalid_key() {
+		assert!(BlockEncryption::from_hex_key("0123").is_err());
+		assert!(BlockEncryption::from_hex_key(&"zz".repeat(32)).is_err());
+		assert_ne!(
+			BlockEncryption::from_hex_key(KEY).unwrap().key_id(),
+			BlockEncryption::from_hex_key(OTHER_KEY).unwrap().key_id()
+		);
+	}
+
+	#[test]
+	fn test_state_transitions() {
+		let plain = DataEncryptionState { key_id: None };
+		let key1 = DataEncryptionState {
+			key_id: Some(blake2sum(b"1")),
+		};
+		let key2 = DataEncryptionState {
+			key_id: Some(blake2sum(b"2")),
+		};
+
+		assert!(plain.check_transition(&plain, false).is_ok());
+		assert!(key1.check_transition(&key1, false).is_ok());
+
+		assert!(plain.check_transition(&key1, false).is_err());
+		assert!(plain.check_transition(&key1, true).is_ok());
+
+		assert!(key1.check_transition(&plain, false).is_err());
+		assert!(key1.check_transition(&plain, true).is_err());
+		assert!(key1.check_transition(&key2, true).is_err());
+	}
+}
@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use garage_util::data::*;
+
+use crate::block::DataBlockPath;
+
+/// Bounded cache of the paths of blocks recently found to be present on disk,
+/// so that checking whether we have a block does not require a `stat` of one
+/// or more files each time.
+///
+/// It is only a positive hint: entries are removed when the block is deleted or
+/// moved by Garage, and when reading the block file fails. A block file that is
+/// deleted out-of-band is still considered present until it is read, e.g. by a scrub.
+pub(crate) struct BlockExistsCache {
+	capacity: usize,
+	inner: Mutex<CacheInner>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+	/// Cached paths, with the time they were last used
+	entries: HashMap<Hash, (DataBlockPath, u64)>,
+	/// Blocks by time they were last used, to evict the least recently used
+	lru: BTreeMap<u64, Hash>,
+	clock: u64,
+}
+
+impl BlockExistsCache {
+	/// Create a cache of at most `capacity` entries; a capacity of 0 disables it
+	pub(crate) fn new(capacity: usize) -> Self {
+		Self {
+			capacity,
+			inner: Mutex::new(CacheInner::default()),
+		}
+	}
+
+	pub(crate) fn get(&self, hash: &Hash) -> Option<DataBlockPath> {
+		if self.capacity == 0 {
+			return None;
+		}
+		let mut inner = self.inner.lock().unwrap();
+		let inner = &mut *inner;
+		inner.clock += 1;
+		let (path, last_used) = inner.entries.get_mut(hash)?;
+		inner.lru.remove(last_used);
+		*last_used = inner.clock;
+		inner.lru.insert(inner.clock, *hash);
+		Some(path.clone())
+	}
+
+	pub(crate) fn insert(&self, hash: Hash, path: DataBlockPath) {
+		if self.capacity == 0 {
+			return;
+		}
+		let mut inner = self.inner.lock().unwrap();
+		inner.clock += 1;
+		let now = inner.clock;
+		if let Some((_, last_used)) = inner.entries.insert(hash, (path, now)) {
+			inner.lru.remove(&last_used);
+		}
+		inner.lru.insert(now, hash);
+
+		while inner.entries.len() > self.capacity {
+			let (_, evicted) = inner.lru.pop_first().unwrap();
+			inner.entries.remove(&evicted);
+		}
+	}
+
+	pub(crate) fn remove(&self, hash: &Hash) {
+		let mut inner = self.inner.lock().unwrap();
+		if let Some((_, last_used)) = inner.entries.remove(hash) {
+			inner.lru.remove(&last_used);
+		}
+	}
+
+	#[cfg(test)]
+	fn len(&self) -> usize {
+		self.inner.lock().unwrap().entries.len()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
+
+	use super::*;
+
+	fn block(i: u8) -> (Hash, DataBlockPath) {
+		let hash = Hash::from([i; 32]);
+		let path = PathBuf::from(format!("/data/{}", hex::encode(hash)));
+		(hash, DataBlockPath::plain(path))
+	}
+
+	#[test]
+	fn test_deletion_invalidates_entry() {
+		let cache = BlockExistsCache::new(16);
+		let (h1, p1) = block(1);
+		let (h2, p2) = block(2);
+
+		cache.insert(h1, p1.clone());
+		cache.insert(h2, p2);
+		assert_eq!(cache.get(&h1).unwrap().into_parts().1, p1.into_parts().1);
+
+		cache.remove(&h1);
+		assert!(cache.get(&h1).is_none());
+		assert!(cache.get(&h2).is_some());
+		assert_eq!(cache.len(), 1);
+
+		// Removing an absent entry is a no-op
+		cache.remove(&h1);
+		assert_eq!(cache.len(), 1);
+	}
+
+	#[test]
+	fn test_least_recently_used_is_evicted() {
+		let cache = BlockExistsCache::new(2);
+		let (h1, p1) = block(1);
+		let (h2, p2) = block(2);
+		let (h3, p3) = block(3);
+
+		cache.insert(h1, p1);
+		cache.insert(h2, p2);
+		// Using h1 makes h2 the least recently used entry
+		assert!(cache.get(&h1).is_some());
+		cache.insert(h3, p3);
+
+		assert_eq!(cache.len(), 2);
+		assert!(cache.get(&h1).is_some());
+		assert!(cache.get(&h2).is_none());
+		assert!(cache.get(&h3).is_some());
+	}
+
+	#[test]
+	fn test_update_and_disabled_cache() {
+		let cache = BlockExistsCache::new(2);
+		let (h1, p1) = block(1);
+		let moved = DataBlockPath::compressed(PathBuf::from("/data2/block.zst"));
+		cache.insert(h1, p1);
+		cache.insert(h1, moved);
+		assert_eq!(cache.len(), 1);
+		assert!(cache.get(&h1).unwrap().as_parts_ref().0.is_compressed());
+
+		let disabled = BlockExistsCache::new(0);
+		let (h2, p2) = block(2);
+		disabled.insert(h2, p2);
+		assert!(disabled.get(&h2).is_none());
+	}
+}
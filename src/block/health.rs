@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+
+use garage_util::data::*;
+use garage_util::error::Error;
+
+use crate::manager::BlockRpc;
+
+/// State of the copy of a block stored on a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockCopyState {
+	/// The block is stored and its content matches its hash
+	Present,
+	/// The block was stored but its content is corrupted; it has been
+	/// moved away and queued for resync
+	Corrupted,
+	/// The block is not stored on the node
+	Missing,
+}
+
+/// State of a block on all the nodes that should store it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHealth {
+	/// Nodes that have a valid copy of the block
+	pub present: Vec<Uuid>,
+	/// Nodes whose copy of the block is corrupted
+	pub corrupted: Vec<Uuid>,
+	/// Nodes that don't have the block
+	pub missing: Vec<Uuid>,
+	/// Nodes that could not be checked, with the error they returned
+	pub errored: Vec<(Uuid, String)>,
+}
+
+impl BlockHealth {
+	/// Aggregate the replies of the nodes to `BlockRpc::CheckBlock`
+	pub(crate) fn from_replies(replies: Vec<(Uuid, Result<BlockRpc, Error>)>) -> Self {
+		let mut health = Self::default();
+		for (node, reply) in replies {
+			match reply {
+				Ok(BlockRpc::CheckBlockReply(BlockCopyState::Present)) => health.present.push(node),
+				Ok(BlockRpc::CheckBlockReply(BlockCopyState::Corrupted)) => {
+					health.corrupted.push(node)
+				}
+				Ok(BlockRpc::CheckBlockReply(BlockCopyState::Missing)) => health.missing.push(node),
+				Ok(m) => health
+					.errored
+					.push((node, Error::unexpected_rpc_message(m).to_string())),
+				Err(e) => health.errored.push((node, e.to_string())),
+			}
+		}
+		health
+	}
+
+	/// Whether all the nodes that should store the block have a valid copy
+	pub fn is_healthy(&self) -> bool {
+		self.corrupted.is_empty() && self.missing.is_empty() && self.errored.is_empty()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn node(i: u8) -> Uuid {
+		Uuid::from([i; 32])
+	}
+
+	#[test]
+	fn test_aggregate_replies() {
+		let health = BlockHealth::from_replies(vec![
+			(
+				node(1),
+				Ok(BlockRpc::CheckBlockReply(BlockCopyState::Present)),
+			),
+			(
+				node(2),
+				Ok(BlockRpc::CheckBlockReply(BlockCopyState::Missing)),
+			),
+			(
+				node(3),
+				Ok(BlockRpc::CheckBlockReply(BlockCopyState::Corrupted)),
+			),
+			(node(4), Err(Error::Timeout)),
+			// A node running an older version cannot decode the CheckBlock
+			// RPC at all, and replies with an error
+			(
+				node(5),
+				Err(Error::Net(garage_net::error::Error::Remote(
+					std::io::ErrorKind::Other,
+					"netapp error: invalid request".into(),
+				))),
+			),
+			(
+				node(6),
+				Ok(BlockRpc::CheckBlockReply(BlockCopyState::Present)),
+			),
+			// Replies of an unexpected type are reported as errors
+			(node(7), Ok(BlockRpc::Ok)),
+		]);
+		assert_eq!(health.present, vec![node(1), node(6)]);
+		assert_eq!(health.missing, vec![node(2)]);
+		assert_eq!(health.corrupted, vec![node(3)]);
+		assert_eq!(
+			health.errored.iter().map(|(n, _)| *n).collect::<Vec<_>>(),
+			vec![node(4), node(5), node(7)]
+		);
+		assert_eq!(health.errored[0].1, Error::Timeout.to_string());
+		assert!(!health.is_healthy());
+
+		let health = BlockHealth::from_replies(vec![
+			(
+				node(1),
+				Ok(BlockRpc::CheckBlockReply(BlockCopyState::Present)),
+			),
+			(
+				node(2),
+				Ok(BlockRpc::CheckBlockReply(BlockCopyState::Present)),
+			),
+		]);
+		assert!(health.is_healthy());
+		assert!(BlockHealth::from_replies(vec![]).is_healthy());
+	}
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use tokio::sync::watch;
+
+use garage_net::message::RequestPriority;
+
+use garage_util::data::*;
+use garage_util::error::*;
+
+/// Coalescing of concurrent fetches of the same block.
+///
+/// The first caller that asks for a block runs the fetch, and callers that
+/// ask for the same block while it is in progress wait for its result instead
+/// of sending their own requests. Nothing is cached: once a fetch is finished
+/// (successfully or not), the next call for that block starts a new one.
+///
+/// A caller only waits for a fetch that runs with a priority at least as high
+/// as its own. A caller with a higher priority starts a new fetch, which the
+/// next callers for that block will wait for, so that a fetch always runs with
+/// the highest priority among the callers that wait for it.
+pub(crate) struct InFlightFetches<T: Clone> {
+	fetches: Mutex<HashMap<Hash, InFlightFetch<T>>>,
+}
+
+struct InFlightFetch<T> {
+	priority: RequestPriority,
+	state: watch::Receiver<FetchState<T>>,
+}
+
+#[derive(Clone)]
+enum FetchState<T> {
+	Pending,
+	Done(T),
+	Failed,
+}
+
+enum Role<T> {
+	Leader(watch::Sender<FetchState<T>>),
+	Follower(watch::Receiver<FetchState<T>>),
+}
+
+impl<T: Clone> InFlightFetches<T> {
+	pub(crate) fn new() -> Self {
+		Self {
+			fetches: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Get the value for block `hash`, calling `fetch` unless a fetch of the
+	/// same block is already in progress with a priority at least as high as
+	/// `priority` (i.e. a lower or equal value). Callers that wait for the fetch
+	/// of another caller receive `Error::MissingBlock` if that fetch fails; the
+	/// error itself is only returned to the caller that ran the fetch.
+	pub(crate) async fn get_or_fetch<F, Fut>(
+		&self,
+		hash: &Hash,
+		priority: RequestPriority,
+		fetch: F,
+	) -> Result<T, Error>
+	where
+		F: FnOnce() -> Fut,
+		Fut: Future<Output = Result<T, Error>>,
+	{
+		let mut fetch = Some(fetch);
+		loop {
+			let role = {
+				let mut fetches = self.fetches.lock().unwrap();
+				match fetches.get(hash) {
+					Some(f) if f.priority <= priority => Role::Follower(f.state.clone()),
+					_ => {
+						let (tx, rx) = watch::channel(FetchState::Pending);
+						fetches.insert(
+							*hash,
+							InFlightFetch {
+								priority,
+								state: rx,
+							},
+						);
+						Role::Leader(tx)
+					}
+				}
+			};
+
+			match role {
+				Role::Leader(tx) => {
+					let _remove = RemoveOnDrop(self, *hash, tx.subscribe());
+					let fetch = fetch.take().expect("fetch can only be run once");
+					let res = fetch().await;
+					tx.send_replace(match &res {
+						Ok(v) => FetchState::Done(v.clone()),
+						Err(_) => FetchState::Failed,
+					});
+					return res;
+				}
+				Role::Follower(mut rx) => {
+					match rx
+						.wait_for(|state| !matches!(state, FetchState::Pending))
+						.await
+					{
+						Ok(state) => match &*state {
+							FetchState::Done(v) => return Ok(v.clone()),
+							_ => return Err(Error::MissingBlock(*hash)),
+						},
+						// The caller that was running the fetch was cancelled
+						// before finishing, try again
+						Err(_) => continue,
+					}
+				}
+			}
+		}
+	}
+
+	#[cfg(test)]
+	pub(crate) fn len(&self) -> usize {
+		self.fetches.lock().unwrap().len()
+	}
+}
+
+/// Counter of the block writes in progress, so that we can wait
+/// for them to finish before exiting
+pub(crate) struct InFlightWrites {
+	count: watch::Sender<usize>,
+}
+
+/// A block write in progress, until it is dropped
+pub(crate) struct InFlightWrite<'a>(&'a InFlightWrites);
+
+impl InFlightWrites {
+	pub(crate) fn new() -> Self {
+		Self {
+			count: watch::channel(0).0,
+		}
+	}
+
+	pub(crate) fn begin(&self) -> InFlightWrite<'_> {
+		self.count.send_modify(|n| *n += 1);
+		InFlightWrite(self)
+	}
+
+	/// Number of writes in progress
+	pub(crate) fn len(&self) -> usize {
+		*self.count.borrow()
+	}
+
+	/// Wait until no write is in progress
+	pub(crate) async fn wait_idle(&self) {
+		let mut rx = self.count.subscribe();
+		// Can't fail: we hold the sender
+		let _ = rx.wait_for(|n| *n == 0).await;
+	}
+}
+
+impl<'a> Drop for InFlightWrite<'a> {
+	fn drop(&mut self) {
+		self.0.count.send_modify(|n| *n -= 1);
+	}
+}
+
+/// Removes the entry of a fetch from the in-flight map when the fetch
+/// is finished or cancelled, unless it has been replaced by a fetch
+/// with a higher priority
+struct RemoveOnDrop<'a, T: Clone>(&'a InFlightFetches<T>, Hash, watch::Receiver<FetchState<T>>);
+
+impl<'a, T: Clone> Drop for RemoveOnDrop<'a, T> {
+	fn drop(&mut self) {
+		let mut fetches = self.0.fetches.lock().unwrap();
+		if fetches
+			.get(&self.1)
+			.is_some_and(|f| f.state.same_channel(&self.2))
+		{
+			fetches.remove(&self.1);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
+	use std::time::Duration;
+
+	use garage_net::message::{PRIO_BACKGROUND, PRIO_NORMAL};
+
+	use super::*;
+
+	async fn mock_rpc(counter: &AtomicUsize, result: Result<u64, Error>) -> Result<u64, Error> {
+		counter.fetch_add(1, Ordering::SeqCst);
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		result
+	}
+
+	#[tokio::test]
+	async fn test_concurrent_fetches_are_coalesced() {
+		let inflight = Arc::new(InFlightFetches::<u64>::new());
+		let rpc_count = Arc::new(AtomicUsize::new(0));
+		let hash = blake2sum(b"block");
+
+		let tasks = (0..10)
+			.map(|_| {
+				let inflight = inflight.clone();
+				let rpc_count = rpc_count.clone();
+				tokio::spawn(async move {
+					inflight
+						.get_or_fetch(&hash, PRIO_NORMAL, || mock_rpc(&rpc_count, Ok(42)))
+						.await
+				})
+			})
+			.collect::<Vec<_>>();
+		for task in tasks {
+			assert_eq!(task.await.unwrap().unwrap(), 42);
+		}
+
+		assert_eq!(rpc_count.load(Ordering::SeqCst), 1);
+		assert_eq!(inflight.len(), 0);
+
+		// Fetches of different blocks are independent
+		let other = blake2sum(b"other block");
+		let (a, b) = tokio::join!(
+			inflight.get_or_fetch(&hash, PRIO_NORMAL, || mock_rpc(&rpc_count, Ok(1))),
+			inflight.get_or_fetch(&other, PRIO_NORMAL, || mock_rpc(&rpc_count, Ok(2))),
+		);
+		assert_eq!((a.unwrap(), b.unwrap()), (1, 2));
+		assert_eq!(rpc_count.load(Ordering::SeqCst), 3);
+	}
+
+	#[tokio::test]
+	async fn test_failed_fetch_is_not_cached() {
+		let inflight = InFlightFetches::<u64>::new();
+		let rpc_count = AtomicUsize::new(0);
+		let hash = blake2sum(b"block");
+
+		let (a, b) = tokio::join!(
+			inflight.get_or_fetch(&hash, PRIO_NORMAL, || mock_rpc(
+				&rpc_count,
+				Err(Error::Timeout)
+			)),
+			inflight.get_or_fetch(&hash, PRIO_NORMAL, || mock_rpc(&rpc_count, Ok(1))),
+		);
+		assert!(matches!(a, Err(Error::Timeout)));
+		assert!(matches!(b, Err(Error::MissingBlock(h)) if h == hash));
+		assert_eq!(rpc_count.load(Ordering::SeqCst), 1);
+		assert_eq!(inflight.len(), 0);
+
+		let c = inflight
+			.get_or_fetch(&hash, PRIO_NORMAL, || mock_rpc(&rpc_count, Ok(2)))
+			.await;
+		assert_eq!(c.unwrap(), 2);
+		assert_eq!(rpc_count.load(Ordering::SeqCst), 2);
+	}
+
+	#[tokio::test]
+	async fn test_fetch_uses_highest_priority() {
+		let inflight = InFlightFetches::<u64>::new();
+		let rpc_count = AtomicUsize::new(0);
+		let hash = blake2sum(b"block");
+
+		let get_after = |delay: u64, priority: RequestPriority, value: u64| {
+			let inflight = &inflight;
+			let rpc_count = &rpc_count;
+			async move {
+				tokio::time::sleep(Duration::from_millis(delay)).await;
+				inflight
+					.get_or_fetch(&hash, priority, || mock_rpc(rpc_count, Ok(value)))
+					.await
+			}
+		};
+
+		// A caller with a higher priority doesn't wait for a background fetch,
+		// and callers that come after it wait for its fetch instead, including
+		// after the background fetch is finished
+		let (background, normal, normal2, background2, late) = tokio::join!(
+			get_after(0, PRIO_BACKGROUND, 1),
+			get_after(50, PRIO_NORMAL, 2),
+			get_after(60, PRIO_NORMAL, 3),
+			get_after(60, PRIO_BACKGROUND, 4),
+			get_after(125, PRIO_BACKGROUND, 5),
+		);
+		assert_eq!(background.unwrap(), 1);
+		assert_eq!(normal.unwrap(), 2);
+		assert_eq!(normal2.unwrap(), 2);
+		assert_eq!(background2.unwrap(), 2);
+		assert_eq!(late.unwrap(), 2);
+		assert_eq!(rpc_count.load(Ordering::SeqCst), 2);
+		assert_eq!(inflight.len(), 0);
+	}
+
+	#[tokio::test]
+	async fn test_wait_for_inflight_writes() {
+		let writes = Arc::new(InFlightWrites::new());
+
+		// Nothing to wait for when there are no writes
+		tokio::time::timeout(Duration::from_millis(10), writes.wait_idle())
+			.await
+			.unwrap();
+
+		let written = Arc::new(AtomicUsize::new(0));
+		let slow_write = {
+			let writes = writes.clone();
+			let written = written.clone();
+			let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+			let task = tokio::spawn(async move {
+				let _write = writes.begin();
+				started_tx.send(()).unwrap();
+				tokio::time::sleep(Duration::from_millis(200)).await;
+				written.store(1, Ordering::SeqCst);
+			});
+			started_rx.await.unwrap();
+			task
+		};
+
+		// Shutdown waits until the slow write is done
+		writes.wait_idle().await;
+		assert_eq!(written.load(Ordering::SeqCst), 1);
+		slow_write.await.unwrap();
+		assert_eq!(*writes.count.borrow(), 0);
+	}
+
+	#[tokio::test]
+	async fn test_cancelled_fetch_is_retried() {
+		let inflight = InFlightFetches::<u64>::new();
+		let rpc_count = AtomicUsize::new(0);
+		let hash = blake2sum(b"block");
+
+		let cancelled = tokio::time::timeout(
+			Duration::from_millis(50),
+			inflight.get_or_fetch(&hash, PRIO_NORMAL, || mock_rpc(&rpc_count, Ok(1))),
+		);
+		let follower = async {
+			tokio::time::sleep(Duration::from_millis(10)).await;
+			inflight
+				.get_or_fetch(&hash, PRIO_NORMAL, || mock_rpc(&rpc_count, Ok(2)))
+				.await
+		};
+		let (a, b) = tokio::join!(cancelled, follower);
+		assert!(a.is_err());
+		assert_eq!(b.unwrap(), 2);
+		assert_eq!(rpc_count.load(Ordering::SeqCst), 2);
+		assert_eq!(inflight.len(), 0);
+	}
+}
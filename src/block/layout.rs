@@ -16,6 +16,13 @@ const HASH_DRIVE_BYTES: (usize, usize) = (2, 3);
 
 const MARKER_FILE_NAME: &str = "garage-marker";
 
+/// Name of the file that records, in each data directory, the number of levels
+/// of subdirectories in which blocks are stored
+const DIR_DEPTH_FILE_NAME: &str = "garage-dir-depth";
+/// Number of levels of subdirectories used by data directories created before
+/// the depth could be configured
+const LEGACY_DIR_DEPTH: usize = 2;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct DataLayout {
 	pub(crate) data_dirs: Vec<DataDir>,
@@ -28,6 +35,15 @@ pub(crate) struct DataLayout {
 	/// Secondary storage locations for each partition = locations
 	/// where data blocks might be, we check from these dirs when reading
 	pub(crate) part_sec: Vec<Vec<Idx>>,
+
+	/// Number of levels of subdirectories in which blocks are stored,
+	/// each level being named after one byte of the block hash
+	#[serde(skip, default = "default_dir_depth")]
+	dir_depth: usize,
+}
+
+fn default_dir_depth() -> usize {
+	LEGACY_DIR_DEPTH
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
@@ -82,6 +98,7 @@ impl DataLayout {
 			markers: HashMap::new(),
 			part_prim,
 			part_sec,
+			dir_depth: LEGACY_DIR_DEPTH,
 		})
 	}
 
@@ -224,6 +241,7 @@ impl DataLayout {
 			markers: self.markers,
 			part_prim,
 			part_sec,
+			dir_depth: self.dir_depth,
 		})
 	}
 
@@ -259,10 +277,58 @@ impl DataLayout {
 		Ok(())
 	}
 
+	/// Set the number of levels of subdirectories in which blocks are stored.
+	/// This fails if a data directory already stores blocks with a different depth,
+	/// as blocks would then not be found at the expected location. The depth is
+	/// recorded in all data directories, except read-only ones which may be on
+	/// read-only mounts.
+	pub(crate) fn set_dir_depth(&mut self, depth: usize) -> Result<(), Error> {
+		if !(1..=3).contains(&depth) {
+			return Err(Error::Message(format!(
+				"Invalid block_dir_depth {}, must be 1, 2 or 3",
+				depth
+			)));
+		}
+
+		for dir in self.data_dirs.iter() {
+			let mut depth_path = dir.path.clone();
+			depth_path.push(DIR_DEPTH_FILE_NAME);
+
+			let existing_depth = match std::fs::read_to_string(&depth_path) {
+				Ok(s) => Some(s.trim().parse::<usize>().ok_or_message(format!(
+					"Invalid content for file `{}` in data directory `{}`",
+					DIR_DEPTH_FILE_NAME,
+					dir.path.display()
+				))?),
+				// Data directories that already contain blocks but no depth file
+				// were created with the legacy depth
+				Err(_) if has_block_subdirs(&dir.path)? => Some(LEGACY_DIR_DEPTH),
+				Err(_) => None,
+			};
+
+			match existing_depth {
+				Some(d) if d != depth => {
+					return Err(Error::Message(format!("Data directory `{}` stores blocks with a block_dir_depth of {}, but block_dir_depth is set to {}. Changing the depth of an existing data directory is not supported: set block_dir_depth back to {}, or move the data to a new data directory.", dir.path.display(), d, depth, d)));
+				}
+				Some(_) if depth_path.exists() => (),
+				_ if dir.state == DataDirState::ReadOnly => (),
+				_ => std::fs::write(&depth_path, depth.to_string())?,
+			}
+		}
+
+		self.dir_depth = depth;
+		Ok(())
+	}
+
 	pub(crate) fn primary_block_dir(&self, hash: &Hash) -> PathBuf {
+		self.block_dir_from(hash, self.primary_data_dir(hash))
+	}
+
+	/// Data directory in which a block is written
+	pub(crate) fn primary_data_dir(&self, hash: &Hash) -> &PathBuf {
 		let ipart = self.partition_from(hash);
 		let idir = self.part_prim[ipart] as usize;
-		self.block_dir_from(hash, &self.data_dirs[idir].path)
+		&self.data_dirs[idir].path
 	}
 
 	pub(crate) fn secondary_block_dirs<'a>(
@@ -285,8 +351,9 @@ impl DataLayout {
 
 	fn block_dir_from(&self, hash: &Hash, dir: &PathBuf) -> PathBuf {
 		let mut path = dir.clone();
-		path.push(hex::encode(&hash.as_slice()[0..1]));
-		path.push(hex::encode(&hash.as_slice()[1..2]));
+		for i in 0..self.dir_depth {
+			path.push(hex::encode(&hash.as_slice()[i..i + 1]));
+		}
 		path
 	}
 
@@ -296,6 +363,7 @@ impl DataLayout {
 			markers: self.markers.clone(),
 			part_prim: self.part_prim.clone(),
 			part_sec: self.part_sec.iter().map(|_| vec![]).collect::<Vec<_>>(),
+			dir_depth: self.dir_depth,
 		}
 	}
 }
@@ -372,3 +440,94 @@ fn dir_not_empty(path: &PathBuf) -> Result<bool, Error> {
 	}
 	Ok(false)
 }
+
+/// Returns true if a data directory contains subdirectories named after
+/// a byte of a hash, i.e. if it stores blocks
+fn has_block_subdirs(path: &PathBuf) -> Result<bool, Error> {
+	if !path.exists() {
+		return Ok(false);
+	}
+	for ent in std::fs::read_dir(path)? {
+		let ent = ent?;
+		let name = ent.file_name();
+		let name = name.to_string_lossy();
+		if ent.file_type()?.is_dir() && name.len() == 2 && hex::decode(&*name).is_ok() {
+			return Ok(true);
+		}
+	}
+	Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+
+	use garage_util::config::DataDir as DataDirConfig;
+
+	use super::*;
+
+	fn layout(dirs: &[(&Path, bool)]) -> DataLayout {
+		let dirs = dirs
+			.iter()
+			.map(|(path, read_only)| DataDirConfig {
+				path: path.to_path_buf(),
+				capacity: (!read_only).then(|| "1G".to_string()),
+				read_only: *read_only,
+			})
+			.collect();
+		DataLayout::initialize(&DataDirEnum::Multiple(dirs)).unwrap()
+	}
+
+	fn read_depth(dir: &Path) -> Option<String> {
+		std::fs::read_to_string(dir.join(DIR_DEPTH_FILE_NAME)).ok()
+	}
+
+	#[test]
+	fn test_dir_depth_marker() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+
+		let mut data_layout = layout(&[(&dir, false)]);
+		assert!(data_layout.set_dir_depth(0).is_err());
+		assert!(data_layout.set_dir_depth(4).is_err());
+		data_layout.set_dir_depth(3).unwrap();
+		assert_eq!(read_depth(&dir).as_deref(), Some("3"));
+		assert_eq!(data_layout.dir_depth, 3);
+
+		// The depth that was recorded is kept when Garage is restarted
+		let mut data_layout = layout(&[(&dir, false)]);
+		data_layout.set_dir_depth(3).unwrap();
+		assert!(data_layout.set_dir_depth(2).is_err());
+		assert_eq!(read_depth(&dir).as_deref(), Some("3"));
+	}
+
+	#[test]
+	fn test_dir_depth_legacy() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		std::fs::create_dir(dir.join("ab")).unwrap();
+
+		// Data directories that store blocks but have no marker use the legacy depth
+		let mut data_layout = layout(&[(&dir, false)]);
+		assert!(data_layout.set_dir_depth(3).is_err());
+		assert_eq!(read_depth(&dir), None);
+		data_layout.set_dir_depth(LEGACY_DIR_DEPTH).unwrap();
+		assert_eq!(read_depth(&dir).as_deref(), Some("2"));
+	}
+
+	#[test]
+	fn test_dir_depth_read_only() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let ro_dir = mktemp::Temp::new_dir().unwrap();
+		std::fs::create_dir(ro_dir.join("ab")).unwrap();
+
+		// The depth is not written to read-only data directories
+		let mut data_layout = layout(&[(&dir, false), (&ro_dir, true)]);
+		data_layout.set_dir_depth(LEGACY_DIR_DEPTH).unwrap();
+		assert_eq!(read_depth(&dir).as_deref(), Some("2"));
+		assert_eq!(read_depth(&ro_dir), None);
+
+		// but it is still checked against their content
+		let new_dir = mktemp::Temp::new_dir().unwrap();
+		let mut data_layout = layout(&[(&new_dir, false), (&ro_dir, true)]);
+		assert!(data_layout.set_dir_depth(3).is_err());
+	}
+}
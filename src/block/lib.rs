@@ -1,14 +1,21 @@
 #[macro_use]
 extern crate tracing;
 
+pub mod health;
 pub mod manager;
 pub mod repair;
 pub mod resync;
 
 mod block;
+mod corruption;
+mod data_cache;
+mod encryption;
+mod exists_cache;
+mod inflight;
 mod layout;
 mod metrics;
 mod rc;
+mod space;
 
-pub use block::zstd_encode;
+pub use block::{zstd_encode, DataBlockElem, DataBlockHeader};
 pub use rc::CalculateRefcount;
@@ -1,33 +1,36 @@
 use std::convert::TryInto;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use arc_swap::{ArcSwap, ArcSwapOption};
 use bytes::Bytes;
+use futures::stream::{self, Stream, StreamExt};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use tokio::fs;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::{mpsc, Mutex, MutexGuard, Semaphore};
+use tokio::sync::{mpsc, watch, Mutex, MutexGuard, OwnedSemaphorePermit, Semaphore};
 
 use opentelemetry::{
 	trace::{FutureExt as OtelFutureExt, TraceContextExt, Tracer},
-	Context,
+	Context, KeyValue,
 };
 
+use garage_net::message::OrderTagStream;
 use garage_net::stream::{read_stream_to_end, stream_asyncread, ByteStream};
 
 use garage_db as db;
 
 use garage_util::background::{vars, BackgroundRunner};
-use garage_util::config::Config;
+use garage_util::config::{Config, LiveConfig};
 use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::metrics::RecordDuration;
 use garage_util::persister::{Persister, PersisterShared};
-use garage_util::time::msec_to_rfc3339;
+use garage_util::time::{msec_to_rfc3339, Clock};
 
 use garage_rpc::rpc_helper::OrderTag;
 use garage_rpc::system::System;
@@ -36,20 +39,40 @@ use garage_rpc::*;
 use garage_table::replication::{TableReplication, TableShardedReplication};
 
 use crate::block::*;
+use crate::corruption::CorruptionTracker;
+use crate::data_cache::BlockDataCache;
+use crate::encryption::*;
+use crate::exists_cache::BlockExistsCache;
+use crate::health::*;
+use crate::inflight::{InFlightFetches, InFlightWrites};
 use crate::layout::*;
 use crate::metrics::*;
 use crate::rc::*;
 use crate::repair::*;
 use crate::resync::*;
+use crate::space::DiskSpaceGuard;
 
 /// Size under which data will be stored inlined in database instead of as files
 pub const INLINE_THRESHOLD: usize = 3072;
 
+/// Size of the chunks in which blocks are read by `read_block_stream`
+const READ_BLOCK_STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Name of the subdirectory of each data directory where corrupted blocks
+/// are moved when they are quarantined
+const QUARANTINE_DIR_NAME: &str = "corrupt";
+
 // The delay between the moment when the reference counter
 // drops to zero, and the moment where we allow ourselves
 // to delete the block locally.
 pub(crate) const BLOCK_GC_DELAY: Duration = Duration::from_secs(600);
 
+/// Maximum time to wait for block writes in progress when shutting down
+const INFLIGHT_WRITES_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of resync queue entries loaded at once by `prune_resync_queue`
+const PRUNE_RESYNC_QUEUE_BATCH_SIZE: usize = 1000;
+
 /// RPC messages used to share blocks of data between nodes
 #[derive(Debug, Serialize, Deserialize)]
 pub enum BlockRpc {
@@ -66,12 +89,61 @@ pub enum BlockRpc {
 	NeedBlockQuery(Hash),
 	/// Response : whether the node do require that block
 	NeedBlockReply(bool),
+	/// Ask other node whether it has a valid copy of a block
+	CheckBlock(Hash),
+	/// Response : state of the copy of the block on the node
+	CheckBlockReply(BlockCopyState),
 }
 
 impl Rpc for BlockRpc {
 	type Response = Result<BlockRpc, Error>;
 }
 
+/// Timeouts of the RPC calls made by the block manager
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlockRpcTimeouts {
+	/// Timeout for reading a block from a node or writing it to nodes
+	pub(crate) block: Duration,
+	/// Timeout for asking nodes whether they need a block
+	pub(crate) need_block_query: Duration,
+}
+
+impl BlockRpcTimeouts {
+	/// Timeouts set in the config (in milliseconds), or `rpc_timeout` for the ones
+	/// that are not set
+	pub(crate) fn new(
+		block_msec: Option<u64>,
+		need_block_query_msec: Option<u64>,
+		rpc_timeout: Duration,
+	) -> Self {
+		Self {
+			block: block_msec.map(Duration::from_millis).unwrap_or(rpc_timeout),
+			need_block_query: need_block_query_msec
+				.map(Duration::from_millis)
+				.unwrap_or(rpc_timeout),
+		}
+	}
+
+	/// Timeout of a block read or write: `timeout` if the caller gave one,
+	/// otherwise the configured one
+	pub(crate) fn block_or(&self, timeout: Option<Duration>) -> Duration {
+		timeout.unwrap_or(self.block)
+	}
+}
+
+/// Wait for a node to reply to a block read for at most `timeout`.
+/// Returns `None` if it didn't reply in time.
+async fn block_reply_within<F: futures::Future>(timeout: Duration, reply: F) -> Option<F::Output> {
+	tokio::select! {
+		res = reply => Some(res),
+		// TODO: sleep less long (fail early), initiate a second request earlier
+		// if the first one doesn't succeed rapidly
+		// TODO: keep first request running when initiating a new one and take the
+		// one that finishes earlier
+		_ = tokio::time::sleep(timeout) => None,
+	}
+}
+
 /// The block manager, handling block exchange between nodes, and block storage on local node
 pub struct BlockManager {
 	/// Replication strategy, allowing to find on which node blocks should be located
@@ -84,7 +156,18 @@ pub struct BlockManager {
 
 	data_fsync: bool,
 	compression_level: Option<i32>,
+	/// Maximum size of the blocks that are stored on this node, 0 for no limit
+	max_block_size: usize,
+	/// Timeouts of block reads and writes, and of resync queries
+	pub(crate) rpc_timeouts: BlockRpcTimeouts,
+	/// Encryption of blocks at rest, if enabled
+	encryption: Option<BlockEncryption>,
+	/// Whether unencrypted blocks can still be read while encryption is enabled
+	encryption_migrate: bool,
 	disable_scrub: bool,
+	/// Values of the configuration options that can be changed at runtime
+	pub(crate) live_config: watch::Receiver<LiveConfig>,
+	read_ahead: usize,
 
 	mutation_lock: Vec<Mutex<BlockManagerLocked>>,
 
@@ -94,11 +177,32 @@ pub struct BlockManager {
 	pub(crate) system: Arc<System>,
 	pub(crate) endpoint: Arc<Endpoint<BlockRpc, Self>>,
 	buffer_kb_semaphore: Arc<Semaphore>,
+	/// Paths of blocks recently found on disk
+	exists_cache: BlockExistsCache,
+	/// Contents of small blocks recently read from disk
+	data_cache: BlockDataCache,
+	/// Refusal of block writes when data directories are almost full
+	space_guard: DiskSpaceGuard,
+	/// Blocks that are currently being fetched from other nodes by `rpc_get_raw_block`
+	inflight_fetches: InFlightFetches<DataBlock>,
+	/// Blocks that are currently being written to disk
+	inflight_writes: InFlightWrites,
+	/// Number of blocks read or written on this node for clients, whichever
+	/// node they are connected to, used to pace background work
+	foreground_ops: AtomicU64,
 
 	pub(crate) metrics: BlockManagerMetrics,
+	corruption: Arc<CorruptionTracker>,
+	/// Whether corrupted blocks are moved to the quarantine directory of their
+	/// data directory, instead of being renamed in place
+	corruption_quarantine: bool,
 
 	pub scrub_persister: PersisterShared<ScrubWorkerPersisted>,
 	tx_scrub_command: ArcSwapOption<mpsc::Sender<ScrubWorkerCommand>>,
+
+	/// Whether replicas that did not return a block are checked after it is read
+	read_repair: bool,
+	tx_read_repair: ArcSwapOption<mpsc::Sender<ReadRepairTask>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -124,30 +228,59 @@ impl BlockManager {
 		config: &Config,
 		replication: TableShardedReplication,
 		system: Arc<System>,
+		live_config: watch::Receiver<LiveConfig>,
+		clock: Arc<dyn Clock>,
 	) -> Result<Arc<Self>, Error> {
 		// Load or compute layout, i.e. assignment of data blocks to the different data directories
 		let data_layout_persister: Persister<DataLayout> =
 			Persister::new(&system.metadata_dir, "data_layout");
-		let mut data_layout = match data_layout_persister.load() {
-			Ok(layout) => layout
-				.update(&config.data_dir)
-				.ok_or_message("invalid data_dir config")?,
-			Err(_) => {
-				DataLayout::initialize(&config.data_dir).ok_or_message("invalid data_dir config")?
-			}
+		let (mut data_layout, existing_node) = match data_layout_persister.load() {
+			Ok(layout) => (
+				layout
+					.update(&config.data_dir)
+					.ok_or_message("invalid data_dir config")?,
+				true,
+			),
+			Err(_) => (
+				DataLayout::initialize(&config.data_dir)
+					.ok_or_message("invalid data_dir config")?,
+				false,
+			),
 		};
 		data_layout.check_markers()?;
+		data_layout.set_dir_depth(config.block_dir_depth)?;
 		data_layout_persister
 			.save(&data_layout)
 			.expect("cannot save data_layout");
 
+		// Check that encryption settings are consistent with the blocks stored on this node
+		let encryption = config
+			.data_encryption_key
+			.as_deref()
+			.map(BlockEncryption::from_hex_key)
+			.transpose()?;
+		let encryption_state = DataEncryptionState {
+			key_id: encryption.as_ref().map(BlockEncryption::key_id),
+		};
+		let encryption_persister: Persister<DataEncryptionState> =
+			Persister::new(&system.metadata_dir, "data_encryption");
+		let previous_encryption_state = match encryption_persister.load() {
+			Ok(state) => state,
+			// Nodes that were set up before block encryption existed store plaintext blocks
+			Err(_) if existing_node => DataEncryptionState { key_id: None },
+			Err(_) => encryption_state,
+		};
+		previous_encryption_state
+			.check_transition(&encryption_state, config.data_encryption_migrate)?;
+		encryption_persister.save(&encryption_state)?;
+
 		// Open metadata tables
 		let rc = db
 			.open_tree("block_local_rc")
 			.expect("Unable to open block_local_rc tree");
 		let rc = BlockRc::new(rc);
 
-		let resync = BlockResyncManager::new(db, &system);
+		let resync = BlockResyncManager::new(db, &system, config, live_config.clone(), clock)?;
 
 		let endpoint = system
 			.netapp
@@ -155,12 +288,18 @@ impl BlockManager {
 
 		let buffer_kb_semaphore = Arc::new(Semaphore::new(config.block_ram_buffer_max / 1024));
 
+		let corruption = Arc::new(CorruptionTracker::new(
+			Duration::from_secs(config.block_corruption_alarm_window_secs),
+			config.block_corruption_alarm_threshold,
+		));
+
 		let metrics = BlockManagerMetrics::new(
 			config.compression_level,
 			rc.rc_table.clone(),
 			resync.queue.clone(),
 			resync.errors.clone(),
 			buffer_kb_semaphore.clone(),
+			corruption.clone(),
 		);
 
 		let scrub_persister = PersisterShared::new(&system.metadata_dir, "scrub_info");
@@ -171,7 +310,17 @@ impl BlockManager {
 			data_layout_persister,
 			data_fsync: config.data_fsync,
 			disable_scrub: config.disable_scrub,
+			live_config,
 			compression_level: config.compression_level,
+			max_block_size: config.max_block_size,
+			rpc_timeouts: BlockRpcTimeouts::new(
+				config.block_rpc_timeout_msec,
+				config.block_need_query_timeout_msec,
+				system.rpc_helper().rpc_timeout(),
+			),
+			encryption,
+			encryption_migrate: config.data_encryption_migrate,
+			read_ahead: config.block_read_ahead,
 			mutation_lock: vec![(); MUTEX_COUNT]
 				.iter()
 				.map(|_| Mutex::new(BlockManagerLocked()))
@@ -181,9 +330,22 @@ impl BlockManager {
 			system,
 			endpoint,
 			buffer_kb_semaphore,
+			exists_cache: BlockExistsCache::new(config.block_exists_cache_size),
+			data_cache: BlockDataCache::new(
+				config.block_cache_size,
+				config.block_cache_max_entry_size,
+			),
+			space_guard: DiskSpaceGuard::new(config.data_dir_min_free_space),
+			inflight_fetches: InFlightFetches::new(),
+			inflight_writes: InFlightWrites::new(),
+			foreground_ops: AtomicU64::new(0),
 			metrics,
+			corruption,
 			scrub_persister,
 			tx_scrub_command: ArcSwapOption::new(None),
+			corruption_quarantine: config.block_corruption_quarantine,
+			read_repair: config.block_read_repair,
+			tx_read_repair: ArcSwapOption::new(None),
 		});
 		block_manager.endpoint.set_handler(block_manager.clone());
 		block_manager.scrub_persister.set_with(|_| ()).unwrap();
@@ -208,6 +370,13 @@ impl BlockManager {
 				self.scrub_persister.clone(),
 			));
 		}
+
+		// Spawn read repair worker
+		if self.read_repair {
+			let (read_repair_tx, read_repair_rx) = read_repair_channel();
+			self.tx_read_repair.store(Some(Arc::new(read_repair_tx)));
+			bg.spawn_worker(ReadRepairWorker::new(self.clone(), read_repair_rx));
+		}
 	}
 
 	pub fn register_bg_vars(&self, vars: &mut vars::BgVars) {
@@ -239,47 +408,79 @@ impl BlockManager {
 	}
 
 	/// Ask nodes that might have a (possibly compressed) block for it
-	/// Return it as a stream with a header
-	async fn rpc_get_raw_block_streaming(
+	/// Return its entire body
+	///
+	/// Concurrent calls for the same block are coalesced into a single request,
+	/// sent with the order tag and the timeout of the first call. Calls with a higher
+	/// priority than the request in progress don't wait for it and send their own.
+	pub(crate) async fn rpc_get_raw_block(
 		&self,
 		hash: &Hash,
 		priority: RequestPriority,
 		order_tag: Option<OrderTag>,
-	) -> Result<DataBlockStream, Error> {
-		self.rpc_get_raw_block_internal(
-			hash,
-			priority,
-			order_tag,
-			|stream| async move { Ok(stream) },
-		)
-		.await
+		timeout: Option<Duration>,
+	) -> Result<DataBlock, Error> {
+		self.inflight_fetches
+			.get_or_fetch(hash, priority, || async {
+				self.rpc_fetch_raw_block(hash, priority, order_tag, timeout)
+					.await
+			})
+			.await
 	}
 
-	/// Ask nodes that might have a (possibly compressed) block for it
-	/// Return its entire body
-	pub(crate) async fn rpc_get_raw_block(
+	/// Ask nodes that might have a (possibly compressed) block for it, and
+	/// receive its entire body. A node whose copy turns out to be corrupted
+	/// ends its stream with an error: the data it sent is discarded and the
+	/// next node is asked.
+	async fn rpc_fetch_raw_block(
 		&self,
 		hash: &Hash,
 		priority: RequestPriority,
 		order_tag: Option<OrderTag>,
+		timeout: Option<Duration>,
 	) -> Result<DataBlock, Error> {
-		self.rpc_get_raw_block_internal(hash, priority, order_tag, |block_stream| async move {
-			let (header, stream) = block_stream.into_parts();
-			read_stream_to_end(stream)
-				.await
-				.err_context("error in block data stream")
-				.map(|data| DataBlock::from_parts(header, data.into_bytes()))
-		})
-		.await
+		let (block, repair_nodes) = self
+			.rpc_get_raw_block_internal(
+				hash,
+				priority,
+				order_tag,
+				timeout,
+				|block_stream| async move {
+					let (header, stream) = block_stream.into_parts();
+					read_stream_to_end(stream)
+						.await
+						.err_context("error in block data stream")
+						.map(|data| DataBlock::from_parts(header, data.into_bytes()))
+				},
+			)
+			.await?;
+		if let Some(tx) = self.tx_read_repair.load().as_ref() {
+			if !repair_nodes.is_empty() {
+				queue_read_repair(
+					tx,
+					&self.buffer_kb_semaphore,
+					hash,
+					repair_nodes,
+					Some(block.clone()),
+				);
+			}
+		}
+		Ok(block)
 	}
 
+	/// Ask nodes that might have a block for it, calling `f` on the stream
+	/// returned by the first one that has it. Returns the result of `f`, and the
+	/// nodes that should be checked by read repair, if it is enabled.
+	/// Each node is given `timeout`, or the configured block RPC timeout,
+	/// to reply before the next one is asked.
 	async fn rpc_get_raw_block_internal<F, Fut, T>(
 		&self,
 		hash: &Hash,
 		priority: RequestPriority,
 		order_tag: Option<OrderTag>,
+		timeout: Option<Duration>,
 		f: F,
-	) -> Result<T, Error>
+	) -> Result<(T, Vec<Uuid>), Error>
 	where
 		F: Fn(DataBlockStream) -> Fut,
 		Fut: futures::Future<Output = Result<T, Error>>,
@@ -289,46 +490,92 @@ impl BlockManager {
 			.rpc_helper()
 			.block_read_nodes_of(hash, self.system.rpc_helper());
 
-		for node in who.iter() {
+		let timeout = self.rpc_timeouts.block_or(timeout);
+		let metric_attributes = rpc_attributes("get_block");
+
+		let mut outcomes = who
+			.iter()
+			.map(|node| (*node, ReplicaReadOutcome::NotTried))
+			.collect::<Vec<_>>();
+
+		for (i, node) in who.iter().enumerate() {
 			let node_id = NodeID::from(*node);
 			let rpc = self.endpoint.call_streaming(
 				&node_id,
 				BlockRpc::GetBlock(*hash, order_tag),
 				priority,
 			);
-			tokio::select! {
-				res = rpc => {
+			// The duration is recorded whether the node answered or timed out
+			let reply = block_reply_within(timeout, rpc)
+				.record_duration(&self.metrics.rpc_duration, &metric_attributes);
+			match reply.await {
+				Some(res) => {
 					let res = match res {
 						Ok(res) => res,
 						Err(e) => {
-							debug!("Get block {:?}: node {:?} could not be contacted: {}", hash, node, e);
+							debug!(
+								"Get block {:?}: node {:?} could not be contacted: {}",
+								hash, node, e
+							);
+							self.metrics.record_rpc("get_block", RpcOutcome::Error);
+							outcomes[i].1 = ReplicaReadOutcome::Unreachable;
 							continue;
 						}
 					};
 					let block_stream = match res.into_parts() {
-						(Ok(BlockRpc::PutBlock { hash: _, header }), Some(stream)) => DataBlockStream::from_parts(header, stream),
+						(Ok(BlockRpc::PutBlock { hash: _, header }), Some(stream)) => {
+							DataBlockStream::from_parts(header, stream)
+						}
 						(Ok(_), _) => {
-							debug!("Get block {:?}: node {:?} returned a malformed response", hash, node);
+							debug!(
+								"Get block {:?}: node {:?} returned a malformed response",
+								hash, node
+							);
+							self.metrics.record_rpc("get_block", RpcOutcome::Error);
+							outcomes[i].1 = ReplicaReadOutcome::Failed;
 							continue;
 						}
 						(Err(e), _) => {
-							debug!("Get block {:?}: node {:?} returned error: {}", hash, node, e);
+							debug!(
+								"Get block {:?}: node {:?} returned error: {}",
+								hash, node, e
+							);
+							self.metrics.record_rpc("get_block", RpcOutcome::Error);
+							outcomes[i].1 = ReplicaReadOutcome::Failed;
 							continue;
 						}
 					};
 					match f(block_stream).await {
-						Ok(ret) => return Ok(ret),
+						Ok(ret) => {
+							self.metrics.record_rpc("get_block", RpcOutcome::Ok);
+							outcomes[i].1 = ReplicaReadOutcome::Ok;
+							// Blocks fetched in the background by the resync or by read
+							// repair itself do not trigger read repair
+							let repair_nodes =
+								if self.read_repair && priority & PRIO_BACKGROUND == 0 {
+									read_repair_nodes(&outcomes)
+								} else {
+									vec![]
+								};
+							return Ok((ret, repair_nodes));
+						}
 						Err(e) => {
-							debug!("Get block {:?}: error reading stream from node {:?}: {}", hash, node, e);
+							debug!(
+								"Get block {:?}: error reading stream from node {:?}: {}",
+								hash, node, e
+							);
+							self.metrics.record_rpc("get_block", RpcOutcome::Error);
+							outcomes[i].1 = ReplicaReadOutcome::Failed;
 						}
 					}
 				}
-				// TODO: sleep less long (fail early), initiate a second request earlier
-				// if the first one doesn't succeed rapidly
-				// TODO: keep first request running when initiating a new one and take the
-				// one that finishes earlier
-				_ = tokio::time::sleep(self.system.rpc_helper().rpc_timeout()) => {
-					debug!("Get block {:?}: node {:?} didn't return block in time, trying next.", hash, node);
+				None => {
+					debug!(
+						"Get block {:?}: node {:?} didn't return block in time, trying next.",
+						hash, node
+					);
+					self.metrics.record_rpc("get_block", RpcOutcome::Timeout);
+					outcomes[i].1 = ReplicaReadOutcome::Unreachable;
 				}
 			};
 		}
@@ -338,18 +585,142 @@ impl BlockManager {
 		Err(err)
 	}
 
+	/// Check which of the nodes that should store a block have a valid copy of it.
+	/// Each node reads and verifies its copy: corrupted copies are moved away
+	/// and queued for resync, as when they are read by clients.
+	pub async fn check_block_health(&self, hash: &Hash) -> Result<BlockHealth, Error> {
+		let who = self.system.cluster_layout().storage_nodes_of(hash);
+		let replies = self
+			.system
+			.rpc_helper()
+			.call_many(
+				&self.endpoint,
+				&who,
+				BlockRpc::CheckBlock(*hash),
+				RequestStrategy::with_priority(PRIO_BACKGROUND),
+			)
+			.record_duration(&self.metrics.rpc_duration, &rpc_attributes("check_block"))
+			.await?;
+		for (_, reply) in replies.iter() {
+			self.metrics
+				.record_rpc("check_block", RpcOutcome::of(reply));
+		}
+		Ok(BlockHealth::from_replies(replies))
+	}
+
+	/// Send a block to the nodes of a read repair task that should have it but don't.
+	/// Returns the number of nodes the block was sent to.
+	pub(crate) async fn repair_replicas(&self, task: ReadRepairTask) -> Result<usize, Error> {
+		let hash = &task.hash;
+		let resps = self
+			.system
+			.rpc_helper()
+			.call_many(
+				&self.endpoint,
+				&task.nodes,
+				BlockRpc::NeedBlockQuery(*hash),
+				RequestStrategy::with_priority(PRIO_BACKGROUND)
+					.with_custom_timeout(self.rpc_timeouts.need_block_query),
+			)
+			.record_duration(
+				&self.metrics.rpc_duration,
+				&rpc_attributes("need_block_query"),
+			)
+			.await?;
+		for (_, resp) in resps.iter() {
+			self.metrics
+				.record_rpc("need_block_query", RpcOutcome::of(resp));
+		}
+
+		let mut need_nodes = vec![];
+		for (node, resp) in resps {
+			match resp {
+				Ok(BlockRpc::NeedBlockReply(true)) => need_nodes.push(node),
+				Ok(BlockRpc::NeedBlockReply(false)) => (),
+				Ok(m) => debug!(
+					"Read repair of block {:?}: unexpected reply from node {:?}: {:?}",
+					hash, node, m
+				),
+				Err(e) => debug!(
+					"Read repair of block {:?}: node {:?} could not be checked: {}",
+					hash, node, e
+				),
+			}
+		}
+		if need_nodes.is_empty() {
+			return Ok(0);
+		}
+
+		info!(
+			block = ?hash,
+			"Read repair of block {:?}: sending it to {} nodes that don't have it",
+			hash,
+			need_nodes.len()
+		);
+		// Send the block that was read when the task was queued. If there was no
+		// room for it in the RAM buffer, read it again.
+		let block = match task.block {
+			Some((block, _permit)) => block,
+			None => match self.find_block(hash).await {
+				Some(path) => self.read_block_from(hash, &path).await?,
+				None => {
+					self.rpc_get_raw_block(hash, PRIO_BACKGROUND | PRIO_SECONDARY, None, None)
+						.await?
+				}
+			},
+		};
+		let (header, bytes) = block.into_parts();
+		let put_block_message = Req::new(BlockRpc::PutBlock {
+			hash: *hash,
+			header,
+		})?
+		.with_stream_from_buffer(bytes);
+		let (res, ack) = self
+			.system
+			.rpc_helper()
+			.try_write_many_sets_with_ack(
+				&self.endpoint,
+				&[need_nodes.clone()],
+				put_block_message,
+				RequestStrategy::with_priority(PRIO_BACKGROUND | PRIO_SECONDARY)
+					.with_quorum(need_nodes.len())
+					.with_custom_timeout(self.rpc_timeouts.block),
+			)
+			.record_duration(&self.metrics.rpc_duration, &rpc_attributes("put_block"))
+			.await;
+		self.metrics.record_write_ack("put_block", &ack);
+		res.err_context("PutBlock RPC")?;
+
+		for node in need_nodes.iter() {
+			self.metrics
+				.read_repair_send_counter
+				.add(1, &[KeyValue::new("to", format!("{:?}", node))]);
+		}
+		Ok(need_nodes.len())
+	}
+
 	// ---- Public interface ----
 
-	/// Ask nodes that might have a block for it, return it as a stream
+	/// Ask nodes that might have a block for it, return it as a stream.
+	/// Each node is given `timeout`, or `block_rpc_timeout_msec` if it is `None`,
+	/// to start sending the block before the next one is asked.
+	///
+	/// The block is received entirely before it is returned, so that a corrupted
+	/// copy, which is only detected at the end of the data sent by a node, is
+	/// replaced by the copy of the next node instead of failing the stream.
+	/// Concurrent reads of the same block share a single request, as in
+	/// `rpc_get_raw_block`.
 	pub async fn rpc_get_block_streaming(
 		&self,
 		hash: &Hash,
 		order_tag: Option<OrderTag>,
+		timeout: Option<Duration>,
 	) -> Result<ByteStream, Error> {
-		let block_stream = self
-			.rpc_get_raw_block_streaming(hash, PRIO_NORMAL | PRIO_SECONDARY, order_tag)
+		let block = self
+			.rpc_get_raw_block(hash, PRIO_NORMAL | PRIO_SECONDARY, order_tag, timeout)
 			.await?;
-		let (header, stream) = block_stream.into_parts();
+		let (header, chunks) = block_chunks(block).into_parts();
+		let stream: ByteStream = Box::pin(chunks.map(|chunk| chunk.map_err(std::io::Error::other)));
 		match header {
 			DataBlockHeader::Plain => Ok(stream),
 			DataBlockHeader::Compressed => {
@@ -362,20 +733,59 @@ impl BlockManager {
 		}
 	}
 
-	/// Send block to nodes that should have it
+	/// Ask nodes for the blocks of a sequence, starting at the block at index
+	/// `position`, and return them in order as a stream of block streams.
+	///
+	/// While the caller consumes a block, up to `block_read_ahead` of the following
+	/// blocks are fetched concurrently and buffered in RAM, so that a sequential
+	/// reader does not wait for the disk and network latency of each block in turn.
+	/// Buffered blocks take space in the RAM buffer (`block_ram_buffer_max`):
+	/// when it is full, blocks are read when they are consumed instead.
+	/// The order tag of the block at index `i` in `hashes` is `order_stream.order(i)`.
+	pub fn rpc_get_blocks_read_ahead(
+		self: &Arc<Self>,
+		hashes: Vec<Hash>,
+		position: usize,
+		order_stream: OrderTagStream,
+	) -> impl Stream<Item = Result<ByteStream, Error>> + Send + 'static {
+		let read_ahead = self.read_ahead;
+		let buffer_kb_semaphore = self.buffer_kb_semaphore.clone();
+		let manager = self.clone();
+		stream::iter(hashes.into_iter().enumerate().skip(position))
+			.map(move |(i, hash)| {
+				let manager = manager.clone();
+				let buffer_kb_semaphore = buffer_kb_semaphore.clone();
+				let order_tag = Some(order_stream.order(i as u64));
+				async move {
+					let block_stream = manager
+						.rpc_get_block_streaming(&hash, order_tag, None)
+						.await?;
+					if read_ahead == 0 {
+						return Ok::<ByteStream, Error>(block_stream);
+					}
+					buffer_block_stream(block_stream, &buffer_kb_semaphore).await
+				}
+			})
+			.buffered(read_ahead + 1)
+	}
+
+	/// Send block to nodes that should have it. Returns once a quorum of nodes
+	/// have stored it, with the nodes that had acknowledged or failed the write by then.
+	/// The write times out after `timeout`, or `block_rpc_timeout_msec` if it is `None`.
 	pub async fn rpc_put_block(
 		&self,
 		hash: Hash,
 		data: Bytes,
 		prevent_compression: bool,
 		order_tag: Option<OrderTag>,
-	) -> Result<(), Error> {
+		timeout: Option<Duration>,
+	) -> Result<WriteAck, Error> {
 		let who = self.system.cluster_layout().current_storage_nodes_of(&hash);
 
 		let compression_level = self.compression_level.filter(|_| !prevent_compression);
-		let (header, bytes) = DataBlock::from_buffer(data, compression_level)
-			.await
-			.into_parts();
+		let block = DataBlock::from_buffer(data, compression_level).await;
+		block.check_max_size(&hash, self.max_block_size)?;
+		let (header, bytes) = block.into_parts();
 
 		let permit = self
 			.buffer_kb_semaphore
@@ -392,19 +802,60 @@ impl BlockManager {
 			put_block_rpc
 		};
 
-		self.system
+		let (res, ack) = self
+			.system
 			.rpc_helper()
-			.try_write_many_sets(
+			.try_write_many_sets_with_ack(
 				&self.endpoint,
 				&[who],
 				put_block_rpc,
 				RequestStrategy::with_priority(PRIO_NORMAL | PRIO_SECONDARY)
 					.with_drop_on_completion(permit)
-					.with_quorum(self.replication.write_quorum()),
+					.with_quorum(self.replication.write_quorum())
+					.with_custom_timeout(self.rpc_timeouts.block_or(timeout)),
 			)
-			.await?;
+			.record_duration(&self.metrics.rpc_duration, &rpc_attributes("put_block"))
+			.await;
+		self.metrics.record_write_ack("put_block", &ack);
+		res?;
 
-		Ok(())
+		if !ack.failed.is_empty() {
+			debug!("Put block {:?}: {}", hash, ack);
+		}
+
+		Ok(ack)
+	}
+
+	/// Number of blocks read or written on this node for clients since it started.
+	/// These are the GetBlock and PutBlock RPCs handled by this node that were not
+	/// sent with a background priority, so that reads and writes for clients of
+	/// other nodes are counted, but not the ones made by the resync or read repair.
+	pub(crate) fn foreground_ops(&self) -> u64 {
+		self.foreground_ops.load(Ordering::Relaxed)
+	}
+
+	/// Wait for all block writes in progress to be finished. Called when shutting down,
+	/// after RPC handlers have been removed so that no new writes are started.
+	/// Gives up after a timeout, so that a stuck write can't prevent shutdown.
+	pub async fn await_inflight_writes(&self) {
+		let wait = self.inflight_writes.wait_idle();
+		if tokio::time::timeout(INFLIGHT_WRITES_SHUTDOWN_TIMEOUT, wait)
+			.await
+			.is_err()
+		{
+			warn!(
+				"{} block writes still in progress after {:?}, shutting down anyway",
+				self.inflight_writes.len(),
+				INFLIGHT_WRITES_SHUTDOWN_TIMEOUT
+			);
+		}
+	}
+
+	/// Get the data directories in which so many corrupted blocks have been found
+	/// recently that the disk is probably failing, with the number of corrupted blocks
+	/// found in each of them during the alarm window
+	pub fn corruption_alarms(&self) -> Vec<(PathBuf, usize)> {
+		self.corruption.alarms()
 	}
 
 	/// Get number of items in the refcount table
@@ -420,6 +871,39 @@ impl BlockManager {
 		Ok(())
 	}
 
+	/// Check that all active data directories are writable, by creating
+	/// and removing a small probe file in each of them
+	pub async fn check_data_dirs_writable(&self) -> Result<(), Error> {
+		let data_layout = self.data_layout.load_full();
+		for dir in data_layout.data_dirs.iter() {
+			if !matches!(dir.state, DataDirState::Active { .. }) {
+				continue;
+			}
+			// Random name, so that concurrent checks don't use the same file
+			let mut probe_path = dir.path.clone();
+			probe_path.push(format!(
+				"garage-probe.tmp{}",
+				hex::encode(thread_rng().gen::<[u8; 16]>())
+			));
+			// Always try to remove the probe file, as a failed write
+			// may still have created it
+			let write_res = fs::write(&probe_path, b"").await;
+			let remove_res = match fs::remove_file(&probe_path).await {
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound && write_res.is_err() => Ok(()),
+				res => res,
+			};
+			write_res.err_context(format!(
+				"data directory {} is not writable",
+				dir.path.display()
+			))?;
+			remove_res.err_context(format!(
+				"could not remove probe file {}",
+				probe_path.display()
+			))?;
+		}
+		Ok(())
+	}
+
 	/// Get the reference count of a block
 	pub fn get_block_rc(&self, hash: &Hash) -> Result<u64, Error> {
 		Ok(self.rc.get_block_rc(hash)?.as_u64())
@@ -436,7 +920,7 @@ impl BlockManager {
 				refcount: 0,
 				error_count: cnt.errors,
 				last_try: cnt.last_try,
-				next_try: cnt.next_try(),
+				next_try: cnt.next_try(self.resync.retry_delay()),
 			});
 		}
 		for block in blocks.iter_mut() {
@@ -445,6 +929,23 @@ impl BlockManager {
 		Ok(blocks)
 	}
 
+	/// List the first `limit` entries of the resync queue, as the time at which
+	/// each block is due to be resynced and the hash of the block
+	pub fn list_resync_queue(&self, limit: usize) -> Result<Vec<(u64, Hash)>, Error> {
+		self.resync.list_queue(limit)
+	}
+
+	/// Remove from the resync queue the blocks that have nothing left to do:
+	/// blocks that are no longer referenced and are not stored on this node.
+	/// Returns the number of queue entries removed.
+	pub async fn prune_resync_queue(&self) -> Result<usize, Error> {
+		self.resync
+			.prune_queue(PRUNE_RESYNC_QUEUE_BATCH_SIZE, |hash| async move {
+				Ok(self.get_block_rc(&hash)? == 0 && self.find_block(&hash).await.is_none())
+			})
+			.await
+	}
+
 	//// ----- Managing the reference counter ----
 
 	/// Increment the number of time a block is used, putting it to resynchronization if it is
@@ -461,15 +962,7 @@ impl BlockManager {
 			// so in all cases we add the block here to the todo list
 			// to check later that it arrived correctly, and if not
 			// we will fecth it from someone.
-			let this = self.clone();
-			tokio::spawn(async move {
-				if let Err(e) = this
-					.resync
-					.put_to_resync(&hash, 2 * this.system.rpc_helper().rpc_timeout())
-				{
-					error!("Block {:?} could not be put in resync queue: {}.", hash, e);
-				}
-			});
+			self.put_to_resync_batched(hash, 2 * self.rpc_timeouts.block);
 		}
 		Ok(())
 	}
@@ -486,17 +979,23 @@ impl BlockManager {
 			// There is a delay before we garbage collect it;
 			// make sure that it is handled in the resync loop
 			// after that delay has passed.
+			self.put_to_resync_batched(hash, BLOCK_GC_DELAY + Duration::from_secs(10));
+		}
+		Ok(())
+	}
+
+	/// Put a block in the resync queue from a background task. All blocks that are
+	/// added before that task runs are inserted together in a single transaction,
+	/// which is much faster when many blocks are referenced or dereferenced at once.
+	fn put_to_resync_batched(self: &Arc<Self>, hash: Hash, delay: Duration) {
+		if self.resync.add_pending(&hash, delay) {
 			let this = self.clone();
 			tokio::spawn(async move {
-				if let Err(e) = this
-					.resync
-					.put_to_resync(&hash, BLOCK_GC_DELAY + Duration::from_secs(10))
-				{
-					error!("Block {:?} could not be put in resync queue: {}.", hash, e);
+				if let Err(e) = this.resync.flush_pending() {
+					error!("Blocks could not be put in resync queue: {}.", e);
 				}
 			});
 		}
-		Ok(())
 	}
 
 	// ---- Reading and writing blocks locally ----
@@ -515,6 +1014,9 @@ impl BlockManager {
 
 	/// Write a block to disk
 	pub(crate) async fn write_block(&self, hash: &Hash, data: &DataBlock) -> Result<(), Error> {
+		// Checked before anything is written, so that no partial file is left behind
+		data.check_max_size(hash, self.max_block_size)?;
+
 		let tracer = opentelemetry::global::tracer("garage");
 
 		self.lock_mutate(hash)
@@ -529,19 +1031,29 @@ impl BlockManager {
 		Ok(())
 	}
 
-	async fn handle_get_block(&self, hash: &Hash, order_tag: Option<OrderTag>) -> Resp<BlockRpc> {
-		let block = match self.read_block(hash).await {
-			Ok(data) => data,
+	async fn handle_get_block(
+		self: &Arc<Self>,
+		hash: &Hash,
+		order_tag: Option<OrderTag>,
+	) -> Resp<BlockRpc> {
+		let block = match self.read_block_stream(hash).await {
+			Ok(stream) => stream,
 			Err(e) => return Resp::new(Err(e)),
 		};
 
-		let (header, data) = block.into_parts();
+		let (header, stream) = block.into_parts();
+
+		// The block is streamed as it is read from disk, and its integrity is
+		// checked as it is sent: if it is found to be corrupted, the stream ends
+		// with an error, so that the requesting node discards the data and asks
+		// the next node that stores the block
+		let stream = stream.map(|chunk| chunk.map_err(|e| std::io::Error::other(e.to_string())));
 
 		let resp = Resp::new(Ok(BlockRpc::PutBlock {
 			hash: *hash,
 			header,
 		}))
-		.with_stream_from_buffer(data);
+		.with_stream(Box::pin(stream));
 
 		if let Some(order_tag) = order_tag {
 			resp.with_order_tag(order_tag)
@@ -550,8 +1062,8 @@ impl BlockManager {
 		}
 	}
 
-	/// Read block from disk, verifying it's integrity
-	pub(crate) async fn read_block(&self, hash: &Hash) -> Result<DataBlock, Error> {
+	/// Read block from disk, verifying it's integrity, without using the block cache
+	pub(crate) async fn read_block_uncached(&self, hash: &Hash) -> Result<DataBlock, Error> {
 		let tracer = opentelemetry::global::tracer("garage");
 		async {
 			match self.find_block(hash).await {
@@ -559,7 +1071,7 @@ impl BlockManager {
 				None => {
 					// Not found but maybe we should have had it ??
 					self.resync
-						.put_to_resync(hash, 2 * self.system.rpc_helper().rpc_timeout())?;
+						.put_to_resync(hash, 2 * self.rpc_timeouts.block)?;
 					return Err(Error::Message(format!(
 						"block {:?} not found on node",
 						hash
@@ -581,33 +1093,196 @@ impl BlockManager {
 	) -> Result<DataBlock, Error> {
 		let (header, path) = block_path.as_parts_ref();
 
-		let mut f = fs::File::open(&path).await?;
+		let mut f = match fs::File::open(&path).await {
+			Ok(f) => f,
+			Err(e) => {
+				self.exists_cache.remove(hash);
+				return Err(e.into());
+			}
+		};
 		let mut data = vec![];
 		f.read_to_end(&mut data).await?;
 		self.metrics.bytes_read.add(data.len() as u64);
 		drop(f);
 
-		let data = DataBlock::from_parts(header, data.into());
+		let mut needs_encryption = false;
+		let data = match &self.encryption {
+			None => Some(data),
+			Some(encryption) => match encryption.decrypt(hash, &data) {
+				Ok(plaintext) => Some(plaintext),
+				Err(_) if self.encryption_migrate => {
+					// Block written before encryption was enabled
+					needs_encryption = true;
+					Some(data)
+				}
+				Err(_) => None,
+			},
+		};
 
-		if data.verify(*hash).is_err() {
-			self.metrics.corruption_counter.add(1);
+		let data = match data.map(|d| DataBlock::from_parts(header, d.into())) {
+			Some(data) if data.verify(*hash).is_ok() => data,
+			_ => {
+				self.handle_corrupted_block(hash, block_path).await?;
+				return Err(Error::CorruptData(*hash));
+			}
+		};
 
-			warn!(
-				"Block {:?} is corrupted. Renaming to .corrupted and resyncing.",
-				hash
-			);
-			self.lock_mutate(hash)
+		if needs_encryption {
+			if let Err(e) = self
+				.lock_mutate(hash)
 				.await
-				.move_block_to_corrupted(block_path)
-				.await?;
-			self.resync.put_to_resync(hash, Duration::from_millis(0))?;
-
-			return Err(Error::CorruptData(*hash));
+				.encrypt_block_in_place(hash, &data, self, block_path)
+				.await
+			{
+				warn!(block = ?hash, "Could not encrypt block {:?}: {}", hash, e);
+			}
 		}
 
 		Ok(data)
 	}
 
+	/// Read block from disk as a stream of chunks of at most 64 KiB,
+	/// without loading the entire block in RAM.
+	///
+	/// The integrity of the block is verified as it is read: if the block turns
+	/// out to be corrupted, the last item of the stream is `Err(Error::CorruptData(..))`
+	/// and the block is moved away and queued for resync, as in `read_block_uncached`.
+	/// The chunks are those of the block as stored on disk, i.e. possibly compressed
+	/// as indicated by the returned header. If blocks are encrypted at rest, the block
+	/// has to be read and decrypted entirely before it can be verified and streamed.
+	/// Blocks in the block cache are streamed from memory, and small blocks read from
+	/// disk are added to the cache once they have been verified.
+	pub async fn read_block_stream(
+		self: &Arc<Self>,
+		hash: &Hash,
+	) -> Result<DataBlockElem<impl Stream<Item = Result<Bytes, Error>> + Send + 'static>, Error> {
+		if let Some(block) = self.data_cache.get(hash) {
+			let (header, chunks) = block_chunks(block).into_parts();
+			return Ok(DataBlockElem::from_parts(header, chunks.left_stream()));
+		}
+
+		let block_path = match self.find_block(hash).await {
+			Some(p) => p,
+			None => {
+				self.resync
+					.put_to_resync(hash, 2 * self.rpc_timeouts.block)?;
+				return Err(Error::Message(format!(
+					"block {:?} not found on node",
+					hash
+				)));
+			}
+		};
+
+		if self.encryption.is_some() {
+			let block = self.read_block_from(hash, &block_path).await?;
+			self.data_cache.insert(*hash, block.clone());
+			let (header, chunks) = block_chunks(block).into_parts();
+			return Ok(DataBlockElem::from_parts(header, chunks.left_stream()));
+		}
+
+		let (header, path) = block_path.into_parts();
+		let file = match fs::File::open(&path).await {
+			Ok(file) => file,
+			Err(e) => {
+				self.exists_cache.remove(hash);
+				return Err(e.into());
+			}
+		};
+
+		let state = Some(BlockStreamState {
+			manager: self.clone(),
+			hash: *hash,
+			block_path: DataBlockPath::from_parts(header, path),
+			reader: DataBlockChunkReader::new(
+				file,
+				header,
+				*hash,
+				READ_BLOCK_STREAM_CHUNK_SIZE,
+				self.data_cache.max_entry_size(),
+			),
+		});
+
+		let stream = stream::unfold(state, |state| async move {
+			let mut state = state?;
+			match state.reader.next_chunk().await {
+				Some(Ok(chunk)) => {
+					state.manager.metrics.bytes_read.add(chunk.len() as u64);
+					Some((Ok(chunk), Some(state)))
+				}
+				Some(Err(e @ Error::CorruptData(_))) => {
+					let hash = state.hash;
+					if let Err(e2) = state
+						.manager
+						.handle_corrupted_block(&hash, &state.block_path)
+						.await
+					{
+						error!(block = ?hash, "Could not move corrupted block {:?}: {}", hash, e2);
+					}
+					Some((Err(e), None))
+				}
+				Some(Err(e)) => Some((Err(e), None)),
+				None => {
+					if let Some(block) = state.reader.into_block() {
+						state.manager.data_cache.insert(state.hash, block);
+					}
+					None
+				}
+			}
+		});
+
+		Ok(DataBlockElem::from_parts(header, stream.right_stream()))
+	}
+
+	/// Move away a block that has been found to be corrupted, and queue it for resync
+	async fn handle_corrupted_block(
+		&self,
+		hash: &Hash,
+		block_path: &DataBlockPath,
+	) -> Result<(), Error> {
+		self.metrics.corruption_counter.add(1);
+
+		let data_dir = self.record_corruption(block_path.as_parts_ref().1);
+		let quarantine_in = data_dir.filter(|_| self.corruption_quarantine);
+		let new_path = self
+			.lock_mutate(hash)
+			.await
+			.set_aside_corrupted_block(
+				hash,
+				block_path,
+				quarantine_in.as_deref(),
+				&self.exists_cache,
+				&self.resync,
+			)
+			.await?;
+		self.data_cache.remove(hash);
+		warn!(
+			block = ?hash,
+			"Block {:?} is corrupted. Moved to {} and resyncing.",
+			hash,
+			new_path.display()
+		);
+		Ok(())
+	}
+
+	/// Count a corrupted block in the data directory that contains it,
+	/// and return that data directory
+	fn record_corruption(&self, block_path: &Path) -> Option<PathBuf> {
+		let data_layout = self.data_layout.load();
+		let data_dir = &data_layout
+			.data_dirs
+			.iter()
+			.find(|dir| block_path.starts_with(&dir.path))?
+			.path;
+		if let Some(count) = self.corruption.record(data_dir) {
+			error!(
+				"{} corrupted blocks were found in data directory {} recently, the disk might be failing and should be replaced!",
+				count,
+				data_dir.display()
+			);
+		}
+		Some(data_dir.clone())
+	}
+
 	/// Check if this node should have a block, but don't actually have it
 	async fn need_block(&self, hash: &Hash) -> Result<bool, Error> {
 		let rc = self.rc.get_block_rc(hash)?;
@@ -615,6 +1290,21 @@ impl BlockManager {
 		Ok(rc.is_nonzero() && !exists)
 	}
 
+	/// State of the local copy of a block
+	async fn check_local_block(&self, hash: &Hash) -> Result<BlockCopyState, Error> {
+		let path = match self.find_block(hash).await {
+			Some(p) => p,
+			None => return Ok(BlockCopyState::Missing),
+		};
+		match self.read_block_from(hash, &path).await {
+			Ok(_) => Ok(BlockCopyState::Present),
+			Err(Error::CorruptData(_)) => Ok(BlockCopyState::Corrupted),
+			// The block was deleted since it was found
+			Err(_) if self.find_block(hash).await.is_none() => Ok(BlockCopyState::Missing),
+			Err(e) => Err(e),
+		}
+	}
+
 	/// Delete block if it is not needed anymore
 	pub(crate) async fn delete_if_unneeded(&self, hash: &Hash) -> Result<(), Error> {
 		self.lock_mutate(hash)
@@ -625,6 +1315,17 @@ impl BlockManager {
 
 	/// Find the path where a block is currently stored
 	pub(crate) async fn find_block(&self, hash: &Hash) -> Option<DataBlockPath> {
+		if let Some(path) = self.exists_cache.get(hash) {
+			return Some(path);
+		}
+		let path = self.find_block_on_disk(hash).await;
+		if let Some(path) = &path {
+			self.exists_cache.insert(*hash, path.clone());
+		}
+		path
+	}
+
+	async fn find_block_on_disk(&self, hash: &Hash) -> Option<DataBlockPath> {
 		let data_layout = self.data_layout.load_full();
 		let dirs = Some(data_layout.primary_block_dir(hash))
 			.into_iter()
@@ -691,6 +1392,14 @@ impl BlockManager {
 
 impl StreamingEndpointHandler<BlockRpc> for BlockManager {
 	async fn handle(self: &Arc<Self>, mut message: Req<BlockRpc>, _from: NodeID) -> Resp<BlockRpc> {
+		if matches!(
+			message.msg(),
+			BlockRpc::PutBlock { .. } | BlockRpc::GetBlock(..)
+		) && message.prio() & PRIO_BACKGROUND == 0
+		{
+			self.foreground_ops.fetch_add(1, Ordering::Relaxed);
+		}
+
 		match message.msg() {
 			BlockRpc::PutBlock { hash, header } => Resp::new(
 				self.handle_put_block(*hash, *header, message.take_stream())
@@ -701,6 +1410,11 @@ impl StreamingEndpointHandler<BlockRpc> for BlockManager {
 			BlockRpc::NeedBlockQuery(h) => {
 				Resp::new(self.need_block(h).await.map(BlockRpc::NeedBlockReply))
 			}
+			BlockRpc::CheckBlock(h) => Resp::new(
+				self.check_local_block(h)
+					.await
+					.map(BlockRpc::CheckBlockReply),
+			),
 			m => Resp::new(Err(Error::unexpected_rpc_message(m))),
 		}
 	}
@@ -714,6 +1428,11 @@ impl BlockManagerLocked {
 		mgr: &BlockManager,
 	) -> Result<(), Error> {
 		let existing_path = mgr.find_block(hash).await;
+		if existing_path.is_none() {
+			let data_layout = mgr.data_layout.load();
+			mgr.space_guard
+				.check_write(data_layout.primary_data_dir(hash))?;
+		}
 		self.write_block_inner(hash, data, mgr, existing_path).await
 	}
 
@@ -724,6 +1443,8 @@ impl BlockManagerLocked {
 		mgr: &BlockManager,
 		existing_path: Option<DataBlockPath>,
 	) -> Result<(), Error> {
+		let _write = mgr.inflight_writes.begin();
+
 		let (header, data) = data.as_parts_ref();
 		let compressed = header.is_compressed();
 
@@ -768,6 +1489,15 @@ impl BlockManagerLocked {
 
 		let mut delete_on_drop = DeleteOnDrop(Some(path_tmp.clone()));
 
+		let encrypted;
+		let data = match &mgr.encryption {
+			Some(encryption) => {
+				encrypted = encryption.encrypt(hash, data);
+				&encrypted[..]
+			}
+			None => &data[..],
+		};
+
 		let mut f = fs::File::create(&path_tmp).await?;
 		f.write_all(data).await?;
 		mgr.metrics.bytes_written.add(data.len() as u64);
@@ -778,13 +1508,15 @@ impl BlockManagerLocked {
 
 		drop(f);
 
-		fs::rename(path_tmp, tgt_path).await?;
+		fs::rename(path_tmp, &tgt_path).await?;
 
 		delete_on_drop.cancel();
 
 		if let Some(to_delete) = to_delete {
 			fs::remove_file(to_delete).await?;
 		}
+		mgr.exists_cache
+			.insert(*hash, DataBlockPath::from_parts(header, tgt_path));
 
 		if mgr.data_fsync {
 			// We want to ensure that when this function returns, data is properly persisted
@@ -804,18 +1536,74 @@ impl BlockManagerLocked {
 		Ok(())
 	}
 
-	async fn move_block_to_corrupted(&self, block_path: &DataBlockPath) -> Result<(), Error> {
+	/// Rewrite an unencrypted block file so that it is encrypted,
+	/// moving it to its primary location if necessary
+	async fn encrypt_block_in_place(
+		&self,
+		hash: &Hash,
+		data: &DataBlock,
+		mgr: &BlockManager,
+		block_path: &DataBlockPath,
+	) -> Result<(), Error> {
 		let (header, path) = block_path.as_parts_ref();
+		if fs::metadata(path).await.is_err() {
+			// Block was deleted or moved since we read it
+			return Ok(());
+		}
 
-		let mut path2 = path.clone();
+		let mut tgt_path = mgr.data_layout.load().primary_block_dir(hash);
+		tgt_path.push(hex::encode(hash));
 		if header.is_compressed() {
-			path2.set_extension("zst.corrupted");
-		} else {
-			path2.set_extension("corrupted");
+			tgt_path.set_extension("zst");
 		}
 
-		fs::rename(path, path2).await?;
-		Ok(())
+		let existing_path = if *path == tgt_path {
+			// Overwrite the file in place
+			None
+		} else {
+			Some(DataBlockPath::from_parts(header, path.clone()))
+		};
+		self.write_block_inner(hash, data, mgr, existing_path).await
+	}
+
+	/// Move away a corrupted block and queue it for resync, returning its new path.
+	/// If `quarantine_in` is the data directory containing the block, the block is
+	/// moved to the quarantine directory of that data directory, under the same
+	/// subdirectories; otherwise it is renamed in place with a `.corrupted` extension.
+	async fn set_aside_corrupted_block(
+		&self,
+		hash: &Hash,
+		block_path: &DataBlockPath,
+		quarantine_in: Option<&Path>,
+		exists_cache: &BlockExistsCache,
+		resync: &BlockResyncManager,
+	) -> Result<PathBuf, Error> {
+		let (header, path) = block_path.as_parts_ref();
+
+		let quarantine_path = quarantine_in.and_then(|data_dir| {
+			let rel_path = path.strip_prefix(data_dir).ok()?;
+			Some(data_dir.join(QUARANTINE_DIR_NAME).join(rel_path))
+		});
+		let path2 = match quarantine_path {
+			Some(path2) => {
+				fs::create_dir_all(path2.parent().unwrap()).await?;
+				path2
+			}
+			None => {
+				let mut path2 = path.clone();
+				if header.is_compressed() {
+					path2.set_extension("zst.corrupted");
+				} else {
+					path2.set_extension("corrupted");
+				}
+				path2
+			}
+		};
+
+		fs::rename(path, &path2).await?;
+		exists_cache.remove(hash);
+		resync.put_to_resync(hash, Duration::from_millis(0))?;
+		Ok(path2)
 	}
 
 	async fn delete_if_unneeded(&self, hash: &Hash, mgr: &BlockManager) -> Result<(), Error> {
@@ -823,7 +1611,11 @@ impl BlockManagerLocked {
 		if rc.is_deletable() {
 			while let Some(path) = mgr.find_block(hash).await {
 				let (_header, path) = path.as_parts_ref();
+				mgr.exists_cache.remove(hash);
+				mgr.data_cache.remove(hash);
+				let size = fs::metadata(path).await.map(|m| m.len()).unwrap_or(0);
 				fs::remove_file(path).await?;
+				mgr.space_guard.space_freed(path, size);
 				mgr.metrics.delete_counter.add(1);
 			}
 		}
@@ -831,6 +1623,68 @@ impl BlockManagerLocked {
 	}
 }
 
+struct BlockStreamState {
+	manager: Arc<BlockManager>,
+	hash: Hash,
+	block_path: DataBlockPath,
+	reader: DataBlockChunkReader<fs::File>,
+}
+
+/// Stream the data of a block that is in memory, in chunks of at most 64 KiB
+fn block_chunks(block: DataBlock) -> DataBlockElem<impl Stream<Item = Result<Bytes, Error>>> {
+	let (header, data) = block.into_parts();
+	let chunks = data
+		.chunks(READ_BLOCK_STREAM_CHUNK_SIZE)
+		.map(|chunk| Ok::<_, Error>(data.slice_ref(chunk)))
+		.collect::<Vec<_>>();
+	DataBlockElem::from_parts(header, stream::iter(chunks))
+}
+
+/// Read a block stream in RAM for read-ahead, taking the space it uses from the
+/// RAM buffer. If the buffer is full, the data read so far is returned followed by
+/// the rest of the stream, which is only read when it is consumed. This doesn't wait
+/// for space in the buffer, as it may be held by blocks that come after this one.
+async fn buffer_block_stream(
+	mut block_stream: ByteStream,
+	buffer_kb_semaphore: &Arc<Semaphore>,
+) -> Result<ByteStream, Error> {
+	let mut chunks = vec![];
+	let mut size = 0;
+	let mut permit: Option<OwnedSemaphorePermit> = None;
+	let mut rest = None;
+
+	while let Some(chunk) = block_stream.next().await {
+		let chunk = chunk.err_context("error in block data stream")?;
+		size += chunk.len();
+		chunks.push(chunk);
+
+		let held_kb = permit.as_ref().map(|p| p.num_permits()).unwrap_or(0);
+		let needed_kb = size.div_ceil(1024) - held_kb;
+		match buffer_kb_semaphore
+			.clone()
+			.try_acquire_many_owned(needed_kb as u32)
+		{
+			Ok(more) => match permit.as_mut() {
+				Some(permit) => permit.merge(more),
+				None => permit = Some(more),
+			},
+			Err(_) => {
+				rest = Some(block_stream);
+				break;
+			}
+		}
+	}
+
+	let stream = stream::iter(chunks.into_iter().map(Ok))
+		.chain(rest.unwrap_or_else(|| Box::pin(stream::empty())))
+		// The space taken in the RAM buffer is released when the stream is dropped
+		.map(move |chunk| {
+			let _permit = &permit;
+			chunk
+		});
+	Ok(Box::pin(stream))
+}
+
 struct DeleteOnDrop(Option<PathBuf>);
 
 impl DeleteOnDrop {
@@ -850,3 +1704,294 @@ impl Drop for DeleteOnDrop {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use garage_util::time::MockClock;
+
+	use super::*;
+	use crate::resync::tests::test_resync_manager;
+
+	#[tokio::test]
+	async fn test_read_ahead_buffer() {
+		use std::sync::atomic::{AtomicUsize, Ordering};
+
+		fn counted_stream(chunks: usize, polled: &Arc<AtomicUsize>) -> ByteStream {
+			let polled = polled.clone();
+			Box::pin(stream::iter(0..chunks).map(move |i| {
+				polled.fetch_add(1, Ordering::SeqCst);
+				Ok(Bytes::from(vec![i as u8; 1500]))
+			}))
+		}
+		let expected = (0..4u8).flat_map(|i| vec![i; 1500]).collect::<Vec<_>>();
+
+		// The block is read entirely, and takes space in the RAM buffer until
+		// it is consumed
+		let semaphore = Arc::new(Semaphore::new(16));
+		let polled = Arc::new(AtomicUsize::new(0));
+		let buffered = buffer_block_stream(counted_stream(4, &polled), &semaphore)
+			.await
+			.unwrap();
+		assert_eq!(polled.load(Ordering::SeqCst), 4);
+		assert_eq!(semaphore.available_permits(), 16 - 6);
+		let data = read_stream_to_end(buffered).await.unwrap().into_bytes();
+		assert_eq!(data, expected);
+		assert_eq!(semaphore.available_permits(), 16);
+
+		// When the RAM buffer is full, the rest of the block is read
+		// when it is consumed
+		let semaphore = Arc::new(Semaphore::new(4));
+		let polled = Arc::new(AtomicUsize::new(0));
+		let buffered = buffer_block_stream(counted_stream(4, &polled), &semaphore)
+			.await
+			.unwrap();
+		assert_eq!(polled.load(Ordering::SeqCst), 3);
+		assert_eq!(semaphore.available_permits(), 1);
+		let data = read_stream_to_end(buffered).await.unwrap().into_bytes();
+		assert_eq!(polled.load(Ordering::SeqCst), 4);
+		assert_eq!(data, expected);
+		assert_eq!(semaphore.available_permits(), 4);
+	}
+
+	#[test]
+	fn test_block_rpc_timeouts() {
+		let rpc_timeout = Duration::from_secs(300);
+
+		let default = BlockRpcTimeouts::new(None, None, rpc_timeout);
+		assert_eq!(default.block, rpc_timeout);
+		assert_eq!(default.need_block_query, rpc_timeout);
+
+		let configured = BlockRpcTimeouts::new(Some(2000), Some(500), rpc_timeout);
+		assert_eq!(configured.block, Duration::from_millis(2000));
+		assert_eq!(configured.need_block_query, Duration::from_millis(500));
+
+		// A timeout given by the caller takes precedence
+		assert_eq!(configured.block_or(None), Duration::from_millis(2000));
+		assert_eq!(
+			configured.block_or(Some(Duration::from_millis(100))),
+			Duration::from_millis(100)
+		);
+	}
+
+	#[tokio::test]
+	async fn test_block_reply_timeout() {
+		use futures::future;
+		use std::time::Instant;
+
+		let timeouts = BlockRpcTimeouts::new(Some(200), None, Duration::from_secs(300));
+
+		// A node that replies in time
+		let reply = block_reply_within(timeouts.block_or(None), async { 42 }).await;
+		assert_eq!(reply, Some(42));
+
+		// A node that doesn't reply is given up after the configured timeout
+		let start = Instant::now();
+		let reply = block_reply_within(timeouts.block_or(None), future::pending::<()>()).await;
+		assert_eq!(reply, None);
+		let elapsed = start.elapsed();
+		assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+		assert!(elapsed < Duration::from_secs(10), "{:?}", elapsed);
+
+		// or after the timeout of the call, when one is given
+		let start = Instant::now();
+		let timeout = timeouts.block_or(Some(Duration::from_millis(20)));
+		let reply = block_reply_within(timeout, future::pending::<()>()).await;
+		assert_eq!(reply, None);
+		let elapsed = start.elapsed();
+		assert!(elapsed >= Duration::from_millis(20), "{:?}", elapsed);
+		assert!(elapsed < Duration::from_millis(200), "{:?}", elapsed);
+	}
+
+	#[tokio::test]
+	async fn test_corrupted_block_quarantine() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let resync = test_resync_manager(&dir, MockClock::new(1_000_000));
+		let exists_cache = BlockExistsCache::new(16);
+		let data_dir = dir.as_path().join("data");
+		let hash = blake2sum(b"corrupted block");
+		let filename = hex::encode(hash.as_slice());
+
+		let mut block_dir = data_dir.clone();
+		for i in 0..2 {
+			block_dir.push(hex::encode(&hash.as_slice()[i..i + 1]));
+		}
+		std::fs::create_dir_all(&block_dir).unwrap();
+		let path = block_dir.join(format!("{}.zst", filename));
+		std::fs::write(&path, b"garbage").unwrap();
+		exists_cache.insert(hash, DataBlockPath::compressed(path.clone()));
+
+		// The block keeps its subdirectories in the quarantine directory
+		let new_path = BlockManagerLocked()
+			.set_aside_corrupted_block(
+				&hash,
+				&DataBlockPath::compressed(path.clone()),
+				Some(&data_dir),
+				&exists_cache,
+				&resync,
+			)
+			.await
+			.unwrap();
+		let mut expected = data_dir.join(QUARANTINE_DIR_NAME);
+		for i in 0..2 {
+			expected.push(hex::encode(&hash.as_slice()[i..i + 1]));
+		}
+		expected.push(format!("{}.zst", filename));
+		assert_eq!(new_path, expected);
+		assert!(!path.exists());
+		assert!(exists_cache.get(&hash).is_none());
+		assert_eq!(std::fs::read(&new_path).unwrap(), b"garbage");
+		assert_eq!(
+			resync.list_queue(usize::MAX).unwrap(),
+			vec![(1_000_000, hash)]
+		);
+
+		// Without quarantine, the block is renamed in place
+		std::fs::write(&path, b"garbage").unwrap();
+		let new_path = BlockManagerLocked()
+			.set_aside_corrupted_block(
+				&hash,
+				&DataBlockPath::compressed(path.clone()),
+				None,
+				&exists_cache,
+				&resync,
+			)
+			.await
+			.unwrap();
+		assert_eq!(
+			new_path,
+			block_dir.join(format!("{}.zst.corrupted", filename))
+		);
+		assert!(!path.exists());
+	}
+
+	/// A block manager on a single node storing all blocks in `dir`, whose
+	/// requests for blocks are sent to itself
+	async fn test_block_manager(dir: &mktemp::Temp) -> Arc<BlockManager> {
+		use garage_rpc::layout::*;
+		use garage_rpc::replication_mode::parse_replication_mode;
+		use garage_util::config::read_config;
+		use garage_util::crdt::Crdt;
+
+		let dir = dir.as_path();
+		let config_file = dir.join("garage.toml");
+		let config = format!(
+			r#"
+metadata_dir = "{dir}/meta"
+data_dir = "{dir}/data"
+db_engine = "lmdb"
+replication_factor = 1
+rpc_bind_addr = "127.0.0.1:3901"
+rpc_secret = "{secret}"
+
+[s3_api]
+s3_region = "garage"
+"#,
+			dir = dir.display(),
+			secret = hex::encode([0u8; 32]),
+		);
+		std::fs::write(&config_file, config).unwrap();
+		std::fs::create_dir(dir.join("data")).unwrap();
+		let config = read_config(config_file).unwrap();
+
+		let network_key = garage_net::NetworkKey::from_slice(&[0u8; 32]).unwrap();
+		let (replication_factor, consistency_mode) = parse_replication_mode(&config).unwrap();
+		let system =
+			System::new(network_key, replication_factor, consistency_mode, &config).unwrap();
+		let replication = TableShardedReplication {
+			system: system.clone(),
+			replication_factor: replication_factor.into(),
+			write_quorum: replication_factor.write_quorum(consistency_mode),
+			read_quorum: 1,
+		};
+
+		let mut history = system.cluster_layout().inner().clone();
+		let update = history.staging.get().roles.update_mutator(
+			system.id,
+			NodeRoleV(Some(NodeRole {
+				zone: "dc1".into(),
+				capacity: Some(1 << 30),
+				tags: vec![],
+			})),
+		);
+		history.staging.get_mut().roles.merge(&update);
+		let version = history.current().version + 1;
+		let (history, _) = history.apply_staged_changes(Some(version)).unwrap();
+		system
+			.layout_manager
+			.update_cluster_layout(&history)
+			.await
+			.unwrap();
+
+		let db = db::open_db(
+			&dir.join("db"),
+			db::Engine::Lmdb,
+			&db::OpenOpt {
+				fsync: false,
+				lmdb_map_size: Some(1 << 24),
+			},
+		)
+		.unwrap();
+		let (_, live_config) = watch::channel(LiveConfig::from(&config));
+		BlockManager::new(
+			&db,
+			&config,
+			replication,
+			system,
+			live_config,
+			Arc::new(MockClock::new(1_000_000)),
+		)
+		.unwrap()
+	}
+
+	#[test]
+	fn test_concurrent_reads_coalesced() {
+		// Disk accesses run on the only blocking thread, which the test occupies
+		// so that the first read can't finish before the others are started
+		let runtime = tokio::runtime::Builder::new_current_thread()
+			.enable_all()
+			.max_blocking_threads(1)
+			.build()
+			.unwrap();
+		runtime.block_on(async {
+			let dir = mktemp::Temp::new_dir().unwrap();
+			let manager = test_block_manager(&dir).await;
+
+			// Larger than the entries of the data cache, so that each read
+			// of the block goes to the disk
+			let data = Bytes::from(vec![42u8; 512 * 1024]);
+			let hash = blake2sum(&data);
+			manager
+				.write_block(&hash, &DataBlock::from_buffer(data.clone(), None).await)
+				.await
+				.unwrap();
+
+			// Concurrent reads of the block are served by a single request
+			let (release, blocked) = std::sync::mpsc::channel::<()>();
+			tokio::task::spawn_blocking(move || blocked.recv());
+			let reads = futures::future::join_all((0..8).map(|_| async {
+				let stream = manager
+					.rpc_get_block_streaming(&hash, None, None)
+					.await
+					.unwrap();
+				read_stream_to_end(stream).await.unwrap().into_bytes()
+			}));
+			let (reads, _) = futures::future::join(reads, async {
+				tokio::task::yield_now().await;
+				release.send(()).unwrap();
+			})
+			.await;
+			for read in reads {
+				assert_eq!(read, data);
+			}
+			assert_eq!(manager.foreground_ops(), 1);
+
+			// A read started after them sends its own request
+			let stream = manager
+				.rpc_get_block_streaming(&hash, None, None)
+				.await
+				.unwrap();
+			assert_eq!(read_stream_to_end(stream).await.unwrap().into_bytes(), data);
+			assert_eq!(manager.foreground_ops(), 2);
+		});
+	}
+}
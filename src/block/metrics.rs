@@ -2,9 +2,14 @@ use std::sync::Arc;
 
 use tokio::sync::Semaphore;
 
-use opentelemetry::{global, metrics::*};
+use opentelemetry::{global, metrics::*, KeyValue};
 
 use garage_db as db;
+use garage_util::error::Error;
+
+use garage_rpc::WriteAck;
+
+use crate::corruption::CorruptionTracker;
 
 /// TableMetrics reference all counter used for metrics
 pub struct BlockManagerMetrics {
@@ -13,12 +18,14 @@ pub struct BlockManagerMetrics {
 	pub(crate) _resync_queue_len: ValueObserver<u64>,
 	pub(crate) _resync_errored_blocks: ValueObserver<u64>,
 	pub(crate) _buffer_free_kb: ValueObserver<u64>,
+	pub(crate) _corruption_alarm: ValueObserver<u64>,
 
 	pub(crate) resync_counter: BoundCounter<u64>,
 	pub(crate) resync_error_counter: BoundCounter<u64>,
 	pub(crate) resync_duration: BoundValueRecorder<f64>,
 	pub(crate) resync_send_counter: Counter<u64>,
 	pub(crate) resync_recv_counter: BoundCounter<u64>,
+	pub(crate) read_repair_send_counter: Counter<u64>,
 
 	pub(crate) bytes_read: BoundCounter<u64>,
 	pub(crate) block_read_duration: BoundValueRecorder<f64>,
@@ -27,6 +34,9 @@ pub struct BlockManagerMetrics {
 	pub(crate) delete_counter: BoundCounter<u64>,
 
 	pub(crate) corruption_counter: BoundCounter<u64>,
+
+	pub(crate) rpc_counter: Counter<u64>,
+	pub(crate) rpc_duration: ValueRecorder<f64>,
 }
 
 impl BlockManagerMetrics {
@@ -36,6 +46,7 @@ impl BlockManagerMetrics {
 		resync_queue: db::Tree,
 		resync_errors: db::Tree,
 		buffer_semaphore: Arc<Semaphore>,
+		corruption: Arc<CorruptionTracker>,
 	) -> Self {
 		let meter = global::meter("garage_model/block");
 		Self {
@@ -83,6 +94,19 @@ impl BlockManagerMetrics {
 					"Available RAM in KiB to use for buffering data blocks to be written to remote nodes",
 				)
 				.init(),
+			_corruption_alarm: meter
+				.u64_value_observer("block.corruption_alarm", move |observer| {
+					for (dir, count) in corruption.alarms() {
+						observer.observe(
+							count as u64,
+							&[KeyValue::new("data_dir", dir.to_string_lossy().into_owned())],
+						);
+					}
+				})
+				.with_description(
+					"Number of corruptions recently found on data directories that reached the alarm threshold",
+				)
+				.init(),
 
 			resync_counter: meter
 				.u64_counter("block.resync_counter")
@@ -108,6 +132,10 @@ impl BlockManagerMetrics {
 				.with_description("Number of blocks received from other nodes in resync operations")
 				.init()
 				.bind(&[]),
+			read_repair_send_counter: meter
+				.u64_counter("block.read_repair_send_counter")
+				.with_description("Number of blocks sent to another node by read repair")
+				.init(),
 
 			bytes_read: meter
 				.u64_counter("block.bytes_read")
@@ -140,6 +168,100 @@ impl BlockManagerMetrics {
 				.with_description("Data corruptions detected on block reads")
 				.init()
 				.bind(&[]),
+
+			rpc_counter: meter
+				.u64_counter("block.rpc_counter")
+				.with_description(
+					"Number of block RPC calls to other nodes, by message type and result (ok, timeout or error)",
+				)
+				.init(),
+			rpc_duration: meter
+				.f64_value_recorder("block.rpc_duration")
+				.with_description("Duration of block RPC calls to other nodes, by message type")
+				.init(),
+		}
+	}
+
+	/// Count a block RPC call to another node that has finished
+	pub(crate) fn record_rpc(&self, rpc: &'static str, outcome: RpcOutcome) {
+		self.rpc_counter.add(
+			1,
+			&[
+				KeyValue::new("rpc", rpc),
+				KeyValue::new("result", outcome.as_str()),
+			],
+		);
+	}
+
+	/// Count the calls of a block write, from the nodes that acknowledged it or failed
+	/// before it returned
+	pub(crate) fn record_write_ack(&self, rpc: &'static str, ack: &WriteAck) {
+		for _ in ack.acked.iter() {
+			self.record_rpc(rpc, RpcOutcome::Ok);
 		}
+		for (node, _) in ack.failed.iter() {
+			let outcome = match ack.timed_out.contains(node) {
+				true => RpcOutcome::Timeout,
+				false => RpcOutcome::Error,
+			};
+			self.record_rpc(rpc, outcome);
+		}
+	}
+}
+
+/// Attributes of the `block.rpc_duration` metric for a message type
+pub(crate) fn rpc_attributes(rpc: &'static str) -> [KeyValue; 1] {
+	[KeyValue::new("rpc", rpc)]
+}
+
+/// Result of a block RPC call, as counted in the `block.rpc_counter` metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RpcOutcome {
+	Ok,
+	/// The node did not answer before the timeout
+	Timeout,
+	/// The node could not be contacted, or answered with an error
+	Error,
+}
+
+impl RpcOutcome {
+	pub(crate) fn of<T>(res: &std::result::Result<T, Error>) -> Self {
+		match res {
+			Ok(_) => RpcOutcome::Ok,
+			Err(e) => Self::of_error(e),
+		}
+	}
+
+	pub(crate) fn of_error(e: &Error) -> Self {
+		match e {
+			Error::Timeout => RpcOutcome::Timeout,
+			_ => RpcOutcome::Error,
+		}
+	}
+
+	fn as_str(&self) -> &'static str {
+		match self {
+			RpcOutcome::Ok => "ok",
+			RpcOutcome::Timeout => "timeout",
+			RpcOutcome::Error => "error",
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_rpc_outcome() {
+		assert_eq!(RpcOutcome::of(&Ok::<_, Error>(())), RpcOutcome::Ok);
+		assert_eq!(
+			RpcOutcome::of(&Err::<(), _>(Error::Timeout)),
+			RpcOutcome::Timeout
+		);
+		assert_eq!(
+			RpcOutcome::of(&Err::<(), _>(Error::Message("connection reset".into()))),
+			RpcOutcome::Error
+		);
 	}
 }
@@ -100,6 +100,7 @@ impl BlockRc {
 					);
 					if cnt as u64 != old_rc.as_u64() {
 						warn!(
+							block = ?hash,
 							"Fixing inconsistent block RC for {:?}: was {}, should be {}",
 							hash,
 							old_rc.as_u64(),
@@ -120,7 +121,7 @@ impl BlockRc {
 				})
 				.map_err(Error::from);
 			if let Err(e) = &res {
-				error!("Failed to fix RC for block {:?}: {}", hash, e);
+				error!(block = ?hash, "Failed to fix RC for block {:?}: {}", hash, e);
 			}
 			res
 		} else {
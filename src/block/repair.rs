@@ -1,4 +1,5 @@
 use core::ops::Bound;
+use std::convert::TryFrom;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
@@ -9,12 +10,16 @@ use tokio::fs;
 use tokio::select;
 use tokio::sync::mpsc;
 use tokio::sync::watch;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 use garage_util::background::*;
+use garage_util::config::LiveConfig;
 use garage_util::data::*;
 use garage_util::error::*;
+use garage_util::formater::format_relative;
 use garage_util::persister::PersisterShared;
 use garage_util::time::*;
+use garage_util::token_bucket::DualTokenBucket;
 use garage_util::tranquilizer::Tranquilizer;
 
 use crate::block::*;
@@ -237,10 +242,16 @@ pub struct ScrubWorker {
 
 	work: ScrubWorkerState,
 	tranquilizer: Tranquilizer,
+	rate_limiter: DualTokenBucket,
+	live_config: watch::Receiver<LiveConfig>,
 
 	persister: PersisterShared<ScrubWorkerPersisted>,
 }
 
+fn scrub_rate_limiter(live_config: &LiveConfig) -> DualTokenBucket {
+	DualTokenBucket::new(live_config.scrub_max_rate as u64, live_config.scrub_max_ops)
+}
+
 fn randomize_next_scrub_run_time(timestamp: u64) -> u64 {
 	// Take SCRUB_INTERVAL and mix in a random interval of 10 days to attempt to
 	// balance scrub load across different cluster nodes.
@@ -302,15 +313,36 @@ impl ScrubWorker {
 				t_cp: now_msec(),
 			},
 		};
+		let mut live_config = manager.live_config.clone();
+		let rate_limiter = scrub_rate_limiter(&live_config.borrow_and_update());
 		Self {
 			manager,
 			rx_cmd,
 			work,
 			tranquilizer: Tranquilizer::new(30),
+			rate_limiter,
+			live_config,
 			persister,
 		}
 	}
 
+	/// Throttle the worker so that the rate at which blocks are read does not
+	/// exceed the maximum scrub rates set in the configuration, if any
+	fn limit_rate(&mut self, state: WorkerState, bytes_read: usize) -> WorkerState {
+		if self.live_config.has_changed().unwrap_or(false) {
+			self.rate_limiter = scrub_rate_limiter(&self.live_config.borrow_and_update());
+		}
+		let delay = self
+			.rate_limiter
+			.take_delay(bytes_read as u64)
+			.as_secs_f32();
+		match state {
+			WorkerState::Throttled(t) if t >= delay => state,
+			_ if delay > 0. => WorkerState::Throttled(delay),
+			_ => state,
+		}
+	}
+
 	async fn handle_cmd(&mut self, cmd: ScrubWorkerCommand) {
 		match cmd {
 			ScrubWorkerCommand::Start => {
@@ -415,19 +447,22 @@ impl Worker for ScrubWorker {
 			ScrubWorkerState::Paused { iterator, t_resume } => {
 				s.progress = Some(format!("{:.2}%", iterator.progress() * 100.));
 				s.freeform = vec![format!(
-					"Scrub paused, resumes at {}",
-					msec_to_rfc3339(*t_resume)
+					"Scrub paused, resumes at {} ({})",
+					msec_to_rfc3339(*t_resume),
+					format_relative(*t_resume)
 				)];
 			}
 			ScrubWorkerState::Finished => {
 				s.freeform = vec![
 					format!(
-						"Last scrub completed at {}",
+						"Last scrub completed at {} ({})",
 						msec_to_rfc3339(time_last_complete_scrub),
+						format_relative(time_last_complete_scrub),
 					),
 					format!(
-						"Next scrub scheduled for {}",
-						msec_to_rfc3339(time_next_run_scrub)
+						"Next scrub scheduled for {} ({})",
+						msec_to_rfc3339(time_next_run_scrub),
+						format_relative(time_next_run_scrub)
 					),
 				];
 			}
@@ -448,13 +483,14 @@ impl Worker for ScrubWorker {
 				let now = now_msec();
 
 				if let Some((_path, hash)) = iterator.next().await? {
-					match self.manager.read_block(&hash).await {
+					let bytes_read = match self.manager.read_block_uncached(&hash).await {
 						Err(Error::CorruptData(_)) => {
-							error!("Found corrupt data block during scrub: {:?}", hash);
+							error!(block = ?hash, "Found corrupt data block during scrub: {:?}", hash);
 							self.persister.set_with(|p| p.corruptions_detected += 1)?;
+							0
 						}
 						Err(e) => return Err(e),
-						_ => (),
+						Ok(block) => block.as_parts_ref().1.len(),
 					};
 
 					if now - *t_cp > 60 * 1000 {
@@ -463,9 +499,10 @@ impl Worker for ScrubWorker {
 						*t_cp = now;
 					}
 
-					Ok(self
+					let state = self
 						.tranquilizer
-						.tranquilize_worker(self.persister.get_with(|p| p.tranquility)))
+						.tranquilize_worker(self.persister.get_with(|p| p.tranquility));
+					Ok(self.limit_rate(state, bytes_read))
 				} else {
 					let next_scrub_timestamp = randomize_next_scrub_run_time(now);
 
@@ -762,3 +799,253 @@ impl BlockStoreIterator {
 		iter.zip(iter_1).all(|(prev, next)| prev >= next)
 	}
 }
+
+// ---- ---- ----
+// FOURTH KIND OF REPAIR: READ REPAIR
+// When read repair is enabled, each time a block is fetched from other nodes,
+// the nodes that did not return it (or that were not asked for it) are checked
+// in the background, and the block is sent to those that should have it.
+// The data that was read is kept for this, as long as it fits in the RAM
+// buffer (`block_ram_buffer_max`); otherwise the block is fetched again.
+// ---- ---- ----
+
+/// Size of the queue of blocks waiting for read repair. Read repair is best-effort:
+/// blocks read while the queue is full are not checked.
+const READ_REPAIR_QUEUE_LEN: usize = 1024;
+
+/// How a node replied when asked for a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReplicaReadOutcome {
+	/// The node returned the block
+	Ok,
+	/// The node replied, but with an error or an invalid response
+	Failed,
+	/// The node could not be contacted or did not reply in time
+	Unreachable,
+	/// The node was not asked for the block because another node returned it first
+	NotTried,
+}
+
+pub(crate) struct ReadRepairTask {
+	pub(crate) hash: Hash,
+	pub(crate) nodes: Vec<Uuid>,
+	/// The block as it was read, with the space it takes in the RAM buffer
+	pub(crate) block: Option<(DataBlock, OwnedSemaphorePermit)>,
+}
+
+pub(crate) fn read_repair_channel() -> (mpsc::Sender<ReadRepairTask>, mpsc::Receiver<ReadRepairTask>)
+{
+	mpsc::channel(READ_REPAIR_QUEUE_LEN)
+}
+
+/// Get the nodes that might not have a valid copy of a block, after it was read
+/// successfully from one of them. Unreachable nodes are not checked, as they will
+/// be repaired by the resync once they are back.
+pub(crate) fn read_repair_nodes(outcomes: &[(Uuid, ReplicaReadOutcome)]) -> Vec<Uuid> {
+	if !outcomes
+		.iter()
+		.any(|(_, outcome)| *outcome == ReplicaReadOutcome::Ok)
+	{
+		return vec![];
+	}
+
+	outcomes
+		.iter()
+		.filter(|(_, outcome)| {
+			matches!(
+				outcome,
+				ReplicaReadOutcome::Failed | ReplicaReadOutcome::NotTried
+			)
+		})
+		.map(|(node, _)| *node)
+		.collect()
+}
+
+/// Queue a check of `nodes` for block `hash`. The block that was read is queued
+/// with it if there is enough space left in the RAM buffer.
+/// Returns true if a check was queued.
+pub(crate) fn queue_read_repair(
+	tx: &mpsc::Sender<ReadRepairTask>,
+	buffer_kb_semaphore: &Arc<Semaphore>,
+	hash: &Hash,
+	nodes: Vec<Uuid>,
+	block: Option<DataBlock>,
+) -> bool {
+	let block = block.and_then(|block| {
+		let kb = u32::try_from(block.as_parts_ref().1.len() / 1024).ok()?;
+		let permit = buffer_kb_semaphore
+			.clone()
+			.try_acquire_many_owned(kb)
+			.ok()?;
+		Some((block, permit))
+	});
+
+	match tx.try_send(ReadRepairTask {
+		hash: *hash,
+		nodes,
+		block,
+	}) {
+		Ok(()) => true,
+		Err(_) => {
+			debug!("Read repair queue is full, not checking block {:?}", hash);
+			false
+		}
+	}
+}
+
+pub(crate) struct ReadRepairWorker {
+	manager: Arc<BlockManager>,
+	rx: mpsc::Receiver<ReadRepairTask>,
+	next: Option<ReadRepairTask>,
+	repaired: u64,
+}
+
+impl ReadRepairWorker {
+	pub(crate) fn new(manager: Arc<BlockManager>, rx: mpsc::Receiver<ReadRepairTask>) -> Self {
+		Self {
+			manager,
+			rx,
+			next: None,
+			repaired: 0,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for ReadRepairWorker {
+	fn name(&self) -> String {
+		"Block read repair".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			queue_length: Some(self.rx.len() as u64),
+			freeform: vec![format!("Blocks sent to other nodes: {}", self.repaired)],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let task = match self.next.take().or_else(|| self.rx.try_recv().ok()) {
+			Some(task) => task,
+			None => return Ok(WorkerState::Idle),
+		};
+		self.repaired += self.manager.repair_replicas(task).await? as u64;
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		match self.rx.recv().await {
+			Some(task) => {
+				self.next = Some(task);
+				WorkerState::Busy
+			}
+			None => WorkerState::Done,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bytes::Bytes;
+
+	use super::*;
+
+	#[test]
+	fn test_divergent_replica_is_scheduled_for_repair() {
+		let [n1, n2, n3, n4] = [1u8, 2, 3, 4].map(|i| Uuid::from([i; 32]));
+
+		// n1 returned an error, n2 did not reply, n3 returned the block,
+		// n4 was not asked for it: n1 and n4 are checked
+		let outcomes = [
+			(n1, ReplicaReadOutcome::Failed),
+			(n2, ReplicaReadOutcome::Unreachable),
+			(n3, ReplicaReadOutcome::Ok),
+			(n4, ReplicaReadOutcome::NotTried),
+		];
+		assert_eq!(read_repair_nodes(&outcomes), vec![n1, n4]);
+
+		// Nothing to check if all nodes returned the block, or were unreachable
+		let outcomes = [
+			(n1, ReplicaReadOutcome::Ok),
+			(n2, ReplicaReadOutcome::Unreachable),
+		];
+		assert!(read_repair_nodes(&outcomes).is_empty());
+
+		// Nor if no node returned the block: we have nothing to repair with
+		let outcomes = [
+			(n1, ReplicaReadOutcome::Failed),
+			(n2, ReplicaReadOutcome::Failed),
+		];
+		assert!(read_repair_nodes(&outcomes).is_empty());
+	}
+
+	#[test]
+	fn test_read_repair_queue_full() {
+		let (tx, _rx) = read_repair_channel();
+		let semaphore = Arc::new(Semaphore::new(1024));
+		let nodes = vec![Uuid::from([1u8; 32])];
+		for i in 0..READ_REPAIR_QUEUE_LEN as u64 {
+			assert!(queue_read_repair(
+				&tx,
+				&semaphore,
+				&blake2sum(&i.to_le_bytes()),
+				nodes.clone(),
+				None
+			));
+		}
+		assert!(!queue_read_repair(
+			&tx,
+			&semaphore,
+			&blake2sum(b"one more"),
+			nodes,
+			None
+		));
+	}
+
+	#[test]
+	fn test_read_repair_keeps_read_data() {
+		let (tx, mut rx) = read_repair_channel();
+		let semaphore = Arc::new(Semaphore::new(16));
+		let data = Bytes::from([vec![1u8; 2048], vec![2u8; 1024]].concat());
+
+		// The block that was read is queued with the read repair task
+		assert!(queue_read_repair(
+			&tx,
+			&semaphore,
+			&blake2sum(b"block"),
+			vec![Uuid::from([1u8; 32])],
+			Some(DataBlock::plain(data.clone())),
+		));
+		let task = rx.try_recv().unwrap();
+		assert_eq!(task.hash, blake2sum(b"block"));
+		assert_eq!(task.nodes, vec![Uuid::from([1u8; 32])]);
+		let (block, permit) = task.block.unwrap();
+		let (header, block_data) = block.into_parts();
+		assert!(!header.is_compressed());
+		assert_eq!(block_data, data);
+
+		// The data takes space in the RAM buffer until the task is done
+		assert_eq!(semaphore.available_permits(), 13);
+		drop(permit);
+		assert_eq!(semaphore.available_permits(), 16);
+	}
+
+	#[test]
+	fn test_read_repair_without_room_in_buffer() {
+		// There is no room for the data in the RAM buffer: the task is queued
+		// without it, and the block will be read again by the worker
+		let (tx, mut rx) = read_repair_channel();
+		let semaphore = Arc::new(Semaphore::new(2));
+		assert!(queue_read_repair(
+			&tx,
+			&semaphore,
+			&blake2sum(b"block"),
+			vec![Uuid::from([1u8; 32])],
+			Some(DataBlock::plain(Bytes::from(vec![1u8; 4096]))),
+		));
+		let task = rx.try_recv().unwrap();
+		assert!(task.block.is_none());
+		assert_eq!(semaphore.available_permits(), 2);
+	}
+}
@@ -1,7 +1,9 @@
 use std::collections::HashSet;
 use std::convert::TryInto;
+use std::future::Future;
+use std::ops::Bound;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -17,11 +19,14 @@ use opentelemetry::{
 use garage_db as db;
 
 use garage_util::background::*;
+use garage_util::config::{Config, LiveConfig, MAX_BLOCK_RESYNC_WORKERS};
 use garage_util::data::*;
 use garage_util::error::*;
+use garage_util::formater::{format_duration, format_relative};
 use garage_util::metrics::RecordDuration;
 use garage_util::persister::PersisterShared;
 use garage_util::time::*;
+use garage_util::token_bucket::DualTokenBucket;
 use garage_util::tranquilizer::Tranquilizer;
 
 use garage_rpc::system::System;
@@ -30,21 +35,29 @@ use garage_rpc::*;
 use garage_table::replication::TableReplication;
 
 use crate::manager::*;
+use crate::metrics::{rpc_attributes, RpcOutcome};
 
-// The delay between the time where a resync operation fails
+// The default delay between the time where a resync operation fails
 // and the time when it is retried, with exponential backoff
 // (multiplied by 2, 4, 8, 16, etc. for every consecutive failure).
+// Can be changed with the `block_resync_retry_delay_secs` config option.
 pub(crate) const RESYNC_RETRY_DELAY: Duration = Duration::from_secs(60);
 // The minimum retry delay is 60 seconds = 1 minute
 // The maximum retry delay is 60 seconds * 2^6 = 60 seconds << 6 = 64 minutes (~1 hour)
 pub(crate) const RESYNC_RETRY_DELAY_MAX_BACKOFF_POWER: u64 = 6;
 
-// No more than 4 resync workers can be running in the system
-pub(crate) const MAX_RESYNC_WORKERS: usize = 8;
+// No more than 8 resync workers can be running in the system
+pub(crate) const MAX_RESYNC_WORKERS: usize = MAX_BLOCK_RESYNC_WORKERS;
 // Resync tranquility is initially set to 2, but can be changed in the CLI
 // and the updated version is persisted over Garage restarts
 const INITIAL_RESYNC_TRANQUILITY: u32 = 2;
 
+// When `block_resync_target_load_percent` is set, resync workers work twice as much
+// when the node is idle and the resync queue has more blocks than this
+const RESYNC_DEEP_QUEUE: usize = 10_000;
+// How often resync workers check the length of the resync queue to adapt their pace
+const RESYNC_QUEUE_LEN_REFRESH: Duration = Duration::from_secs(10);
+
 pub struct BlockResyncManager {
 	pub(crate) queue: db::Tree,
 	pub(crate) notify: Arc<Notify>,
@@ -53,6 +66,21 @@ pub struct BlockResyncManager {
 	busy_set: BusySet,
 
 	persister: PersisterShared<ResyncPersistedConfig>,
+
+	/// Configuration options that can be changed at runtime,
+	/// including the delay before retrying a failed resync
+	live_config: watch::Receiver<LiveConfig>,
+
+	/// Clock used to schedule resyncs
+	clock: Arc<dyn Clock>,
+
+	/// Blocks waiting to be put in the queue together, with the time
+	/// at which they should be resynced
+	pending: Mutex<Vec<(Hash, u64)>>,
+
+	/// Rate limiter shared by all resync workers, with the rates
+	/// it was built for
+	rate_limiter: Mutex<((u64, u64), DualTokenBucket)>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
@@ -71,7 +99,8 @@ impl Default for ResyncPersistedConfig {
 }
 
 enum ResyncIterResult {
-	BusyDidSomething,
+	/// A block was resynced, reading or writing this number of bytes
+	BusyDidSomething(usize),
 	BusyDidNothing,
 	IdleFor(Duration),
 }
@@ -85,7 +114,13 @@ struct BusyBlock {
 }
 
 impl BlockResyncManager {
-	pub(crate) fn new(db: &db::Db, system: &System) -> Self {
+	pub(crate) fn new(
+		db: &db::Db,
+		system: &System,
+		config: &Config,
+		live_config: watch::Receiver<LiveConfig>,
+		clock: Arc<dyn Clock>,
+	) -> Result<Self, Error> {
 		let queue = db
 			.open_tree("block_local_resync_queue")
 			.expect("Unable to open block_local_resync_queue tree");
@@ -94,22 +129,143 @@ impl BlockResyncManager {
 			.open_tree("block_local_resync_errors")
 			.expect("Unable to open block_local_resync_errors tree");
 
-		let persister = PersisterShared::new(&system.metadata_dir, "resync_cfg");
+		let persister: PersisterShared<ResyncPersistedConfig> =
+			PersisterShared::new(&system.metadata_dir, "resync_cfg");
 
-		Self {
+		// The number of workers set in the config file overrides
+		// the one that was set at runtime and persisted
+		if let Some(n_workers) = config.block_resync_workers {
+			if !(1..=MAX_RESYNC_WORKERS).contains(&n_workers) {
+				return Err(Error::Message(format!(
+					"Invalid block_resync_workers, must be between 1 and {}",
+					MAX_RESYNC_WORKERS
+				)));
+			}
+			persister.set_with(|x| x.n_workers = n_workers)?;
+		}
+
+		Ok(Self {
 			queue,
 			notify: Arc::new(Notify::new()),
 			errors,
 			busy_set: Arc::new(Mutex::new(HashSet::new())),
 			persister,
+			live_config,
+			clock,
+			pending: Mutex::new(vec![]),
+			rate_limiter: Mutex::new(((0, 0), DualTokenBucket::new(0, 0))),
+		})
+	}
+
+	/// Percentage of time resync workers should spend resyncing, if set in the configuration
+	fn target_load_percent(&self) -> Option<u32> {
+		self.live_config.borrow().block_resync_target_load_percent
+	}
+
+	/// Duration that each resync step should last, if set in the configuration
+	fn step_duration(&self) -> Option<Duration> {
+		self.live_config
+			.borrow()
+			.block_resync_step_duration_msec
+			.map(Duration::from_millis)
+	}
+
+	/// Throttle a resync worker that has just read or written `bytes` bytes,
+	/// so that all workers together do not exceed the maximum resync rates set
+	/// in the configuration, if any
+	fn limit_rate(&self, state: WorkerState, bytes: usize) -> WorkerState {
+		let rates = {
+			let live_config = self.live_config.borrow();
+			(
+				live_config.block_resync_max_rate as u64,
+				live_config.block_resync_max_ops,
+			)
+		};
+		let delay = {
+			let mut rate_limiter = self.rate_limiter.lock().unwrap();
+			if rate_limiter.0 != rates {
+				*rate_limiter = (rates, DualTokenBucket::new(rates.0, rates.1));
+			}
+			rate_limiter.1.take_delay(bytes as u64).as_secs_f32()
+		};
+		match state {
+			WorkerState::Throttled(t) if t >= delay => state,
+			_ if delay > 0. => WorkerState::Throttled(delay),
+			_ => state,
 		}
 	}
 
+	/// Delay before retrying a failed resync, before exponential backoff
+	pub(crate) fn retry_delay(&self) -> Duration {
+		self.live_config
+			.borrow()
+			.block_resync_retry_delay_secs
+			.map(Duration::from_secs)
+			.unwrap_or(RESYNC_RETRY_DELAY)
+	}
+
 	/// Get length of resync queue
 	pub fn queue_len(&self) -> Result<usize, Error> {
 		Ok(self.queue.len()?)
 	}
 
+	/// List the first `limit` entries of the resync queue, as the time
+	/// at which each block is due to be resynced and the hash of the block
+	pub fn list_queue(&self, limit: usize) -> Result<Vec<(u64, Hash)>, Error> {
+		self.list_queue_after(None, limit)
+	}
+
+	/// List the first `limit` entries of the resync queue that come after
+	/// entry `after`, or from the start of the queue if it is `None`
+	pub fn list_queue_after(
+		&self,
+		after: Option<(u64, Hash)>,
+		limit: usize,
+	) -> Result<Vec<(u64, Hash)>, Error> {
+		let start = match after {
+			Some((when, hash)) => Bound::Excluded(resync_queue_key(&hash, when)),
+			None => Bound::Unbounded,
+		};
+		let mut entries = vec![];
+		for it in self.queue.range((start, Bound::Unbounded))?.take(limit) {
+			let (key, _) = it?;
+			let entry = decode_resync_queue_key(&key).ok_or_else(|| {
+				Error::Message(format!("Invalid resync queue key: {}", hex::encode(&key)))
+			})?;
+			entries.push(entry);
+		}
+		Ok(entries)
+	}
+
+	/// Remove the entries of the queue whose block `can_prune` returns true for,
+	/// reading the queue in batches of `batch_size` entries.
+	/// Returns the number of entries removed.
+	pub(crate) async fn prune_queue<F, Fut>(
+		&self,
+		batch_size: usize,
+		can_prune: F,
+	) -> Result<usize, Error>
+	where
+		F: Fn(Hash) -> Fut,
+		Fut: Future<Output = Result<bool, Error>>,
+	{
+		let mut pruned = 0;
+		let mut after = None;
+		loop {
+			let batch = self.list_queue_after(after, batch_size)?;
+			for &(when, hash) in batch.iter() {
+				if can_prune(hash).await? {
+					self.queue.remove(resync_queue_key(&hash, when))?;
+					pruned += 1;
+				}
+			}
+			if batch.len() < batch_size {
+				return Ok(pruned);
+			}
+			after = batch.last().copied();
+		}
+	}
+
 	/// Get number of blocks that have an error
 	pub fn errors_len(&self) -> Result<usize, Error> {
 		Ok(self.errors.len()?)
@@ -117,11 +273,11 @@ impl BlockResyncManager {
 
 	/// Clear the error counter for a block and put it in queue immediately
 	pub fn clear_backoff(&self, hash: &Hash) -> Result<(), Error> {
-		let now = now_msec();
+		let now = self.clock.now_msec();
 		if let Some(ec) = self.errors.get(hash)? {
 			let mut ec = ErrorCounter::decode(&ec);
 			if ec.errors > 0 {
-				ec.last_try = now - ec.delay_msec();
+				ec.last_try = now - ec.delay_msec(self.retry_delay());
 				self.errors.insert(hash, ec.encode())?;
 				self.put_to_resync_at(hash, now)?;
 				return Ok(());
@@ -239,34 +395,68 @@ impl BlockResyncManager {
 	// is a natural condition that is handled properly).
 
 	pub(crate) fn put_to_resync(&self, hash: &Hash, delay: Duration) -> db::Result<()> {
-		let when = now_msec() + delay.as_millis() as u64;
+		let when = self.clock.now_msec() + delay.as_millis() as u64;
 		self.put_to_resync_at(hash, when)
 	}
 
 	pub(crate) fn put_to_resync_at(&self, hash: &Hash, when: u64) -> db::Result<()> {
 		trace!("Put resync_queue: {} {:?}", when, hash);
-		let mut key = u64::to_be_bytes(when).to_vec();
-		key.extend(hash.as_ref());
-		self.queue.insert(key, hash.as_ref())?;
+		self.queue
+			.insert(resync_queue_key(hash, when), hash.as_ref())?;
+		self.notify.notify_waiters();
+		Ok(())
+	}
+
+	/// Put several blocks in the resync queue in a single transaction,
+	/// each one with the time at which it should be resynced.
+	pub(crate) fn put_many_to_resync(&self, entries: &[(Hash, u64)]) -> Result<(), Error> {
+		if entries.is_empty() {
+			return Ok(());
+		}
+		self.queue.db().transaction(|tx| {
+			for (hash, when) in entries.iter() {
+				trace!("Put resync_queue: {} {:?}", when, hash);
+				tx.insert(&self.queue, resync_queue_key(hash, *when), hash.as_ref())?;
+			}
+			Ok::<_, db::TxError<Error>>(())
+		})?;
 		self.notify.notify_waiters();
 		Ok(())
 	}
 
+	/// Add a block to the batch of blocks that will be put in the resync queue
+	/// by the next call to `flush_pending`. Returns true if the batch was empty,
+	/// in which case the caller is responsible for calling `flush_pending`.
+	pub(crate) fn add_pending(&self, hash: &Hash, delay: Duration) -> bool {
+		let when = self.clock.now_msec() + delay.as_millis() as u64;
+		let mut pending = self.pending.lock().unwrap();
+		pending.push((*hash, when));
+		pending.len() == 1
+	}
+
+	/// Put all blocks of the pending batch in the resync queue.
+	/// Returns the number of blocks that were queued.
+	pub(crate) fn flush_pending(&self) -> Result<usize, Error> {
+		let batch = std::mem::take(&mut *self.pending.lock().unwrap());
+		self.put_many_to_resync(&batch)?;
+		Ok(batch.len())
+	}
+
 	async fn resync_iter(&self, manager: &BlockManager) -> Result<ResyncIterResult, db::Error> {
 		if let Some(block) = self.get_block_to_resync()? {
 			let time_msec = u64::from_be_bytes(block.time_bytes[0..8].try_into().unwrap());
-			let now = now_msec();
+			let now = self.clock.now_msec();
 
 			if now >= time_msec {
 				let hash = Hash::try_from(&block.hash_bytes[..]).unwrap();
 
 				if let Some(ec) = self.errors.get(hash.as_slice())? {
 					let ec = ErrorCounter::decode(&ec);
-					if now < ec.next_try() {
+					if now < ec.next_try(self.retry_delay()) {
 						// if next retry after an error is not yet,
 						// don't do resync and return early, but still
 						// make sure the item is still in queue at expected time
-						self.put_to_resync_at(&hash, ec.next_try())?;
+						self.put_to_resync_at(&hash, ec.next_try(self.retry_delay()))?;
 						// ec.next_try() > now >= time_msec, so this remove
 						// is not removing the one we added just above
 						// (we want to do the remove after the insert to ensure
@@ -297,18 +487,43 @@ impl BlockResyncManager {
 
 				manager.metrics.resync_counter.add(1);
 
+				let bytes = *res.as_ref().unwrap_or(&0);
 				if let Err(e) = &res {
 					manager.metrics.resync_error_counter.add(1);
-					error!("Error when resyncing {:?}: {}", hash, e);
+					error!(block = ?hash, "Error when resyncing {:?}: {}", hash, e);
+
+					if e.is_block_too_large() {
+						// Retrying cannot succeed until max_block_size is changed
+						// on the nodes concerned. The block will be resynced again
+						// when it is queued again, e.g. by a scrub or a repair.
+						warn!(
+							block = ?hash,
+							"Not retrying resync of {:?}, block exceeds max_block_size",
+							hash
+						);
+						self.errors.remove(hash.as_slice())?;
+						self.queue.remove(&block.time_bytes)?;
+						return Ok(ResyncIterResult::BusyDidSomething(0));
+					}
 
-					let err_counter = match self.errors.get(hash.as_slice())? {
+					let mut err_counter = match self.errors.get(hash.as_slice())? {
 						Some(ec) => ErrorCounter::decode(&ec).add1(now + 1),
 						None => ErrorCounter::new(now + 1),
 					};
+					if !e.is_retryable() {
+						// Retrying soon is very unlikely to help
+						err_counter = err_counter.max_backoff();
+					}
 
 					self.errors.insert(hash.as_slice(), err_counter.encode())?;
 
-					self.put_to_resync_at(&hash, err_counter.next_try())?;
+					let next_try = err_counter.next_try(self.retry_delay());
+					debug!(
+						"Next resync of {:?} scheduled {}",
+						hash,
+						format_relative(next_try)
+					);
+					self.put_to_resync_at(&hash, next_try)?;
 					// err_counter.next_try() >= now + 1 > now,
 					// the entry we remove from the queue is not
 					// the entry we inserted with put_to_resync_at
@@ -318,7 +533,7 @@ impl BlockResyncManager {
 					self.queue.remove(&block.time_bytes)?;
 				}
 
-				Ok(ResyncIterResult::BusyDidSomething)
+				Ok(ResyncIterResult::BusyDidSomething(bytes))
 			} else {
 				Ok(ResyncIterResult::IdleFor(Duration::from_millis(
 					time_msec - now,
@@ -351,10 +566,13 @@ impl BlockResyncManager {
 		Ok(None)
 	}
 
-	async fn resync_block(&self, manager: &BlockManager, hash: &Hash) -> Result<(), Error> {
+	/// Resync a block, and return the number of bytes of block data that were
+	/// read from or written to the disk
+	async fn resync_block(&self, manager: &BlockManager, hash: &Hash) -> Result<usize, Error> {
 		let existing_path = manager.find_block(hash).await;
 		let exists = existing_path.is_some();
 		let rc = manager.rc.get_block_rc(hash)?;
+		let mut bytes = 0;
 
 		if exists != rc.is_needed() || exists != rc.is_nonzero() {
 			debug!(
@@ -374,7 +592,7 @@ impl BlockResyncManager {
 				)));
 			}
 
-			info!("Resync block {:?}: offloading and deleting", hash);
+			info!(block = ?hash, "Resync block {:?}: offloading and deleting", hash);
 			let existing_path = existing_path.unwrap();
 
 			let mut who = manager
@@ -393,13 +611,24 @@ impl BlockResyncManager {
 					&manager.endpoint,
 					&who,
 					BlockRpc::NeedBlockQuery(*hash),
-					RequestStrategy::with_priority(PRIO_BACKGROUND),
+					RequestStrategy::with_priority(PRIO_BACKGROUND)
+						.with_custom_timeout(manager.rpc_timeouts.need_block_query),
+				)
+				.record_duration(
+					&manager.metrics.rpc_duration,
+					&rpc_attributes("need_block_query"),
 				)
 				.await?;
+			for (_, resp) in who_needs_resps.iter() {
+				manager
+					.metrics
+					.record_rpc("need_block_query", RpcOutcome::of(resp));
+			}
 
 			let mut need_nodes = vec![];
 			for (node, needed) in who_needs_resps {
-				match needed.err_context("NeedBlockQuery RPC")? {
+				// Not wrapped in an error context, so that the error stays retryable
+				match needed? {
 					BlockRpc::NeedBlockReply(needed) => {
 						if needed {
 							need_nodes.push(node);
@@ -426,26 +655,33 @@ impl BlockResyncManager {
 				}
 
 				let block = manager.read_block_from(hash, &existing_path).await?;
+				bytes += block.as_parts_ref().1.len();
 				let (header, bytes) = block.into_parts();
 				let put_block_message = Req::new(BlockRpc::PutBlock {
 					hash: *hash,
 					header,
 				})?
 				.with_stream_from_buffer(bytes);
-				manager
+				let (res, ack) = manager
 					.system
 					.rpc_helper()
-					.try_call_many(
+					.try_write_many_sets_with_ack(
 						&manager.endpoint,
-						&need_nodes,
+						&[need_nodes.clone()],
 						put_block_message,
 						RequestStrategy::with_priority(PRIO_BACKGROUND | PRIO_SECONDARY)
-							.with_quorum(need_nodes.len()),
+							.with_quorum(need_nodes.len())
+							.with_custom_timeout(manager.rpc_timeouts.block),
 					)
-					.await
-					.err_context("PutBlock RPC")?;
+					.record_duration(&manager.metrics.rpc_duration, &rpc_attributes("put_block"))
+					.await;
+				manager.metrics.record_write_ack("put_block", &ack);
+				// Not wrapped in an error context, so that resync_iter can tell
+				// that the block was rejected by the remote nodes for its size
+				res?;
 			}
 			info!(
+				block = ?hash,
 				"Deleting unneeded block {:?}, offload finished ({} / {})",
 				hash,
 				need_nodes.len(),
@@ -470,23 +706,26 @@ impl BlockResyncManager {
 
 			if !storage_nodes.contains(&manager.system.id) {
 				info!(
+					block = ?hash,
 					"Resync block {:?}: block is absent with refcount > 0, but it will drop to zero after all metadata is synced. Not fetching the block.",
 					hash
 				);
-				return Ok(());
+				return Ok(bytes);
 			}
 
 			// We know we need the block. Fetch it.
 			info!(
+				block = ?hash,
 				"Resync block {:?}: fetching absent but needed block (refcount > 0)",
 				hash
 			);
 
 			let block_data = manager
-				.rpc_get_raw_block(hash, PRIO_BACKGROUND | PRIO_SECONDARY, None)
+				.rpc_get_raw_block(hash, PRIO_BACKGROUND | PRIO_SECONDARY, None, None)
 				.await;
 			if matches!(block_data, Err(Error::MissingBlock(_))) {
 				warn!(
+					block = ?hash,
 					"Could not fetch needed block {:?}, no node returned valid data. Checking that refcount is correct.",
 					hash
 				);
@@ -497,10 +736,28 @@ impl BlockResyncManager {
 			manager.metrics.resync_recv_counter.add(1);
 
 			manager.write_block(hash, &block_data).await?;
+			bytes += block_data.as_parts_ref().1.len();
 		}
 
-		Ok(())
+		Ok(bytes)
+	}
+}
+
+/// Key of a block in the resync queue: the time at which it should
+/// be resynced, followed by its hash, so that blocks are ordered by time
+pub(crate) fn resync_queue_key(hash: &Hash, when: u64) -> Vec<u8> {
+	let mut key = u64::to_be_bytes(when).to_vec();
+	key.extend(hash.as_ref());
+	key
+}
+
+fn decode_resync_queue_key(key: &[u8]) -> Option<(u64, Hash)> {
+	if key.len() != 8 + 32 {
+		return None;
 	}
+	let when = u64::from_be_bytes(key[0..8].try_into().unwrap());
+	let hash = Hash::try_from(&key[8..])?;
+	Some((when, hash))
 }
 
 impl Drop for BusyBlock {
@@ -516,6 +773,11 @@ pub(crate) struct ResyncWorker {
 	tranquilizer: Tranquilizer,
 	next_delay: Duration,
 	persister: PersisterShared<ResyncPersistedConfig>,
+	/// Value of the foreground operations counter of the block manager
+	/// at the end of the previous step
+	last_foreground_ops: u64,
+	/// Length of the resync queue, and when it was last checked
+	queue_len: Option<(Instant, usize)>,
 }
 
 impl ResyncWorker {
@@ -527,8 +789,54 @@ impl ResyncWorker {
 			tranquilizer: Tranquilizer::new(30),
 			next_delay: Duration::from_secs(10),
 			persister,
+			last_foreground_ops: 0,
+			queue_len: None,
 		}
 	}
+
+	/// Pace the worker after a resync step that did something, either with the
+	/// tranquility, by targeting a fraction of time spent resyncing, or by
+	/// targeting a duration for each step
+	fn tranquilize(&mut self, tranquility: u32) -> WorkerState {
+		// When set, the target step duration takes precedence in the tranquilizer
+		self.tranquilizer
+			.set_target_duration(self.manager.resync.step_duration());
+
+		let foreground_ops = self.manager.foreground_ops();
+		let foreground_busy = foreground_ops != self.last_foreground_ops;
+		self.last_foreground_ops = foreground_ops;
+
+		let target = match self.manager.resync.target_load_percent() {
+			Some(pct) => pct,
+			None => return self.tranquilizer.tranquilize_worker(tranquility),
+		};
+
+		let queue_len = match self.queue_len {
+			Some((checked, len)) if checked.elapsed() < RESYNC_QUEUE_LEN_REFRESH => len,
+			_ => {
+				let len = self.manager.resync.queue_len().unwrap_or(0);
+				self.queue_len = Some((Instant::now(), len));
+				len
+			}
+		};
+
+		let load = adaptive_resync_load(target, queue_len, foreground_busy);
+		self.tranquilizer.tranquilize_worker_load(load)
+	}
+}
+
+/// Fraction of time a resync worker should spend resyncing, given the target
+/// percentage, the length of the resync queue, and whether clients of the node
+/// have read or written blocks since the previous resync step
+fn adaptive_resync_load(target_percent: u32, queue_len: usize, foreground_busy: bool) -> f64 {
+	let target = target_percent.clamp(1, 100) as f64 / 100.;
+	if foreground_busy {
+		target / 2.
+	} else if queue_len > RESYNC_DEEP_QUEUE {
+		f64::min(target * 2., 1.)
+	} else {
+		target
+	}
 }
 
 #[async_trait]
@@ -547,10 +855,23 @@ impl Worker for ResyncWorker {
 			};
 		}
 
+		let (tranquility, freeform) = match (
+			self.manager.resync.step_duration(),
+			self.manager.resync.target_load_percent(),
+		) {
+			(Some(step), _) => (
+				None,
+				vec![format!("Target step duration: {}", format_duration(step))],
+			),
+			(None, Some(pct)) => (None, vec![format!("Target load: {}%", pct)]),
+			(None, None) => (Some(tranquility), vec![]),
+		};
+
 		WorkerStatus {
 			queue_length: Some(self.manager.resync.queue_len().unwrap_or(0) as u64),
-			tranquility: Some(tranquility),
+			tranquility,
 			persistent_errors: Some(self.manager.resync.errors_len().unwrap_or(0) as u64),
+			freeform,
 			..Default::default()
 		}
 	}
@@ -564,8 +885,9 @@ impl Worker for ResyncWorker {
 
 		self.tranquilizer.reset();
 		match self.manager.resync.resync_iter(&self.manager).await {
-			Ok(ResyncIterResult::BusyDidSomething) => {
-				Ok(self.tranquilizer.tranquilize_worker(tranquility))
+			Ok(ResyncIterResult::BusyDidSomething(bytes)) => {
+				let state = self.tranquilize(tranquility);
+				Ok(self.manager.resync.limit_rate(state, bytes))
 			}
 			Ok(ResyncIterResult::BusyDidNothing) => Ok(WorkerState::Busy),
 			Ok(ResyncIterResult::IdleFor(delay)) => {
@@ -637,12 +959,350 @@ impl ErrorCounter {
 		}
 	}
 
-	fn delay_msec(&self) -> u64 {
-		(RESYNC_RETRY_DELAY.as_millis() as u64)
+	/// Counter with enough errors that the next try is after the maximum delay
+	fn max_backoff(self) -> Self {
+		Self {
+			errors: std::cmp::max(self.errors, RESYNC_RETRY_DELAY_MAX_BACKOFF_POWER + 1),
+			last_try: self.last_try,
+		}
+	}
+
+	fn delay_msec(&self, retry_delay: Duration) -> u64 {
+		(retry_delay.as_millis() as u64)
 			<< std::cmp::min(self.errors - 1, RESYNC_RETRY_DELAY_MAX_BACKOFF_POWER)
 	}
 
-	pub(crate) fn next_try(&self) -> u64 {
-		self.last_try + self.delay_msec()
+	pub(crate) fn next_try(&self, retry_delay: Duration) -> u64 {
+		self.last_try + self.delay_msec(retry_delay)
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+
+	pub(crate) fn test_resync_manager(dir: &mktemp::Temp, clock: MockClock) -> BlockResyncManager {
+		let db = db::open_db(
+			&dir.as_path().join("db"),
+			db::Engine::Lmdb,
+			&db::OpenOpt {
+				fsync: false,
+				lmdb_map_size: Some(1 << 24),
+			},
+		)
+		.unwrap();
+		let (_, live_config) = watch::channel(LiveConfig {
+			scrub_max_rate: 0,
+			scrub_max_ops: 0,
+			block_resync_retry_delay_secs: None,
+			block_resync_target_load_percent: None,
+			block_resync_step_duration_msec: None,
+			block_resync_max_rate: 0,
+			block_resync_max_ops: 0,
+			log_level: None,
+		});
+		BlockResyncManager {
+			queue: db.open_tree("queue").unwrap(),
+			notify: Arc::new(Notify::new()),
+			errors: db.open_tree("errors").unwrap(),
+			busy_set: Arc::new(Mutex::new(HashSet::new())),
+			persister: PersisterShared::new(dir.as_path(), "resync_cfg"),
+			live_config,
+			clock: Arc::new(clock),
+			pending: Mutex::new(vec![]),
+			rate_limiter: Mutex::new(((0, 0), DualTokenBucket::new(0, 0))),
+		}
+	}
+
+	#[test]
+	fn test_adaptive_resync_load() {
+		assert_eq!(adaptive_resync_load(20, 0, false), 0.2);
+		// Back off when clients are reading or writing blocks
+		assert_eq!(adaptive_resync_load(20, 0, true), 0.1);
+		assert_eq!(adaptive_resync_load(20, 1_000_000, true), 0.1);
+		// Work harder when idle with a deep queue
+		assert_eq!(adaptive_resync_load(20, RESYNC_DEEP_QUEUE, false), 0.2);
+		assert_eq!(adaptive_resync_load(20, RESYNC_DEEP_QUEUE + 1, false), 0.4);
+		assert_eq!(adaptive_resync_load(80, 1_000_000, false), 1.);
+		// Invalid values are clamped
+		assert_eq!(adaptive_resync_load(0, 0, false), 0.01);
+		assert_eq!(adaptive_resync_load(500, 0, false), 1.);
+	}
+
+	#[test]
+	fn test_resync_queue_order() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let clock = MockClock::new(1_000_000);
+		let resync = test_resync_manager(&dir, clock.clone());
+		let h1 = Hash::from([1u8; 32]);
+		let h2 = Hash::from([2u8; 32]);
+		let h3 = Hash::from([3u8; 32]);
+
+		// Blocks are queued at the current time of the clock plus their delay,
+		// and popped by ascending time, whatever the order of their hashes
+		resync.put_to_resync(&h3, Duration::from_secs(10)).unwrap();
+		clock.advance(Duration::from_secs(5));
+		resync.put_to_resync(&h2, Duration::ZERO).unwrap();
+		resync.put_to_resync(&h1, Duration::from_secs(30)).unwrap();
+
+		// Blocks being processed are kept busy until they are dropped
+		let mut busy = vec![];
+		while let Some(block) = resync.get_block_to_resync().unwrap() {
+			busy.push(block);
+		}
+		let queued = busy
+			.iter()
+			.map(|b| {
+				let time = u64::from_be_bytes(b.time_bytes[0..8].try_into().unwrap());
+				(time, Hash::try_from(&b.hash_bytes[..]).unwrap())
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			queued,
+			vec![(1_005_000, h2), (1_010_000, h3), (1_035_000, h1)]
+		);
+	}
+
+	#[test]
+	fn test_list_resync_queue() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let clock = MockClock::new(1_000_000);
+		let resync = test_resync_manager(&dir, clock.clone());
+		let h1 = Hash::from([1u8; 32]);
+		let h2 = Hash::from([2u8; 32]);
+
+		resync.put_to_resync(&h1, Duration::from_secs(10)).unwrap();
+		resync.put_to_resync(&h2, Duration::ZERO).unwrap();
+		clock.advance(Duration::from_secs(20));
+		resync.put_to_resync(&h2, Duration::ZERO).unwrap();
+
+		assert_eq!(
+			resync.list_queue(usize::MAX).unwrap(),
+			vec![(1_000_000, h2), (1_010_000, h1), (1_020_000, h2)]
+		);
+		assert_eq!(
+			resync.list_queue(2).unwrap(),
+			vec![(1_000_000, h2), (1_010_000, h1)]
+		);
+		assert_eq!(
+			resync
+				.list_queue_after(Some((1_000_000, h2)), usize::MAX)
+				.unwrap(),
+			vec![(1_010_000, h1), (1_020_000, h2)]
+		);
+		assert_eq!(
+			resync.list_queue_after(Some((1_010_000, h1)), 1).unwrap(),
+			vec![(1_020_000, h2)]
+		);
+		assert_eq!(
+			resync
+				.list_queue_after(Some((1_020_000, h2)), usize::MAX)
+				.unwrap(),
+			vec![]
+		);
+
+		assert_eq!(
+			decode_resync_queue_key(&resync_queue_key(&h1, 42)),
+			Some((42, h1))
+		);
+		assert_eq!(decode_resync_queue_key(&[0u8; 39]), None);
+	}
+
+	#[tokio::test]
+	async fn test_prune_resync_queue() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let clock = MockClock::new(1_000_000);
+		let resync = test_resync_manager(&dir, clock.clone());
+
+		// Ten entries, read in pages of three, the blocks with an even
+		// first byte being pruned, including the last entries of some pages
+		let hashes = (0..10u8).map(|i| Hash::from([i; 32])).collect::<Vec<_>>();
+		for h in hashes.iter() {
+			resync.put_to_resync(h, Duration::ZERO).unwrap();
+			clock.advance(Duration::from_secs(1));
+		}
+
+		let checked = Mutex::new(vec![]);
+		let pruned = resync
+			.prune_queue(3, |hash| {
+				checked.lock().unwrap().push(hash);
+				async move { Ok(hash.as_slice()[0] % 2 == 0) }
+			})
+			.await
+			.unwrap();
+		assert_eq!(pruned, 5);
+
+		// Each entry is checked once, in the order of the queue
+		assert_eq!(checked.into_inner().unwrap(), hashes);
+		assert_eq!(
+			resync.list_queue(usize::MAX).unwrap(),
+			(1..10u8)
+				.step_by(2)
+				.map(|i| (1_000_000 + i as u64 * 1000, Hash::from([i; 32])))
+				.collect::<Vec<_>>()
+		);
+
+		// Errors stop the pruning
+		let res = resync
+			.prune_queue(3, |_| async { Err(Error::Message("oops".into())) })
+			.await;
+		assert!(res.is_err());
+		assert_eq!(resync.queue_len().unwrap(), 5);
+	}
+
+	#[test]
+	fn test_put_many_to_resync() {
+		let dir1 = mktemp::Temp::new_dir().unwrap();
+		let dir2 = mktemp::Temp::new_dir().unwrap();
+		let clock = MockClock::new(1_000_000);
+		let batched = test_resync_manager(&dir1, clock.clone());
+		let single = test_resync_manager(&dir2, clock);
+
+		let entries = (0..100u64)
+			.map(|i| (blake2sum(&i.to_le_bytes()), 1_000_000 + (i % 7) * 1000))
+			.collect::<Vec<_>>();
+
+		batched.put_many_to_resync(&entries).unwrap();
+		for (hash, when) in entries.iter() {
+			single.put_to_resync_at(hash, *when).unwrap();
+		}
+
+		let dump = |resync: &BlockResyncManager| {
+			resync
+				.queue
+				.iter()
+				.unwrap()
+				.map(|x| x.unwrap())
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(dump(&batched).len(), 100);
+		assert_eq!(dump(&batched), dump(&single));
+	}
+
+	#[test]
+	fn test_resync_pending_batch() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let clock = MockClock::new(1_000_000);
+		let resync = test_resync_manager(&dir, clock.clone());
+		let h1 = Hash::from([1u8; 32]);
+		let h2 = Hash::from([2u8; 32]);
+
+		// Only the first block of a batch requires a flush to be scheduled
+		assert!(resync.add_pending(&h1, Duration::from_secs(10)));
+		clock.advance(Duration::from_secs(1));
+		assert!(!resync.add_pending(&h2, Duration::ZERO));
+		assert_eq!(resync.queue_len().unwrap(), 0);
+
+		assert_eq!(resync.flush_pending().unwrap(), 2);
+		assert_eq!(resync.queue_len().unwrap(), 2);
+		assert_eq!(resync.flush_pending().unwrap(), 0);
+		assert!(resync.add_pending(&h1, Duration::ZERO));
+
+		let first = resync.get_block_to_resync().unwrap().unwrap();
+		assert_eq!(
+			first.time_bytes,
+			resync_queue_key(&h2, 1_001_000),
+			"blocks are queued at the time they were added, plus their delay"
+		);
+	}
+
+	#[test]
+	fn test_resync_clear_backoff() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let clock = MockClock::new(1_000_000);
+		let resync = test_resync_manager(&dir, clock.clone());
+		let h = Hash::from([1u8; 32]);
+
+		assert!(resync.clear_backoff(&h).is_err());
+
+		// After two errors, the next try is two retry delays after the last try
+		let ec = ErrorCounter::new(clock.now_msec()).add1(clock.now_msec());
+		resync.errors.insert(h.as_slice(), ec.encode()).unwrap();
+		assert_eq!(ec.next_try(resync.retry_delay()), 1_000_000 + 2 * 60_000);
+
+		// Clearing the backoff makes the block retried at the current time
+		clock.advance(Duration::from_secs(10));
+		resync.clear_backoff(&h).unwrap();
+		let ec = ErrorCounter::decode(&resync.errors.get(h.as_slice()).unwrap().unwrap());
+		assert_eq!(ec.next_try(resync.retry_delay()), 1_010_000);
+		let block = resync.get_block_to_resync().unwrap().unwrap();
+		assert_eq!(block.time_bytes[0..8], 1_010_000u64.to_be_bytes());
+	}
+
+	#[test]
+	fn test_resync_rate_limit() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let mut resync = test_resync_manager(&dir, MockClock::new(1_000_000));
+		let mut live_config = resync.live_config.borrow().clone();
+
+		// No limit by default
+		for _ in 0..100 {
+			assert_eq!(
+				resync.limit_rate(WorkerState::Busy, 1 << 20),
+				WorkerState::Busy
+			);
+		}
+
+		// 2 blocks per second: the third block in a row is throttled,
+		// unless the worker is already throttled for longer
+		live_config.block_resync_max_ops = 2;
+		let (send_live_config, recv_live_config) = watch::channel(live_config.clone());
+		resync.live_config = recv_live_config;
+		for _ in 0..2 {
+			assert_eq!(resync.limit_rate(WorkerState::Busy, 0), WorkerState::Busy);
+		}
+		assert!(matches!(
+			resync.limit_rate(WorkerState::Busy, 0),
+			WorkerState::Throttled(t) if t > 0. && t <= 0.5
+		));
+		assert_eq!(
+			resync.limit_rate(WorkerState::Throttled(10.), 0),
+			WorkerState::Throttled(10.)
+		);
+
+		// The limit on bytes applies too, and changes are taken into account
+		live_config.block_resync_max_ops = 0;
+		live_config.block_resync_max_rate = 1000;
+		send_live_config.send(live_config).unwrap();
+		assert_eq!(
+			resync.limit_rate(WorkerState::Busy, 1000),
+			WorkerState::Busy
+		);
+		assert!(matches!(
+			resync.limit_rate(WorkerState::Busy, 1000),
+			WorkerState::Throttled(t) if t > 0.9 && t <= 1.
+		));
+	}
+
+	#[test]
+	fn test_resync_backoff() {
+		let retry_delay = Duration::from_secs(10);
+		let base = retry_delay.as_millis() as u64;
+
+		let mut ec = ErrorCounter::new(0);
+		let mut delays = vec![ec.next_try(retry_delay)];
+		for _ in 0..10 {
+			ec = ec.add1(0);
+			delays.push(ec.next_try(retry_delay));
+		}
+
+		// Delays double after each consecutive failure...
+		assert_eq!(&delays[..4], &[base, 2 * base, 4 * base, 8 * base]);
+		// ...until they are capped
+		let max = base << RESYNC_RETRY_DELAY_MAX_BACKOFF_POWER;
+		assert!(delays.windows(2).all(|w| w[0] <= w[1]));
+		assert!(delays.iter().all(|d| *d <= max));
+		assert_eq!(*delays.last().unwrap(), max);
+
+		// Errors that are not retryable skip the exponential backoff
+		let ec_max = ErrorCounter::new(0).max_backoff();
+		assert_eq!(ec_max.next_try(retry_delay), max);
+		assert_eq!(ec_max.add1(0).next_try(retry_delay), max);
+		assert_eq!(ec.max_backoff().errors, ec.errors);
+
+		// Encoding the counter keeps its state
+		let ec2 = ErrorCounter::decode(&ec.encode());
+		assert_eq!(ec2.errors, ec.errors);
+		assert_eq!(ec2.next_try(retry_delay), ec.next_try(retry_delay));
 	}
 }
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use garage_util::config::MinFreeSpace;
+use garage_util::error::Error;
+
+/// Time during which the free space of a data directory is not checked again
+const SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Source of the free space of the filesystem that contains a path
+pub(crate) trait SpaceSource: Send + Sync {
+	/// Available and total space in bytes, `None` if it can't be determined
+	fn space(&self, path: &Path) -> Option<(u64, u64)>;
+}
+
+/// Available and total space of a data directory, with the time it was checked
+type CachedSpace = (Instant, Option<(u64, u64)>);
+
+/// Free space as reported by `statvfs`
+struct Statvfs;
+
+impl SpaceSource for Statvfs {
+	fn space(&self, path: &Path) -> Option<(u64, u64)> {
+		let x = nix::sys::statvfs::statvfs(path).ok()?;
+		let avail = x.blocks_available() as u64 * x.fragment_size() as u64;
+		let total = x.blocks() as u64 * x.fragment_size() as u64;
+		Some((avail, total))
+	}
+}
+
+/// Refuses writes of new blocks to data directories whose filesystem has less
+/// free space than `data_dir_min_free_space`. Only writes go through this check:
+/// reads and deletions of blocks are always allowed, so that a full node can
+/// still serve data and recover space.
+pub(crate) struct DiskSpaceGuard {
+	min_free: Option<MinFreeSpace>,
+	source: Box<dyn SpaceSource>,
+	check_interval: Duration,
+	/// Last known space of each data directory
+	cache: Mutex<HashMap<PathBuf, CachedSpace>>,
+}
+
+impl DiskSpaceGuard {
+	pub(crate) fn new(min_free: Option<MinFreeSpace>) -> Self {
+		Self::with_source(min_free, Box::new(Statvfs), SPACE_CHECK_INTERVAL)
+	}
+
+	fn with_source(
+		min_free: Option<MinFreeSpace>,
+		source: Box<dyn SpaceSource>,
+		check_interval: Duration,
+	) -> Self {
+		Self {
+			min_free,
+			source,
+			check_interval,
+			cache: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Fails with the retryable `Error::InsufficientSpace` if a new block
+	/// must not be written to data directory `dir`
+	pub(crate) fn check_write(&self, dir: &Path) -> Result<(), Error> {
+		let min_free = match &self.min_free {
+			Some(m) => m,
+			None => return Ok(()),
+		};
+
+		let space = {
+			let mut cache = self.cache.lock().unwrap();
+			match cache.get(dir) {
+				Some((checked, space)) if checked.elapsed() < self.check_interval => *space,
+				_ => {
+					let space = self.source.space(dir);
+					cache.insert(dir.to_path_buf(), (Instant::now(), space));
+					space
+				}
+			}
+		};
+
+		match space {
+			Some((avail, total)) if min_free.is_below(avail, total) => {
+				Err(Error::InsufficientSpace(format!(
+					"data directory {} has {} available, below data_dir_min_free_space",
+					dir.display(),
+					bytesize::ByteSize::b(avail),
+				)))
+			}
+			// If the free space can't be determined, don't block writes
+			_ => Ok(()),
+		}
+	}
+
+	/// Add the size of a deleted block file to the cached free space of the
+	/// data directory that contained it, so that writes can resume as soon as
+	/// enough space is available without checking the filesystem again
+	pub(crate) fn space_freed(&self, path: &Path, size: u64) {
+		let mut cache = self.cache.lock().unwrap();
+		for (dir, (_, space)) in cache.iter_mut() {
+			if let Some((avail, total)) = space.as_mut().filter(|_| path.starts_with(dir)) {
+				*avail = avail.saturating_add(size).min(*total);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+	use std::sync::Arc;
+
+	use super::*;
+
+	const TOTAL: u64 = 1000;
+
+	#[derive(Default)]
+	struct MockSpace {
+		avail: AtomicU64,
+		calls: AtomicUsize,
+	}
+
+	impl SpaceSource for Arc<MockSpace> {
+		fn space(&self, _path: &Path) -> Option<(u64, u64)> {
+			self.calls.fetch_add(1, Ordering::SeqCst);
+			Some((self.avail.load(Ordering::SeqCst), TOTAL))
+		}
+	}
+
+	fn guard(min_free: MinFreeSpace, avail: u64) -> (DiskSpaceGuard, Arc<MockSpace>) {
+		let space = Arc::new(MockSpace::default());
+		space.avail.store(avail, Ordering::SeqCst);
+		let guard = DiskSpaceGuard::with_source(
+			Some(min_free),
+			Box::new(space.clone()),
+			Duration::from_secs(3600),
+		);
+		(guard, space)
+	}
+
+	#[test]
+	fn test_threshold() {
+		let dir = Path::new("/data");
+
+		let (g, _) = guard(MinFreeSpace::Bytes(100), 100);
+		assert!(g.check_write(dir).is_ok());
+		let (g, _) = guard(MinFreeSpace::Bytes(100), 99);
+		assert!(matches!(
+			g.check_write(dir),
+			Err(e @ Error::InsufficientSpace(_)) if e.is_retryable()
+		));
+
+		let (g, _) = guard(MinFreeSpace::Percent(5.0), 50);
+		assert!(g.check_write(dir).is_ok());
+		let (g, _) = guard(MinFreeSpace::Percent(5.0), 49);
+		assert!(g.check_write(dir).is_err());
+
+		// No threshold configured
+		let g = DiskSpaceGuard::new(None);
+		assert!(g.check_write(Path::new("/nonexistent")).is_ok());
+	}
+
+	#[test]
+	fn test_cached_check() {
+		let (g, space) = guard(MinFreeSpace::Bytes(100), 500);
+		for _ in 0..10 {
+			assert!(g.check_write(Path::new("/data1")).is_ok());
+		}
+		assert_eq!(space.calls.load(Ordering::SeqCst), 1);
+
+		// Each data directory is checked separately
+		assert!(g.check_write(Path::new("/data2")).is_ok());
+		assert_eq!(space.calls.load(Ordering::SeqCst), 2);
+
+		// The disk fills up: the cached value is used until it expires
+		space.avail.store(10, Ordering::SeqCst);
+		assert!(g.check_write(Path::new("/data1")).is_ok());
+		assert_eq!(space.calls.load(Ordering::SeqCst), 2);
+
+		// With an expired cache, the new value is used
+		let g = DiskSpaceGuard::with_source(
+			Some(MinFreeSpace::Bytes(100)),
+			Box::new(space.clone()),
+			Duration::ZERO,
+		);
+		assert!(g.check_write(Path::new("/data1")).is_err());
+	}
+
+	#[test]
+	fn test_writes_resume_after_deletions() {
+		let dir = Path::new("/data");
+		let (g, space) = guard(MinFreeSpace::Bytes(100), 10);
+		assert!(g.check_write(dir).is_err());
+
+		// Deletions don't go through the guard; once they have freed space,
+		// writes are allowed again without waiting for the check interval
+		g.space_freed(&dir.join("ab/cd/block"), 50);
+		assert!(g.check_write(dir).is_err());
+		g.space_freed(&dir.join("ab/cd/block2"), 50);
+		assert!(g.check_write(dir).is_ok());
+		assert_eq!(space.calls.load(Ordering::SeqCst), 1);
+
+		// Deletions in other data directories don't count
+		let (g, _) = guard(MinFreeSpace::Bytes(100), 10);
+		assert!(g.check_write(dir).is_err());
+		g.space_freed(Path::new("/data2/ab/cd/block"), 500);
+		assert!(g.check_write(dir).is_err());
+	}
+}
@@ -16,7 +16,17 @@ impl AdminRpcHandler {
 			BlockOperation::ListErrors => Ok(AdminRpc::BlockErrorList(
 				self.garage.block_manager.list_resync_errors()?,
 			)),
-			BlockOperation::Info { hash } => self.handle_block_info(hash).await,
+			BlockOperation::ListQueue { limit } => Ok(AdminRpc::BlockResyncQueue(
+				self.garage.block_manager.list_resync_queue(*limit)?,
+			)),
+			BlockOperation::PruneQueue => {
+				let pruned = self.garage.block_manager.prune_resync_queue().await?;
+				Ok(AdminRpc::Ok(format!(
+					"{} blocks removed from the resync queue",
+					pruned
+				)))
+			}
+			BlockOperation::Info { hash, check } => self.handle_block_info(hash, *check).await,
 			BlockOperation::RetryNow { all, blocks } => {
 				self.handle_block_retry_now(*all, blocks).await
 			}
@@ -24,9 +34,13 @@ impl AdminRpcHandler {
 		}
 	}
 
-	async fn handle_block_info(&self, hash: &String) -> Result<AdminRpc, Error> {
+	async fn handle_block_info(&self, hash: &str, check: bool) -> Result<AdminRpc, Error> {
 		let hash = self.find_block_hash_by_prefix(hash)?;
 		let refcount = self.garage.block_manager.get_block_rc(&hash)?;
+		let health = match check {
+			true => Some(self.garage.block_manager.check_block_health(&hash).await?),
+			false => None,
+		};
 		let block_refs = self
 			.garage
 			.block_ref_table
@@ -54,6 +68,7 @@ impl AdminRpcHandler {
 		Ok(AdminRpc::BlockInfo {
 			hash,
 			refcount,
+			health,
 			versions,
 			uploads,
 		})
@@ -210,7 +210,7 @@ impl AdminRpcHandler {
 		// 1. delete authorization from keys that had access
 		for (key_id, _) in bucket.authorized_keys() {
 			helper
-				.set_bucket_key_permissions(bucket.id, key_id, BucketKeyPerm::NO_PERMISSIONS)
+				.set_bucket_key_permissions(bucket.id, &key_id, BucketKeyPerm::NO_PERMISSIONS)
 				.await?;
 		}
 
@@ -311,9 +311,18 @@ impl AdminRpcHandler {
 			.get_existing_matching_key(&query.key_pattern)
 			.await?;
 
-		let allow_read = query.read || key.allow_read(&bucket_id);
-		let allow_write = query.write || key.allow_write(&bucket_id);
-		let allow_owner = query.owner || key.allow_owner(&bucket_id);
+		let perm = helper
+			.bucket()
+			.get_existing_bucket(bucket_id)
+			.await?
+			.state
+			.into_option()
+			.unwrap()
+			.key_permissions(&key.key_id);
+
+		let allow_read = query.read || perm.allow_read;
+		let allow_write = query.write || perm.allow_write;
+		let allow_owner = query.owner || perm.allow_owner;
 
 		helper
 			.set_bucket_key_permissions(
@@ -346,9 +355,18 @@ impl AdminRpcHandler {
 			.get_existing_matching_key(&query.key_pattern)
 			.await?;
 
-		let allow_read = !query.read && key.allow_read(&bucket_id);
-		let allow_write = !query.write && key.allow_write(&bucket_id);
-		let allow_owner = !query.owner && key.allow_owner(&bucket_id);
+		let perm = helper
+			.bucket()
+			.get_existing_bucket(bucket_id)
+			.await?
+			.state
+			.into_option()
+			.unwrap()
+			.key_permissions(&key.key_id);
+
+		let allow_read = !query.read && perm.allow_read;
+		let allow_write = !query.write && perm.allow_write;
+		let allow_owner = !query.owner && perm.allow_owner;
 
 		helper
 			.set_bucket_key_permissions(
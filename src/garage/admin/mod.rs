@@ -23,6 +23,7 @@ use garage_table::*;
 use garage_rpc::layout::PARTITION_BITS;
 use garage_rpc::*;
 
+use garage_block::health::BlockHealth;
 use garage_block::manager::BlockResyncErrorInfo;
 
 use garage_model::bucket_table::*;
@@ -66,9 +67,11 @@ pub enum AdminRpc {
 	WorkerVars(Vec<(Uuid, String, String)>),
 	WorkerInfo(usize, garage_util::background::WorkerInfo),
 	BlockErrorList(Vec<BlockResyncErrorInfo>),
+	BlockResyncQueue(Vec<(u64, Hash)>),
 	BlockInfo {
 		hash: Hash,
 		refcount: u64,
+		health: Option<BlockHealth>,
 		versions: Vec<Result<Version, Uuid>>,
 		uploads: Vec<MultipartUpload>,
 	},
@@ -190,14 +193,14 @@ impl AdminRpcHandler {
 
 	fn gather_stats_local(&self, opt: StatsOpt) -> Result<String, Error> {
 		let mut ret = String::new();
+		let build_info = garage_util::version::build_info();
 		writeln!(
 			&mut ret,
-			"\nGarage version: {} [features: {}]\nRust compiler version: {}",
-			garage_util::version::garage_version(),
-			garage_util::version::garage_features()
-				.map(|list| list.join(", "))
-				.unwrap_or_else(|| "(unknown)".into()),
-			garage_util::version::rust_version(),
+			"\nGarage version: {} [features: {}]\nGit commit: {}\nRust compiler version: {}",
+			build_info.version,
+			build_info.features.join(", "),
+			build_info.git_commit,
+			build_info.rust_version,
 		)
 		.unwrap();
 
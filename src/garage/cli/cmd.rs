@@ -3,6 +3,7 @@ use std::time::Duration;
 
 use format_table::format_table;
 use garage_util::error::*;
+use garage_util::formater::format_duration;
 
 use garage_rpc::layout::*;
 use garage_rpc::system::*;
@@ -122,7 +123,6 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 		.map(|adv| (adv.id, adv))
 		.collect::<HashMap<_, _>>();
 
-	let tf = timeago::Formatter::new();
 	let mut drain_msg = false;
 	let mut failed_nodes = vec!["ID\tHostname\tTags\tZone\tCapacity\tLast seen".to_string()];
 	let mut listed = HashSet::new();
@@ -149,7 +149,7 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 				Some(adv) => (
 					adv.status.hostname.as_deref().unwrap_or("?"),
 					adv.last_seen_secs_ago
-						.map(|s| tf.convert(Duration::from_secs(s)))
+						.map(|s| format!("{} ago", format_duration(Duration::from_secs(s))))
 						.unwrap_or_else(|| "never seen".into()),
 				),
 				None => ("??", "never seen".into()),
@@ -249,13 +249,17 @@ pub async fn cmd_admin(
 		AdminRpc::BlockErrorList(el) => {
 			print_block_error_list(el);
 		}
+		AdminRpc::BlockResyncQueue(q) => {
+			print_block_resync_queue(q);
+		}
 		AdminRpc::BlockInfo {
 			hash,
 			refcount,
+			health,
 			versions,
 			uploads,
 		} => {
-			print_block_info(hash, refcount, versions, uploads);
+			print_block_info(hash, refcount, health, versions, uploads);
 		}
 		r => {
 			error!("Unexpected response: {:?}", r);
@@ -59,6 +59,11 @@ pub enum Command {
 	/// Convert metadata db between database engine formats
 	#[structopt(name = "convert-db", version = garage_version())]
 	ConvertDb(convert_db::ConvertDbOpt),
+
+	/// Upgrade the on-disk format of node data to the version of this binary
+	/// (must be run offline directly on the server node)
+	#[structopt(name = "upgrade-format", version = garage_version())]
+	UpgradeFormat(UpgradeFormatOpt),
 }
 
 #[derive(StructOpt, Debug)]
@@ -521,6 +526,13 @@ pub struct OfflineRepairOpt {
 	pub what: OfflineRepairWhat,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
+pub struct UpgradeFormatOpt {
+	/// Confirm the upgrade of the on-disk format
+	#[structopt(long = "yes")]
+	pub yes: bool,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
 pub enum OfflineRepairWhat {
 	/// Repair K2V item counters
@@ -592,11 +604,26 @@ pub enum BlockOperation {
 	/// List all blocks that currently have a resync error
 	#[structopt(name = "list-errors", version = garage_version())]
 	ListErrors,
+	/// List the blocks in the resync queue, by the time they are due to be resynced
+	#[structopt(name = "list-queue", version = garage_version())]
+	ListQueue {
+		/// Maximum number of queue entries to show
+		#[structopt(long = "limit", default_value = "100")]
+		limit: usize,
+	},
+	/// Remove from the resync queue the blocks that are not referenced
+	/// anymore and not stored on this node
+	#[structopt(name = "prune-queue", version = garage_version())]
+	PruneQueue,
 	/// Get detailed information about a single block
 	#[structopt(name = "info", version = garage_version())]
 	Info {
 		/// Hash of the block for which to retrieve information
 		hash: String,
+		/// Also read and verify the copies of the block on all the nodes
+		/// that should store it. Corrupted copies are moved away and resynced.
+		#[structopt(long = "check")]
+		check: bool,
 	},
 	/// Retry now the resync of one or many blocks
 	#[structopt(name = "retry-now", version = garage_version())]
@@ -1,13 +1,14 @@
 use std::collections::HashMap;
-use std::time::Duration;
 
 use format_table::format_table;
 use garage_util::background::*;
 use garage_util::crdt::*;
 use garage_util::data::*;
 use garage_util::error::*;
+use garage_util::formater::format_relative;
 use garage_util::time::*;
 
+use garage_block::health::BlockHealth;
 use garage_block::manager::BlockResyncErrorInfo;
 
 use garage_model::bucket_table::*;
@@ -99,7 +100,11 @@ pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
 
 			println!("\nAuthorized buckets:");
 			let mut table = vec![];
-			for (bucket_id, perm) in p.authorized_buckets.items().iter() {
+			for (bucket_id, _) in p.authorized_buckets.items().iter() {
+				let perm = match relevant_buckets.get(bucket_id).and_then(|b| b.params()) {
+					Some(bucket_params) => bucket_params.key_permissions(&key.key_id),
+					None => continue,
+				};
 				if !perm.is_any() {
 					continue;
 				}
@@ -212,10 +217,7 @@ pub fn print_bucket_info(
 
 			println!("\nAuthorized keys:");
 			let mut table = vec![];
-			for (k, perm) in p.authorized_keys.items().iter() {
-				if !perm.is_any() {
-					continue;
-				}
+			for (k, perm) in p.authorized_key_permissions().iter() {
 				let rflag = if perm.allow_read { "R" } else { " " };
 				let wflag = if perm.allow_write { "W" } else { " " };
 				let oflag = if perm.allow_owner { "O" } else { " " };
@@ -276,11 +278,10 @@ pub fn print_worker_list(wi: HashMap<usize, WorkerInfo>, wlo: WorkerListOpt) {
 			continue;
 		}
 
-		let tf = timeago::Formatter::new();
 		let err_ago = info
 			.last_error
 			.as_ref()
-			.map(|(_, t)| tf.convert(Duration::from_millis(now_msec() - t)))
+			.map(|(_, t)| format_relative(*t))
 			.unwrap_or_default();
 		let (total_err, consec_err) = if info.errors > 0 {
 			(info.errors.to_string(), info.consecutive_errors.to_string())
@@ -336,11 +337,7 @@ pub fn print_worker_info(tid: usize, info: WorkerInfo) {
 	table.push(format!("Consecutive errs:\t{}", info.consecutive_errors));
 	if let Some((s, t)) = info.last_error {
 		table.push(format!("Last error:\t{}", s));
-		let tf = timeago::Formatter::new();
-		table.push(format!(
-			"Last error time:\t{}",
-			tf.convert(Duration::from_millis(now_msec() - t))
-		));
+		table.push(format!("Last error time:\t{}", format_relative(t)));
 	}
 
 	table.push("".into());
@@ -377,32 +374,45 @@ pub fn print_worker_vars(wv: Vec<(Uuid, String, String)>) {
 
 pub fn print_block_error_list(el: Vec<BlockResyncErrorInfo>) {
 	let now = now_msec();
-	let tf = timeago::Formatter::new();
-	let mut tf2 = timeago::Formatter::new();
-	tf2.ago("");
 
 	let mut table = vec!["Hash\tRC\tErrors\tLast error\tNext try".into()];
 	for e in el {
 		let next_try = if e.next_try > now {
-			tf2.convert(Duration::from_millis(e.next_try - now))
+			format_relative(e.next_try)
 		} else {
 			"asap".to_string()
 		};
 		table.push(format!(
-			"{}\t{}\t{}\t{}\tin {}",
+			"{}\t{}\t{}\t{}\t{}",
 			hex::encode(e.hash.as_slice()),
 			e.refcount,
 			e.error_count,
-			tf.convert(Duration::from_millis(now - e.last_try)),
+			format_relative(e.last_try),
 			next_try
 		));
 	}
 	format_table(table);
 }
 
+pub fn print_block_resync_queue(q: Vec<(u64, Hash)>) {
+	let now = now_msec();
+
+	let mut table = vec!["Hash\tNext try".into()];
+	for (when, hash) in q {
+		let next_try = if when > now {
+			format_relative(when)
+		} else {
+			"asap".to_string()
+		};
+		table.push(format!("{}\t{}", hex::encode(hash.as_slice()), next_try));
+	}
+	format_table(table);
+}
+
 pub fn print_block_info(
 	hash: Hash,
 	refcount: u64,
+	health: Option<BlockHealth>,
 	versions: Vec<Result<Version, Uuid>>,
 	uploads: Vec<MultipartUpload>,
 ) {
@@ -410,6 +420,24 @@ pub fn print_block_info(
 	println!("Refcount: {}", refcount);
 	println!();
 
+	if let Some(health) = health {
+		let mut table = vec!["Node\tCopy".into()];
+		for (nodes, state) in [
+			(&health.present, "present"),
+			(&health.corrupted, "CORRUPTED"),
+			(&health.missing, "MISSING"),
+		] {
+			for node in nodes.iter() {
+				table.push(format!("{:?}\t{}", node, state));
+			}
+		}
+		for (node, err) in health.errored.iter() {
+			table.push(format!("{:?}\terror: {}", node, err));
+		}
+		format_table(table);
+		println!();
+	}
+
 	let mut table = vec!["Version\tBucket\tKey\tMPU\tDeleted".into()];
 	let mut nondeleted_count = 0;
 	for v in versions.iter() {
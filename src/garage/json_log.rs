@@ -0,0 +1,48 @@
+use std::fmt;
+
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::{self, FormatEvent, FormatFields, Json, Writer};
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Event formatter that writes one JSON object per line, as the default
+/// JSON formatter of `tracing_subscriber` does, and adds the ID of the
+/// local node to all log lines so that logs from a whole cluster can be
+/// aggregated and filtered by node.
+pub struct JsonFormat {
+	inner: format::Format<Json>,
+	node_id: Option<String>,
+}
+
+impl JsonFormat {
+	pub fn new(node_id: Option<String>) -> Self {
+		Self {
+			inner: format::format()
+				.json()
+				.with_current_span(true)
+				.with_span_list(true),
+			node_id,
+		}
+	}
+}
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+	S: Subscriber + for<'a> LookupSpan<'a>,
+	N: for<'a> FormatFields<'a> + 'static,
+{
+	fn format_event(
+		&self,
+		ctx: &FmtContext<'_, S, N>,
+		mut writer: Writer<'_>,
+		event: &Event<'_>,
+	) -> fmt::Result {
+		let mut buf = String::new();
+		self.inner.format_event(ctx, Writer::new(&mut buf), event)?;
+
+		match (&self.node_id, buf.strip_prefix('{')) {
+			(Some(node_id), Some(rest)) => write!(writer, "{{\"node_id\":\"{}\",{}", node_id, rest),
+			_ => writer.write_str(&buf),
+		}
+	}
+}
@@ -6,6 +6,7 @@ extern crate tracing;
 
 mod admin;
 mod cli;
+mod json_log;
 mod repair;
 mod secrets;
 mod server;
@@ -25,10 +26,13 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 
 use structopt::StructOpt;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{reload, EnvFilter, Registry};
 
 use garage_net::util::parse_and_resolve_peer_addr;
 use garage_net::NetworkKey;
 
+use garage_util::config::{Config, LogFormat};
 use garage_util::error::*;
 
 use garage_rpc::system::*;
@@ -111,8 +115,18 @@ async fn main() {
 	// to avoid this behavior in Garage as this would risk putting the process in an
 	// unknown/uncontrollable state. We prefer to exit the process and restart it
 	// from scratch, so that it boots back into a fresh, known state.
+	// The exception are panics in background workers, which are caught and
+	// cause the worker to be restarted after a delay.
 	let panic_version_info = version.clone();
 	std::panic::set_hook(Box::new(move |panic_info| {
+		if garage_util::background::worker::panic_is_caught() {
+			eprintln!("======== PANIC in background worker, restarting it ========");
+			eprintln!("{}", panic_info);
+			eprintln!("GARAGE VERSION: {}", panic_version_info);
+			eprintln!("BACKTRACE:");
+			eprintln!("{:?}", backtrace::Backtrace::new());
+			return;
+		}
 		eprintln!("======== PANIC (internal Garage error) ========");
 		eprintln!("{}", panic_info);
 		eprintln!();
@@ -138,15 +152,18 @@ async fn main() {
 	let opt = Opt::from_clap(&Opt::clap().version(version.as_str()).get_matches());
 
 	// Initialize logging as well as other libraries used in Garage
-	init_logging(&opt);
+	let log_filter = init_logging(&opt);
 
 	sodiumoxide::init().expect("Unable to init sodiumoxide");
 
 	let res = match opt.cmd {
-		Command::Server => server::run_server(opt.config_file, opt.secrets).await,
+		Command::Server => server::run_server(opt.config_file, opt.secrets, log_filter).await,
 		Command::OfflineRepair(repair_opt) => {
 			repair::offline::offline_repair(opt.config_file, opt.secrets, repair_opt).await
 		}
+		Command::UpgradeFormat(upgrade_opt) => {
+			repair::offline::upgrade_format(opt.config_file, opt.secrets, upgrade_opt)
+		}
 		Command::ConvertDb(conv_opt) => {
 			cli::convert_db::do_conversion(conv_opt).map_err(From::from)
 		}
@@ -162,16 +179,32 @@ async fn main() {
 	}
 }
 
-fn init_logging(opt: &Opt) {
-	if std::env::var("RUST_LOG").is_err() {
-		let default_log = match &opt.cmd {
-			Command::Server => "netapp=info,garage=info",
-			_ => "netapp=warn,garage=warn",
-		};
-		std::env::set_var("RUST_LOG", default_log)
-	}
+/// Handle used to change the filter of log messages once logging is initialized
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
 
-	let env_filter = tracing_subscriber::filter::EnvFilter::from_default_env();
+/// Default filter of the log messages written by the daemon
+pub const DEFAULT_SERVER_LOG_FILTER: &str = "netapp=info,garage=info";
+
+fn init_logging(opt: &Opt) -> LogFilterHandle {
+	let config = match &opt.cmd {
+		Command::Server => garage_util::config::read_config(opt.config_file.clone()).ok(),
+		_ => None,
+	};
+
+	let default_log = match &opt.cmd {
+		Command::Server => DEFAULT_SERVER_LOG_FILTER,
+		_ => "netapp=warn,garage=warn",
+	};
+	let log_level = config.as_ref().and_then(|c| c.log_level.as_deref());
+	let env_filter = match log_filter(log_level, default_log) {
+		Ok(filter) => filter,
+		Err(e) => {
+			eprintln!("Invalid value for log_level: {}", e);
+			std::process::exit(1);
+		}
+	};
+	let (env_filter, filter_handle) = reload::Layer::new(env_filter);
+	let subscriber = tracing_subscriber::registry().with(env_filter);
 
 	if std::env::var("GARAGE_LOG_TO_SYSLOG")
 		.map(|x| x == "1" || x == "true")
@@ -189,17 +222,19 @@ fn init_logging(opt: &Opt) {
 			)
 			.expect("Unable to init syslog");
 
-			tracing_subscriber::fmt()
-				.with_writer(syslog)
-				.with_env_filter(env_filter)
-				.with_ansi(false) // disable ANSI escape sequences (colours)
-				.with_file(false)
-				.with_level(false)
-				.without_time()
-				.compact()
+			subscriber
+				.with(
+					tracing_subscriber::fmt::layer()
+						.with_writer(syslog)
+						.with_ansi(false) // disable ANSI escape sequences (colours)
+						.with_file(false)
+						.with_level(false)
+						.without_time()
+						.compact(),
+				)
 				.init();
 
-			return;
+			return filter_handle;
 		}
 		#[cfg(not(feature = "syslog"))]
 		{
@@ -208,10 +243,60 @@ fn init_logging(opt: &Opt) {
 		}
 	}
 
-	tracing_subscriber::fmt()
-		.with_writer(std::io::stderr)
-		.with_env_filter(env_filter)
-		.init();
+	let (log_format, node_id) = log_format_and_node_id(config);
+	match log_format {
+		LogFormat::Text => subscriber
+			.with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+			.init(),
+		LogFormat::Json => subscriber
+			.with(
+				tracing_subscriber::fmt::layer()
+					.json()
+					.event_format(json_log::JsonFormat::new(node_id))
+					.with_writer(std::io::stderr),
+			)
+			.init(),
+	}
+	filter_handle
+}
+
+/// Filter of the log messages: the `RUST_LOG` environment variable if it is
+/// set, otherwise `log_level` from the configuration, otherwise `default`
+pub fn log_filter(
+	log_level: Option<&str>,
+	default: &str,
+) -> Result<EnvFilter, tracing_subscriber::filter::ParseError> {
+	if std::env::var("RUST_LOG").is_ok() {
+		return Ok(EnvFilter::from_default_env());
+	}
+	EnvFilter::try_new(log_level.unwrap_or(default))
+}
+
+/// Determine the log format to use, from the `GARAGE_LOG_FORMAT` environment
+/// variable or the `log_format` configuration option. When running the server,
+/// also return the ID of the local node so that it can be added to JSON logs.
+fn log_format_and_node_id(config: Option<Config>) -> (LogFormat, Option<String>) {
+	let log_format = match std::env::var("GARAGE_LOG_FORMAT").as_deref() {
+		Ok("json") => LogFormat::Json,
+		Ok("text") => LogFormat::Text,
+		Ok(other) => {
+			eprintln!(
+				"Invalid value for GARAGE_LOG_FORMAT: '{}', expected 'text' or 'json'",
+				other
+			);
+			std::process::exit(1);
+		}
+		Err(_) => config
+			.as_ref()
+			.map(|c| c.log_format)
+			.unwrap_or(LogFormat::Text),
+	};
+
+	let node_id = config
+		.and_then(|c| read_node_id(&c.metadata_dir).ok())
+		.map(|id| hex::encode(&id[..8]));
+
+	(log_format, node_id)
 }
 
 async fn cli_command(opt: Opt) -> Result<(), Error> {
@@ -245,6 +330,7 @@ async fn cli_command(opt: Opt) -> Result<(), Error> {
 	let (_pk, sk) = sodiumoxide::crypto::sign::ed25519::gen_keypair();
 
 	let netapp = NetApp::new(GARAGE_VERSION_TAG, network_key, sk, None);
+	netapp.set_app_version_check(is_version_tag_compatible);
 
 	// Find and parse the address of the target host
 	let (id, addr, is_default_addr) = if let Some(h) = opt.rpc_host {
@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use garage_util::config::*;
 use garage_util::error::*;
 
+use garage_model::format_version::*;
 use garage_model::garage::Garage;
 
 use crate::cli::structs::*;
@@ -45,3 +46,33 @@ pub async fn offline_repair(
 
 	Ok(())
 }
+
+pub fn upgrade_format(
+	config_file: PathBuf,
+	secrets: Secrets,
+	opt: UpgradeFormatOpt,
+) -> Result<(), Error> {
+	if !opt.yes {
+		return Err(Error::Message(
+			"Please add the --yes flag to upgrade the on-disk format. Make sure that no Garage process is running on this node.".into(),
+		));
+	}
+
+	info!("Loading configuration...");
+	let config = fill_secrets(read_config(config_file)?, secrets)?;
+
+	let previous = upgrade_format_version(&config.metadata_dir)?;
+	if previous == DATASTORE_FORMAT_VERSION {
+		println!(
+			"Node data already uses format version {}, nothing to do.",
+			DATASTORE_FORMAT_VERSION
+		);
+	} else {
+		println!(
+			"Node data upgraded from format version {} to format version {}.",
+			previous, DATASTORE_FORMAT_VERSION
+		);
+	}
+
+	Ok(())
+}
@@ -65,6 +65,15 @@ pub fn fill_secrets(mut config: Config, secrets: Secrets) -> Result<Config, Erro
 		allow_world_readable,
 	)?;
 
+	fill_secret(
+		&mut config.data_encryption_key,
+		&config.data_encryption_key_file,
+		&None,
+		&None,
+		"data_encryption_key",
+		allow_world_readable,
+	)?;
+
 	fill_secret(
 		&mut config.admin.admin_token,
 		&config.admin.admin_token_file,
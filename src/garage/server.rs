@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 use tokio::sync::watch;
+use tracing_subscriber::EnvFilter;
 
 use garage_util::background::*;
 use garage_util::config::*;
@@ -18,6 +19,7 @@ use crate::admin::*;
 use crate::secrets::{fill_secrets, Secrets};
 #[cfg(feature = "telemetry-otlp")]
 use crate::tracing_setup::*;
+use crate::{LogFilterHandle, DEFAULT_SERVER_LOG_FILTER};
 
 async fn wait_from(mut chan: watch::Receiver<bool>) {
 	while !*chan.borrow() {
@@ -27,9 +29,16 @@ async fn wait_from(mut chan: watch::Receiver<bool>) {
 	}
 }
 
-pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Error> {
+pub async fn run_server(
+	config_file: PathBuf,
+	secrets: Secrets,
+	log_filter: LogFilterHandle,
+) -> Result<(), Error> {
+	info!("Starting {}", garage_util::version::build_info());
+
 	info!("Loading configuration...");
-	let config = fill_secrets(read_config(config_file)?, secrets)?;
+	let (config, config_reloader) = ConfigReloader::load(config_file)?;
+	let config = fill_secrets(config, secrets)?;
 
 	// ---- Initialize Garage internals ----
 
@@ -44,10 +53,16 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 		.init();
 
 	info!("Initializing Garage main data store...");
-	let garage = Garage::new(config.clone())?;
+	let garage = Garage::new_with_live_config(config.clone(), config_reloader.subscribe())?;
 
 	info!("Initializing background runner...");
 	let watch_cancel = watch_shutdown_signal();
+	watch_log_level(
+		config_reloader.subscribe(),
+		log_filter,
+		std::env::var("RUST_LOG").is_ok(),
+	);
+	watch_reload_signal(config_reloader, watch_cancel.clone());
 	let (background, await_background_done) = BackgroundRunner::new(watch_cancel.clone());
 
 	info!("Spawning Garage workers...");
@@ -113,7 +128,7 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 
 	if let Some(web_config) = &config.s3_web {
 		info!("Initializing web server...");
-		let web_server = WebServer::new(garage.clone(), &web_config);
+		let web_server = WebServer::new(garage.clone(), web_config)?;
 		servers.push((
 			"Web",
 			tokio::spawn(web_server.run(web_config.bind_addr.clone(), watch_cancel.clone())),
@@ -157,6 +172,10 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 	garage.system.netapp.drop_all_handlers();
 	opentelemetry::global::shutdown_tracer_provider();
 
+	// Don't exit in the middle of writing a data block
+	info!("Waiting for block writes in progress to finish...");
+	garage.block_manager.await_inflight_writes().await;
+
 	// Await for netapp RPC system to end
 	run_system.await?;
 	info!("Netapp exited");
@@ -182,17 +201,82 @@ fn watch_shutdown_signal() -> watch::Receiver<bool> {
 		let mut sigint = signal(SignalKind::interrupt()).expect("Failed to install SIGINT handler");
 		let mut sigterm =
 			signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
-		let mut sighup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
 		tokio::select! {
 			_ = sigint.recv() => info!("Received SIGINT, shutting down."),
 			_ = sigterm.recv() => info!("Received SIGTERM, shutting down."),
-			_ = sighup.recv() => info!("Received SIGHUP, shutting down."),
 		}
 		send_cancel.send(true).unwrap();
 	});
 	watch_cancel
 }
 
+/// Change the filter of log messages when the `log_level` option is reloaded,
+/// unless the filter was set with the `RUST_LOG` environment variable
+fn watch_log_level(
+	mut live_config: watch::Receiver<LiveConfig>,
+	handle: LogFilterHandle,
+	rust_log_set: bool,
+) {
+	let mut log_level = live_config.borrow_and_update().log_level.clone();
+	tokio::spawn(async move {
+		while live_config.changed().await.is_ok() {
+			let new_log_level = live_config.borrow_and_update().log_level.clone();
+			if new_log_level == log_level {
+				continue;
+			}
+			log_level = new_log_level;
+			if rust_log_set {
+				warn!("RUST_LOG is set and takes precedence over the new value of log_level");
+				continue;
+			}
+			let filter =
+				match EnvFilter::try_new(log_level.as_deref().unwrap_or(DEFAULT_SERVER_LOG_FILTER))
+				{
+					Ok(filter) => filter,
+					Err(e) => {
+						error!(
+							"Invalid value for log_level, keeping the current one: {}",
+							e
+						);
+						continue;
+					}
+				};
+			let filter_str = filter.to_string();
+			match handle.reload(filter) {
+				Ok(()) => info!("Log filter changed to {}", filter_str),
+				Err(e) => error!("Unable to change the log filter: {}", e),
+			}
+		}
+	});
+}
+
+#[cfg(unix)]
+fn watch_reload_signal(reloader: ConfigReloader, mut must_exit: watch::Receiver<bool>) {
+	use tokio::signal::unix::*;
+
+	tokio::spawn(async move {
+		let mut sighup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+		while !*must_exit.borrow() {
+			tokio::select! {
+				_ = sighup.recv() => {
+					info!("Received SIGHUP, reloading configuration.");
+					if let Err(e) = reloader.reload() {
+						error!("Unable to reload configuration, keeping current values: {}", e);
+					}
+				}
+				r = must_exit.changed() => {
+					if r.is_err() {
+						break;
+					}
+				}
+			}
+		}
+	});
+}
+
+#[cfg(windows)]
+fn watch_reload_signal(_reloader: ConfigReloader, _must_exit: watch::Receiver<bool>) {}
+
 #[cfg(windows)]
 fn watch_shutdown_signal() -> watch::Receiver<bool> {
 	use tokio::signal::windows::*;
@@ -213,3 +297,70 @@ fn watch_shutdown_signal() -> watch::Receiver<bool> {
 	});
 	watch_cancel
 }
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use tracing_subscriber::{reload, Registry};
+
+	use super::*;
+
+	fn live_config(log_level: Option<&str>) -> LiveConfig {
+		LiveConfig {
+			scrub_max_rate: 0,
+			scrub_max_ops: 0,
+			block_resync_retry_delay_secs: None,
+			block_resync_target_load_percent: None,
+			block_resync_step_duration_msec: None,
+			block_resync_max_rate: 0,
+			block_resync_max_ops: 0,
+			log_level: log_level.map(String::from),
+		}
+	}
+
+	fn current_filter(handle: &LogFilterHandle) -> String {
+		handle.with_current(|f| f.to_string()).unwrap()
+	}
+
+	/// Wait for the log level watcher to process the last change
+	async fn settle() {
+		tokio::time::sleep(Duration::from_millis(100)).await;
+	}
+
+	#[tokio::test]
+	async fn test_log_level_reload() {
+		let (_layer, handle) =
+			reload::Layer::<EnvFilter, Registry>::new(EnvFilter::new(DEFAULT_SERVER_LOG_FILTER));
+		let (tx, rx) = watch::channel(live_config(None));
+		watch_log_level(rx, handle.clone(), false);
+
+		tx.send_replace(live_config(Some("garage=debug")));
+		settle().await;
+		assert_eq!(current_filter(&handle), "garage=debug");
+
+		// An invalid value is ignored
+		tx.send_replace(live_config(Some("garage=notalevel")));
+		settle().await;
+		assert_eq!(current_filter(&handle), "garage=debug");
+
+		// Removing the option restores the default filter
+		tx.send_replace(live_config(None));
+		settle().await;
+		assert_eq!(
+			current_filter(&handle),
+			EnvFilter::new(DEFAULT_SERVER_LOG_FILTER).to_string()
+		);
+	}
+
+	#[tokio::test]
+	async fn test_log_level_reload_with_rust_log() {
+		let (_layer, handle) = reload::Layer::<EnvFilter, Registry>::new(EnvFilter::new("info"));
+		let (tx, rx) = watch::channel(live_config(None));
+		watch_log_level(rx, handle.clone(), true);
+
+		tx.send_replace(live_config(Some("garage=debug")));
+		settle().await;
+		assert_eq!(current_filter(&handle), "info");
+	}
+}
@@ -51,3 +51,37 @@ async fn test_admin_bucket_perms() {
 
 	assert!(hb().await.is_err());
 }
+
+#[cfg(feature = "metrics")]
+#[tokio::test]
+async fn test_admin_metrics() {
+	use http::{Request, StatusCode};
+	use http_body_util::{BodyExt, Full as FullBody};
+	use hyper::body::Bytes;
+	use hyper_util::client::legacy::Client;
+	use hyper_util::rt::TokioExecutor;
+
+	let ctx = common::context();
+
+	ctx.client.list_buckets().send().await.unwrap();
+
+	let client = Client::builder(TokioExecutor::new()).build_http();
+	let req = Request::builder()
+		.method("GET")
+		.uri(format!(
+			"http://127.0.0.1:{}/metrics",
+			ctx.garage.admin_port
+		))
+		.body(FullBody::new(Bytes::new()))
+		.unwrap();
+	let resp = client.request(req).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+
+	let body = resp.into_body().collect().await.unwrap().to_bytes();
+	let body = std::str::from_utf8(&body).unwrap();
+	assert!(body.contains("# TYPE api_s3_request_counter counter"));
+	assert!(body
+		.lines()
+		.any(|l| l.starts_with("api_s3_request_counter{")
+			&& l.contains("api_endpoint=\"ListBuckets\"")));
+}
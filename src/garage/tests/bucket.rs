@@ -1,6 +1,6 @@
 use crate::common;
 use crate::common::ext::CommandExt;
-use aws_sdk_s3::operation::delete_bucket::DeleteBucketOutput;
+use aws_sdk_s3::operation::RequestId;
 
 #[tokio::test]
 async fn test_bucket_all() {
@@ -87,7 +87,8 @@ async fn test_bucket_all() {
 			.await
 			.unwrap();
 
-		assert_eq!(r, DeleteBucketOutput::builder().build());
+		// The response is empty, except for the ID of the request
+		assert!(r.request_id().is_some());
 	}
 	{
 		// Check bucket is deleted with List buckets
@@ -75,6 +75,7 @@ api_bind_addr = "127.0.0.1:{k2v_port}"
 bind_addr = "127.0.0.1:{web_port}"
 root_domain = ".web.garage"
 index = "index.html"
+path_style = true
 
 [admin]
 api_bind_addr = "127.0.0.1:{admin_port}"
@@ -609,3 +609,109 @@ async fn test_batch() {
 		])
 	);
 }
+
+async fn insert_items(ctx: &common::Context, bucket: &str, keys: &[&str]) {
+	let items = keys
+		.iter()
+		.map(|sk| json!({"pk": "root", "sk": sk, "ct": null, "v": BASE64_STANDARD.encode(sk)}))
+		.collect::<Vec<_>>();
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.to_string())
+		.body(serde_json::to_vec(&items).unwrap())
+		.method(Method::POST)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+}
+
+async fn delete_item(ctx: &common::Context, bucket: &str, key: &str) {
+	let query = json!([{"partitionKey": "root", "start": key, "singleItem": true}]);
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.to_string())
+		.query_param("delete", Option::<&str>::None)
+		.body(serde_json::to_vec(&query).unwrap())
+		.method(Method::POST)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+}
+
+async fn read_page(
+	ctx: &common::Context,
+	bucket: &str,
+	start: Option<&str>,
+	reverse: bool,
+) -> (Vec<String>, Option<String>) {
+	let query = json!([{"partitionKey": "root", "start": start, "limit": 2, "reverse": reverse}]);
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.to_string())
+		.query_param("search", Option::<&str>::None)
+		.body(serde_json::to_vec(&query).unwrap())
+		.method(Method::POST)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let json_res = json_body(res).await;
+	let keys = json_res[0]["items"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|item| item["sk"].as_str().unwrap().to_string())
+		.collect();
+	let next_start = json_res[0]["nextStart"].as_str().map(str::to_string);
+	(keys, next_start)
+}
+
+#[tokio::test]
+async fn test_batch_pagination_with_mutations() {
+	let ctx = common::context();
+
+	for (reverse, bucket, inserted, expected) in [
+		(
+			false,
+			"test-k2v-pagination",
+			"g",
+			vec!["b", "d", "g", "h", "j"],
+		),
+		(
+			true,
+			"test-k2v-pagination-reverse",
+			"e",
+			vec!["j", "h", "e", "d", "b"],
+		),
+	] {
+		let bucket = ctx.create_bucket(bucket);
+		insert_items(&ctx, &bucket, &["b", "d", "f", "h", "j"]).await;
+
+		let mut listed = vec![];
+		let mut start = None;
+		loop {
+			let (keys, next_start) = read_page(&ctx, &bucket, start.as_deref(), reverse).await;
+			if listed.is_empty() {
+				// After the first page, delete nextStart itself,
+				// and insert a key right after it
+				assert_eq!(next_start.as_deref(), Some("f"));
+				delete_item(&ctx, &bucket, "f").await;
+				insert_items(&ctx, &bucket, &[inserted]).await;
+			}
+			listed.extend(keys);
+			match next_start {
+				Some(next_start) => start = Some(next_start),
+				None => break,
+			}
+		}
+
+		// No key is listed twice, and keys that existed during the whole
+		// listing or were inserted after nextStart are all listed
+		assert_eq!(listed, expected);
+	}
+}
@@ -7,6 +7,7 @@ use base64::prelude::*;
 use serde_json::json;
 
 use crate::json_body;
+use garage_api_common::encoding::uri_encode;
 use http_body_util::BodyExt;
 use hyper::{Method, StatusCode};
 
@@ -726,3 +727,464 @@ async fn test_item_return_format() {
 	let res_body = json_body(res).await;
 	assert_json_eq!(res_body, json!([null]));
 }
+
+#[tokio::test]
+async fn test_conditional_write() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("test-k2v-conditional-write");
+	let (ctx, bucket) = (&ctx, &bucket);
+
+	let put = move |if_match: Option<String>, body: &'static [u8]| {
+		let mut req = ctx.k2v.request.builder(bucket.clone());
+		req.path("item")
+			.query_param("sort_key", Some("sk"))
+			.body(body.to_vec())
+			.method(Method::PUT);
+		if let Some(ct) = if_match {
+			req.signed_header("if-match", format!("\"{}\"", ct));
+		}
+		async move { req.send().await.unwrap() }
+	};
+	let read_token = move || async move {
+		let res = ctx
+			.k2v
+			.request
+			.builder(bucket.clone())
+			.path("item")
+			.query_param("sort_key", Some("sk"))
+			.signed_header("accept", "application/octet-stream")
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::OK);
+		let ct = res.headers()["x-garage-causality-token"]
+			.to_str()
+			.unwrap()
+			.to_string();
+		(ct, res.into_body().collect().await.unwrap().to_bytes())
+	};
+
+	// Create only if never written, with the empty causality token
+	let res = put(Some("AAAAAAAAAAA".into()), b"v1").await;
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+	let res = put(Some("AAAAAAAAAAA".into()), b"v1 again").await;
+	assert_eq!(res.status(), StatusCode::CONFLICT);
+
+	// Read-modify-write with an up-to-date token
+	let (ct1, value) = read_token().await;
+	assert_eq!(value, &b"v1"[..]);
+	let res = put(Some(ct1.clone()), b"v2").await;
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+	let (ct2, value) = read_token().await;
+	assert_eq!(value, &b"v2"[..]);
+
+	// A write with the old token is rejected, and the response gives
+	// the current token; the item is not modified
+	let res = put(Some(ct1), b"lost update").await;
+	assert_eq!(res.status(), StatusCode::CONFLICT);
+	assert_eq!(res.headers()["x-garage-causality-token"], ct2.as_str());
+	let (ct, value) = read_token().await;
+	assert_eq!(value, &b"v2"[..]);
+	assert_eq!(ct, ct2);
+
+	// Same for deletions
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("item")
+		.query_param("sort_key", Some("sk"))
+		.method(Method::DELETE)
+		.signed_header("if-match", "AAAAAAAAAAA")
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::CONFLICT);
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.path("item")
+		.query_param("sort_key", Some("sk"))
+		.method(Method::DELETE)
+		.signed_header("if-match", ct2)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+	// Invalid tokens are rejected
+	let res = put(Some("invalid".into()), b"v3").await;
+	assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+async fn read_index_delimited(
+	ctx: &common::Context,
+	bucket: &str,
+	params: &[(&str, String)],
+) -> (Vec<String>, Vec<String>, Option<String>) {
+	let mut req = ctx.k2v.request.builder(bucket.to_string());
+	req.query_param("delimiter", Some("/"));
+	for (k, v) in params {
+		req.query_param(k, Some(uri_encode(v, true)));
+	}
+	let res = req.send().await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let res_body = json_body(res).await;
+	let to_strings = |v: &serde_json::Value| {
+		v.as_array()
+			.unwrap()
+			.iter()
+			.map(|x| x.as_str().unwrap().to_string())
+			.collect::<Vec<_>>()
+	};
+	let pks = res_body["partitionKeys"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|x| x["pk"].as_str().unwrap().to_string())
+		.collect();
+	let cps = to_strings(&res_body["commonPrefixes"]);
+	let next_start = res_body["nextStart"].as_str().map(str::to_string);
+	(pks, cps, next_start)
+}
+
+#[tokio::test]
+async fn test_index_delimiter() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("test-k2v-index-delimiter");
+
+	for pk in ["a", "b/1", "b/2", "b/3/x", "c/1/x", "c/1/y", "c/2", "d"] {
+		let res = ctx
+			.k2v
+			.request
+			.builder(bucket.clone())
+			.path(pk)
+			.query_param("sort_key", Some("sk"))
+			.body(b"value".to_vec())
+			.method(Method::PUT)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::NO_CONTENT);
+	}
+	tokio::time::sleep(Duration::from_millis(100)).await;
+
+	// Whole listing
+	let (pks, cps, next_start) = read_index_delimited(&ctx, &bucket, &[]).await;
+	assert_eq!(pks, ["a", "d"]);
+	assert_eq!(cps, ["b/", "c/"]);
+	assert_eq!(next_start, None);
+
+	// Nested delimiters, with a prefix
+	let (pks, cps, _) = read_index_delimited(&ctx, &bucket, &[("prefix", "c/".into())]).await;
+	assert_eq!(pks, ["c/2"]);
+	assert_eq!(cps, ["c/1/"]);
+	let (pks, cps, _) = read_index_delimited(&ctx, &bucket, &[("prefix", "b/".into())]).await;
+	assert_eq!(pks, ["b/1", "b/2"]);
+	assert_eq!(cps, ["b/3/"]);
+
+	// Paginated listing, in both directions: each common prefix
+	// is returned once, even if its keys span several pages
+	for (reverse, expected) in [
+		(false, ["a", "b/", "c/", "d"]),
+		(true, ["d", "c/", "b/", "a"]),
+	] {
+		let mut listed = vec![];
+		let mut start = None;
+		loop {
+			let mut params = vec![("limit", "1".to_string()), ("reverse", reverse.to_string())];
+			if let Some(s) = start.take() {
+				params.push(("start", s));
+			}
+			let (pks, cps, next_start) = read_index_delimited(&ctx, &bucket, &params).await;
+			assert_eq!(pks.len() + cps.len(), 1);
+			listed.extend(pks);
+			listed.extend(cps);
+			match next_start {
+				Some(ns) => start = Some(ns),
+				None => break,
+			}
+		}
+		assert_eq!(listed, expected);
+	}
+
+	// Without a delimiter, there is no commonPrefixes field
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.clone())
+		.send()
+		.await
+		.unwrap();
+	let res_body = json_body(res).await;
+	assert!(res_body.get("commonPrefixes").is_none());
+	assert_eq!(res_body["partitionKeys"].as_array().unwrap().len(), 8);
+}
+
+async fn read_index(
+	ctx: &common::Context,
+	bucket: &str,
+	params: &[(&str, String)],
+) -> serde_json::Value {
+	let mut req = ctx.k2v.request.builder(bucket.to_string());
+	for (k, v) in params {
+		req.query_param(k, Some(uri_encode(v, true)));
+	}
+	let res = req.send().await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	json_body(res).await
+}
+
+fn index_pks(res_body: &serde_json::Value) -> Vec<String> {
+	res_body["partitionKeys"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|x| x["pk"].as_str().unwrap().to_string())
+		.collect()
+}
+
+/// Partition key `a` has one value, `b` three values in three items,
+/// and `c` two conflicting values in a single item. All values are 6 bytes long.
+async fn put_index_items(ctx: &common::Context, bucket: &str) {
+	let items = [
+		("a", "1"),
+		("b", "1"),
+		("b", "2"),
+		("b", "3"),
+		("c", "1"),
+		("c", "1"),
+	];
+	for (i, (pk, sk)) in items.iter().enumerate() {
+		let res = ctx
+			.k2v
+			.request
+			.builder(bucket.to_string())
+			.path(pk)
+			.query_param("sort_key", Some(sk))
+			.body(format!("value{}", i).into_bytes())
+			.method(Method::PUT)
+			.send()
+			.await
+			.unwrap();
+		assert_eq!(res.status(), StatusCode::NO_CONTENT);
+	}
+	tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+#[tokio::test]
+async fn test_index_filters() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("test-k2v-index-filters");
+	put_index_items(&ctx, &bucket).await;
+
+	let res_body = read_index(&ctx, &bucket, &[]).await;
+	assert_eq!(index_pks(&res_body), ["a", "b", "c"]);
+
+	for (params, expected) in [
+		(vec![("min_entries", "2")], vec!["b"]),
+		(vec![("min_values", "2")], vec!["b", "c"]),
+		(
+			vec![("min_entries", "1"), ("min_values", "2")],
+			vec!["b", "c"],
+		),
+		(vec![("min_entries", "2"), ("min_values", "2")], vec!["b"]),
+		(vec![("min_entries", "4")], vec![]),
+	] {
+		let params = params
+			.into_iter()
+			.map(|(k, v)| (k, v.to_string()))
+			.collect::<Vec<_>>();
+		let res_body = read_index(&ctx, &bucket, &params).await;
+		assert_eq!(index_pks(&res_body), expected, "{:?}", params);
+		assert_eq!(res_body["more"], false);
+	}
+
+	// With pagination, pages whose partition keys are all filtered out
+	// are empty but still allow to continue the listing
+	let mut pages = vec![];
+	let mut start = None;
+	loop {
+		let mut params = vec![("limit", "1".to_string()), ("min_values", "2".to_string())];
+		if let Some(s) = start.take() {
+			params.push(("start", s));
+		}
+		let res_body = read_index(&ctx, &bucket, &params).await;
+		pages.push(index_pks(&res_body));
+		match res_body["nextStart"].as_str() {
+			Some(ns) => {
+				assert_eq!(res_body["more"], true);
+				start = Some(ns.to_string());
+			}
+			None => break,
+		}
+	}
+	assert_eq!(pages, [vec![], vec!["b"], vec!["c"]]);
+}
+
+#[tokio::test]
+async fn test_index_totals() {
+	let ctx = common::context();
+	let bucket = ctx.create_bucket("test-k2v-index-totals");
+	put_index_items(&ctx, &bucket).await;
+
+	// Totals are only returned when requested
+	let res_body = read_index(&ctx, &bucket, &[]).await;
+	assert!(res_body.get("totals").is_none());
+
+	let totals = |res_body: &serde_json::Value| {
+		let t = &res_body["totals"];
+		["entries", "conflicts", "values", "bytes"].map(|k| t[k].as_i64().unwrap())
+	};
+
+	let res_body = read_index(&ctx, &bucket, &[("totals", "true".into())]).await;
+	assert_eq!(totals(&res_body), [5, 1, 6, 36]);
+
+	// Totals only cover the partition keys that are returned
+	let res_body = read_index(
+		&ctx,
+		&bucket,
+		&[("totals", "true".into()), ("min_values", "2".into())],
+	)
+	.await;
+	assert_eq!(index_pks(&res_body), ["b", "c"]);
+	assert_eq!(totals(&res_body), [4, 1, 5, 30]);
+
+	// The totals of the pages of a paginated listing add up
+	// to the totals of the whole listing
+	let mut sum = [0; 4];
+	let mut start = None;
+	loop {
+		let mut params = vec![("limit", "1".to_string()), ("totals", "true".to_string())];
+		if let Some(s) = start.take() {
+			params.push(("start", s));
+		}
+		let res_body = read_index(&ctx, &bucket, &params).await;
+		assert_eq!(index_pks(&res_body).len(), 1);
+		for (s, t) in sum.iter_mut().zip(totals(&res_body)) {
+			*s += t;
+		}
+		match res_body["nextStart"].as_str() {
+			Some(ns) => start = Some(ns.to_string()),
+			None => break,
+		}
+	}
+	assert_eq!(sum, [5, 1, 6, 36]);
+}
+
+async fn insert_partitions(ctx: &common::Context, bucket: &str, pks: &[&str]) {
+	let items = pks
+		.iter()
+		.map(|pk| json!({"pk": pk, "sk": "x", "ct": null, "v": BASE64_STANDARD.encode(pk)}))
+		.collect::<Vec<_>>();
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.to_string())
+		.body(serde_json::to_vec(&items).unwrap())
+		.method(Method::POST)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::NO_CONTENT);
+}
+
+async fn delete_partition(ctx: &common::Context, bucket: &str, pk: &str) {
+	let query = json!([{"partitionKey": pk, "start": "x", "singleItem": true}]);
+	let res = ctx
+		.k2v
+		.request
+		.builder(bucket.to_string())
+		.query_param("delete", Option::<&str>::None)
+		.body(serde_json::to_vec(&query).unwrap())
+		.method(Method::POST)
+		.send()
+		.await
+		.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+}
+
+async fn read_index_page(
+	ctx: &common::Context,
+	bucket: &str,
+	start: Option<&str>,
+	reverse: bool,
+) -> (Vec<String>, Option<String>) {
+	let mut req = ctx.k2v.request.builder(bucket.to_string());
+	req.query_param("limit", Some("2"))
+		.query_param("reverse", Some(reverse.to_string()));
+	if let Some(start) = start {
+		req.query_param("start", Some(start));
+	}
+	let res = req.send().await.unwrap();
+	assert_eq!(res.status(), StatusCode::OK);
+	let res_body = json_body(res).await;
+	let pks = res_body["partitionKeys"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|pk| pk["pk"].as_str().unwrap().to_string())
+		.collect();
+	let next_start = res_body["nextStart"].as_str().map(str::to_string);
+	(pks, next_start)
+}
+
+#[tokio::test]
+async fn test_index_pagination_with_mutations() {
+	let ctx = common::context();
+
+	for (reverse, bucket, inserted, expected) in [
+		(
+			false,
+			"test-k2v-index-pagination",
+			"g",
+			vec!["b", "d", "g", "h", "j"],
+		),
+		(
+			true,
+			"test-k2v-index-pagination-reverse",
+			"e",
+			vec!["j", "h", "e", "d", "b"],
+		),
+	] {
+		let bucket = ctx.create_bucket(bucket);
+		insert_partitions(&ctx, &bucket, &["b", "d", "f", "h", "j"]).await;
+		tokio::time::sleep(Duration::from_millis(100)).await;
+
+		let mut listed = vec![];
+		let mut start = None;
+		loop {
+			let (pks, next_start) = read_index_page(&ctx, &bucket, start.as_deref(), reverse).await;
+			if listed.is_empty() {
+				// After the first page, delete the partition of nextStart, create
+				// one right after it, and add an item to a partition already listed
+				assert_eq!(next_start.as_deref(), Some("f"));
+				delete_partition(&ctx, &bucket, "f").await;
+				insert_partitions(&ctx, &bucket, &[inserted]).await;
+				let res = ctx
+					.k2v
+					.request
+					.builder(bucket.clone())
+					.path(pks[0].clone())
+					.query_param("sort_key", Some("y"))
+					.body(b"more".to_vec())
+					.method(Method::PUT)
+					.send()
+					.await
+					.unwrap();
+				assert_eq!(res.status(), StatusCode::NO_CONTENT);
+				tokio::time::sleep(Duration::from_millis(100)).await;
+			}
+			listed.extend(pks);
+			match next_start {
+				Some(next_start) => start = Some(next_start),
+				None => break,
+			}
+		}
+
+		// No partition key is listed twice, and partition keys that existed during
+		// the whole listing or were created after nextStart are all listed
+		assert_eq!(listed, expected);
+	}
+}
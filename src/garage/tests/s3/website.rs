@@ -323,6 +323,37 @@ async fn test_website_s3_api() {
 		assert_eq!(resp.headers().get(LOCATION).unwrap(), "https://perdu.com");
 	}
 
+	// The redirect location is object metadata, so it also applies to objects
+	// whose content is stored in data blocks rather than inline
+	{
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key("test-redirect-large.html")
+			.website_redirect_location("/new-location.html")
+			.body(ByteStream::from(vec![b'x'; 16 * 1024]))
+			.send()
+			.await
+			.unwrap();
+
+		for method in ["GET", "HEAD"] {
+			let req = Request::builder()
+				.method(method)
+				.uri(format!(
+					"http://127.0.0.1:{}/test-redirect-large.html",
+					ctx.garage.web_port
+				))
+				.header("Host", format!("{}.web.garage", BCKT_NAME))
+				.body(Body::new(Bytes::new()))
+				.unwrap();
+
+			let resp = client.request(req).await.unwrap();
+
+			assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+			assert_eq!(resp.headers().get(LOCATION).unwrap(), "/new-location.html");
+		}
+	}
+
 	// Test CORS with an allowed preflight request
 	{
 		let req = Request::builder()
@@ -533,3 +564,92 @@ async fn test_website_check_domain() {
 		})
 	);
 }
+
+#[tokio::test]
+async fn test_website_path_style() {
+	const BCKT_NAME: &str = "my-path-style-website";
+	// A bucket named after the root domain of the web endpoint
+	const ROOT_BCKT_NAME: &str = "web.garage";
+	let ctx = common::context();
+	let client = Client::builder(TokioExecutor::new()).build_http();
+	// Bucket names with dots cannot be used with virtual-hosted-style S3 requests
+	let s3_client = aws_sdk_s3::Client::from_conf(
+		ctx.client
+			.config()
+			.to_builder()
+			.force_path_style(true)
+			.build(),
+	);
+
+	for (bucket, body) in [
+		(BCKT_NAME, BODY.as_ref()),
+		(ROOT_BCKT_NAME, BODY_ERR.as_ref()),
+	] {
+		ctx.create_bucket(bucket);
+		s3_client
+			.put_object()
+			.bucket(bucket)
+			.key("index.html")
+			.body(ByteStream::from_static(body))
+			.send()
+			.await
+			.unwrap();
+		ctx.garage
+			.command()
+			.args(["bucket", "website", "--allow", bucket])
+			.quiet()
+			.expect_success_status("Could not allow website on bucket");
+	}
+
+	let req = |host: &str, path: &str| {
+		Request::builder()
+			.method("GET")
+			.uri(format!("http://127.0.0.1:{}{}", ctx.garage.web_port, path))
+			.header("Host", host)
+			.body(Body::new(Bytes::new()))
+			.unwrap()
+	};
+
+	// Requests to an IP address are path-style
+	let ip_host = format!("127.0.0.1:{}", ctx.garage.web_port);
+	let resp = client
+		.request(req(&ip_host, &format!("/{}/", BCKT_NAME)))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		resp.into_body().collect().await.unwrap().to_bytes(),
+		BODY.as_ref()
+	);
+
+	// The root of a bucket without a trailing slash is redirected
+	let resp = client
+		.request(req(&ip_host, &format!("/{}", BCKT_NAME)))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::FOUND);
+	assert_eq!(
+		resp.headers().get(LOCATION).unwrap(),
+		&format!("/{}/", BCKT_NAME)
+	);
+
+	// Requests to the root domain are served by the bucket named after it,
+	// if there is one
+	let resp = client.request(req("web.garage", "/")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		resp.into_body().collect().await.unwrap().to_bytes(),
+		BODY_ERR.as_ref()
+	);
+
+	// Requests with a bucket in the host name are unchanged
+	let resp = client
+		.request(req(&format!("{}.web.garage", BCKT_NAME), "/"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		resp.into_body().collect().await.unwrap().to_bytes(),
+		BODY.as_ref()
+	);
+}
@@ -119,7 +119,114 @@ mod v08 {
 	impl garage_util::migrate::InitialFormat for Bucket {}
 }
 
-pub use v08::*;
+mod v11 {
+	use crate::permission::BucketKeyPerm;
+	use garage_util::crdt;
+	use garage_util::data::{fasthash, Uuid};
+	use serde::{Deserialize, Serialize};
+
+	use super::v08;
+
+	pub use v08::{
+		BucketQuotas, CorsRule, LifecycleExpiration, LifecycleFilter, LifecycleRule, WebsiteConfig,
+	};
+
+	/// A bucket is a collection of objects
+	///
+	/// Its parameters are not directly accessible as:
+	///  - It must be possible to merge parameters, hence the use of a LWW CRDT.
+	///  - A bucket has 2 states, Present or Deleted and parameters make sense only if present.
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct Bucket {
+		/// ID of the bucket
+		pub id: Uuid,
+		/// State, and configuration if not deleted, of the bucket
+		pub state: crdt::Deletable<BucketParams>,
+	}
+
+	/// Configuration for a bucket
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct BucketParams {
+		/// Bucket's creation date
+		pub creation_date: u64,
+		/// Map of keys that have or had access to the bucket, with the last
+		/// permissions that were set for them. Only used to order permission
+		/// changes: the permissions of a key are given by `authorized_key_set`.
+		pub authorized_keys: crdt::Map<String, BucketKeyPerm>,
+		/// Set of the permissions given to keys on the bucket, as pairs of a key ID
+		/// and of its permissions. Setting the permissions of a key removes the pairs
+		/// of that key that have been observed, so that a revocation does not cancel
+		/// a grant made concurrently on another node.
+		pub authorized_key_set: crdt::OrSet<(String, BucketKeyPerm)>,
+
+		/// Map of aliases that are or have been given to this bucket
+		/// in the global namespace
+		/// (not authoritative: this is just used as an indication to
+		/// map back to aliases when doing ListBuckets)
+		pub aliases: crdt::LwwMap<String, bool>,
+		/// Map of aliases that are or have been given to this bucket
+		/// in namespaces local to keys
+		/// key = (access key id, alias name)
+		pub local_aliases: crdt::LwwMap<(String, String), bool>,
+
+		/// Whether this bucket is allowed for website access
+		/// (under all of its global alias names),
+		/// and if so, the website configuration XML document
+		pub website_config: crdt::Lww<Option<WebsiteConfig>>,
+		/// CORS rules
+		pub cors_config: crdt::Lww<Option<Vec<CorsRule>>>,
+		/// Lifecycle configuration
+		pub lifecycle_config: crdt::Lww<Option<Vec<LifecycleRule>>>,
+		/// Bucket quotas
+		pub quotas: crdt::Lww<BucketQuotas>,
+	}
+
+	impl garage_util::migrate::Migrate for Bucket {
+		const VERSION_MARKER: &'static [u8] = b"G11bkt";
+
+		type Previous = v08::Bucket;
+
+		fn migrate(old: v08::Bucket) -> Bucket {
+			Bucket {
+				id: old.id,
+				state: match old.state {
+					crdt::Deletable::Present(p) => crdt::Deletable::Present(migrate_params(p)),
+					crdt::Deletable::Deleted => crdt::Deletable::Deleted,
+				},
+			}
+		}
+	}
+
+	fn migrate_params(old: v08::BucketParams) -> BucketParams {
+		// The tags of the additions to the authorized key set are derived
+		// from the permissions, so that all nodes that migrate a given bucket
+		// obtain exactly the same set
+		let mut authorized_key_set = crdt::OrSet::new();
+		for (key_id, perm) in old.authorized_keys.items().iter() {
+			if perm.is_any() {
+				let tag = fasthash(format!("{}:{}", key_id, perm.timestamp).as_bytes());
+				crdt::Crdt::merge(
+					&mut authorized_key_set,
+					&crdt::OrSet::add_mutator_with_tag((key_id.clone(), *perm), tag),
+				);
+			}
+		}
+
+		BucketParams {
+			creation_date: old.creation_date,
+			authorized_keys: old.authorized_keys,
+			authorized_key_set,
+			aliases: old.aliases,
+			local_aliases: old.local_aliases,
+			website_config: old.website_config,
+			cors_config: old.cors_config,
+			lifecycle_config: old.lifecycle_config,
+			quotas: old.quotas,
+		}
+	}
+}
+
+pub use v11::*;
 
 impl AutoCrdt for BucketQuotas {
 	const WARN_IF_DIFFERENT: bool = true;
@@ -131,6 +238,7 @@ impl BucketParams {
 		BucketParams {
 			creation_date: now_msec(),
 			authorized_keys: crdt::Map::new(),
+			authorized_key_set: crdt::OrSet::new(),
 			aliases: crdt::LwwMap::new(),
 			local_aliases: crdt::LwwMap::new(),
 			website_config: crdt::Lww::new(None),
@@ -145,6 +253,7 @@ impl Crdt for BucketParams {
 	fn merge(&mut self, o: &Self) {
 		self.creation_date = std::cmp::min(self.creation_date, o.creation_date);
 		self.authorized_keys.merge(&o.authorized_keys);
+		self.authorized_key_set.merge(&o.authorized_key_set);
 
 		self.aliases.merge(&o.aliases);
 		self.local_aliases.merge(&o.local_aliases);
@@ -156,6 +265,59 @@ impl Crdt for BucketParams {
 	}
 }
 
+impl BucketParams {
+	/// Get the permissions of a key on this bucket, from the authorized key set.
+	/// If permissions were given to the key concurrently on several nodes, the key
+	/// has all of them.
+	pub fn key_permissions(&self, key_id: &String) -> BucketKeyPerm {
+		self.authorized_key_set
+			.items()
+			.filter(|(k, _)| k == key_id)
+			.fold(BucketKeyPerm::NO_PERMISSIONS, |acc, (_, perm)| {
+				BucketKeyPerm {
+					timestamp: std::cmp::max(acc.timestamp, perm.timestamp),
+					allow_read: acc.allow_read || perm.allow_read,
+					allow_write: acc.allow_write || perm.allow_write,
+					allow_owner: acc.allow_owner || perm.allow_owner,
+				}
+			})
+	}
+
+	/// Get the keys that have some permissions on this bucket, with their permissions
+	pub fn authorized_key_permissions(&self) -> Vec<(String, BucketKeyPerm)> {
+		let mut key_ids = self
+			.authorized_key_set
+			.items()
+			.map(|(k, _)| k)
+			.collect::<Vec<_>>();
+		key_ids.dedup();
+		key_ids
+			.into_iter()
+			.map(|k| (k.clone(), self.key_permissions(k)))
+			.collect()
+	}
+
+	/// Replace the authorized keys of these parameters by mutators that set the
+	/// permissions of a key, to be merged in the stored bucket. The permissions of
+	/// the key that we have observed are removed from the authorized key set, and
+	/// replaced by the new ones unless they are empty. A revocation thus does not
+	/// cancel a grant made concurrently on another node, but it resets the
+	/// permissions of the key, so that a later grant of some permissions does not
+	/// give back the others.
+	pub fn set_key_permissions(&mut self, key_id: &String, perm: BucketKeyPerm) {
+		self.authorized_keys = crdt::Map::put_mutator(key_id.clone(), perm);
+
+		let mut set_mutator = crdt::OrSet::new();
+		for x in self.authorized_key_set.items().filter(|(k, _)| k == key_id) {
+			set_mutator.merge(&self.authorized_key_set.remove_mutator(x));
+		}
+		if perm.is_any() {
+			set_mutator.merge(&crdt::OrSet::add_mutator((key_id.clone(), perm)));
+		}
+		self.authorized_key_set = set_mutator;
+	}
+}
+
 pub fn parse_lifecycle_date(date: &str) -> Result<chrono::NaiveDate, &'static str> {
 	use chrono::prelude::*;
 
@@ -213,12 +375,12 @@ impl Bucket {
 		self.state.as_option_mut()
 	}
 
-	/// Return the list of authorized keys, when each was updated, and the permission associated to
-	/// the key
-	pub fn authorized_keys(&self) -> &[(String, BucketKeyPerm)] {
+	/// Return the list of keys that have some permissions on the bucket,
+	/// and their permissions
+	pub fn authorized_keys(&self) -> Vec<(String, BucketKeyPerm)> {
 		self.params()
-			.map(|s| s.authorized_keys.items())
-			.unwrap_or(&[])
+			.map(|s| s.authorized_key_permissions())
+			.unwrap_or_default()
 	}
 
 	pub fn aliases(&self) -> &[(String, u64, bool)] {
@@ -261,3 +423,124 @@ impl TableSchema for BucketTable {
 		filter.apply(entry.is_deleted())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn set_key_permissions(params: &mut BucketParams, key_id: &String, perm: BucketKeyPerm) {
+		let mut update = params.clone();
+		update.set_key_permissions(key_id, perm);
+		params.merge(&update);
+	}
+
+	#[test]
+	fn test_revoke_then_partial_grant() {
+		let key_id = "GK31c2f218a2e44f485b94239e".to_string();
+		let mut params = BucketParams::new();
+
+		set_key_permissions(
+			&mut params,
+			&key_id,
+			BucketKeyPerm {
+				timestamp: 1,
+				allow_read: true,
+				allow_write: true,
+				allow_owner: true,
+			},
+		);
+		assert!(params.key_permissions(&key_id).allow_owner);
+
+		set_key_permissions(
+			&mut params,
+			&key_id,
+			BucketKeyPerm {
+				timestamp: 2,
+				..BucketKeyPerm::NO_PERMISSIONS
+			},
+		);
+		assert!(!params.key_permissions(&key_id).is_any());
+		assert!(!params.authorized_keys.get(&key_id).unwrap().is_any());
+
+		// Allow only reads, starting from the current permissions of the key
+		// as the admin API does
+		let mut perm = params.key_permissions(&key_id);
+		perm.timestamp = 3;
+		perm.allow_read = true;
+		set_key_permissions(&mut params, &key_id, perm);
+
+		let perm = params.key_permissions(&key_id);
+		assert!(perm.allow_read);
+		assert!(!perm.allow_write);
+		assert!(!perm.allow_owner);
+	}
+
+	#[test]
+	fn test_concurrent_grant_and_revoke() {
+		let key_id = "GK31c2f218a2e44f485b94239e".to_string();
+		let mut base = BucketParams::new();
+		set_key_permissions(
+			&mut base,
+			&key_id,
+			BucketKeyPerm {
+				timestamp: 1,
+				allow_read: true,
+				..BucketKeyPerm::NO_PERMISSIONS
+			},
+		);
+
+		// Node a gives write permissions to the key, while node b revokes the
+		// permissions of the key without having observed that grant, with a
+		// more recent timestamp
+		let mut a = base.clone();
+		set_key_permissions(
+			&mut a,
+			&key_id,
+			BucketKeyPerm {
+				timestamp: 2,
+				allow_read: true,
+				allow_write: true,
+				allow_owner: false,
+			},
+		);
+		let mut b = base.clone();
+		set_key_permissions(
+			&mut b,
+			&key_id,
+			BucketKeyPerm {
+				timestamp: 3,
+				..BucketKeyPerm::NO_PERMISSIONS
+			},
+		);
+
+		// The grant wins, whatever the order of the merges
+		let mut ab = a.clone();
+		ab.merge(&b);
+		let mut ba = b.clone();
+		ba.merge(&a);
+		assert_eq!(ab, ba);
+		let perm = ab.key_permissions(&key_id);
+		assert!(perm.allow_read);
+		assert!(perm.allow_write);
+		assert!(!perm.allow_owner);
+		assert_eq!(
+			ab.authorized_key_permissions(),
+			vec![(key_id.clone(), perm)]
+		);
+
+		// A revocation that has observed the grant wins over it
+		let mut c = ab.clone();
+		set_key_permissions(
+			&mut c,
+			&key_id,
+			BucketKeyPerm {
+				timestamp: 4,
+				..BucketKeyPerm::NO_PERMISSIONS
+			},
+		);
+		c.merge(&a);
+		c.merge(&b);
+		assert!(!c.key_permissions(&key_id).is_any());
+		assert!(c.authorized_key_permissions().is_empty());
+	}
+}
@@ -0,0 +1,143 @@
+//! Version of the format of the data stored by a node, which is recorded in its
+//! metadata directory so that a binary that cannot read this data refuses to start
+//! instead of failing later when decoding it.
+
+use std::path::Path;
+
+use garage_util::error::Error;
+use garage_util::persister::Persister;
+
+/// Version of the format of the metadata and data stored by this version of Garage.
+/// It is increased when the data written by previous versions can no longer be
+/// read without being upgraded first with `garage upgrade-format`; changes that
+/// are handled by the `Migrate` trait when decoding do not require a new version.
+pub const DATASTORE_FORMAT_VERSION: u64 = 1;
+
+const FORMAT_VERSION_FILE_NAME: &str = "format_version";
+
+mod v1 {
+	use serde::{Deserialize, Serialize};
+
+	#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+	pub struct DatastoreFormat {
+		pub version: u64,
+	}
+
+	impl garage_util::migrate::InitialFormat for DatastoreFormat {
+		const VERSION_MARKER: &'static [u8] = b"G1dsfmt";
+	}
+}
+
+pub use v1::*;
+
+/// Check that the data of the node was written with the format version of this
+/// binary. The version is recorded if the metadata directory has none yet, either
+/// because it is new or because it was written before the version was recorded.
+pub fn check_format_version(metadata_dir: &Path) -> Result<(), Error> {
+	check_format_version_is(metadata_dir, DATASTORE_FORMAT_VERSION)
+}
+
+fn check_format_version_is(metadata_dir: &Path, expected: u64) -> Result<(), Error> {
+	match read_format_version(metadata_dir)? {
+		Some(found) if found == expected => Ok(()),
+		Some(found) if found < expected => Err(Error::Message(format!(
+			"The data in metadata directory `{}` uses format version {}, but this version of Garage requires format version {}. Stop all other Garage processes using this directory, then run `garage upgrade-format --yes` with this version of Garage to upgrade it.",
+			metadata_dir.display(),
+			found,
+			expected
+		))),
+		Some(found) => Err(Error::Message(format!(
+			"The data in metadata directory `{}` uses format version {}, which was written by a more recent version of Garage, but this version of Garage only supports format version {}. Run a version of Garage that supports format version {}.",
+			metadata_dir.display(),
+			found,
+			expected,
+			found
+		))),
+		None => write_format_version(metadata_dir, expected),
+	}
+}
+
+/// Record that the data of the node uses the format version of this binary, once
+/// it has been upgraded from a previous format version. Returns the previous version.
+pub fn upgrade_format_version(metadata_dir: &Path) -> Result<u64, Error> {
+	upgrade_format_version_to(metadata_dir, DATASTORE_FORMAT_VERSION)
+}
+
+fn upgrade_format_version_to(metadata_dir: &Path, target: u64) -> Result<u64, Error> {
+	let found = read_format_version(metadata_dir)?.unwrap_or(target);
+	if found > target {
+		return Err(Error::Message(format!(
+			"The data in metadata directory `{}` uses format version {}, which is more recent than format version {} of this version of Garage. Downgrading the format is not supported.",
+			metadata_dir.display(),
+			found,
+			target
+		)));
+	}
+	// Upgrades from one format version to the next are run here, when a
+	// format version that requires an upgrade of the stored data is added.
+	write_format_version(metadata_dir, target)?;
+	Ok(found)
+}
+
+fn read_format_version(metadata_dir: &Path) -> Result<Option<u64>, Error> {
+	let persister = Persister::<DatastoreFormat>::new(metadata_dir, FORMAT_VERSION_FILE_NAME);
+	match persister.load() {
+		Ok(format) => Ok(Some(format.version)),
+		Err(_) if !metadata_dir.join(FORMAT_VERSION_FILE_NAME).exists() => Ok(None),
+		Err(e) => Err(Error::Message(format!(
+			"Unable to read the format version of the data in metadata directory `{}`: {}",
+			metadata_dir.display(),
+			e
+		))),
+	}
+}
+
+fn write_format_version(metadata_dir: &Path, version: u64) -> Result<(), Error> {
+	Persister::<DatastoreFormat>::new(metadata_dir, FORMAT_VERSION_FILE_NAME)
+		.save(&DatastoreFormat { version })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_missing_format_version() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		assert_eq!(read_format_version(&dir).unwrap(), None);
+
+		// The version of the binary is recorded when there is none yet
+		check_format_version_is(&dir, 2).unwrap();
+		assert_eq!(read_format_version(&dir).unwrap(), Some(2));
+	}
+
+	#[test]
+	fn test_matching_format_version() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		write_format_version(&dir, 2).unwrap();
+		check_format_version_is(&dir, 2).unwrap();
+		assert_eq!(read_format_version(&dir).unwrap(), Some(2));
+	}
+
+	#[test]
+	fn test_mismatched_format_version() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+
+		// Data written by an older version has to be upgraded first
+		write_format_version(&dir, 1).unwrap();
+		let err = check_format_version_is(&dir, 2).unwrap_err().to_string();
+		assert!(err.contains("uses format version 1"));
+		assert!(err.contains("requires format version 2"));
+		assert!(err.contains("garage upgrade-format"));
+		assert_eq!(upgrade_format_version_to(&dir, 2).unwrap(), 1);
+		check_format_version_is(&dir, 2).unwrap();
+
+		// Data written by a newer version cannot be read nor downgraded
+		write_format_version(&dir, 3).unwrap();
+		let err = check_format_version_is(&dir, 2).unwrap_err().to_string();
+		assert!(err.contains("uses format version 3"));
+		assert!(err.contains("only supports format version 2"));
+		assert!(upgrade_format_version_to(&dir, 2).is_err());
+		assert_eq!(read_format_version(&dir).unwrap(), Some(3));
+	}
+}
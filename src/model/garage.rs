@@ -1,6 +1,8 @@
 use std::str::FromStr;
 use std::sync::Arc;
 
+use tokio::sync::watch;
+
 use garage_net::NetworkKey;
 
 use garage_db as db;
@@ -9,6 +11,7 @@ use garage_util::background::*;
 use garage_util::config::*;
 use garage_util::error::*;
 use garage_util::persister::PersisterShared;
+use garage_util::time::RealClock;
 
 use garage_rpc::replication_mode::*;
 use garage_rpc::system::System;
@@ -26,6 +29,7 @@ use crate::s3::version_table::*;
 
 use crate::bucket_alias_table::*;
 use crate::bucket_table::*;
+use crate::format_version;
 use crate::helper;
 use crate::index_counter::*;
 use crate::key_table::*;
@@ -93,9 +97,20 @@ pub struct GarageK2V {
 impl Garage {
 	/// Create and run garage
 	pub fn new(config: Config) -> Result<Arc<Self>, Error> {
+		let (_, live_config) = watch::channel(LiveConfig::from(&config));
+		Self::new_with_live_config(config, live_config)
+	}
+
+	/// Create and run garage, taking the values of the configuration options that
+	/// can be changed at runtime from `live_config` (see `ConfigReloader`)
+	pub fn new_with_live_config(
+		config: Config,
+		live_config: watch::Receiver<LiveConfig>,
+	) -> Result<Arc<Self>, Error> {
 		// Create meta dir and data dir if they don't exist already
 		std::fs::create_dir_all(&config.metadata_dir)
 			.ok_or_message("Unable to create Garage metadata directory")?;
+		format_version::check_format_version(&config.metadata_dir)?;
 		match &config.data_dir {
 			DataDirEnum::Single(data_dir) => {
 				std::fs::create_dir_all(data_dir).ok_or_message(format!(
@@ -170,7 +185,14 @@ impl Garage {
 		};
 
 		info!("Initialize block manager...");
-		let block_manager = BlockManager::new(&db, &config, data_rep_param, system.clone())?;
+		let block_manager = BlockManager::new(
+			&db,
+			&config,
+			data_rep_param,
+			system.clone(),
+			live_config,
+			Arc::new(RealClock),
+		)?;
 		block_manager.register_bg_vars(&mut bg_vars);
 
 		// ---- admin tables ----
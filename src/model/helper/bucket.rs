@@ -1,5 +1,8 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
+use futures::stream::{self, Stream, TryStreamExt};
+
 use garage_util::data::*;
 use garage_util::error::OkOrMessage;
 use garage_util::time::*;
@@ -10,7 +13,12 @@ use crate::bucket_table::*;
 use crate::garage::Garage;
 use crate::helper::error::*;
 use crate::key_table::*;
+use crate::s3::mpu_table::*;
 use crate::s3::object_table::*;
+use crate::s3::version_table::*;
+
+/// Number of objects read at once by `BucketHelper::list_bucket_blocks`
+const BLOCK_LIST_PAGE_SIZE: usize = 100;
 
 pub struct BucketHelper<'a>(pub(crate) &'a Garage);
 
@@ -250,4 +258,272 @@ impl<'a> BucketHelper<'a> {
 
 		Ok(ret)
 	}
+
+	// ----
+
+	/// Stream the hashes of the data blocks referenced by the versions of the
+	/// objects of a bucket that are not deleted, in the order of the object keys.
+	/// This includes the blocks of uploads in progress, and of the parts of
+	/// multipart uploads in progress. Objects are read page by page, so that the
+	/// whole list never has to be held in memory. Objects stored inline in the
+	/// object table have no data blocks.
+	pub fn list_bucket_blocks(
+		&self,
+		bucket_id: Uuid,
+	) -> impl Stream<Item = Result<Hash, Error>> + 'a {
+		let garage = self.0;
+		stream::try_unfold(
+			Some(None),
+			move |start: Option<Option<String>>| async move {
+				let start = match start {
+					Some(start) => start,
+					None => return Ok(None),
+				};
+				let objects = garage
+					.object_table
+					.get_range(
+						&bucket_id,
+						start.clone(),
+						None,
+						BLOCK_LIST_PAGE_SIZE,
+						EnumerationOrder::Forward,
+					)
+					.await?;
+				let (objects, next) = split_page(objects, &start, BLOCK_LIST_PAGE_SIZE);
+				let uploads = futures::future::try_join_all(
+					multipart_upload_ids(&objects)
+						.iter()
+						.map(|upload_id| garage.mpu_table.get(upload_id, &EmptyKey)),
+				)
+				.await?
+				.into_iter()
+				.flatten()
+				.map(|mpu| (mpu.upload_id, mpu))
+				.collect::<HashMap<_, _>>();
+				let versions = futures::future::try_join_all(
+					block_version_uuids(&objects, &uploads)
+						.iter()
+						.map(|uuid| garage.version_table.get(uuid, &EmptyKey)),
+				)
+				.await?;
+
+				let hashes = referenced_blocks(versions.iter().flatten());
+				Ok::<_, Error>(Some((
+					stream::iter(hashes.into_iter().map(Ok::<_, Error>)),
+					next,
+				)))
+			},
+		)
+		.try_flatten()
+	}
+}
+
+/// Remove the first object of a page read from `start`, which is the last object
+/// of the previous page as ranges start inclusively, and compute the start of the
+/// next page. There is no next page if this one is not full.
+fn split_page(
+	objects: Vec<Object>,
+	start: &Option<String>,
+	page_size: usize,
+) -> (Vec<Object>, Option<Option<String>>) {
+	let next = match objects.last() {
+		Some(last) if objects.len() == page_size => Some(Some(last.key.clone())),
+		_ => None,
+	};
+	let objects = objects
+		.into_iter()
+		.filter(|o| Some(&o.key) != start.as_ref())
+		.collect();
+	(objects, next)
+}
+
+/// IDs of the multipart uploads in progress of objects
+fn multipart_upload_ids(objects: &[Object]) -> Vec<Uuid> {
+	objects
+		.iter()
+		.flat_map(|o| o.versions())
+		.filter(|v| v.is_uploading(Some(true)))
+		.map(|v| v.uuid)
+		.collect()
+}
+
+/// UUIDs of the versions of objects that can reference data blocks, in the
+/// order of the objects: complete versions whose data is not inline, versions
+/// being uploaded, and the parts of the multipart uploads in progress
+fn block_version_uuids(objects: &[Object], uploads: &HashMap<Uuid, MultipartUpload>) -> Vec<Uuid> {
+	let mut ret = vec![];
+	for v in objects.iter().flat_map(|o| o.versions()) {
+		match &v.state {
+			ObjectVersionState::Complete(ObjectVersionData::FirstBlock(..))
+			| ObjectVersionState::Uploading {
+				multipart: false, ..
+			} => ret.push(v.uuid),
+			ObjectVersionState::Uploading {
+				multipart: true, ..
+			} => {
+				if let Some(mpu) = uploads.get(&v.uuid).filter(|mpu| !mpu.deleted.get()) {
+					ret.extend(mpu.parts.items().iter().map(|(_, part)| part.version));
+				}
+			}
+			_ => (),
+		}
+	}
+	ret
+}
+
+/// Hashes of the data blocks of versions that are not deleted,
+/// in the order of their data
+fn referenced_blocks<'v>(versions: impl Iterator<Item = &'v Version>) -> Vec<Hash> {
+	versions
+		.filter(|v| !v.deleted.get())
+		.flat_map(|v| v.blocks.items().iter().map(|(_, b)| b.hash))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use super::*;
+	use crate::test::*;
+
+	const BUCKET_ID: [u8; 32] = [1u8; 32];
+
+	fn object(key: &str, data: Option<ObjectVersionData>) -> (Object, Uuid) {
+		let version = object_version(1000, data);
+		let uuid = version.uuid;
+		(
+			Object::new(BUCKET_ID.into(), key.into(), vec![version]),
+			uuid,
+		)
+	}
+
+	/// A version of the object `key` made of blocks of 10 bytes in its first part
+	fn object_blocks(uuid: Uuid, key: &str, hashes: &[Hash]) -> Version {
+		let blocks = hashes
+			.iter()
+			.enumerate()
+			.map(|(i, hash)| (1, i as u64 * 10, *hash, 10))
+			.collect::<Vec<_>>();
+		version(uuid, BUCKET_ID.into(), key, &blocks)
+	}
+
+	/// Store objects and versions in the local tables of a single-node Garage
+	async fn seeded_garage(
+		dir: &mktemp::Temp,
+		objects: &[Object],
+		versions: &[Version],
+		uploads: &[MultipartUpload],
+	) -> Arc<Garage> {
+		let garage = test_garage(dir.as_path());
+		assign_local_node(&garage).await;
+		for v in versions {
+			apply(&garage.version_table, v);
+		}
+		for mpu in uploads {
+			apply(&garage.mpu_table, mpu);
+		}
+		for o in objects {
+			apply(&garage.object_table, o);
+		}
+		garage
+	}
+
+	async fn list_bucket_blocks(garage: &Garage) -> Vec<Hash> {
+		garage
+			.bucket_helper()
+			.list_bucket_blocks(BUCKET_ID.into())
+			.try_collect()
+			.await
+			.unwrap()
+	}
+
+	#[tokio::test]
+	async fn test_list_bucket_blocks() {
+		let [h1, h2, h3, h4, h5, h6, h7] = [1u8, 2, 3, 4, 5, 6, 7].map(|i| blake2sum(&[i]));
+		let (o1, u1) = object(
+			"a",
+			Some(ObjectVersionData::FirstBlock(object_meta(10), h1)),
+		);
+		let (o2, _) = object(
+			"b",
+			Some(ObjectVersionData::Inline(object_meta(10), vec![0; 10])),
+		);
+		let (o3, _) = object("c", Some(ObjectVersionData::DeleteMarker));
+		let (o4, upload_id) = object("d", None);
+		let (o5, u5) = object(
+			"e",
+			Some(ObjectVersionData::FirstBlock(object_meta(10), h3)),
+		);
+		let (o6, u6) = object(
+			"f",
+			Some(ObjectVersionData::FirstBlock(object_meta(10), h7)),
+		);
+
+		// The parts of the multipart upload in progress of "d"
+		let (p1, p2) = (gen_uuid(), gen_uuid());
+		let mut mpu = MultipartUpload::new(upload_id, 1000, o4.bucket_id, o4.key.clone(), false);
+		for (part_number, version) in [(1, p1), (2, p2)] {
+			mpu.parts.put(
+				MpuPartKey {
+					part_number,
+					timestamp: 1000,
+				},
+				MpuPart {
+					version,
+					etag: None,
+					checksum: None,
+					size: None,
+				},
+			);
+		}
+
+		// A deleted version references no blocks
+		let mut v6 = object_blocks(u6, "f", &[h7]);
+		v6.deleted.set();
+
+		let garage = seeded_garage(
+			&mktemp::Temp::new_dir().unwrap(),
+			&[o1, o2, o3, o4, o5, o6],
+			&[
+				object_blocks(u1, "a", &[h1, h2]),
+				object_blocks(p1, "d", &[h4]),
+				object_blocks(p2, "d", &[h5, h6]),
+				object_blocks(u5, "e", &[h3]),
+				v6,
+			],
+			&[mpu],
+		)
+		.await;
+
+		assert_eq!(
+			list_bucket_blocks(&garage).await,
+			vec![h1, h2, h4, h5, h6, h3]
+		);
+	}
+
+	#[tokio::test]
+	async fn test_list_bucket_blocks_pages() {
+		// Enough objects for the listing to span three pages
+		let count = 2 * BLOCK_LIST_PAGE_SIZE + 5;
+		let mut objects = vec![];
+		let mut versions = vec![];
+		let mut expected = vec![];
+		for i in 0..count {
+			let key = format!("k{:04}", i);
+			let hash = blake2sum(key.as_bytes());
+			let (o, uuid) = object(
+				&key,
+				Some(ObjectVersionData::FirstBlock(object_meta(10), hash)),
+			);
+			objects.push(o);
+			versions.push(object_blocks(uuid, &key, &[hash]));
+			expected.push(hash);
+		}
+		let garage =
+			seeded_garage(&mktemp::Temp::new_dir().unwrap(), &objects, &versions, &[]).await;
+
+		// Each block is listed once, in the order of the object keys
+		assert_eq!(list_bucket_blocks(&garage).await, expected);
+	}
 }
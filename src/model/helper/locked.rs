@@ -372,10 +372,12 @@ impl<'a> LockedHelper<'a> {
 		// ---- timestamp-ensured causality barrier ----
 
 		if let Some(bstate) = bucket.state.as_option_mut() {
-			bstate.authorized_keys = Map::put_mutator(key_id.clone(), perm);
+			bstate.set_key_permissions(key_id, perm);
 			self.0.bucket_table.insert(&bucket).await?;
 		}
 
+		// The authorized buckets of the key are only an index of the buckets on
+		// which it has or had permissions, the permissions are read from the bucket
 		if let Some(kstate) = key.state.as_option_mut() {
 			kstate.authorized_buckets = Map::put_mutator(bucket_id, perm);
 			self.0.key_table.insert(&key).await?;
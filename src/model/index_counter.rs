@@ -390,3 +390,111 @@ impl<T: CountedItem> LocalCounterEntry<T> {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::s3::object_table::Object;
+
+	use garage_util::crdt::Crdt;
+
+	use garage_rpc::layout::*;
+	use garage_rpc::replication_mode::{ConsistencyMode, ReplicationFactor};
+
+	fn objects_entry(node_values: &[(Uuid, i64)]) -> CounterEntry<Object> {
+		let mut values = BTreeMap::new();
+		values.insert(
+			"objects".to_string(),
+			CounterValue {
+				node_values: node_values.iter().map(|(n, v)| (*n, (1, *v))).collect(),
+			},
+		);
+		CounterEntry {
+			pk: gen_uuid(),
+			sk: EmptyKey,
+			values,
+		}
+	}
+
+	/// Stage the given node roles and apply them as a new layout version
+	fn apply_roles(
+		mut history: LayoutHistory,
+		roles: &[(Uuid, Option<NodeRole>)],
+	) -> LayoutHistory {
+		for (node, role) in roles {
+			let update = history
+				.staging
+				.get()
+				.roles
+				.update_mutator(*node, NodeRoleV(role.clone()));
+			history.staging.get_mut().roles.merge(&update);
+		}
+		let version = history.current().version + 1;
+		history.apply_staged_changes(Some(version)).unwrap().0
+	}
+
+	#[test]
+	fn test_filtered_values_layout_versions() {
+		// `current` stores the partition in the current layout version,
+		// `draining` stored it in the previous layout version and has not
+		// finished transferring its data, `removed` is no longer in any version
+		let current = gen_uuid();
+		let draining = gen_uuid();
+		let removed = gen_uuid();
+
+		let entry = objects_entry(&[(current, 3), (draining, 5), (removed, 100)]);
+
+		let only_current = entry.filtered_values_with_nodes(&[current]);
+		assert_eq!(only_current.get("objects"), Some(&3));
+
+		// The counts of the draining node are taken into account
+		// when nodes of all active layout versions are given
+		let all_versions = entry.filtered_values_with_nodes(&[current, draining]);
+		assert_eq!(all_versions.get("objects"), Some(&5));
+
+		assert!(entry.filtered_values_with_nodes(&[]).is_empty());
+	}
+
+	#[test]
+	fn test_filtered_values_layout_transition() {
+		let rf = ReplicationFactor::new(1).unwrap();
+		let role = NodeRole {
+			zone: "dc1".into(),
+			capacity: Some(1 << 30),
+			tags: vec![],
+		};
+
+		// `old` stores all data in version 1, and is replaced by `new`
+		// in version 2; `removed` never had a role
+		let old = gen_uuid();
+		let new = gen_uuid();
+		let removed = gen_uuid();
+
+		let history = apply_roles(LayoutHistory::new(rf), &[(old, Some(role.clone()))]);
+		let history = apply_roles(history, &[(old, None), (new, Some(role))]);
+		let v2 = history.current().version;
+		assert_eq!(v2, 2);
+
+		// During the transition, the counts of `old` still count
+		let helper = LayoutHelper::new(
+			rf,
+			ConsistencyMode::Consistent,
+			history.clone(),
+			Default::default(),
+		);
+		assert_eq!(helper.versions().len(), 2);
+		let entry = objects_entry(&[(old, 5), (new, 3), (removed, 100)]);
+		assert_eq!(entry.filtered_values(&helper).get("objects"), Some(&5));
+
+		// Once `new` has synced the new version, only its counts are used
+		let mut synced = history.clone();
+		synced.update_trackers.sync_ack_map.set_max(new, v2);
+		let helper = LayoutHelper::new(rf, ConsistencyMode::Consistent, synced, Default::default());
+		assert_eq!(helper.versions().len(), 1);
+		assert_eq!(entry.filtered_values(&helper).get("objects"), Some(&3));
+
+		// Values of nodes that are not in any version are never used
+		let entry = objects_entry(&[(removed, 100)]);
+		assert!(entry.filtered_values(&helper).is_empty());
+	}
+}
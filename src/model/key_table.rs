@@ -1,12 +1,9 @@
 use serde::{Deserialize, Serialize};
 
 use garage_util::crdt::{self, Crdt};
-use garage_util::data::*;
 
 use garage_table::{DeletedFilter, EmptyKey, Entry, TableSchema};
 
-use crate::permission::BucketKeyPerm;
-
 mod v08 {
 	use crate::permission::BucketKeyPerm;
 	use garage_util::crdt;
@@ -35,9 +32,10 @@ mod v08 {
 		/// Flag to allow users having this key to create buckets
 		pub allow_create_bucket: crdt::Lww<bool>,
 
-		/// If the key is present: it gives some permissions,
-		/// a map of bucket IDs (uuids) to permissions.
-		/// Otherwise no permissions are granted to key
+		/// Map of the IDs (uuids) of buckets on which the key has or had
+		/// permissions, with the last permissions that were set. This is only
+		/// an index of these buckets: the permissions of the key on a bucket
+		/// are given by the authorized key set of the bucket.
 		pub authorized_buckets: crdt::Map<Uuid, BucketKeyPerm>,
 
 		/// A key can have a local view of buckets names it is
@@ -120,29 +118,6 @@ impl Key {
 	pub fn params_mut(&mut self) -> Option<&mut KeyParams> {
 		self.state.as_option_mut()
 	}
-
-	/// Get permissions for a bucket
-	pub fn bucket_permissions(&self, bucket: &Uuid) -> BucketKeyPerm {
-		self.params()
-			.and_then(|params| params.authorized_buckets.get(bucket))
-			.cloned()
-			.unwrap_or(BucketKeyPerm::NO_PERMISSIONS)
-	}
-
-	/// Check if `Key` is allowed to read in bucket
-	pub fn allow_read(&self, bucket: &Uuid) -> bool {
-		self.bucket_permissions(bucket).allow_read
-	}
-
-	/// Check if `Key` is allowed to write in bucket
-	pub fn allow_write(&self, bucket: &Uuid) -> bool {
-		self.bucket_permissions(bucket).allow_write
-	}
-
-	/// Check if `Key` is owner of bucket
-	pub fn allow_owner(&self, bucket: &Uuid) -> bool {
-		self.bucket_permissions(bucket).allow_owner
-	}
 }
 
 impl Entry<EmptyKey, String> for Key {
@@ -13,6 +13,10 @@ pub mod key_table;
 pub mod k2v;
 pub mod s3;
 
+pub mod format_version;
 pub mod garage;
 pub mod helper;
 pub mod snapshot;
+
+#[cfg(test)]
+pub(crate) mod test;
@@ -288,39 +288,26 @@ async fn process_object(
 
 		if let Some(expire) = &rule.expiration {
 			if let Some(current_version) = object.versions().iter().rev().find(|v| v.is_data()) {
-				let version_date = next_date(current_version.timestamp);
-
 				let current_version_data = match &current_version.state {
 					ObjectVersionState::Complete(c) => c,
 					_ => unreachable!(),
 				};
 
 				let size_match = check_size_filter(current_version_data, &rule.filter);
-				let date_match = match expire {
-					LifecycleExpiration::AfterDays(n_days) => {
-						(now_date - version_date) >= chrono::Duration::days(*n_days as i64)
-					}
-					LifecycleExpiration::AtDate(exp_date) => {
-						if let Ok(exp_date) = parse_lifecycle_date(exp_date) {
-							now_date >= exp_date
-						} else {
-							warn!("Invalid expiration date stored in bucket {:?} lifecycle config: {}", bucket.id, exp_date);
-							false
-						}
+				let date_match = match is_expired(expire, current_version.timestamp, now_date) {
+					Ok(expired) => expired,
+					Err(_) => {
+						warn!(
+							"Invalid expiration date stored in bucket {:?} lifecycle config: {:?}",
+							bucket.id, expire
+						);
+						false
 					}
 				};
 
 				if size_match && date_match {
 					// Delete expired version
-					let deleted_object = Object::new(
-						object.bucket_id,
-						object.key.clone(),
-						vec![ObjectVersion {
-							uuid: gen_uuid(),
-							timestamp: std::cmp::max(now_msec(), current_version.timestamp + 1),
-							state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
-						}],
-					);
+					let deleted_object = expired_object(object, current_version, now_msec());
 					info!(
 						"Lifecycle: expiring 1 object in bucket {:?}",
 						object.bucket_id
@@ -368,6 +355,37 @@ async fn process_object(
 	Ok(Skip::NextObject)
 }
 
+/// Whether an object version created at `version_timestamp` has expired at `now_date`,
+/// or an error if the expiration date is invalid
+fn is_expired(
+	expire: &LifecycleExpiration,
+	version_timestamp: u64,
+	now_date: NaiveDate,
+) -> Result<bool, &'static str> {
+	match expire {
+		LifecycleExpiration::AfterDays(n_days) => {
+			let version_date = next_date(version_timestamp);
+			Ok((now_date - version_date) >= chrono::Duration::days(*n_days as i64))
+		}
+		LifecycleExpiration::AtDate(exp_date) => Ok(now_date >= parse_lifecycle_date(exp_date)?),
+	}
+}
+
+/// The object to insert in the object table to expire the current version of an object:
+/// a delete marker that comes after that version, so that once merged, the version is
+/// deleted and its data blocks are no longer referenced
+fn expired_object(object: &Object, current_version: &ObjectVersion, now: u64) -> Object {
+	Object::new(
+		object.bucket_id,
+		object.key.clone(),
+		vec![ObjectVersion {
+			uuid: gen_uuid(),
+			timestamp: std::cmp::max(now, current_version.timestamp + 1),
+			state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+		}],
+	)
+}
+
 fn check_size_filter(version_data: &ObjectVersionData, filter: &LifecycleFilter) -> bool {
 	let size = match version_data {
 		ObjectVersionData::Inline(meta, _) | ObjectVersionData::FirstBlock(meta, _) => meta.size,
@@ -412,3 +430,130 @@ fn today(use_local_tz: bool) -> NaiveDate {
 	}
 	Utc::now().naive_utc().date()
 }
+
+#[cfg(test)]
+mod tests {
+	use garage_table::Entry;
+
+	use super::*;
+	use crate::test::*;
+
+	fn ts(date: &str) -> u64 {
+		DateTime::parse_from_rfc3339(date)
+			.unwrap()
+			.timestamp_millis() as u64
+	}
+
+	fn date(date: &str) -> NaiveDate {
+		NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+	}
+
+	#[test]
+	fn test_is_expired() {
+		// Days are counted from the midnight that follows the creation of the version
+		let created = ts("2024-03-01T12:00:00Z");
+		let after_one_day = LifecycleExpiration::AfterDays(1);
+		assert_eq!(
+			is_expired(&after_one_day, created, date("2024-03-01")),
+			Ok(false)
+		);
+		assert_eq!(
+			is_expired(&after_one_day, created, date("2024-03-02")),
+			Ok(false)
+		);
+		assert_eq!(
+			is_expired(&after_one_day, created, date("2024-03-03")),
+			Ok(true)
+		);
+
+		let at_date = LifecycleExpiration::AtDate("2024-06-01T00:00:00Z".into());
+		assert_eq!(is_expired(&at_date, created, date("2024-05-31")), Ok(false));
+		assert_eq!(is_expired(&at_date, created, date("2024-06-01")), Ok(true));
+
+		let invalid = LifecycleExpiration::AtDate("2024-06-01T12:00:00Z".into());
+		assert!(is_expired(&invalid, created, date("2024-06-02")).is_err());
+	}
+
+	#[tokio::test]
+	async fn test_expired_object_deletes_current_version() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let garage = test_garage(&dir);
+
+		let bucket_id = gen_uuid();
+		let object_version = object_version(
+			ts("2024-03-01T12:00:00Z"),
+			Some(ObjectVersionData::FirstBlock(
+				object_meta(2_000_000),
+				blake2sum(b"block 0"),
+			)),
+		);
+		let object = Object::new(bucket_id, "cache/file".into(), vec![object_version.clone()]);
+
+		let hashes = [blake2sum(b"block 0"), blake2sum(b"block 1")];
+		let version = version(
+			object_version.uuid,
+			bucket_id,
+			"cache/file",
+			&[
+				(1, 0, hashes[0], 1_000_000),
+				(1, 1_000_000, hashes[1], 1_000_000),
+			],
+		);
+
+		apply(&garage.version_table, &version);
+		apply(&garage.object_table, &object);
+		assert!(garage
+			.version_table
+			.data
+			.insert_queue_entries()
+			.unwrap()
+			.is_empty());
+		assert!(garage
+			.block_ref_table
+			.data
+			.insert_queue_entries()
+			.unwrap()
+			.is_empty());
+
+		// The delete marker comes after the current version, even if the clock is late
+		let expired = expired_object(&object, &object_version, object_version.timestamp - 1000);
+		assert_eq!(expired.versions().len(), 1);
+		assert!(expired.versions()[0].timestamp > object_version.timestamp);
+
+		// Once merged, only the delete marker is left in the object, and the
+		// deletion of the version is queued for the version table
+		apply(&garage.object_table, &expired);
+		let object = garage
+			.object_table
+			.data
+			.read_entry(&bucket_id, &"cache/file".to_string())
+			.unwrap()
+			.map(|bytes| garage.object_table.data.decode_entry(&bytes).unwrap())
+			.unwrap();
+		assert!(object.versions().iter().all(|v| !v.is_data()));
+		assert!(object.is_tombstone());
+
+		let queued_versions = garage.version_table.data.insert_queue_entries().unwrap();
+		assert_eq!(queued_versions.len(), 1);
+		assert_eq!(queued_versions[0].uuid, object_version.uuid);
+		assert!(queued_versions[0].deleted.get());
+
+		// When the version table receives it, the references to the blocks
+		// of the version are queued for deletion in turn
+		apply(&garage.version_table, &queued_versions[0]);
+		let mut queued_block_refs = garage.block_ref_table.data.insert_queue_entries().unwrap();
+		queued_block_refs.sort_by_key(|block_ref| block_ref.block);
+		let mut expected = hashes.to_vec();
+		expected.sort();
+		assert_eq!(
+			queued_block_refs
+				.iter()
+				.map(|block_ref| block_ref.block)
+				.collect::<Vec<_>>(),
+			expected
+		);
+		assert!(queued_block_refs
+			.iter()
+			.all(|block_ref| block_ref.version == object_version.uuid && block_ref.deleted.get()));
+	}
+}
@@ -407,6 +407,20 @@ impl Object {
 	pub fn versions(&self) -> &[ObjectVersion] {
 		&self.versions[..]
 	}
+
+	/// Whether a version of a previous state of this object was deleted in this state,
+	/// either because it was removed from the object or because it was aborted.
+	/// The deletion of such versions is propagated to the version table.
+	/// `found` is the result of the search of `old` in the versions of this state.
+	pub(crate) fn deletes_version(&self, old: &ObjectVersion, found: Result<usize, usize>) -> bool {
+		match found {
+			Err(_) => true,
+			Ok(i) => {
+				self.versions[i].state == ObjectVersionState::Aborted
+					&& old.state != ObjectVersionState::Aborted
+			}
+		}
+	}
 }
 
 impl Crdt for ObjectVersionState {
@@ -575,14 +589,7 @@ impl TableSchema for ObjectTable {
 					.binary_search_by(|nv| nv.cmp_key().cmp(&v.cmp_key()));
 
 				// Propagate deletion of old versions to the Version table
-				let delete_version = match new_v_id {
-					Err(_) => true,
-					Ok(i) => {
-						new_v.versions[i].state == ObjectVersionState::Aborted
-							&& v.state != ObjectVersionState::Aborted
-					}
-				};
-				if delete_version {
+				if new_v.deletes_version(v, new_v_id) {
 					let deleted_version = Version::new(
 						v.uuid,
 						VersionBacklink::Object {
@@ -150,6 +150,40 @@ impl Version {
 			.0
 			.part_number)
 	}
+
+	/// Total size of the data of this version, before any kind of compression or encryption
+	pub fn total_size(&self) -> u64 {
+		self.blocks.items().iter().map(|(_, b)| b.size).sum()
+	}
+
+	/// Find the block that contains the byte at `byte_offset` of the data of this
+	/// version, and the offset of that byte in the block. Parts are skipped by
+	/// looking at their last block, and the block is then found by binary search
+	/// in its part, as the offsets of blocks are relative to their part.
+	pub fn block_at_offset(&self, byte_offset: u64) -> Option<(&VersionBlock, u64)> {
+		let items = self.blocks.items();
+		let mut part_begin = 0;
+		let mut i = 0;
+		while i < items.len() {
+			let part_number = items[i].0.part_number;
+			let part_len = items[i..].partition_point(|(k, _)| k.part_number == part_number);
+			let part = &items[i..i + part_len];
+			let (last_key, last_block) = part.last().unwrap();
+			let part_size = last_key.offset + last_block.size;
+
+			if byte_offset < part_begin + part_size {
+				let offset_in_part = byte_offset - part_begin;
+				let j = part.partition_point(|(k, _)| k.offset <= offset_in_part);
+				let (key, block) = &part[j.checked_sub(1)?];
+				let offset_in_block = offset_in_part - key.offset;
+				return (offset_in_block < block.size).then_some((block, offset_in_block));
+			}
+
+			part_begin += part_size;
+			i += part_len;
+		}
+		None
+	}
 }
 
 impl Ord for VersionBlockKey {
@@ -236,3 +270,85 @@ impl TableSchema for VersionTable {
 		filter.apply(entry.deleted.get())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn version(blocks: &[(u64, u64, u64)]) -> Version {
+		let blocks = blocks
+			.iter()
+			.map(|(part_number, offset, size)| {
+				let hash = blake2sum(&[*part_number as u8, *offset as u8]);
+				(*part_number, *offset, hash, *size)
+			})
+			.collect::<Vec<_>>();
+		crate::test::version(gen_uuid(), gen_uuid(), "object", &blocks)
+	}
+
+	fn block_at(version: &Version, byte_offset: u64) -> Option<(u64, u64, u64)> {
+		let (block, offset_in_block) = version.block_at_offset(byte_offset)?;
+		let (key, _) = version
+			.blocks
+			.items()
+			.iter()
+			.find(|(_, b)| b.hash == block.hash)
+			.unwrap();
+		Some((key.part_number, key.offset, offset_in_block))
+	}
+
+	#[test]
+	fn test_block_at_offset() {
+		let single_part = version(&[(1, 0, 100), (1, 100, 100), (1, 200, 50)]);
+		assert_eq!(single_part.total_size(), 250);
+		assert_eq!(block_at(&single_part, 0), Some((1, 0, 0)));
+		assert_eq!(block_at(&single_part, 42), Some((1, 0, 42)));
+		assert_eq!(block_at(&single_part, 99), Some((1, 0, 99)));
+		assert_eq!(block_at(&single_part, 100), Some((1, 100, 0)));
+		assert_eq!(block_at(&single_part, 249), Some((1, 200, 49)));
+		assert_eq!(block_at(&single_part, 250), None);
+		assert_eq!(block_at(&single_part, 1000), None);
+
+		// Offsets of blocks are relative to their part
+		let multipart = version(&[(1, 0, 100), (1, 100, 20), (2, 0, 100), (4, 0, 30)]);
+		assert_eq!(multipart.total_size(), 250);
+		assert_eq!(block_at(&multipart, 119), Some((1, 100, 19)));
+		assert_eq!(block_at(&multipart, 120), Some((2, 0, 0)));
+		assert_eq!(block_at(&multipart, 219), Some((2, 0, 99)));
+		assert_eq!(block_at(&multipart, 220), Some((4, 0, 0)));
+		assert_eq!(block_at(&multipart, 249), Some((4, 0, 29)));
+		assert_eq!(block_at(&multipart, 250), None);
+
+		let empty = version(&[]);
+		assert_eq!(empty.total_size(), 0);
+		assert_eq!(block_at(&empty, 0), None);
+	}
+
+	#[test]
+	fn test_merge_conflicting_blocks() {
+		let key = VersionBlockKey {
+			part_number: 1,
+			offset: 0,
+		};
+		let block = |data: &[u8]| VersionBlock {
+			hash: blake2sum(data),
+			size: data.len() as u64,
+		};
+		let (b1, b2) = (block(b"first writer"), block(b"second writer"));
+
+		let mut v1 = version(&[(1, 100, 10)]);
+		let mut v2 = v1.clone();
+		v1.blocks.put(key, b1);
+		v2.blocks.put(key, b2);
+
+		// Whatever the order of the merge, the replicas keep the same block,
+		// the greatest one, at an offset where the two versions differ
+		let mut v12 = v1.clone();
+		v12.merge(&v2);
+		let mut v21 = v2.clone();
+		v21.merge(&v1);
+		assert_eq!(v12, v21);
+		assert_eq!(v12.blocks.get(&key), Some(&std::cmp::max(b1, b2)));
+		assert_eq!(v12.blocks.items().len(), 2);
+	}
+}
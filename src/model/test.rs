@@ -0,0 +1,139 @@
+//! Fixtures shared by the tests of the tables and helpers of this crate
+
+use std::path::Path;
+use std::sync::Arc;
+
+use garage_rpc::layout::*;
+use garage_table::replication::TableReplication;
+use garage_table::{Entry, Table, TableSchema};
+use garage_util::config::read_config;
+use garage_util::crdt::Crdt;
+use garage_util::data::*;
+
+use crate::garage::Garage;
+use crate::s3::object_table::*;
+use crate::s3::version_table::*;
+
+/// A single-node Garage instance storing its data in `dir`. It is not
+/// connected to any other node, and has no cluster layout.
+pub(crate) fn test_garage(dir: &Path) -> Arc<Garage> {
+	let config_file = dir.join("garage.toml");
+	let config = format!(
+		r#"
+metadata_dir = "{dir}/meta"
+data_dir = "{dir}/data"
+db_engine = "sqlite"
+replication_factor = 1
+rpc_bind_addr = "127.0.0.1:3901"
+rpc_secret = "{secret}"
+
+[s3_api]
+s3_region = "garage"
+"#,
+		dir = dir.display(),
+		secret = hex::encode([0u8; 32]),
+	);
+	std::fs::write(&config_file, config).unwrap();
+	Garage::new(read_config(config_file).unwrap()).unwrap()
+}
+
+/// Apply a cluster layout in which the node of `garage` stores all data,
+/// so that table reads and writes are served locally
+pub(crate) async fn assign_local_node(garage: &Garage) {
+	let mut history = garage.system.cluster_layout().inner().clone();
+	let update = history.staging.get().roles.update_mutator(
+		garage.system.id,
+		NodeRoleV(Some(NodeRole {
+			zone: "dc1".into(),
+			capacity: Some(1 << 30),
+			tags: vec![],
+		})),
+	);
+	history.staging.get_mut().roles.merge(&update);
+	let version = history.current().version + 1;
+	let (history, _) = history.apply_staged_changes(Some(version)).unwrap();
+	garage
+		.system
+		.layout_manager
+		.update_cluster_layout(&history)
+		.await
+		.unwrap();
+}
+
+/// Merge an entry into the local copy of a table, as the table RPC
+/// handler would do, running the table's `updated` hook
+pub(crate) fn apply<F: TableSchema, R: TableReplication>(table: &Table<F, R>, entry: &F::E) {
+	table
+		.data
+		.update_entry_with(entry.partition_key(), entry.sort_key(), |_tx, old| {
+			let mut new = old.unwrap_or_else(|| entry.clone());
+			new.merge(entry);
+			Ok(new)
+		})
+		.unwrap();
+}
+
+/// Metadata of a plaintext object of `size` bytes, with no headers
+pub(crate) fn object_meta(size: u64) -> ObjectVersionMeta {
+	ObjectVersionMeta {
+		size,
+		etag: "etag".into(),
+		encryption: ObjectVersionEncryption::Plaintext {
+			inner: ObjectVersionMetaInner {
+				headers: vec![],
+				checksum: None,
+			},
+		},
+	}
+}
+
+/// A new version of an object created at `timestamp`, complete with `data`,
+/// or a multipart upload in progress if `data` is `None`
+pub(crate) fn object_version(timestamp: u64, data: Option<ObjectVersionData>) -> ObjectVersion {
+	let state = match data {
+		Some(data) => ObjectVersionState::Complete(data),
+		None => ObjectVersionState::Uploading {
+			multipart: true,
+			encryption: ObjectVersionEncryption::Plaintext {
+				inner: ObjectVersionMetaInner {
+					headers: vec![],
+					checksum: None,
+				},
+			},
+			checksum_algorithm: None,
+		},
+	};
+	ObjectVersion {
+		uuid: gen_uuid(),
+		timestamp,
+		state,
+	}
+}
+
+/// The version `uuid` of object `key` in bucket `bucket_id`, made of `blocks`
+/// given as (part number, offset in the part, hash, size)
+pub(crate) fn version(
+	uuid: Uuid,
+	bucket_id: Uuid,
+	key: &str,
+	blocks: &[(u64, u64, Hash, u64)],
+) -> Version {
+	let backlink = VersionBacklink::Object {
+		bucket_id,
+		key: key.into(),
+	};
+	let mut version = Version::new(uuid, backlink, false);
+	for (part_number, offset, hash, size) in blocks {
+		version.blocks.put(
+			VersionBlockKey {
+				part_number: *part_number,
+				offset: *offset,
+			},
+			VersionBlock {
+				hash: *hash,
+				size: *size,
+			},
+		);
+	}
+	version
+}
@@ -7,7 +7,7 @@ use std::task::Poll;
 
 use arc_swap::ArcSwapOption;
 use bytes::Bytes;
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
 
 use futures::io::AsyncReadExt;
 use futures::Stream;
@@ -29,6 +29,7 @@ use crate::error::*;
 use crate::message::*;
 use crate::netapp::*;
 use crate::recv::*;
+use crate::request_id::*;
 use crate::send::*;
 use crate::stream::*;
 use crate::util::*;
@@ -77,7 +78,7 @@ impl ClientConn {
 		// check they are running the same version as us
 		let mut their_version_tag = VersionTag::default();
 		read.read_exact(&mut their_version_tag[..]).await?;
-		if their_version_tag != netapp.version_tag {
+		if !netapp.is_version_tag_compatible(&their_version_tag) {
 			let msg = format!(
 				"different version tags: {} (theirs) vs. {} (ours)",
 				hex::encode(their_version_tag),
@@ -85,6 +86,13 @@ impl ClientConn {
 			);
 			error!("Cannot connect to {}: {}", hex::encode(&peer_id[..8]), msg);
 			return Err(Error::VersionMismatch(msg));
+		} else if their_version_tag != netapp.version_tag {
+			warn!(
+				"Connecting to {} which runs a different but compatible version: {} (theirs) vs. {} (ours)",
+				hex::encode(&peer_id[..8]),
+				hex::encode(their_version_tag),
+				hex::encode(netapp.version_tag)
+			);
 		}
 
 		// Build and launch stuff that manages sending requests client-side
@@ -166,6 +174,10 @@ impl ClientConn {
 				let telemetry_id: Bytes = Bytes::new();
 			}
 		};
+		let telemetry_id: Bytes = match RequestId::current() {
+			Some(request_id) => [&telemetry_id[..], request_id.as_slice()].concat().into(),
+			None => telemetry_id,
+		};
 
 		// Encode request
 		let req_enc = req.into_enc(prio, path.as_bytes().to_vec().into(), telemetry_id);
@@ -121,7 +121,13 @@ where
 		if *target == self.netapp.id {
 			match self.handler.load_full() {
 				None => Err(Error::NoHandler),
-				Some(h) => Ok(h.handle(req.into_req_local(), self.netapp.id).await),
+				Some(h) => {
+					let req = Req {
+						prio,
+						..req.into_req_local()
+					};
+					Ok(h.handle(req, self.netapp.id).await)
+				}
 			}
 		} else {
 			let conn = self
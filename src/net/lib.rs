@@ -20,6 +20,7 @@ pub mod util;
 
 pub mod endpoint;
 pub mod message;
+pub mod request_id;
 
 mod client;
 mod recv;
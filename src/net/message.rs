@@ -109,6 +109,7 @@ pub struct Req<M: Message> {
 	pub(crate) msg_ser: Option<Bytes>,
 	pub(crate) stream: AttachedStream,
 	pub(crate) order_tag: Option<OrderTag>,
+	pub(crate) prio: RequestPriority,
 }
 
 impl<M: Message> Req<M> {
@@ -151,6 +152,11 @@ impl<M: Message> Req<M> {
 		&self.msg
 	}
 
+	/// Get the priority with which this request was sent
+	pub fn prio(&self) -> RequestPriority {
+		self.prio
+	}
+
 	/// Takes out the stream attached to this request, if any
 	pub fn take_stream(&mut self) -> Option<ByteStream> {
 		std::mem::replace(&mut self.stream, AttachedStream::None).into_stream()
@@ -182,6 +188,7 @@ impl<M: Message> Req<M> {
 				.map(AttachedStream::Stream)
 				.unwrap_or(AttachedStream::None),
 			order_tag: enc.order_tag,
+			prio: enc.prio,
 		})
 	}
 }
@@ -205,6 +212,7 @@ impl<M: Message> IntoReq<M> for M {
 			msg_ser: Some(Bytes::from(msg_ser)),
 			stream: AttachedStream::None,
 			order_tag: None,
+			prio: PRIO_NORMAL,
 		})
 	}
 	fn into_req_local(self) -> Req<M> {
@@ -213,6 +221,7 @@ impl<M: Message> IntoReq<M> for M {
 			msg_ser: None,
 			stream: AttachedStream::None,
 			order_tag: None,
+			prio: PRIO_NORMAL,
 		}
 	}
 }
@@ -240,6 +249,7 @@ impl<M: Message> Clone for Req<M> {
 			msg_ser: self.msg_ser.clone(),
 			stream,
 			order_tag: self.order_tag,
+			prio: self.prio,
 		}
 	}
 }
@@ -538,3 +548,32 @@ fn read_exact_error_to_error(e: ReadExactError) -> Error {
 		ReadExactError::UnexpectedEos => Error::Framing,
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[derive(Serialize, Deserialize)]
+	struct TestMsg(u64);
+
+	impl Message for TestMsg {
+		type Response = ();
+	}
+
+	#[test]
+	fn test_req_prio() {
+		let req = TestMsg(42).into_req_local();
+		assert_eq!(req.prio(), PRIO_NORMAL);
+
+		// The priority of a request received from another node
+		// is the one it was sent with
+		let enc = TestMsg(42).into_req().unwrap().into_enc(
+			PRIO_BACKGROUND | PRIO_SECONDARY,
+			Bytes::from_static(b"test"),
+			Bytes::new(),
+		);
+		let req = Req::<TestMsg>::from_enc(enc).unwrap();
+		assert_eq!(req.prio(), PRIO_BACKGROUND | PRIO_SECONDARY);
+		assert_eq!(req.msg().0, 42);
+	}
+}
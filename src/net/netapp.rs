@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::convert::TryInto;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, RwLock};
 
@@ -56,6 +57,7 @@ impl Message for HelloMessage {
 
 type OnConnectHandler = Box<dyn Fn(NodeID, SocketAddr, bool) + Send + Sync>;
 type OnDisconnectHandler = Box<dyn Fn(NodeID, bool) + Send + Sync>;
+type AppVersionCheck = Box<dyn Fn(u64, u64) -> bool + Send + Sync>;
 
 /// NetApp is the main class that handles incoming and outgoing connections.
 ///
@@ -83,6 +85,7 @@ pub struct NetApp {
 
 	on_connected_handler: ArcSwapOption<OnConnectHandler>,
 	on_disconnected_handler: ArcSwapOption<OnDisconnectHandler>,
+	app_version_check: ArcSwapOption<AppVersionCheck>,
 }
 
 struct ListenParams {
@@ -119,6 +122,7 @@ impl NetApp {
 			endpoints: RwLock::new(HashMap::new()),
 			hello_endpoint: ArcSwapOption::new(None),
 			on_connected_handler: ArcSwapOption::new(None),
+			app_version_check: ArcSwapOption::new(None),
 			on_disconnected_handler: ArcSwapOption::new(None),
 		});
 
@@ -134,6 +138,33 @@ impl NetApp {
 		netapp
 	}
 
+	/// Set the function used to check that the app version tag announced by a peer
+	/// (second argument) is compatible with ours (first argument). If it is not set,
+	/// peers can only connect if they have exactly the same app version tag as us.
+	pub fn set_app_version_check<F>(&self, check: F)
+	where
+		F: Fn(u64, u64) -> bool + Sized + Send + Sync + 'static,
+	{
+		self.app_version_check
+			.store(Some(Arc::new(Box::new(check))));
+	}
+
+	/// Check that a version tag announced by a peer is compatible with ours
+	pub(crate) fn is_version_tag_compatible(&self, theirs: &VersionTag) -> bool {
+		if theirs == &self.version_tag {
+			return true;
+		}
+		if theirs[0..8] != self.version_tag[0..8] {
+			return false;
+		}
+		let ours_app = u64::from_be_bytes(self.version_tag[8..16].try_into().unwrap());
+		let theirs_app = u64::from_be_bytes(theirs[8..16].try_into().unwrap());
+		match self.app_version_check.load().as_ref() {
+			Some(check) => check(ours_app, theirs_app),
+			None => false,
+		}
+	}
+
 	/// Set the handler to be called when a new connection (incoming or outgoing) has
 	/// been successfully established. Do not set this if using a peering strategy,
 	/// as the peering strategy will need to set this itself.
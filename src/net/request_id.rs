@@ -0,0 +1,120 @@
+//! Request IDs allow to correlate all the operations made, possibly on
+//! several nodes, to handle a single client request.
+//!
+//! A request ID is generated when a request enters the cluster (e.g. in the
+//! handler of an HTTP API), and is stored in a task-local variable for the
+//! duration of the request. All RPCs sent from within that scope carry the
+//! request ID, and the remote node handles them within a scope that has the
+//! same request ID. In both cases, a tracing span with a `request_id` field
+//! is entered so that all log lines include the request ID.
+
+use std::convert::TryInto;
+use std::fmt;
+use std::future::Future;
+
+use rand::prelude::*;
+use tracing::Instrument;
+
+/// Length of a request ID, in bytes
+pub(crate) const REQUEST_ID_LEN: usize = 16;
+
+/// Length of the OpenTelemetry span context propagated in RPC requests,
+/// when the `telemetry` feature is enabled
+pub(crate) const TELEMETRY_CONTEXT_LEN: usize = 29;
+
+tokio::task_local! {
+	static CURRENT_REQUEST_ID: RequestId;
+}
+
+/// The identifier of a client request
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId([u8; REQUEST_ID_LEN]);
+
+impl RequestId {
+	/// Generate a new random request ID
+	pub fn new() -> Self {
+		Self(thread_rng().gen())
+	}
+
+	/// Get the request ID of the request being handled by the current task, if any
+	pub fn current() -> Option<Self> {
+		CURRENT_REQUEST_ID.try_with(|id| *id).ok()
+	}
+
+	/// Run a future in the scope of this request ID, i.e. with this request ID
+	/// being returned by `RequestId::current()` and being added to all log lines
+	pub async fn scope<F: Future>(self, f: F) -> F::Output {
+		let span = tracing::info_span!("request", request_id = %self);
+		CURRENT_REQUEST_ID.scope(self, f.instrument(span)).await
+	}
+
+	pub(crate) fn as_slice(&self) -> &[u8] {
+		&self.0[..]
+	}
+
+	pub(crate) fn try_from_slice(bytes: &[u8]) -> Option<Self> {
+		bytes.try_into().ok().map(Self)
+	}
+}
+
+impl Default for RequestId {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl fmt::Display for RequestId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", hex::encode_upper(self.0))
+	}
+}
+
+impl fmt::Debug for RequestId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "RequestId({})", self)
+	}
+}
+
+/// Split the telemetry ID header of an RPC request into the OpenTelemetry
+/// span context it contains (possibly empty), and the request ID it contains, if any.
+///
+/// The request ID is appended after the span context so that nodes that
+/// don't know about request IDs simply see an invalid span context.
+pub(crate) fn split_telemetry_id(telemetry_id: &[u8]) -> (&[u8], Option<RequestId>) {
+	match telemetry_id.len() {
+		REQUEST_ID_LEN => (&[], RequestId::try_from_slice(telemetry_id)),
+		n if n == TELEMETRY_CONTEXT_LEN + REQUEST_ID_LEN => {
+			let (context, request_id) = telemetry_id.split_at(TELEMETRY_CONTEXT_LEN);
+			(context, RequestId::try_from_slice(request_id))
+		}
+		_ => (telemetry_id, None),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_split_telemetry_id() {
+		let id = RequestId::new();
+
+		assert_eq!(split_telemetry_id(&[]), (&[][..], None));
+		assert_eq!(split_telemetry_id(id.as_slice()), (&[][..], Some(id)));
+
+		let context = [42u8; TELEMETRY_CONTEXT_LEN];
+		assert_eq!(split_telemetry_id(&context), (&context[..], None));
+
+		let mut both = context.to_vec();
+		both.extend_from_slice(id.as_slice());
+		assert_eq!(split_telemetry_id(&both), (&context[..], Some(id)));
+	}
+
+	#[tokio::test]
+	async fn test_request_id_scope() {
+		let id = RequestId::new();
+		assert_eq!(RequestId::current(), None);
+		assert_eq!(id.scope(async { RequestId::current() }).await, Some(id));
+		assert_eq!(RequestId::current(), None);
+	}
+}
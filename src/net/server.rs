@@ -26,6 +26,7 @@ use crate::error::*;
 use crate::message::*;
 use crate::netapp::*;
 use crate::recv::*;
+use crate::request_id::*;
 use crate::send::*;
 use crate::stream::*;
 use crate::util::*;
@@ -135,13 +136,16 @@ impl ServerConn {
 		};
 
 		if let Some(handler) = handler_opt {
+			let telemetry_id = req_enc.telemetry_id.clone();
+			let (_telemetry_context, request_id) = split_telemetry_id(&telemetry_id);
+
 			cfg_if::cfg_if! {
 				if #[cfg(feature = "telemetry")] {
 					let tracer = opentelemetry::global::tracer("netapp");
 
-					let mut span = if !req_enc.telemetry_id.is_empty() {
+					let mut span = if !_telemetry_context.is_empty() {
 						let propagator = BinaryPropagator::new();
-						let context = propagator.from_bytes(req_enc.telemetry_id.to_vec());
+						let context = propagator.from_bytes(_telemetry_context.to_vec());
 						let context = Context::new().with_remote_span_context(context);
 						tracer.span_builder(format!(">> RPC {}", path))
 							.with_kind(SpanKind::Server)
@@ -158,13 +162,17 @@ impl ServerConn {
 					span.set_attribute(KeyValue::new("path", path.to_string()));
 					span.set_attribute(KeyValue::new("len_query_msg", req_enc.msg.len() as i64));
 
-					handler.handle(req_enc, self.peer_id)
-						.with_context(Context::current_with_span(span))
-						.await
+					let fut = handler.handle(req_enc, self.peer_id)
+						.with_context(Context::current_with_span(span));
 				} else {
-					handler.handle(req_enc, self.peer_id).await
+					let fut = handler.handle(req_enc, self.peer_id);
 				}
 			}
+
+			match request_id {
+				Some(request_id) => request_id.scope(fut).await,
+				None => fut.await,
+			}
 		} else {
 			Err(Error::NoHandler)
 		}
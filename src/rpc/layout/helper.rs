@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
@@ -8,6 +9,14 @@ use garage_util::data::*;
 use super::*;
 use crate::replication_mode::*;
 
+/// Storage sets of a partition, shared between the partitions that are stored
+/// on the same nodes in all layout versions
+pub type SharedStorageSets = Arc<Vec<Vec<Uuid>>>;
+
+/// Storage sets computed by `partitions_storage_sets`, indexed by the placement
+/// of the partition in each layout version
+type StorageSetsCache<'a> = HashMap<Vec<Option<&'a [CompactNodeType]>>, SharedStorageSets>;
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct RpcLayoutDigest {
 	/// Cluster layout version
@@ -209,6 +218,29 @@ impl LayoutHelper {
 			.collect()
 	}
 
+	/// Get the storage sets of all partitions, as returned by `storage_sets_of`,
+	/// along with the first hash of each partition. Partitions that are stored
+	/// on the same nodes in all layout versions share the same storage sets,
+	/// which are only computed once.
+	pub fn partitions_storage_sets(&self) -> Vec<(Partition, Hash, SharedStorageSets)> {
+		let mut cache = StorageSetsCache::new();
+		self.current()
+			.partitions()
+			.map(|(partition, first_hash)| {
+				let placement = self
+					.versions()
+					.iter()
+					.map(|v| v.partition_placement(partition))
+					.collect::<Vec<_>>();
+				let storage_sets = cache
+					.entry(placement)
+					.or_insert_with(|| Arc::new(self.storage_sets_of(&first_hash)))
+					.clone();
+				(partition, first_hash, storage_sets)
+			})
+			.collect()
+	}
+
 	pub fn storage_nodes_of(&self, position: &Hash) -> Vec<Uuid> {
 		let mut ret = vec![];
 		for version in self.versions().iter() {
@@ -1,11 +1,14 @@
 use std::cmp::min;
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
 
 use garage_util::crdt::Crdt;
+use garage_util::data::*;
 use garage_util::error::*;
 
 use crate::layout::*;
-use crate::replication_mode::ReplicationFactor;
+use crate::replication_mode::{ConsistencyMode, ReplicationFactor};
 
 // This function checks that the partition size S computed is at least better than the
 // one given by a very naive algorithm. To do so, we try to run the naive algorithm
@@ -156,3 +159,96 @@ fn test_assignment() {
 	assert_eq!(cl.check(), Ok(()));
 	assert!(check_against_naive(cl.current()).unwrap());
 }
+
+#[test]
+fn test_partitions_storage_sets() {
+	let mut cl = LayoutHistory::new(ReplicationFactor::new(3).unwrap());
+	update_layout(&mut cl, &[4000, 1000, 2000], &["A", "B", "C"], 3);
+	let v = cl.current().version;
+	let (mut cl, _) = cl.apply_staged_changes(Some(v + 1)).unwrap();
+
+	// Add a second layout version, so that partitions are stored on several sets
+	let node_capacity_vec = [4000, 1000, 1000, 3000, 1000, 1000, 2000, 10000, 2000];
+	let node_zone_vec = ["A", "B", "C", "C", "C", "B", "G", "H", "I"];
+	update_layout(&mut cl, &node_capacity_vec, &node_zone_vec, 2);
+	let v = cl.current().version;
+	let (cl, _) = cl.apply_staged_changes(Some(v + 1)).unwrap();
+
+	let helper = LayoutHelper::new(
+		ReplicationFactor::new(3).unwrap(),
+		ConsistencyMode::Consistent,
+		cl,
+		HashMap::new(),
+	);
+	let partitions = helper.partitions_storage_sets();
+	assert_eq!(partitions.len(), NB_PARTITIONS);
+	let mut distinct = HashMap::<Vec<Vec<Uuid>>, *const Vec<Vec<Uuid>>>::new();
+	for ((partition, first_hash, storage_sets), (p, h)) in
+		partitions.iter().zip(helper.current().partitions())
+	{
+		// Same output, in the same order, as computing each partition separately
+		assert_eq!((*partition, *first_hash), (p, h));
+		assert_eq!(**storage_sets, helper.storage_sets_of(&h));
+		assert_eq!(storage_sets.len(), helper.versions().len());
+
+		// Identical storage sets are shared
+		let ptr = distinct
+			.entry((**storage_sets).clone())
+			.or_insert(Arc::as_ptr(storage_sets));
+		assert_eq!(*ptr, Arc::as_ptr(storage_sets));
+	}
+	assert!(distinct.len() < NB_PARTITIONS);
+}
+
+// Compares the time taken by `partitions_storage_sets` with computing the storage
+// sets of each partition separately, as was done before. Run with:
+// cargo test -p garage_rpc --release -- --ignored --nocapture bench_partitions_storage_sets
+#[test]
+#[ignore = "benchmark"]
+fn bench_partitions_storage_sets() {
+	const ITERATIONS: u32 = 1000;
+
+	let mut cl = LayoutHistory::new(ReplicationFactor::new(3).unwrap());
+	update_layout(&mut cl, &[4000, 1000, 2000], &["A", "B", "C"], 3);
+	let v = cl.current().version;
+	let (mut cl, _) = cl.apply_staged_changes(Some(v + 1)).unwrap();
+
+	let node_capacity_vec = [4000, 1000, 1000, 3000, 1000, 1000, 2000, 10000, 2000];
+	let node_zone_vec = ["A", "B", "C", "C", "C", "B", "G", "H", "I"];
+	update_layout(&mut cl, &node_capacity_vec, &node_zone_vec, 2);
+	let v = cl.current().version;
+	let (cl, _) = cl.apply_staged_changes(Some(v + 1)).unwrap();
+
+	let helper = LayoutHelper::new(
+		ReplicationFactor::new(3).unwrap(),
+		ConsistencyMode::Consistent,
+		cl,
+		HashMap::new(),
+	);
+
+	let start = Instant::now();
+	let mut separate = vec![];
+	for _ in 0..ITERATIONS {
+		separate = helper
+			.current()
+			.partitions()
+			.map(|(p, h)| (p, h, helper.storage_sets_of(&h)))
+			.collect::<Vec<_>>();
+	}
+	let separate_time = start.elapsed() / ITERATIONS;
+
+	let start = Instant::now();
+	let mut shared = vec![];
+	for _ in 0..ITERATIONS {
+		shared = helper.partitions_storage_sets();
+	}
+	let shared_time = start.elapsed() / ITERATIONS;
+
+	for ((p1, h1, s1), (p2, h2, s2)) in separate.iter().zip(shared.iter()) {
+		assert_eq!((p1, h1, s1), (p2, h2, &**s2));
+	}
+	println!(
+		"storage sets of {} partitions: {:?} computed separately, {:?} with partitions_storage_sets",
+		NB_PARTITIONS, separate_time, shared_time
+	);
+}
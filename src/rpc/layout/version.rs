@@ -117,16 +117,12 @@ impl LayoutVersion {
 	pub fn nodes_of(&self, position: &Hash, n: usize) -> impl Iterator<Item = Uuid> + '_ {
 		assert_eq!(n, self.replication_factor);
 
-		let data = &self.ring_assignment_data;
-
-		let partition_nodes = if data.len() == self.replication_factor * (1 << PARTITION_BITS) {
-			let partition_idx = self.partition_of(position) as usize;
-			let partition_start = partition_idx * self.replication_factor;
-			let partition_end = (partition_idx + 1) * self.replication_factor;
-			&data[partition_start..partition_end]
-		} else {
-			warn!("Ring not yet ready, read/writes will be lost!");
-			&[]
+		let partition_nodes = match self.partition_placement(self.partition_of(position)) {
+			Some(nodes) => nodes,
+			None => {
+				warn!("Ring not yet ready, read/writes will be lost!");
+				&[]
+			}
 		};
 
 		partition_nodes
@@ -134,6 +130,18 @@ impl LayoutVersion {
 			.map(move |i| self.node_id_vec[*i as usize])
 	}
 
+	/// Return the indices in `node_id_vec` of the nodes that store a partition,
+	/// or `None` if the ring is not ready
+	pub(crate) fn partition_placement(&self, partition: Partition) -> Option<&[CompactNodeType]> {
+		let data = &self.ring_assignment_data;
+		if data.len() == self.replication_factor * (1 << PARTITION_BITS) {
+			let partition_start = partition as usize * self.replication_factor;
+			Some(&data[partition_start..partition_start + self.replication_factor])
+		} else {
+			None
+		}
+	}
+
 	// ===================== internal information extractors ======================
 
 	pub(crate) fn expect_get_node_capacity(&self, uuid: &Uuid) -> u64 {
@@ -436,6 +436,28 @@ impl RpcHelper {
 		msg: N,
 		strategy: RequestStrategy<T>,
 	) -> Result<Vec<S>, Error>
+	where
+		M: Rpc<Response = Result<S, Error>> + 'static,
+		N: IntoReq<M>,
+		H: StreamingEndpointHandler<M> + 'static,
+		S: Send + 'static,
+		T: Send + 'static,
+	{
+		self.try_write_many_sets_with_ack(endpoint, to_sets, msg, strategy)
+			.await
+			.0
+	}
+
+	/// Same as `try_write_many_sets`, but also returns which nodes had acknowledged
+	/// the write, and which had failed, at the moment the quorum was reached,
+	/// or at the moment it became impossible to reach.
+	pub async fn try_write_many_sets_with_ack<M, N, H, S, T>(
+		&self,
+		endpoint: &Arc<Endpoint<M, H>>,
+		to_sets: &[Vec<Uuid>],
+		msg: N,
+		strategy: RequestStrategy<T>,
+	) -> (Result<Vec<S>, Error>, WriteAck)
 	where
 		M: Rpc<Response = Result<S, Error>> + 'static,
 		N: IntoReq<M>,
@@ -472,7 +494,7 @@ impl RpcHelper {
 		msg: N,
 		strategy: RequestStrategy<T>,
 		quorum: usize,
-	) -> Result<Vec<S>, Error>
+	) -> (Result<Vec<S>, Error>, WriteAck)
 	where
 		M: Rpc<Response = Result<S, Error>> + 'static,
 		N: IntoReq<M>,
@@ -487,7 +509,13 @@ impl RpcHelper {
 		let (strategy, drop_on_complete) = strategy.extract_drop_on_complete();
 
 		// Send one request to each peer of the quorum sets
-		let msg = msg.into_req().map_err(garage_net::error::Error::from)?;
+		let msg = match msg.into_req() {
+			Ok(msg) => msg,
+			Err(e) => {
+				let e = garage_net::error::Error::from(e).into();
+				return (Err(e), result_tracker.write_ack());
+			}
+		};
 		let requests = result_tracker.nodes.keys().map(|peer| {
 			let self2 = self.clone();
 			let msg = msg.clone();
@@ -512,7 +540,8 @@ impl RpcHelper {
 					drop(drop_on_complete);
 				});
 
-				return Ok(result_tracker.success_values());
+				let ack = result_tracker.write_ack();
+				return (Ok(result_tracker.success_values()), ack);
 			}
 
 			// If there is a quorum set for which too many errors were received,
@@ -534,7 +563,8 @@ impl RpcHelper {
 		// running request handler.)
 
 		// Failure, could not get quorum
-		Err(result_tracker.quorum_error())
+		let ack = result_tracker.write_ack();
+		(Err(result_tracker.quorum_error()), ack)
 	}
 
 	// ---- functions not related to MAKING RPCs, but just determining to what nodes
@@ -662,6 +692,43 @@ impl RpcHelper {
 
 // ------- utility for tracking successes/errors among write sets --------
 
+/// Nodes that had acknowledged a write, and nodes that had failed it,
+/// at the moment a quorum of acknowledgements was reached.
+/// Requests to pending nodes continue in the background.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteAck {
+	/// Nodes that acknowledged the write
+	pub acked: Vec<Uuid>,
+	/// Nodes that returned an error, with the error message
+	pub failed: Vec<(Uuid, String)>,
+	/// Nodes of `failed` that did not answer before the timeout
+	pub timed_out: Vec<Uuid>,
+	/// Nodes that had not answered yet
+	pub pending: Vec<Uuid>,
+}
+
+impl WriteAck {
+	/// Total number of nodes the write was sent to
+	pub fn total(&self) -> usize {
+		self.acked.len() + self.failed.len() + self.pending.len()
+	}
+
+	/// Whether all nodes had acknowledged the write
+	pub fn is_complete(&self) -> bool {
+		self.acked.len() == self.total()
+	}
+}
+
+impl std::fmt::Display for WriteAck {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "stored on {}/{} nodes", self.acked.len(), self.total())?;
+		if !self.failed.is_empty() {
+			write!(f, ", {} failed", self.failed.len())?;
+		}
+		Ok(())
+	}
+}
+
 pub struct QuorumSetResultTracker<S, E> {
 	/// The set of nodes and the index of the quorum sets they belong to
 	pub nodes: HashMap<Uuid, Vec<usize>>,
@@ -764,3 +831,113 @@ where
 		)
 	}
 }
+
+impl<S> QuorumSetResultTracker<S, Error> {
+	/// Summary of the results received so far
+	pub fn write_ack(&self) -> WriteAck {
+		let acked = self.successes.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+		let failed = self
+			.failures
+			.iter()
+			.map(|(n, e)| (*n, e.to_string()))
+			.collect::<Vec<_>>();
+		let timed_out = self
+			.failures
+			.iter()
+			.filter(|(_, e)| matches!(e, Error::Timeout))
+			.map(|(n, _)| *n)
+			.collect::<Vec<_>>();
+		let mut pending = self
+			.nodes
+			.keys()
+			.copied()
+			.filter(|n| !acked.contains(n) && !failed.iter().any(|(f, _)| f == n))
+			.collect::<Vec<_>>();
+		pending.sort();
+		WriteAck {
+			acked,
+			failed,
+			timed_out,
+			pending,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn node(i: u8) -> Uuid {
+		Uuid::from([i; 32])
+	}
+
+	#[test]
+	fn test_write_ack_partial_failure() {
+		let nodes = vec![node(1), node(2), node(3)];
+
+		// One node fails, the two others acknowledge: quorum of 2 is reached
+		let mut tracker = QuorumSetResultTracker::<(), Error>::new(std::slice::from_ref(&nodes), 2);
+		tracker.register_result(node(1), Ok(()));
+		tracker.register_result(node(2), Err(Error::Message("disk full".into())));
+		assert!(!tracker.all_quorums_ok());
+		assert!(!tracker.too_many_failures());
+		tracker.register_result(node(3), Ok(()));
+		assert!(tracker.all_quorums_ok());
+		let ack = tracker.write_ack();
+		assert_eq!(ack.acked, vec![node(1), node(3)]);
+		assert_eq!(ack.failed, vec![(node(2), "disk full".to_string())]);
+		assert!(ack.timed_out.is_empty());
+		assert!(ack.pending.is_empty());
+		assert_eq!(ack.total(), 3);
+		assert!(!ack.is_complete());
+		assert_eq!(ack.to_string(), "stored on 2/3 nodes, 1 failed");
+
+		// Quorum reached before the last node answered
+		let mut tracker = QuorumSetResultTracker::<(), Error>::new(std::slice::from_ref(&nodes), 2);
+		tracker.register_result(node(3), Ok(()));
+		tracker.register_result(node(1), Ok(()));
+		assert!(tracker.all_quorums_ok());
+		let ack = tracker.write_ack();
+		assert_eq!(ack.acked, vec![node(3), node(1)]);
+		assert!(ack.failed.is_empty());
+		assert_eq!(ack.pending, vec![node(2)]);
+		assert_eq!(ack.to_string(), "stored on 2/3 nodes");
+
+		// All nodes acknowledged
+		let mut tracker = QuorumSetResultTracker::<(), Error>::new(std::slice::from_ref(&nodes), 2);
+		for n in nodes.iter() {
+			tracker.register_result(*n, Ok(()));
+		}
+		assert!(tracker.write_ack().is_complete());
+
+		// Two failures: quorum can't be reached anymore
+		let mut tracker = QuorumSetResultTracker::<(), Error>::new(&[nodes], 2);
+		tracker.register_result(node(1), Err(Error::Timeout));
+		tracker.register_result(node(2), Err(Error::Timeout));
+		assert!(tracker.too_many_failures());
+		assert!(matches!(
+			tracker.quorum_error(),
+			Error::Quorum(2, Some(1), 0, 3, _)
+		));
+	}
+
+	#[test]
+	fn test_write_ack_several_sets() {
+		// During a layout change, a node can belong to the sets of both layout versions
+		let sets = vec![
+			vec![node(1), node(2), node(3)],
+			vec![node(2), node(3), node(4)],
+		];
+		let mut tracker = QuorumSetResultTracker::<(), Error>::new(&sets, 2);
+		tracker.register_result(node(2), Ok(()));
+		tracker.register_result(node(1), Err(Error::Timeout));
+		tracker.register_result(node(3), Ok(()));
+		assert!(tracker.all_quorums_ok());
+		let ack = tracker.write_ack();
+		assert_eq!(ack.acked, vec![node(2), node(3)]);
+		assert_eq!(ack.failed.len(), 1);
+		assert_eq!(ack.timed_out, vec![node(1)]);
+		assert_eq!(ack.pending, vec![node(4)]);
+		assert_eq!(ack.total(), 4);
+	}
+}
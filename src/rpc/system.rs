@@ -26,6 +26,7 @@ use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::persister::Persister;
 use garage_util::time::*;
+use garage_util::version::ProtocolVersion;
 
 #[cfg(feature = "consul-discovery")]
 use crate::consul::ConsulDiscovery;
@@ -42,10 +43,29 @@ use crate::system_metrics::*;
 const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
 const STATUS_EXCHANGE_INTERVAL: Duration = Duration::from_secs(10);
 
+/// Version of the protocol used between Garage nodes.
+/// Version 2.0 changed the format of bucket entries (authorized key set),
+/// and added new variants to `BlockRpc` and `AdminRpc`, that nodes running
+/// version 1.0 cannot decode. Clusters are upgraded from version 1.0 by
+/// restarting all nodes at once, see the upgrade guide in the documentation.
+pub const GARAGE_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::new(2, 0);
+
 /// Version tag used for version check upon Netapp connection.
-/// Cluster nodes with different version tags are deemed
-/// incompatible and will refuse to connect.
-pub const GARAGE_VERSION_TAG: u64 = 0x6761726167650010; // garage 0x0010 (1.0)
+/// Cluster nodes with incompatible protocol versions (see `ProtocolVersion`)
+/// will refuse to connect.
+pub const GARAGE_VERSION_TAG: u64 = GARAGE_PROTOCOL_VERSION.version_tag(); // garage 0x0020 (2.0)
+
+/// Check that the version tag announced by a peer (`theirs`) has a protocol version
+/// that is compatible with the one in our version tag (`ours`)
+pub fn is_version_tag_compatible(ours: u64, theirs: u64) -> bool {
+	match (
+		ProtocolVersion::from_version_tag(ours),
+		ProtocolVersion::from_version_tag(theirs),
+	) {
+		(Some(ours), Some(theirs)) => ours.is_compatible_with(&theirs),
+		_ => false,
+	}
+}
 
 /// RPC endpoint used for calls related to membership
 pub const SYSTEM_RPC_PATH: &str = "garage_rpc/system.rs/SystemRpc";
@@ -117,6 +137,11 @@ pub struct System {
 	pub metadata_dir: PathBuf,
 	/// Path to data directory
 	pub data_dir: DataDirEnum,
+
+	/// Interval between two full anti-entropy syncs of the tables, if configured
+	pub table_sync_interval: Option<Duration>,
+	/// Maximum random delay added to the anti-entropy interval, if configured
+	pub table_sync_jitter: Option<Duration>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -257,6 +282,7 @@ impl System {
 			.filter(|x| x.rpc_bind_outgoing)
 			.map(|x| x.rpc_bind_addr.ip());
 		let netapp = NetApp::new(GARAGE_VERSION_TAG, network_key, node_key, bind_outgoing_to);
+		netapp.set_app_version_check(is_version_tag_compatible);
 		let system_endpoint = netapp.endpoint(SYSTEM_RPC_PATH.into());
 
 		// ---- setup netapp public listener and full mesh peering strategy ----
@@ -326,6 +352,9 @@ impl System {
 
 			metadata_dir: config.metadata_dir.clone(),
 			data_dir: config.data_dir.clone(),
+
+			table_sync_interval: config.table_sync_interval_secs.map(Duration::from_secs),
+			table_sync_jitter: config.table_sync_jitter_secs.map(Duration::from_secs),
 		});
 
 		sys.system_endpoint.set_handler(sys.clone());
@@ -319,6 +319,16 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 
 	// ---- Insert queue functions ----
 
+	/// Entries waiting in the insert queue of this table. They are usually
+	/// queued by the `updated` hook of another table; this gives a way to
+	/// inspect them, e.g. in tests.
+	pub fn insert_queue_entries(&self) -> Result<Vec<F::E>, Error> {
+		self.insert_queue
+			.iter()?
+			.map(|kv| self.decode_entry(&kv?.1))
+			.collect()
+	}
+
 	pub(crate) fn queue_insert(
 		&self,
 		tx: &mut db::Transaction,
@@ -1,13 +1,60 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 use opentelemetry::{global, metrics::*, KeyValue};
 
 use garage_db as db;
 
+/// Progress of the current full sync of a table, updated by the sync worker
+#[derive(Default)]
+pub(crate) struct SyncProgress {
+	/// Number of partitions to sync in the current full sync
+	pub(crate) total: AtomicU64,
+	/// Number of partitions being synced right now
+	pub(crate) syncing: AtomicU64,
+	/// Number of partitions already synced in the current full sync
+	pub(crate) synced: AtomicU64,
+	/// Number of partitions of the current full sync whose last sync attempt
+	/// failed, and that will be retried
+	pub(crate) failed: AtomicU64,
+}
+
+impl SyncProgress {
+	/// Start a new full sync of `total` partitions
+	pub(crate) fn start(&self, total: usize) {
+		self.total.store(total as u64, Ordering::Relaxed);
+		self.synced.store(0, Ordering::Relaxed);
+		self.failed.store(0, Ordering::Relaxed);
+	}
+
+	/// Record the outcome of an attempt to sync a partition, `failed_before`
+	/// telling whether a previous attempt in the current full sync had failed
+	pub(crate) fn record(&self, success: bool, failed_before: bool) {
+		match (success, failed_before) {
+			(true, false) => {
+				self.synced.fetch_add(1, Ordering::Relaxed);
+			}
+			(true, true) => {
+				self.synced.fetch_add(1, Ordering::Relaxed);
+				self.failed.fetch_sub(1, Ordering::Relaxed);
+			}
+			(false, false) => {
+				self.failed.fetch_add(1, Ordering::Relaxed);
+			}
+			(false, true) => (),
+		}
+	}
+}
+
 /// TableMetrics reference all counter used for metrics
 pub struct TableMetrics {
 	pub(crate) _table_size: ValueObserver<u64>,
 	pub(crate) _merkle_tree_size: ValueObserver<u64>,
 	pub(crate) _merkle_todo_len: ValueObserver<u64>,
 	pub(crate) _gc_todo_len: ValueObserver<u64>,
+	pub(crate) _sync_partitions: ValueObserver<u64>,
+
+	pub(crate) sync_progress: Arc<SyncProgress>,
 
 	pub(crate) get_request_counter: BoundCounter<u64>,
 	pub(crate) get_request_duration: BoundValueRecorder<f64>,
@@ -29,6 +76,8 @@ impl TableMetrics {
 		gc_todo: db::Tree,
 	) -> Self {
 		let meter = global::meter(table_name);
+		let sync_progress = Arc::new(SyncProgress::default());
+		let sync_progress2 = sync_progress.clone();
 		TableMetrics {
 			_table_size: meter
 				.u64_value_observer(
@@ -86,6 +135,31 @@ impl TableMetrics {
 				)
 				.with_description("Table garbage collector TODO queue length")
 				.init(),
+			_sync_partitions: meter
+				.u64_value_observer(
+					"table.sync_partitions",
+					move |observer| {
+						let p = &sync_progress2;
+						for (state, value) in [
+							("total", &p.total),
+							("syncing", &p.syncing),
+							("synced", &p.synced),
+							("failed", &p.failed),
+						] {
+							observer.observe(
+								value.load(Ordering::Relaxed),
+								&[
+									KeyValue::new("table_name", table_name),
+									KeyValue::new("state", state),
+								],
+							);
+						}
+					},
+				)
+				.with_description("Number of partitions in the current full sync of the table (total), being synced, already synced, and waiting to be retried after a failure")
+				.init(),
+
+			sync_progress,
 
 			get_request_counter: meter
 				.u64_counter("table.get_request_counter")
@@ -130,3 +204,41 @@ impl TableMetrics {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_sync_progress_failures() {
+		let p = SyncProgress::default();
+		let state = |p: &SyncProgress| {
+			(
+				p.synced.load(Ordering::Relaxed),
+				p.failed.load(Ordering::Relaxed),
+			)
+		};
+
+		p.start(3);
+		p.record(true, false);
+		assert_eq!(state(&p), (1, 0));
+
+		// A partition that keeps failing is only counted once
+		p.record(false, false);
+		p.record(false, true);
+		assert_eq!(state(&p), (1, 1));
+
+		// Another partition fails, then both are synced on retry
+		p.record(false, false);
+		assert_eq!(state(&p), (1, 2));
+		p.record(true, true);
+		p.record(true, true);
+		assert_eq!(state(&p), (3, 0));
+		assert_eq!(p.total.load(Ordering::Relaxed), 3);
+
+		// A new full sync starts from scratch
+		p.record(false, false);
+		p.start(2);
+		assert_eq!(state(&p), (0, 0));
+	}
+}
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use garage_rpc::layout::*;
 use garage_rpc::system::System;
 use garage_util::data::*;
+use garage_util::error::Error;
 
 use crate::replication::*;
 
@@ -57,17 +58,17 @@ impl TableReplication for TableFullReplication {
 		0u16
 	}
 
-	fn sync_partitions(&self) -> SyncPartitions {
+	fn sync_partitions(&self) -> Result<SyncPartitions, Error> {
 		let layout = self.system.cluster_layout();
 		let layout_version = layout.current().version;
-		SyncPartitions {
+		Ok(SyncPartitions {
 			layout_version,
 			partitions: vec![SyncPartition {
 				partition: 0u16,
 				first_hash: [0u8; 32].into(),
 				last_hash: [0xff; 32].into(),
-				storage_sets: vec![layout.current().all_nodes().to_vec()],
+				storage_sets: Arc::new(vec![layout.current().all_nodes().to_vec()]),
 			}],
-		}
+		})
 	}
 }
@@ -1,5 +1,6 @@
 use garage_rpc::layout::*;
 use garage_util::data::*;
+use garage_util::error::Error;
 
 /// Trait to describe how a table shall be replicated
 pub trait TableReplication: Send + Sync + 'static {
@@ -25,7 +26,7 @@ pub trait TableReplication: Send + Sync + 'static {
 	/// Get partition for data with given hash
 	fn partition_of(&self, hash: &Hash) -> Partition;
 	/// List of partitions and nodes to sync with in current layout
-	fn sync_partitions(&self) -> SyncPartitions;
+	fn sync_partitions(&self) -> Result<SyncPartitions, Error>;
 }
 
 #[derive(Debug)]
@@ -34,10 +35,13 @@ pub struct SyncPartitions {
 	pub partitions: Vec<SyncPartition>,
 }
 
+/// A partition to synchronize, covering the hashes from `first_hash` (inclusive)
+/// to `last_hash` (exclusive). The last partition ends at `[0xFF; 32]`, and also
+/// covers the keys that start with that hash.
 #[derive(Debug)]
 pub struct SyncPartition {
 	pub partition: Partition,
 	pub first_hash: Hash,
 	pub last_hash: Hash,
-	pub storage_sets: Vec<Vec<Uuid>>,
+	pub storage_sets: SharedStorageSets,
 }
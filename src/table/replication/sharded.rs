@@ -3,6 +3,7 @@ use std::sync::Arc;
 use garage_rpc::layout::*;
 use garage_rpc::system::System;
 use garage_util::data::*;
+use garage_util::error::Error;
 
 use crate::replication::*;
 
@@ -49,35 +50,128 @@ impl TableReplication for TableShardedReplication {
 		self.system.cluster_layout().current().partition_of(hash)
 	}
 
-	fn sync_partitions(&self) -> SyncPartitions {
+	fn sync_partitions(&self) -> Result<SyncPartitions, Error> {
 		let layout = self.system.cluster_layout();
 		let layout_version = layout.ack_map_min();
 
-		let mut partitions = layout
-			.current()
-			.partitions()
-			.map(|(partition, first_hash)| {
-				let storage_sets = layout.storage_sets_of(&first_hash);
-				SyncPartition {
+		let partitions = partition_ranges(layout.partitions_storage_sets())?
+			.into_iter()
+			.map(
+				|(partition, first_hash, last_hash, storage_sets)| SyncPartition {
 					partition,
 					first_hash,
-					last_hash: [0u8; 32].into(), // filled in just after
+					last_hash,
 					storage_sets,
-				}
+				},
+			)
+			.collect::<Vec<_>>();
+
+		Ok(SyncPartitions {
+			layout_version,
+			partitions,
+		})
+	}
+}
+
+/// Compute the ranges of hashes `[first_hash, last_hash)` covered by partitions,
+/// given the first hash of each of them, with some associated data. Partitions
+/// are sorted by their first hash, and the ranges are contiguous: the first one
+/// starts at the zero hash, and the last one ends at `[0xFF; 32]` (see
+/// `SyncPartition`), so that together they cover the whole keyspace exactly once.
+/// Fails if several partitions start at the same hash, as their ranges would overlap.
+fn partition_ranges<T>(
+	mut starts: Vec<(Partition, Hash, T)>,
+) -> Result<Vec<(Partition, Hash, Hash, T)>, Error> {
+	starts.sort_by_key(|(_, first_hash, _)| *first_hash);
+	if let Some(w) = starts.windows(2).find(|w| w[0].1 == w[1].1) {
+		return Err(Error::Message(format!(
+			"partitions {} and {} both start at hash {:?}",
+			w[0].0, w[1].0, w[0].1
+		)));
+	}
+
+	let next_first_hashes = starts
+		.iter()
+		.skip(1)
+		.map(|(_, first_hash, _)| *first_hash)
+		.chain(std::iter::once([0xFFu8; 32].into()))
+		.collect::<Vec<_>>();
+
+	Ok(starts
+		.into_iter()
+		.zip(next_first_hashes)
+		.enumerate()
+		.map(|(i, ((partition, first_hash, data), last_hash))| {
+			let first_hash = if i == 0 { [0u8; 32].into() } else { first_hash };
+			(partition, first_hash, last_hash, data)
+		})
+		.collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn check_tiling(ranges: &[(Partition, Hash, Hash, ())]) {
+		assert_eq!(ranges[0].1, Hash::from([0u8; 32]));
+		assert_eq!(ranges[ranges.len() - 1].2, Hash::from([0xFFu8; 32]));
+		for (_, first_hash, last_hash, _) in ranges.iter() {
+			assert!(first_hash < last_hash);
+		}
+		for w in ranges.windows(2) {
+			assert_eq!(w[0].2, w[1].1);
+		}
+	}
+
+	#[test]
+	fn test_partition_ranges_tile_keyspace() {
+		// Partitions as produced by a layout with 256 partitions, in a scrambled order
+		let mut starts = (0..256u16)
+			.map(|i| {
+				let mut first_hash = [0u8; 32];
+				first_hash[0] = i as u8;
+				(i, Hash::from(first_hash), ())
 			})
 			.collect::<Vec<_>>();
+		starts.reverse();
+		starts.swap(10, 200);
 
-		for i in 0..partitions.len() {
-			partitions[i].last_hash = if i + 1 < partitions.len() {
-				partitions[i + 1].first_hash
-			} else {
-				[0xFFu8; 32].into()
-			};
+		let ranges = partition_ranges(starts).unwrap();
+		assert_eq!(ranges.len(), 256);
+		check_tiling(&ranges);
+		for (i, (partition, first_hash, _, _)) in ranges.iter().enumerate() {
+			assert_eq!(*partition, i as u16);
+			assert_eq!(first_hash.as_slice()[0], i as u8);
 		}
 
-		SyncPartitions {
-			layout_version,
-			partitions,
+		// Each hash falls in exactly one range
+		for h in [[0u8; 32], [0x42; 32], [0x80; 32], [0xFE; 32]] {
+			let h = Hash::from(h);
+			let n = ranges
+				.iter()
+				.filter(|(_, first_hash, last_hash, _)| *first_hash <= h && h < *last_hash)
+				.count();
+			assert_eq!(n, 1);
 		}
 	}
+
+	#[test]
+	fn test_partition_ranges_edge_cases() {
+		assert!(partition_ranges::<()>(vec![]).unwrap().is_empty());
+
+		// A single partition covers everything, even if it doesn't start at zero
+		let ranges = partition_ranges(vec![(7, Hash::from([0x10; 32]), ())]).unwrap();
+		assert_eq!(ranges.len(), 1);
+		assert_eq!(ranges[0].0, 7);
+		check_tiling(&ranges);
+	}
+
+	#[test]
+	fn test_partition_ranges_overlapping() {
+		assert!(partition_ranges(vec![
+			(0, Hash::from([0u8; 32]), ()),
+			(1, Hash::from([0u8; 32]), ()),
+		])
+		.is_err());
+	}
 }
@@ -1,4 +1,6 @@
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
+use std::ops::Bound;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -27,8 +29,11 @@ use crate::merkle::*;
 use crate::replication::*;
 use crate::*;
 
-// Do anti-entropy every 10 minutes
+// Do anti-entropy every 10 minutes by default
 const ANTI_ENTROPY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// Add up to 1 minute of random delay to each anti-entropy round by default,
+// so that all nodes don't start syncing at the same time
+const ANTI_ENTROPY_JITTER: Duration = Duration::from_secs(60);
 
 pub struct TableSyncer<F: TableSchema, R: TableReplication> {
 	system: Arc<System>,
@@ -80,13 +85,28 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 		self.add_full_sync_tx
 			.store(Some(Arc::new(add_full_sync_tx)));
 
+		let interval = self
+			.system
+			.table_sync_interval
+			.unwrap_or(ANTI_ENTROPY_INTERVAL);
+		let jitter = self.system.table_sync_jitter.unwrap_or(ANTI_ENTROPY_JITTER);
+		info!(
+			"{}: anti-entropy sync every {:?} (+ up to {:?} jitter)",
+			F::TABLE_NAME,
+			interval,
+			jitter
+		);
+
 		bg.spawn_worker(SyncWorker {
 			syncer: self.clone(),
 			layout_notify: self.system.layout_notify(),
 			layout_digest: self.system.cluster_layout().sync_digest(),
 			add_full_sync_rx,
 			todo: None,
-			next_full_sync: Instant::now() + Duration::from_secs(20),
+			failed: HashSet::new(),
+			interval,
+			jitter,
+			next_full_sync: jittered_tick(Duration::from_secs(20), jitter),
 		});
 	}
 
@@ -117,7 +137,7 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 				partition.storage_sets
 			);
 			let mut result_tracker = QuorumSetResultTracker::new(
-				&partition.storage_sets,
+				&partition.storage_sets[..],
 				self.data.replication.write_quorum(),
 			);
 
@@ -172,7 +192,17 @@ impl<F: TableSchema, R: TableReplication> TableSyncer<F, R> {
 		while !*must_exit.borrow() {
 			let mut items = Vec::new();
 
-			for item in self.data.store.range(begin.to_vec()..end.to_vec())? {
+			// The last partition also covers the keys that start with its end hash
+			let end_bound = if *end == Hash::from([0xFFu8; 32]) {
+				Bound::Unbounded
+			} else {
+				Bound::Excluded(end.to_vec())
+			};
+			for item in self
+				.data
+				.store
+				.range((Bound::Included(begin.to_vec()), end_bound))?
+			{
 				let (key, value) = item?;
 				items.push((key.to_vec(), Arc::new(ByteBuf::from(value))));
 
@@ -485,9 +515,13 @@ struct SyncWorker<F: TableSchema, R: TableReplication> {
 	layout_digest: SyncLayoutDigest,
 
 	add_full_sync_rx: mpsc::UnboundedReceiver<()>,
+	interval: Duration,
+	jitter: Duration,
 	next_full_sync: Instant,
 
 	todo: Option<SyncPartitions>,
+	/// First hash of the partitions of `todo` whose last sync attempt failed
+	failed: HashSet<Hash>,
 }
 
 impl<F: TableSchema, R: TableReplication> SyncWorker<F, R> {
@@ -505,7 +539,18 @@ impl<F: TableSchema, R: TableReplication> SyncWorker<F, R> {
 	}
 
 	fn add_full_sync(&mut self) {
-		let mut partitions = self.syncer.data.replication.sync_partitions();
+		let mut partitions = match self.syncer.data.replication.sync_partitions() {
+			Ok(partitions) => partitions,
+			Err(e) => {
+				error!(
+					"{}: Unable to compute the partitions to sync, retrying later: {}",
+					F::TABLE_NAME,
+					e
+				);
+				self.next_full_sync = jittered_tick(self.interval, self.jitter);
+				return;
+			}
+		};
 		info!(
 			"{}: Adding full sync for ack layout version {}",
 			F::TABLE_NAME,
@@ -513,8 +558,16 @@ impl<F: TableSchema, R: TableReplication> SyncWorker<F, R> {
 		);
 
 		partitions.partitions.shuffle(&mut thread_rng());
+
+		self.syncer
+			.data
+			.metrics
+			.sync_progress
+			.start(partitions.partitions.len());
+
 		self.todo = Some(partitions);
-		self.next_full_sync = Instant::now() + ANTI_ENTROPY_INTERVAL;
+		self.failed.clear();
+		self.next_full_sync = jittered_tick(self.interval, self.jitter);
 	}
 }
 
@@ -536,9 +589,21 @@ impl<F: TableSchema, R: TableReplication> Worker for SyncWorker<F, R> {
 
 		if let Some(todo) = &mut self.todo {
 			let partition = todo.partitions.pop().unwrap();
+			let progress = &self.syncer.data.metrics.sync_progress;
 
 			// process partition
-			if let Err(e) = self.syncer.sync_partition(&partition, must_exit).await {
+			progress.syncing.store(1, Ordering::Relaxed);
+			let res = self.syncer.sync_partition(&partition, must_exit).await;
+			progress.syncing.store(0, Ordering::Relaxed);
+
+			let failed_before = if res.is_ok() {
+				self.failed.remove(&partition.first_hash)
+			} else {
+				!self.failed.insert(partition.first_hash)
+			};
+			progress.record(res.is_ok(), failed_before);
+
+			if let Err(e) = res {
 				error!(
 					"{}: Failed to sync partition {:?}: {}",
 					F::TABLE_NAME,
@@ -597,6 +662,13 @@ impl<F: TableSchema, R: TableReplication> Worker for SyncWorker<F, R> {
 
 // ---- UTIL ----
 
+/// Compute the instant of the next periodic tick, which is `interval` from now
+/// plus a random delay of at most `jitter`
+fn jittered_tick(interval: Duration, jitter: Duration) -> Instant {
+	let jitter = jitter.mul_f64(thread_rng().gen::<f64>());
+	Instant::now() + interval + jitter
+}
+
 fn hash_of_merkle_node(x: &MerkleNode) -> Result<Hash, Error> {
 	Ok(blake2sum(&nonversioned_encode(x)?[..]))
 }
@@ -73,3 +73,144 @@ impl BackgroundRunner {
 			.expect("Could not put worker in queue");
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::time::Duration;
+
+	use async_trait::async_trait;
+
+	use super::*;
+	use crate::error::Error;
+
+	struct TestWorker {
+		index: usize,
+		started: Arc<AtomicUsize>,
+		exited: Arc<AtomicUsize>,
+	}
+
+	#[async_trait]
+	impl Worker for TestWorker {
+		fn name(&self) -> String {
+			format!("Test worker #{}", self.index)
+		}
+
+		async fn work(
+			&mut self,
+			_must_exit: &mut watch::Receiver<bool>,
+		) -> Result<WorkerState, Error> {
+			self.started.fetch_add(1, Ordering::SeqCst);
+			Ok(WorkerState::Idle)
+		}
+
+		async fn wait_for_work(&mut self) -> WorkerState {
+			futures::future::pending().await
+		}
+	}
+
+	impl Drop for TestWorker {
+		fn drop(&mut self) {
+			self.exited.fetch_add(1, Ordering::SeqCst);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_background_runner_join() {
+		const N: usize = 10;
+		let started = Arc::new(AtomicUsize::new(0));
+		let exited = Arc::new(AtomicUsize::new(0));
+
+		let (send_stop, stop_signal) = watch::channel(false);
+		let (runner, await_all_done) = BackgroundRunner::new(stop_signal);
+		for index in 0..N {
+			runner.spawn_worker(TestWorker {
+				index,
+				started: started.clone(),
+				exited: exited.clone(),
+			});
+		}
+
+		while runner.get_worker_info().len() < N {
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		let mut names = runner
+			.get_worker_info()
+			.into_values()
+			.map(|i| i.name)
+			.collect::<Vec<_>>();
+		names.sort();
+		assert_eq!(names.len(), N);
+		assert_eq!(names[0], "Test worker #0");
+		assert_eq!(started.load(Ordering::SeqCst), N);
+		assert_eq!(exited.load(Ordering::SeqCst), 0);
+
+		// Once the stop signal is sent, awaiting the runner waits for all workers to exit
+		send_stop.send(true).unwrap();
+		tokio::time::timeout(Duration::from_secs(5), await_all_done)
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(exited.load(Ordering::SeqCst), N);
+	}
+
+	struct PanickingWorker {
+		panicked: bool,
+		runs: Arc<AtomicUsize>,
+	}
+
+	#[async_trait]
+	impl Worker for PanickingWorker {
+		fn name(&self) -> String {
+			"Panicking worker".into()
+		}
+
+		async fn work(
+			&mut self,
+			_must_exit: &mut watch::Receiver<bool>,
+		) -> Result<WorkerState, Error> {
+			if !self.panicked {
+				self.panicked = true;
+				assert!(worker::panic_is_caught());
+				panic!("test panic");
+			}
+			match self.runs.fetch_add(1, Ordering::SeqCst) {
+				0..=1 => Ok(WorkerState::Busy),
+				_ => Ok(WorkerState::Done),
+			}
+		}
+
+		async fn wait_for_work(&mut self) -> WorkerState {
+			unreachable!()
+		}
+	}
+
+	#[tokio::test]
+	async fn test_background_runner_panic() {
+		let runs = Arc::new(AtomicUsize::new(0));
+		assert!(!worker::panic_is_caught());
+
+		let (send_stop, stop_signal) = watch::channel(false);
+		let (runner, await_all_done) = BackgroundRunner::new(stop_signal);
+		runner.spawn_worker(PanickingWorker {
+			panicked: false,
+			runs: runs.clone(),
+		});
+
+		// The worker is restarted after the panic, and then runs normally
+		let info = loop {
+			let info = runner.get_worker_info();
+			match info.into_values().next() {
+				Some(i) if i.state == WorkerState::Done => break i,
+				_ => tokio::time::sleep(Duration::from_millis(50)).await,
+			}
+		};
+		assert_eq!(runs.load(Ordering::SeqCst), 3);
+		assert_eq!(info.errors, 1);
+		assert_eq!(info.consecutive_errors, 0);
+		assert!(info.last_error.unwrap().0.contains("test panic"));
+
+		send_stop.send(true).unwrap();
+		await_all_done.await.unwrap();
+	}
+}
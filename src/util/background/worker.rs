@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -9,6 +10,7 @@ use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use tokio::select;
 use tokio::sync::{mpsc, watch};
+use tracing::Instrument;
 
 use crate::background::{WorkerInfo, WorkerStatus};
 use crate::error::Error;
@@ -95,8 +97,10 @@ impl WorkerProcessor {
 						let task_id = next_task_id;
 						next_task_id += 1;
 						let stop_signal = self.stop_signal.clone();
+						let span = info_span!("worker", name = %new_worker.name(), tid = task_id);
 						let mut worker = WorkerHandler {
 								task_id,
+								span,
 								stop_signal,
 								worker: new_worker,
 								state: WorkerState::Busy,
@@ -177,6 +181,8 @@ impl WorkerProcessor {
 
 struct WorkerHandler {
 	task_id: usize,
+	/// Tracing span in which the worker runs, identifying it by name and TID
+	span: tracing::Span,
 	stop_signal: watch::Receiver<bool>,
 	worker: Box<dyn Worker>,
 	state: WorkerState,
@@ -187,29 +193,33 @@ struct WorkerHandler {
 
 impl WorkerHandler {
 	async fn step(&mut self) {
+		let span = self.span.clone();
+		self.step_inner().instrument(span).await
+	}
+
+	async fn step_inner(&mut self) {
+		// Once the stop signal has been received, a worker that was waiting is
+		// not made to wait again, as the signal will not change anymore
+		if *self.stop_signal.borrow() && self.state != WorkerState::Busy {
+			return;
+		}
 		match self.state {
-			WorkerState::Busy => match self.worker.work(&mut self.stop_signal).await {
-				Ok(s) => {
-					self.state = s;
-					self.consecutive_errors = 0;
-				}
-				Err(e) => {
-					error!(
-						"Error in worker {} (TID {}): {}",
-						self.worker.name(),
-						self.task_id,
-						e
-					);
-					self.errors += 1;
-					self.consecutive_errors += 1;
-					self.last_error = Some((format!("{}", e), now_msec()));
-					// Sleep a bit so that error won't repeat immediately, exponential backoff
-					// strategy (min 1sec, max ~60sec)
-					self.state = WorkerState::Throttled(
-						(1.5f32).powf(std::cmp::min(10, self.consecutive_errors - 1) as f32),
-					);
+			WorkerState::Busy => {
+				// A panic in a worker is handled like an error, so that the worker
+				// is restarted after a delay instead of silently stopping forever.
+				// The panic hook of the Garage daemon checks `panic_is_caught`
+				// to let such panics unwind up to here instead of aborting.
+				let work = IN_WORKER.scope((), self.worker.work(&mut self.stop_signal));
+				let work = AssertUnwindSafe(work);
+				match work.catch_unwind().await {
+					Ok(Ok(s)) => {
+						self.state = s;
+						self.consecutive_errors = 0;
+					}
+					Ok(Err(e)) => self.record_error(format!("{}", e)),
+					Err(panic) => self.record_error(panic_message(panic)),
 				}
-			},
+			}
 			WorkerState::Throttled(delay) => {
 				// Sleep for given delay and go back to busy state
 				select! {
@@ -220,9 +230,13 @@ impl WorkerHandler {
 				}
 			}
 			WorkerState::Idle => {
+				let wait = AssertUnwindSafe(IN_WORKER.scope((), self.worker.wait_for_work()));
 				select! {
-					new_st = self.worker.wait_for_work() => {
-						self.state = new_st;
+					new_st = wait.catch_unwind() => {
+						match new_st {
+							Ok(new_st) => self.state = new_st,
+							Err(panic) => self.record_error(panic_message(panic)),
+						}
 					}
 					_ = self.stop_signal.changed() => (),
 				}
@@ -230,4 +244,44 @@ impl WorkerHandler {
 			WorkerState::Done => unreachable!(),
 		}
 	}
+
+	fn record_error(&mut self, e: String) {
+		error!(
+			"Error in worker {} (TID {}): {}",
+			self.worker.name(),
+			self.task_id,
+			e
+		);
+		self.errors += 1;
+		self.consecutive_errors += 1;
+		self.last_error = Some((e, now_msec()));
+		// Sleep a bit so that error won't repeat immediately, exponential backoff
+		// strategy (min 1sec, max ~60sec)
+		self.state = WorkerState::Throttled(
+			(1.5f32).powf(std::cmp::min(10, self.consecutive_errors - 1) as f32),
+		);
+	}
+}
+
+tokio::task_local! {
+	/// Set while the code of a worker is running, see `panic_is_caught`
+	static IN_WORKER: ();
+}
+
+/// Returns true if the current code is running in a background worker,
+/// in which case a panic is caught and the worker is restarted after a delay.
+/// Panics in tasks spawned by a worker are not caught.
+pub fn panic_is_caught() -> bool {
+	IN_WORKER.try_with(|_| ()).is_ok()
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+	let msg = match panic.downcast_ref::<&str>() {
+		Some(msg) => msg.to_string(),
+		None => match panic.downcast_ref::<String>() {
+			Some(msg) => msg.clone(),
+			None => "unknown panic payload".into(),
+		},
+	};
+	format!("worker panicked: {}", msg)
 }
@@ -1,3 +1,6 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
 use rustc_version::version;
 
 fn main() {
@@ -5,4 +8,46 @@ fn main() {
 	// the garage_build_info metric.
 	let v = version().unwrap();
 	println!("cargo:rustc-env=RUSTC_VERSION={v}");
+
+	// Acquire the git commit from which Garage is built, either from the
+	// GIT_COMMIT environment variable (e.g. for builds outside of a git checkout)
+	// or from git itself. If neither is available, the commit is unknown.
+	println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+	println!("cargo:rerun-if-changed=build.rs");
+	let commit = std::env::var("GIT_COMMIT").ok().or_else(|| {
+		rerun_if_git_head_changed();
+		git(&["rev-parse", "--short=12", "HEAD"])
+	});
+	if let Some(commit) = commit {
+		println!("cargo:rustc-env=GARAGE_GIT_COMMIT={commit}");
+	}
+}
+
+/// Ask cargo to run this script again when the commit checked out changes,
+/// i.e. when HEAD is moved or when the branch it points to is updated
+fn rerun_if_git_head_changed() {
+	let git_path = |name: &str| git(&["rev-parse", "--git-path", name]).map(PathBuf::from);
+	let mut watched = vec![git_path("HEAD")];
+	if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+		// The branch is either in a file of its own, which is created when it is
+		// updated, or in packed-refs. Watching the directory of the branch also
+		// catches the creation of its file.
+		watched.push(git_path(&head_ref).and_then(|p| p.parent().map(Path::to_path_buf)));
+		watched.push(git_path("packed-refs"));
+	}
+	// Cargo would run the script again on every build if a path did not exist
+	for path in watched.into_iter().flatten().filter(|p| p.exists()) {
+		println!("cargo:rerun-if-changed={}", path.display());
+	}
+}
+
+/// Output of a git command, if it succeeded and printed something
+fn git(args: &[&str]) -> Option<String> {
+	let output = Command::new("git")
+		.args(args)
+		.output()
+		.ok()
+		.filter(|output| output.status.success())?;
+	let out = String::from_utf8(output.stdout).ok()?;
+	Some(out.trim().to_string()).filter(|o| !o.is_empty())
 }
@@ -1,13 +1,19 @@
 //! Contains type and functions related to Garage configuration file
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use serde::{de, Deserialize};
+use tokio::sync::watch;
 
 use crate::error::Error;
 use crate::socket_address::UnixOrTCPSocketAddress;
 
+/// Maximum value of `block_resync_workers`
+pub const MAX_BLOCK_RESYNC_WORKERS: usize = 8;
+
 /// Represent the whole configuration
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
@@ -22,15 +28,34 @@ pub struct Config {
 	/// Whether to fsync after all data block writes (disabled by default)
 	#[serde(default)]
 	pub data_fsync: bool,
+	/// Free space below which no new data blocks are written to a data directory
+	#[serde(default)]
+	pub data_dir_min_free_space: Option<MinFreeSpace>,
 
 	/// Disable automatic scrubbing of the data directory
 	#[serde(default)]
 	pub disable_scrub: bool,
+	/// Maximum rate at which data blocks are read by the scrub, in bytes per second
+	/// (0 for no limit other than the scrub tranquility)
+	#[serde(deserialize_with = "deserialize_capacity", default)]
+	pub scrub_max_rate: usize,
+	/// Maximum number of data blocks read by the scrub per second
+	/// (0 for no limit other than the scrub tranquility)
+	#[serde(default)]
+	pub scrub_max_ops: u64,
 
 	/// Use local timezone
 	#[serde(default)]
 	pub use_local_tz: bool,
 
+	/// Format of the log messages written by the daemon (text or json)
+	#[serde(default)]
+	pub log_format: LogFormat,
+	/// Filter of the log messages written by the daemon, with the syntax of
+	/// the `RUST_LOG` environment variable, which takes precedence over it
+	#[serde(default)]
+	pub log_level: Option<String>,
+
 	/// Optional directory where metadata snapshots will be store
 	pub metadata_snapshots_dir: Option<PathBuf>,
 
@@ -44,6 +69,14 @@ pub struct Config {
 		default = "default_block_size"
 	)]
 	pub block_size: usize,
+	/// Maximum size of a data block accepted for storage on this node,
+	/// 0 for no limit
+	#[serde(deserialize_with = "deserialize_capacity", default)]
+	pub max_block_size: usize,
+	/// Maximum size of an object that can be uploaded through the S3 API
+	/// of this node, 0 for no limit
+	#[serde(deserialize_with = "deserialize_capacity", default)]
+	pub max_object_size: usize,
 
 	/// Number of replicas. Can be any positive integer, but uneven numbers are more favorable.
 	/// - 1 for single-node clusters, or to disable replication
@@ -75,6 +108,82 @@ pub struct Config {
 	)]
 	pub block_ram_buffer_max: usize,
 
+	/// Number of levels of subdirectories in which data blocks are stored
+	/// in the data directory (1, 2 or 3)
+	#[serde(default = "default_block_dir_depth")]
+	pub block_dir_depth: usize,
+
+	/// Number of blocks to fetch in advance when reading objects sequentially
+	#[serde(default = "default_block_read_ahead")]
+	pub block_read_ahead: usize,
+
+	/// Number of resync workers, overrides the value set at runtime
+	pub block_resync_workers: Option<usize>,
+	/// Delay before retrying a failed block resync, in seconds,
+	/// which is then doubled for each consecutive failure
+	pub block_resync_retry_delay_secs: Option<u64>,
+	/// Percentage of time each resync worker should spend resyncing, instead of
+	/// pacing them with the resync tranquility. The actual value is adapted
+	/// to the load of the node and to the length of the resync queue
+	pub block_resync_target_load_percent: Option<u32>,
+	/// Duration that each step of a resync worker (resyncing a block and
+	/// sleeping) should last on average, in milliseconds. Replaces the resync
+	/// tranquility and `block_resync_target_load_percent`
+	pub block_resync_step_duration_msec: Option<u64>,
+	/// Maximum rate at which data blocks are read or written by all resync
+	/// workers together, in bytes per second (0 for no limit)
+	#[serde(deserialize_with = "deserialize_capacity", default)]
+	pub block_resync_max_rate: usize,
+	/// Maximum number of data blocks resynced per second by all resync
+	/// workers together (0 for no limit)
+	#[serde(default)]
+	pub block_resync_max_ops: u64,
+
+	/// Number of corrupted blocks found on a single data directory during
+	/// the alarm window above which an alarm is raised
+	#[serde(default = "default_block_corruption_alarm_threshold")]
+	pub block_corruption_alarm_threshold: usize,
+	/// Duration of the window over which corrupted blocks are counted, in seconds
+	#[serde(default = "default_block_corruption_alarm_window_secs")]
+	pub block_corruption_alarm_window_secs: u64,
+	/// Move corrupted blocks to the `corrupt` subdirectory of their data directory,
+	/// instead of renaming them in place
+	#[serde(default)]
+	pub block_corruption_quarantine: bool,
+
+	/// Number of paths of blocks found on disk that are kept in memory,
+	/// to avoid checking the filesystem each time. 0 disables the cache
+	#[serde(default = "default_block_exists_cache_size")]
+	pub block_exists_cache_size: usize,
+	/// Maximum size in RAM of the contents of blocks recently read from disk
+	/// that are kept in memory. 0 disables the cache
+	#[serde(
+		deserialize_with = "deserialize_capacity",
+		default = "default_block_cache_size"
+	)]
+	pub block_cache_size: usize,
+	/// Maximum size of a block for it to be kept in the block cache
+	#[serde(
+		deserialize_with = "deserialize_capacity",
+		default = "default_block_cache_max_entry_size"
+	)]
+	pub block_cache_max_entry_size: usize,
+
+	/// When a block is read from other nodes, check in the background that the
+	/// other nodes that should store it have a valid copy, and send it to them if not
+	#[serde(default)]
+	pub block_read_repair: bool,
+
+	/// Key used to encrypt data blocks at rest: 32 bytes hex encoded.
+	/// Blocks are stored unencrypted if not set
+	pub data_encryption_key: Option<String>,
+	/// Optional file where the data encryption key is read from
+	pub data_encryption_key_file: Option<PathBuf>,
+	/// Allow enabling encryption on a node that already stores unencrypted blocks,
+	/// which stay readable and are encrypted when they are next read
+	#[serde(default)]
+	pub data_encryption_migrate: bool,
+
 	/// Skip the permission check of secret files. Useful when
 	/// POSIX ACLs (or more complex chmods) are used.
 	#[serde(default)]
@@ -100,6 +209,18 @@ pub struct Config {
 	pub rpc_ping_timeout_msec: Option<u64>,
 	/// Timeout for Netapp RPC calls
 	pub rpc_timeout_msec: Option<u64>,
+	/// Timeout for RPC calls that read or write data blocks,
+	/// defaults to `rpc_timeout_msec`
+	pub block_rpc_timeout_msec: Option<u64>,
+	/// Timeout for asking other nodes whether they need a block during resync,
+	/// defaults to `rpc_timeout_msec`
+	pub block_need_query_timeout_msec: Option<u64>,
+
+	/// Interval between two full anti-entropy syncs of the metadata tables
+	pub table_sync_interval_secs: Option<u64>,
+	/// Maximum random delay added to the anti-entropy sync interval,
+	/// so that nodes don't all sync at the same time
+	pub table_sync_jitter_secs: Option<u64>,
 
 	// -- Bootstrapping and discovery
 	/// Bootstrap peers RPC address
@@ -157,6 +278,95 @@ pub struct DataDir {
 	pub read_only: bool,
 }
 
+/// Format of the log messages written by the daemon
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+	/// Human-readable text
+	#[default]
+	Text,
+	/// One JSON object per line, for log aggregation systems
+	Json,
+}
+
+/// Minimum free space on a filesystem, either as a number of bytes
+/// (an integer, or a string such as `"10GB"`) or as a percentage
+/// of the size of the filesystem (a string such as `"5%"`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MinFreeSpace {
+	Bytes(u64),
+	Percent(f64),
+}
+
+impl MinFreeSpace {
+	/// Whether a filesystem with `avail` bytes available out of `total`
+	/// has less free space than this
+	pub fn is_below(&self, avail: u64, total: u64) -> bool {
+		match *self {
+			Self::Bytes(min) => avail < min,
+			Self::Percent(pct) => (avail as f64) < total as f64 * pct / 100.0,
+		}
+	}
+}
+
+impl std::str::FromStr for MinFreeSpace {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, String> {
+		match s.trim().strip_suffix('%') {
+			Some(pct) => match pct.trim().parse::<f64>() {
+				Ok(pct) if (0.0..=100.0).contains(&pct) => Ok(Self::Percent(pct)),
+				_ => Err(format!("invalid percentage: {}", s)),
+			},
+			None => s
+				.parse::<bytesize::ByteSize>()
+				.map(|x| Self::Bytes(x.as_u64()))
+				.map_err(|e| format!("invalid capacity value: {}", e)),
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for MinFreeSpace {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: de::Deserializer<'de>,
+	{
+		struct MinFreeSpaceVisitor;
+
+		impl<'de> serde::de::Visitor<'de> for MinFreeSpaceVisitor {
+			type Value = MinFreeSpace;
+			fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+				formatter.write_str("int, '<capacity>' or '<percentage>%'")
+			}
+
+			fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+			where
+				E: de::Error,
+			{
+				value.parse().map_err(E::custom)
+			}
+
+			fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+			where
+				E: de::Error,
+			{
+				u64::try_from(v)
+					.map(MinFreeSpace::Bytes)
+					.map_err(|_| E::custom("capacity value out of bound".to_owned()))
+			}
+
+			fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+			where
+				E: de::Error,
+			{
+				Ok(MinFreeSpace::Bytes(v))
+			}
+		}
+
+		deserializer.deserialize_any(MinFreeSpaceVisitor)
+	}
+}
+
 /// Configuration for S3 api
 #[derive(Deserialize, Debug, Clone)]
 pub struct S3ApiConfig {
@@ -186,6 +396,23 @@ pub struct WebConfig {
 	/// Whether to add the requested domain to exported Prometheus metrics
 	#[serde(default)]
 	pub add_host_to_metrics: bool,
+	/// Whether buckets can also be addressed by the first segment of the path
+	/// (`http://<root_domain>/<bucket>/<key>`) for requests to the root domain
+	/// or to an IP address
+	#[serde(default)]
+	pub path_style: bool,
+	/// Whether objects with a compressible content type are served from their
+	/// gzip-compressed variant, stored as `<key>.gz`, to clients that accept gzip.
+	/// When there is no such variant, the request costs two object table lookups
+	#[serde(default)]
+	pub precompressed: bool,
+	/// Value of the Cache-Control header to add to responses for objects
+	/// that were not uploaded with their own Cache-Control header
+	pub cache_control: Option<String>,
+	/// Time given to open connections to finish when shutting down, in seconds,
+	/// after which they are closed
+	#[serde(default = "default_web_shutdown_grace_period_secs")]
+	pub shutdown_grace_period_secs: u64,
 }
 
 /// Configuration for the admin and monitoring HTTP API
@@ -206,6 +433,10 @@ pub struct AdminConfig {
 
 	/// OTLP server to where to export traces
 	pub trace_sink: Option<String>,
+
+	/// Maximum number of blocks in the resync queue above which the
+	/// node is reported as not ready by the /ready endpoint
+	pub readiness_max_resync_queue: Option<usize>,
 }
 
 #[derive(Deserialize, Debug, Clone, Default)]
@@ -257,9 +488,313 @@ pub struct KubernetesDiscoveryConfig {
 
 /// Read and parse configuration
 pub fn read_config(config_file: PathBuf) -> Result<Config, Error> {
+	Ok(load_config(&config_file)?.1)
+}
+
+/// Read and parse configuration, also returning the values read from the file
+/// (after environment overrides) so that they can be compared on reload
+fn load_config(config_file: &Path) -> Result<(toml::Table, Config), Error> {
+	load_config_with_env(config_file, &std::env::vars().collect())
+}
+
+fn load_config_with_env(
+	config_file: &Path,
+	env: &BTreeMap<String, String>,
+) -> Result<(toml::Table, Config), Error> {
 	let config = std::fs::read_to_string(config_file)?;
 
-	Ok(toml::from_str(&config)?)
+	let mut values: toml::Table = toml::from_str(&config)?;
+	apply_env_overrides(&mut values, env);
+
+	let config: Config = toml::Value::Table(values.clone()).try_into()?;
+	config.validate()?;
+
+	Ok((values, config))
+}
+
+/// Configuration options that can be changed without restarting Garage,
+/// by reloading the configuration file with a `ConfigReloader`
+pub const LIVE_RELOADABLE_OPTIONS: &[&str] = &[
+	"scrub_max_rate",
+	"scrub_max_ops",
+	"block_resync_retry_delay_secs",
+	"block_resync_target_load_percent",
+	"block_resync_step_duration_msec",
+	"block_resync_max_rate",
+	"block_resync_max_ops",
+	"log_level",
+];
+
+/// Values of the configuration options that can be changed at runtime
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveConfig {
+	pub scrub_max_rate: usize,
+	pub scrub_max_ops: u64,
+	pub block_resync_retry_delay_secs: Option<u64>,
+	pub block_resync_target_load_percent: Option<u32>,
+	pub block_resync_step_duration_msec: Option<u64>,
+	pub block_resync_max_rate: usize,
+	pub block_resync_max_ops: u64,
+	pub log_level: Option<String>,
+}
+
+impl From<&Config> for LiveConfig {
+	fn from(config: &Config) -> Self {
+		Self {
+			scrub_max_rate: config.scrub_max_rate,
+			scrub_max_ops: config.scrub_max_ops,
+			block_resync_retry_delay_secs: config.block_resync_retry_delay_secs,
+			block_resync_target_load_percent: config.block_resync_target_load_percent,
+			block_resync_step_duration_msec: config.block_resync_step_duration_msec,
+			block_resync_max_rate: config.block_resync_max_rate,
+			block_resync_max_ops: config.block_resync_max_ops,
+			log_level: config.log_level.clone(),
+		}
+	}
+}
+
+/// Reloads the configuration file at runtime (e.g. when receiving SIGHUP),
+/// and publishes the new values of the live-reloadable options to subscribers.
+/// Changes to other options are ignored with a warning, as they require a restart.
+pub struct ConfigReloader {
+	config_file: PathBuf,
+	values: Mutex<toml::Table>,
+	live: watch::Sender<LiveConfig>,
+}
+
+impl ConfigReloader {
+	/// Read the configuration file, and return the configuration along with
+	/// a reloader that can read it again later
+	pub fn load(config_file: PathBuf) -> Result<(Config, Self), Error> {
+		let (values, config) = load_config(&config_file)?;
+		let (live, _) = watch::channel(LiveConfig::from(&config));
+		let reloader = Self {
+			config_file,
+			values: Mutex::new(values),
+			live,
+		};
+		Ok((config, reloader))
+	}
+
+	/// Get a receiver for the current values of the live-reloadable options
+	pub fn subscribe(&self) -> watch::Receiver<LiveConfig> {
+		self.live.subscribe()
+	}
+
+	/// Read the configuration file again and publish the new values of the
+	/// live-reloadable options. If the file is invalid, an error is returned
+	/// and the values in use are not changed.
+	pub fn reload(&self) -> Result<LiveConfig, Error> {
+		let (new_values, config) = load_config(&self.config_file)?;
+
+		let mut values = self.values.lock().unwrap();
+		for option in restart_required_changes(&values, &new_values) {
+			warn!(
+				"Configuration option `{}` was changed, Garage must be restarted for this change to take effect",
+				option
+			);
+		}
+		// Only remember the new values of the options that were applied,
+		// so that we keep warning about the others until Garage is restarted
+		for option in LIVE_RELOADABLE_OPTIONS {
+			match new_values.get(*option) {
+				Some(v) => values.insert(option.to_string(), v.clone()),
+				None => values.remove(*option),
+			};
+		}
+
+		let live = LiveConfig::from(&config);
+		self.live.send_if_modified(|current| {
+			let modified = *current != live;
+			if modified {
+				info!("Applying new configuration: {:?}", live);
+				*current = live.clone();
+			}
+			modified
+		});
+		Ok(live)
+	}
+}
+
+/// Returns the paths of the options that differ between two versions
+/// of the configuration file and that cannot be changed at runtime
+fn restart_required_changes(old: &toml::Table, new: &toml::Table) -> Vec<String> {
+	let mut ret = vec![];
+	diff_options("", old, new, &mut ret);
+	ret
+}
+
+fn diff_options(prefix: &str, old: &toml::Table, new: &toml::Table, ret: &mut Vec<String>) {
+	let mut keys = old.keys().chain(new.keys()).collect::<Vec<_>>();
+	keys.sort();
+	keys.dedup();
+
+	for key in keys {
+		let path = format!("{}{}", prefix, key);
+		if LIVE_RELOADABLE_OPTIONS.contains(&path.as_str()) {
+			continue;
+		}
+		match (old.get(key), new.get(key)) {
+			(Some(toml::Value::Table(o)), Some(toml::Value::Table(n))) => {
+				diff_options(&format!("{}.", path), o, n, ret)
+			}
+			(o, n) if o != n => ret.push(path),
+			_ => (),
+		}
+	}
+}
+
+/// Prefix of the environment variables that override configuration values.
+/// It is distinct from that of the other environment variables read by Garage,
+/// such as `GARAGE_RPC_SECRET` or `GARAGE_LOG_FORMAT`.
+pub const ENV_OVERRIDE_PREFIX: &str = "GARAGE_CONFIG__";
+
+/// Override the values of a parsed configuration file with those of environment
+/// variables. The name of the variable for a field is the path of that field,
+/// in upper case, with sections separated by a double underscore and prefixed
+/// by `GARAGE_CONFIG__`, e.g. `GARAGE_CONFIG__S3_WEB__BIND_ADDR` for `bind_addr`
+/// in `[s3_web]`.
+///
+/// Values are parsed as TOML values (e.g. `3`, `true` or `["a", "b"]`), and taken
+/// as strings if they are not valid TOML, or if they are numbers or booleans and
+/// the field is a string in the configuration file. Variables that do not
+/// correspond to a configuration field are ignored, like unknown fields in the
+/// configuration file, including those whose path goes through a field that is
+/// not a section.
+fn apply_env_overrides(config: &mut toml::Table, env: &BTreeMap<String, String>) {
+	'vars: for (name, raw) in env.iter() {
+		let path = match name.strip_prefix(ENV_OVERRIDE_PREFIX) {
+			Some(path) if !path.is_empty() => path.to_lowercase(),
+			_ => continue,
+		};
+		let mut fields = path.split("__").collect::<Vec<_>>();
+		let field = fields.pop().unwrap();
+
+		let mut table = &mut *config;
+		for section in fields {
+			let entry = table
+				.entry(section)
+				.or_insert_with(|| toml::Value::Table(toml::Table::new()));
+			table = match entry {
+				toml::Value::Table(t) => t,
+				_ => {
+					warn!(
+						"Ignoring environment variable {}: `{}` is not a section",
+						name, section
+					);
+					continue 'vars;
+				}
+			};
+		}
+
+		let value = env_override_value(table.get(field), raw);
+		table.insert(field.to_string(), value);
+	}
+}
+
+fn env_override_value(current: Option<&toml::Value>, raw: &str) -> toml::Value {
+	let parsed = toml::from_str::<toml::Table>(&format!("value = {}", raw))
+		.ok()
+		.and_then(|mut t| t.remove("value"));
+	match (parsed, current) {
+		(Some(v @ (toml::Value::String(_) | toml::Value::Array(_) | toml::Value::Table(_))), _) => {
+			v
+		}
+		(Some(v), Some(current)) if !current.is_str() => v,
+		(Some(v), None) => v,
+		_ => toml::Value::String(raw.to_string()),
+	}
+}
+
+impl Config {
+	/// Check the invariants of the configuration that cannot be expressed
+	/// by its type, returning an error that names the offending field
+	pub fn validate(&self) -> Result<(), Error> {
+		fn invalid(field: &str, msg: &str) -> Result<(), Error> {
+			Err(Error::Message(format!(
+				"Invalid configuration: `{}` {}",
+				field, msg
+			)))
+		}
+
+		if self.replication_factor == Some(0) {
+			return invalid("replication_factor", "must be at least 1");
+		}
+		if !["consistent", "degraded", "dangerous"].contains(&self.consistency_mode.as_str()) {
+			return invalid(
+				"consistency_mode",
+				"must be one of `consistent`, `degraded` or `dangerous`",
+			);
+		}
+		if self.block_size == 0 {
+			return invalid("block_size", "must not be zero");
+		}
+		if self.max_block_size != 0 && max_stored_block_size(self.block_size) > self.max_block_size
+		{
+			return invalid(
+				"max_block_size",
+				&format!(
+					"must be at least {} bytes with a `block_size` of {} bytes, \
+					to account for the overhead of compression and SSE-C encryption",
+					max_stored_block_size(self.block_size),
+					self.block_size
+				),
+			);
+		}
+		if self.block_rpc_timeout_msec == Some(0) {
+			return invalid("block_rpc_timeout_msec", "must not be zero");
+		}
+		if self.block_need_query_timeout_msec == Some(0) {
+			return invalid("block_need_query_timeout_msec", "must not be zero");
+		}
+		if !(1..=3).contains(&self.block_dir_depth) {
+			return invalid("block_dir_depth", "must be between 1 and 3");
+		}
+		if let Some(n) = self.block_resync_workers {
+			if !(1..=MAX_BLOCK_RESYNC_WORKERS).contains(&n) {
+				return invalid(
+					"block_resync_workers",
+					&format!("must be between 1 and {}", MAX_BLOCK_RESYNC_WORKERS),
+				);
+			}
+		}
+		if let Some(pct) = self.block_resync_target_load_percent {
+			if !(1..=100).contains(&pct) {
+				return invalid(
+					"block_resync_target_load_percent",
+					"must be between 1 and 100",
+				);
+			}
+		}
+		if self.block_resync_step_duration_msec == Some(0) {
+			return invalid("block_resync_step_duration_msec", "must not be zero");
+		}
+		if self.block_corruption_alarm_threshold == 0 {
+			return invalid("block_corruption_alarm_threshold", "must be at least 1");
+		}
+		if self.table_sync_interval_secs == Some(0) {
+			return invalid("table_sync_interval_secs", "must not be zero");
+		}
+		if let Some(root_domain) = &self.s3_api.root_domain {
+			if root_domain.trim_start_matches('.').is_empty() {
+				return invalid("s3_api.root_domain", "must not be empty");
+			}
+		}
+		if let Some(web) = &self.s3_web {
+			if web.root_domain.trim_start_matches('.').is_empty() {
+				return invalid("s3_web.root_domain", "must not be empty");
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Upper bound of the size of the stored data blocks of an object split
+/// in blocks of `block_size` bytes, which can be slightly larger than
+/// `block_size` when they are compressed or encrypted with SSE-C
+pub fn max_stored_block_size(block_size: usize) -> usize {
+	block_size + block_size / 64 + 4096
 }
 
 fn default_db_engine() -> String {
@@ -272,6 +807,33 @@ fn default_block_size() -> usize {
 fn default_block_ram_buffer_max() -> usize {
 	256 * 1024 * 1024
 }
+fn default_block_dir_depth() -> usize {
+	2
+}
+fn default_block_read_ahead() -> usize {
+	2
+}
+fn default_block_corruption_alarm_threshold() -> usize {
+	5
+}
+fn default_block_corruption_alarm_window_secs() -> u64 {
+	24 * 3600
+}
+fn default_block_exists_cache_size() -> usize {
+	16384
+}
+
+fn default_block_cache_size() -> usize {
+	32 * 1024 * 1024
+}
+
+fn default_block_cache_max_entry_size() -> usize {
+	256 * 1024
+}
+
+fn default_web_shutdown_grace_period_secs() -> u64 {
+	30
+}
 
 fn default_consistency_mode() -> String {
 	"consistent".into()
@@ -376,6 +938,7 @@ where
 #[cfg(test)]
 mod tests {
 	use crate::error::Error;
+	use std::collections::BTreeMap;
 	use std::fs::File;
 	use std::io::Write;
 
@@ -405,4 +968,342 @@ mod tests {
 
 		Ok(())
 	}
+
+	const ENV_TEST_CONFIG: &str = r#"
+		metadata_dir = "/tmp/garage/meta"
+		data_dir = "/tmp/garage/data"
+		replication_factor = 3
+		rpc_bind_addr = "[::]:3901"
+		rpc_secret = "0123"
+
+		[s3_api]
+		s3_region = "garage"
+
+		[s3_web]
+		bind_addr = "[::]:3902"
+		root_domain = ".web.garage"
+		"#;
+
+	fn env(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+		vars.iter()
+			.map(|(k, v)| (k.to_string(), v.to_string()))
+			.collect()
+	}
+
+	fn env_config(vars: &[(&str, &str)]) -> Result<super::Config, Error> {
+		let mut config: toml::Table = toml::from_str(ENV_TEST_CONFIG)?;
+		super::apply_env_overrides(&mut config, &env(vars));
+		Ok(toml::Value::Table(config).try_into()?)
+	}
+
+	#[test]
+	fn test_env_overrides() -> Result<(), Error> {
+		let config = env_config(&[
+			("GARAGE_CONFIG__REPLICATION_FACTOR", "1"),
+			("GARAGE_CONFIG__S3_WEB__BIND_ADDR", "127.0.0.1:3912"),
+			("GARAGE_CONFIG__S3_API__ROOT_DOMAIN", ".s3.garage"),
+			("GARAGE_CONFIG__RPC_SECRET", "4567"),
+			(
+				"GARAGE_CONFIG__DATA_DIR",
+				r#"[{ path = "/mnt/d1", capacity = "1T" }]"#,
+			),
+			("GARAGE_CONFIG__K2V_API__API_BIND_ADDR", "[::]:3904"),
+		])?;
+		assert_eq!(config.replication_factor, Some(1));
+		assert_eq!(
+			config.s3_web.unwrap().bind_addr.to_string(),
+			"http://127.0.0.1:3912"
+		);
+		assert_eq!(config.s3_api.root_domain.as_deref(), Some(".s3.garage"));
+		assert_eq!(config.rpc_secret.as_deref(), Some("4567"));
+		assert!(matches!(config.data_dir, super::DataDirEnum::Multiple(d) if d.len() == 1));
+		assert_eq!(
+			config.k2v_api.unwrap().api_bind_addr.to_string(),
+			"http://[::]:3904"
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_env_overrides_absent() -> Result<(), Error> {
+		// Other environment variables of Garage are not configuration overrides
+		let config = env_config(&[
+			("HOME", "/root"),
+			("GARAGE_CONFIG__", "1"),
+			("GARAGE_CONFIG_FILE", "/etc/garage.toml"),
+			("GARAGE_RPC_SECRET", "4567"),
+			("GARAGE_LOG_FORMAT", "json"),
+			("GARAGE_REPLICATION_FACTOR", "1"),
+		])?;
+		assert_eq!(config.replication_factor, Some(3));
+		assert_eq!(
+			config.s3_web.unwrap().bind_addr.to_string(),
+			"http://[::]:3902"
+		);
+		assert_eq!(config.rpc_secret.as_deref(), Some("0123"));
+		assert!(config.s3_api.root_domain.is_none());
+
+		// A variable whose path goes through an option that is not a section is ignored
+		let config = env_config(&[
+			("GARAGE_CONFIG__RPC_SECRET__FOO", "1"),
+			("GARAGE_CONFIG__S3_WEB__BIND_ADDR__PORT", "3912"),
+		])?;
+		assert_eq!(config.rpc_secret.as_deref(), Some("0123"));
+		assert_eq!(
+			config.s3_web.unwrap().bind_addr.to_string(),
+			"http://[::]:3902"
+		);
+		Ok(())
+	}
+
+	#[test]
+	fn test_env_overrides_read_config() -> Result<(), Error> {
+		let path = mktemp::Temp::new_file()?;
+		std::fs::write(path.as_path(), ENV_TEST_CONFIG)?;
+
+		let (_, config) = super::load_config_with_env(
+			path.as_path(),
+			&env(&[("GARAGE_CONFIG__TABLE_SYNC_JITTER_SECS", "42")]),
+		)?;
+		assert_eq!(config.table_sync_jitter_secs, Some(42));
+		assert_eq!(config.replication_factor, Some(3));
+		Ok(())
+	}
+
+	#[test]
+	fn test_reload() -> Result<(), Error> {
+		let path = mktemp::Temp::new_file()?;
+		std::fs::write(path.as_path(), ENV_TEST_CONFIG)?;
+
+		let (config, reloader) = super::ConfigReloader::load(path.to_path_buf())?;
+		assert_eq!(config.scrub_max_rate, 0);
+		let mut live = reloader.subscribe();
+		assert_eq!(live.borrow_and_update().scrub_max_rate, 0);
+
+		// Reloading an unchanged file does not notify subscribers
+		reloader.reload()?;
+		assert!(!live.has_changed().unwrap());
+
+		// Live-reloadable options are propagated to subscribers
+		let changed = format!(
+			"scrub_max_rate = 1000000\nblock_resync_retry_delay_secs = 10\nblock_resync_step_duration_msec = 100\nblock_resync_max_rate = \"50M\"\nlog_level = \"garage=debug\"\n{}",
+			ENV_TEST_CONFIG
+		);
+		std::fs::write(path.as_path(), &changed)?;
+		reloader.reload()?;
+		assert!(live.has_changed().unwrap());
+		{
+			let live = live.borrow_and_update();
+			assert_eq!(live.scrub_max_rate, 1000000);
+			assert_eq!(live.block_resync_retry_delay_secs, Some(10));
+			assert_eq!(live.block_resync_step_duration_msec, Some(100));
+			assert_eq!(live.block_resync_max_rate, 50_000_000);
+			assert_eq!(live.log_level.as_deref(), Some("garage=debug"));
+		}
+
+		// An invalid file does not change the values in use
+		std::fs::write(path.as_path(), "scrub_max_rate = 1")?;
+		assert!(reloader.reload().is_err());
+		assert!(!live.has_changed().unwrap());
+		assert_eq!(live.borrow().scrub_max_rate, 1000000);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_reload_restart_required() -> Result<(), Error> {
+		let old: toml::Table = toml::from_str(ENV_TEST_CONFIG)?;
+		let new: toml::Table = toml::from_str(
+			&ENV_TEST_CONFIG
+				.replace("replication_factor = 3", "replication_factor = 1")
+				.replace("[::]:3902", "[::]:3912"),
+		)?;
+		assert_eq!(
+			super::restart_required_changes(&old, &new),
+			vec!["replication_factor", "s3_web.bind_addr"]
+		);
+
+		let new: toml::Table =
+			toml::from_str(&format!("scrub_max_ops = 100\n{}", ENV_TEST_CONFIG))?;
+		assert!(super::restart_required_changes(&old, &new).is_empty());
+		Ok(())
+	}
+
+	fn parse_config(extra: &str, sections: &str) -> Result<super::Config, Error> {
+		let config = format!(
+			r#"
+			metadata_dir = "/tmp/garage/meta"
+			data_dir = "/tmp/garage/data"
+			rpc_bind_addr = "[::]:3901"
+			{}
+
+			[s3_api]
+			s3_region = "garage"
+			api_bind_addr = "[::]:3900"
+			{}
+			"#,
+			extra, sections
+		);
+		let config: super::Config = toml::from_str(&config)?;
+		config.validate()?;
+		Ok(config)
+	}
+
+	fn assert_invalid(extra: &str, sections: &str, field: &str) {
+		match parse_config(extra, sections) {
+			Err(Error::Message(msg)) => assert!(msg.contains(field), "{}", msg),
+			r => panic!("expected invalid `{}`, got {:?}", field, r),
+		}
+	}
+
+	#[test]
+	fn test_validate_default() {
+		assert!(parse_config("replication_factor = 3", "").is_ok());
+	}
+
+	#[test]
+	fn test_validate_replication_factor() {
+		assert_invalid("replication_factor = 0", "", "`replication_factor`");
+	}
+
+	#[test]
+	fn test_validate_consistency_mode() {
+		assert!(parse_config(r#"consistency_mode = "degraded""#, "").is_ok());
+		assert_invalid(r#"consistency_mode = "eventual""#, "", "`consistency_mode`");
+	}
+
+	#[test]
+	fn test_min_free_space() {
+		use super::MinFreeSpace;
+
+		let config = parse_config(r#"data_dir_min_free_space = "10GB""#, "").unwrap();
+		assert_eq!(
+			config.data_dir_min_free_space,
+			Some(MinFreeSpace::Bytes(10_000_000_000))
+		);
+		let config = parse_config("data_dir_min_free_space = 4096", "").unwrap();
+		assert_eq!(
+			config.data_dir_min_free_space,
+			Some(MinFreeSpace::Bytes(4096))
+		);
+		let config = parse_config(r#"data_dir_min_free_space = "2.5%""#, "").unwrap();
+		assert_eq!(
+			config.data_dir_min_free_space,
+			Some(MinFreeSpace::Percent(2.5))
+		);
+		assert!(parse_config(r#"data_dir_min_free_space = "150%""#, "").is_err());
+		assert!(parse_config(r#"data_dir_min_free_space = "lots""#, "").is_err());
+
+		assert!(MinFreeSpace::Bytes(100).is_below(99, 1000));
+		assert!(!MinFreeSpace::Bytes(100).is_below(100, 1000));
+		assert!(MinFreeSpace::Percent(10.0).is_below(99, 1000));
+		assert!(!MinFreeSpace::Percent(10.0).is_below(100, 1000));
+	}
+
+	#[test]
+	fn test_validate_block_size() {
+		assert_invalid("block_size = 0", "", "`block_size`");
+		assert!(parse_config("max_block_size = \"2M\"", "").is_ok());
+		assert_invalid("max_block_size = \"1M\"", "", "`max_block_size`");
+		assert!(parse_config("block_size = \"10M\"\nmax_block_size = \"11M\"", "").is_ok());
+		assert_invalid(
+			"block_size = \"10M\"\nmax_block_size = \"10M\"",
+			"",
+			"`max_block_size`",
+		);
+	}
+
+	#[test]
+	fn test_validate_block_rpc_timeouts() {
+		let config = parse_config(
+			"block_rpc_timeout_msec = 2000\nblock_need_query_timeout_msec = 500",
+			"",
+		)
+		.unwrap();
+		assert_eq!(config.block_rpc_timeout_msec, Some(2000));
+		assert_eq!(config.block_need_query_timeout_msec, Some(500));
+		assert_invalid("block_rpc_timeout_msec = 0", "", "`block_rpc_timeout_msec`");
+		assert_invalid(
+			"block_need_query_timeout_msec = 0",
+			"",
+			"`block_need_query_timeout_msec`",
+		);
+	}
+
+	#[test]
+	fn test_validate_block_dir_depth() {
+		assert_invalid("block_dir_depth = 0", "", "`block_dir_depth`");
+		assert_invalid("block_dir_depth = 4", "", "`block_dir_depth`");
+	}
+
+	#[test]
+	fn test_validate_block_resync_workers() {
+		assert!(parse_config("block_resync_workers = 8", "").is_ok());
+		assert_invalid("block_resync_workers = 0", "", "`block_resync_workers`");
+		assert_invalid("block_resync_workers = 9", "", "`block_resync_workers`");
+		assert!(parse_config("block_resync_target_load_percent = 100", "").is_ok());
+		assert_invalid(
+			"block_resync_target_load_percent = 0",
+			"",
+			"`block_resync_target_load_percent`",
+		);
+		assert!(parse_config("block_resync_step_duration_msec = 100", "").is_ok());
+		assert_invalid(
+			"block_resync_step_duration_msec = 0",
+			"",
+			"`block_resync_step_duration_msec`",
+		);
+	}
+
+	#[test]
+	fn test_validate_block_corruption_alarm_threshold() {
+		assert_invalid(
+			"block_corruption_alarm_threshold = 0",
+			"",
+			"`block_corruption_alarm_threshold`",
+		);
+	}
+
+	#[test]
+	fn test_validate_table_sync_interval() {
+		assert_invalid(
+			"table_sync_interval_secs = 0",
+			"",
+			"`table_sync_interval_secs`",
+		);
+	}
+
+	#[test]
+	fn test_validate_root_domain() {
+		assert!(parse_config("", r#"root_domain = ".s3.garage""#).is_ok());
+		assert!(parse_config("", r#"root_domain = "s3.garage""#).is_ok());
+		assert_invalid("", r#"root_domain = ".""#, "`s3_api.root_domain`");
+
+		let web = |root_domain| {
+			format!(
+				r#"
+				[s3_web]
+				bind_addr = "[::]:3902"
+				root_domain = "{}"
+				"#,
+				root_domain
+			)
+		};
+		assert!(parse_config("", &web(".web.garage")).is_ok());
+		assert_invalid("", &web(""), "`s3_web.root_domain`");
+	}
+
+	#[test]
+	fn test_validate_bind_addr() {
+		assert!(parse_config("", "").is_ok());
+		let config = r#"
+			metadata_dir = "/tmp/garage/meta"
+			data_dir = "/tmp/garage/data"
+			rpc_bind_addr = "not an address"
+
+			[s3_api]
+			s3_region = "garage"
+			"#;
+		assert!(toml::from_str::<super::Config>(config).is_err());
+	}
 }
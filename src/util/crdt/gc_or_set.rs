@@ -0,0 +1,300 @@
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::crdt::*;
+use crate::time::now_msec;
+
+/// Identifier of an addition of an element to a `GcOrSet`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GcOrSetTag {
+	timestamp: u64,
+	discriminator: u64,
+}
+
+/// Observed-Remove Set CRDT with garbage collection of tombstones
+///
+/// This is the same CRDT as `OrSet`, except that the tag of each addition
+/// contains the timestamp at which it was made. This allows the tombstones of
+/// removed additions to be deleted once they are older than a stability watermark,
+/// so that the set does not grow forever as elements are added and removed.
+///
+/// **Precondition for garbage collection:** `gc(watermark)` must only be called
+/// with a watermark such that all nodes have observed all the operations made
+/// on the set before that time (e.g. a timestamp that the whole cluster agrees is
+/// stable, minus the maximum clock skew). The set remembers the highest watermark it
+/// has been collected at, and ignores any addition older than it that it does not
+/// already contain: under the precondition, such an addition has necessarily been
+/// removed, and its tombstone collected. Additions made through `add_mutator` are
+/// always timestamped after the watermark, so that they are never ignored.
+/// Additions older than the watermark that are still present can always be removed:
+/// their tombstones are kept until the next collection, so that they also reach
+/// the nodes that have not collected their tombstones yet.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GcOrSet<T> {
+	adds: Vec<(T, Vec<GcOrSetTag>)>,
+	removes: Vec<GcOrSetTag>,
+	watermark: u64,
+}
+
+impl<T> GcOrSet<T>
+where
+	T: Clone + Ord,
+{
+	/// Create a new empty set CRDT
+	pub fn new() -> Self {
+		Self {
+			adds: vec![],
+			removes: vec![],
+			watermark: 0,
+		}
+	}
+
+	/// Returns a set that contains a single addition of the specified element,
+	/// timestamped with the current time (or just after the garbage collection
+	/// watermark of this set, if the clock is late). This can be used to build
+	/// a delta-mutator.
+	#[must_use = "CRDT mutators are meant to be merged into a CRDT and not ignored."]
+	pub fn add_mutator(&self, x: T) -> Self {
+		let timestamp = std::cmp::max(now_msec(), self.watermark + 1);
+		Self::add_mutator_at(x, timestamp)
+	}
+
+	/// Returns a set that contains a single addition of the specified element,
+	/// with the specified timestamp. This can be used to build a delta-mutator.
+	#[must_use = "CRDT mutators are meant to be merged into a CRDT and not ignored."]
+	pub fn add_mutator_at(x: T, timestamp: u64) -> Self {
+		let tag = GcOrSetTag {
+			timestamp,
+			discriminator: thread_rng().gen(),
+		};
+		Self {
+			adds: vec![(x, vec![tag])],
+			removes: vec![],
+			watermark: 0,
+		}
+	}
+
+	/// Returns a set that removes all the additions of the specified element
+	/// that are present in this set. This can be used to build a delta-mutator.
+	#[must_use = "CRDT mutators are meant to be merged into a CRDT and not ignored."]
+	pub fn remove_mutator(&self, x: &T) -> Self {
+		let removes = match self.adds.binary_search_by(|(x2, _)| x2.cmp(x)) {
+			Ok(i) => self.adds[i].1.clone(),
+			Err(_) => vec![],
+		};
+		Self {
+			adds: vec![],
+			removes,
+			watermark: 0,
+		}
+	}
+
+	/// Add an element to the set
+	pub fn add(&mut self, x: T) {
+		let mutator = self.add_mutator(x);
+		self.merge(&mutator);
+	}
+
+	/// Remove an element from the set
+	pub fn remove(&mut self, x: &T) {
+		let mutator = self.remove_mutator(x);
+		self.merge(&mutator);
+	}
+
+	/// Delete the tombstones of additions made before `watermark`.
+	/// See the documentation of `GcOrSet` for the precondition
+	/// under which this is safe.
+	pub fn gc(&mut self, watermark: u64) {
+		self.watermark = std::cmp::max(self.watermark, watermark);
+		let watermark = self.watermark;
+		self.removes.retain(|tag| tag.timestamp >= watermark);
+	}
+
+	fn has_addition(&self, tag: &GcOrSetTag) -> bool {
+		self.adds
+			.iter()
+			.any(|(_, tags)| tags.binary_search(tag).is_ok())
+	}
+
+	/// Returns the number of tombstones stored in the set
+	pub fn tombstones(&self) -> usize {
+		self.removes.len()
+	}
+
+	/// Returns true if the element is present in the set
+	pub fn contains(&self, x: &T) -> bool {
+		self.adds.binary_search_by(|(x2, _)| x2.cmp(x)).is_ok()
+	}
+
+	/// Returns an iterator over all elements of the set, in ascending order
+	pub fn items(&self) -> impl Iterator<Item = &T> {
+		self.adds.iter().map(|(x, _)| x)
+	}
+
+	/// Returns the number of elements in the set
+	pub fn len(&self) -> usize {
+		self.adds.len()
+	}
+
+	/// Returns true if the set is empty
+	pub fn is_empty(&self) -> bool {
+		self.adds.is_empty()
+	}
+}
+
+impl<T> Crdt for GcOrSet<T>
+where
+	T: Clone + Ord,
+{
+	fn merge(&mut self, other: &Self) {
+		// Tombstones from before our watermark have already been collected,
+		// and additions from before our watermark that we don't already have
+		// have been removed, so their tombstones are not needed anymore.
+		// Tombstones of additions that we still have must always be applied.
+		let watermark = self.watermark;
+
+		for tag in other.removes.iter() {
+			if tag.timestamp < watermark && !self.has_addition(tag) {
+				continue;
+			}
+			if let Err(i) = self.removes.binary_search(tag) {
+				self.removes.insert(i, *tag);
+			}
+		}
+
+		for (x, tags) in other.adds.iter() {
+			let new_tags = tags.iter().filter(|tag| tag.timestamp >= watermark);
+			match self.adds.binary_search_by(|(x2, _)| x2.cmp(x)) {
+				Ok(i) => {
+					let my_tags = &mut self.adds[i].1;
+					for tag in new_tags {
+						if let Err(j) = my_tags.binary_search(tag) {
+							my_tags.insert(j, *tag);
+						}
+					}
+				}
+				Err(i) => {
+					let new_tags = new_tags.copied().collect::<Vec<_>>();
+					if !new_tags.is_empty() {
+						self.adds.insert(i, (x.clone(), new_tags));
+					}
+				}
+			}
+		}
+
+		// Drop additions that have been removed, and elements
+		// that no longer have any live addition
+		let removes = &self.removes;
+		self.adds.retain_mut(|(_, tags)| {
+			tags.retain(|tag| removes.binary_search(tag).is_err());
+			!tags.is_empty()
+		});
+
+		if other.watermark > self.watermark {
+			self.gc(other.watermark);
+		}
+	}
+}
+
+impl<T> Default for GcOrSet<T>
+where
+	T: Clone + Ord,
+{
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_gc_or_set_concurrent_add() {
+		let mut a = GcOrSet::new();
+		a.merge(&GcOrSet::add_mutator_at("x", 10));
+		let a0 = a.clone();
+
+		// b removes x, and a observes that removal
+		let mut b = a.clone();
+		b.remove(&"x");
+		a.merge(&b);
+		assert!(!a.contains(&"x"));
+		assert_eq!(b.tombstones(), 1);
+
+		// Both nodes have observed all operations before 15: b collects its
+		// tombstones before having seen a concurrent addition made by a,
+		// which is necessarily after the watermark
+		a.merge(&GcOrSet::add_mutator_at("x", 20));
+		b.gc(15);
+		assert_eq!(b.tombstones(), 0);
+		b.merge(&a);
+		assert!(b.contains(&"x"));
+		assert_eq!(b.tombstones(), 0);
+
+		// Merging a stale copy of the set does not bring back the removed addition
+		b.merge(&a0);
+		b.remove(&"x");
+		assert!(!b.contains(&"x"));
+		b.merge(&a0);
+		assert!(!b.contains(&"x"));
+
+		// The watermark is propagated by merges
+		a.merge(&b);
+		assert!(!a.contains(&"x"));
+		assert_eq!(a.watermark, 15);
+		assert_eq!(a.tombstones(), 1);
+		a.merge(&a0);
+		assert!(!a.contains(&"x"));
+	}
+
+	#[test]
+	fn test_gc_or_set_gc_keeps_elements() {
+		let mut a = GcOrSet::new();
+		a.merge(&GcOrSet::add_mutator_at("x", 10));
+		a.merge(&GcOrSet::add_mutator_at("y", 10));
+		a.remove(&"y");
+
+		// Collecting tombstones never drops elements that are present
+		a.gc(100);
+		assert!(a.contains(&"x"));
+		assert!(!a.contains(&"y"));
+		assert_eq!(a.tombstones(), 0);
+
+		// New additions are made after the watermark
+		a.add("z");
+		a.merge(&GcOrSet::add_mutator_at("w", 50));
+		assert_eq!(a.items().copied().collect::<Vec<_>>(), vec!["x", "z"]);
+	}
+
+	#[test]
+	fn test_gc_or_set_remove_after_gc() {
+		let mut a = GcOrSet::new();
+		a.merge(&GcOrSet::add_mutator_at("x", 10));
+		let a0 = a.clone();
+		let mut b = a.clone();
+
+		// An addition made before the watermark can still be removed
+		a.gc(100);
+		let remove = a.remove_mutator(&"x");
+		a.merge(&remove);
+		assert!(!a.contains(&"x"));
+
+		// The removal reaches nodes that have not collected their tombstones yet
+		b.merge(&a);
+		assert!(!b.contains(&"x"));
+		assert_eq!(b.tombstones(), 0);
+
+		// Merging a stale copy of the set does not bring back the removed addition
+		a.merge(&a0);
+		b.merge(&a0);
+		assert!(!a.contains(&"x"));
+		assert!(!b.contains(&"x"));
+
+		// The tombstone is deleted by the next collection
+		a.gc(200);
+		assert_eq!(a.tombstones(), 0);
+		a.merge(&a0);
+		assert!(!a.contains(&"x"));
+	}
+}
@@ -200,3 +200,84 @@ where
 		Self::new()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::crdt::Deletable;
+
+	#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+	struct TestValue(u8);
+
+	impl AutoCrdt for TestValue {
+		const WARN_IF_DIFFERENT: bool = false;
+	}
+
+	type TestMap = LwwMap<u8, Deletable<TestValue>>;
+
+	/// Small maps that cover the interesting cases: different keys,
+	/// same key with different timestamps, same key and timestamp with
+	/// different values, and deletions
+	fn sample_maps() -> Vec<TestMap> {
+		let mut maps = vec![TestMap::new()];
+		for k in 0..2 {
+			for ts in 1..3 {
+				maps.push(TestMap::raw_item(k, ts, Deletable::delete()));
+				for v in 0..2 {
+					maps.push(TestMap::raw_item(k, ts, Deletable::present(TestValue(v))));
+				}
+			}
+		}
+		let n = maps.len();
+		for i in 1..n {
+			let mut m = maps[i].clone();
+			m.merge(&maps[n - i]);
+			maps.push(m);
+		}
+		maps
+	}
+
+	fn merged(a: &TestMap, b: &TestMap) -> TestMap {
+		let mut m = a.clone();
+		m.merge(b);
+		m
+	}
+
+	#[test]
+	fn test_lww_map_merge_properties() {
+		let maps = sample_maps();
+		for a in maps.iter() {
+			// idempotence
+			assert_eq!(&merged(a, a), a);
+			for b in maps.iter() {
+				// commutativity
+				assert_eq!(merged(a, b), merged(b, a));
+				for c in maps.iter() {
+					// associativity
+					assert_eq!(merged(&merged(a, b), c), merged(a, &merged(b, c)));
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn test_lww_map_conflicts() {
+		let a = TestMap::raw_item(1, 10, Deletable::present(TestValue(1)));
+		let b = TestMap::raw_item(1, 11, Deletable::present(TestValue(0)));
+		let c = TestMap::raw_item(1, 10, Deletable::present(TestValue(2)));
+		let d = TestMap::raw_item(1, 11, Deletable::delete());
+
+		// the highest timestamp wins
+		assert_eq!(
+			merged(&a, &b).get(&1),
+			Some(&Deletable::present(TestValue(0)))
+		);
+		// equal timestamps are resolved by merging the values, here the highest value wins
+		assert_eq!(
+			merged(&a, &c).get(&1),
+			Some(&Deletable::present(TestValue(2)))
+		);
+		// a deletion with the same timestamp wins over a value
+		assert!(merged(&b, &d).get(&1).unwrap().is_deleted());
+	}
+}
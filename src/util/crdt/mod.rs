@@ -13,13 +13,19 @@ mod bool;
 #[allow(clippy::module_inception)]
 mod crdt;
 mod deletable;
+mod gc_or_set;
 mod lww;
 mod lww_map;
 mod map;
+mod or_set;
+mod rga;
 
 pub use self::bool::*;
 pub use crdt::*;
 pub use deletable::*;
+pub use gc_or_set::*;
 pub use lww::*;
 pub use lww_map::*;
 pub use map::*;
+pub use or_set::*;
+pub use rga::*;
@@ -0,0 +1,178 @@
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::crdt::*;
+
+/// Observed-Remove Set CRDT
+///
+/// Each addition of an element to the set is identified by a unique tag.
+/// Removing an element removes only the tags of the additions that were
+/// observed by the node doing the removal. This means that when an element is added
+/// on one node and removed concurrently on another node that had not seen that addition,
+/// the element is still present after the two states are merged: a removal only wins
+/// over the additions it has observed.
+///
+/// Internally, the set is stored as a vector of elements, sorted by ascending order,
+/// each associated with the sorted list of tags of its live additions, and a sorted
+/// vector of the tags of removed additions (tombstones). Tombstones are needed so that
+/// merging with an older copy of the set does not bring back removed elements.
+/// As with `Map`, modifications take `O(n)` time, so `OrSet` should be used for
+/// reasonably small sets.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OrSet<T> {
+	adds: Vec<(T, Vec<u64>)>,
+	removes: Vec<u64>,
+}
+
+impl<T> OrSet<T>
+where
+	T: Clone + Ord,
+{
+	/// Create a new empty set CRDT
+	pub fn new() -> Self {
+		Self {
+			adds: vec![],
+			removes: vec![],
+		}
+	}
+
+	/// Returns a set that contains a single addition of the specified element,
+	/// with a fresh random tag. This can be used to build a delta-mutator.
+	#[must_use = "CRDT mutators are meant to be merged into a CRDT and not ignored."]
+	pub fn add_mutator(x: T) -> Self {
+		Self::add_mutator_with_tag(x, thread_rng().gen())
+	}
+
+	/// Returns a set that contains a single addition of the specified element,
+	/// with the specified tag. The tag must be unique among all the additions
+	/// of this element, unless it is derived deterministically from the same data
+	/// on all nodes (e.g. when migrating from another representation).
+	#[must_use = "CRDT mutators are meant to be merged into a CRDT and not ignored."]
+	pub fn add_mutator_with_tag(x: T, tag: u64) -> Self {
+		Self {
+			adds: vec![(x, vec![tag])],
+			removes: vec![],
+		}
+	}
+
+	/// Returns a set that removes all the additions of the specified element
+	/// that are present in this set. This can be used to build a delta-mutator.
+	#[must_use = "CRDT mutators are meant to be merged into a CRDT and not ignored."]
+	pub fn remove_mutator(&self, x: &T) -> Self {
+		let removes = match self.adds.binary_search_by(|(x2, _)| x2.cmp(x)) {
+			Ok(i) => self.adds[i].1.clone(),
+			Err(_) => vec![],
+		};
+		Self {
+			adds: vec![],
+			removes,
+		}
+	}
+
+	/// Add an element to the set
+	pub fn add(&mut self, x: T) {
+		self.merge(&Self::add_mutator(x));
+	}
+
+	/// Remove an element from the set
+	pub fn remove(&mut self, x: &T) {
+		let mutator = self.remove_mutator(x);
+		self.merge(&mutator);
+	}
+
+	/// Returns true if the element is present in the set
+	pub fn contains(&self, x: &T) -> bool {
+		self.adds.binary_search_by(|(x2, _)| x2.cmp(x)).is_ok()
+	}
+
+	/// Returns an iterator over all elements of the set, in ascending order
+	pub fn items(&self) -> impl Iterator<Item = &T> {
+		self.adds.iter().map(|(x, _)| x)
+	}
+
+	/// Returns the number of elements in the set
+	pub fn len(&self) -> usize {
+		self.adds.len()
+	}
+
+	/// Returns true if the set is empty
+	pub fn is_empty(&self) -> bool {
+		self.adds.is_empty()
+	}
+}
+
+impl<T> Crdt for OrSet<T>
+where
+	T: Clone + Ord,
+{
+	fn merge(&mut self, other: &Self) {
+		for tag in other.removes.iter() {
+			if let Err(i) = self.removes.binary_search(tag) {
+				self.removes.insert(i, *tag);
+			}
+		}
+
+		for (x, tags) in other.adds.iter() {
+			match self.adds.binary_search_by(|(x2, _)| x2.cmp(x)) {
+				Ok(i) => {
+					let my_tags = &mut self.adds[i].1;
+					for tag in tags.iter() {
+						if let Err(j) = my_tags.binary_search(tag) {
+							my_tags.insert(j, *tag);
+						}
+					}
+				}
+				Err(i) => {
+					self.adds.insert(i, (x.clone(), tags.clone()));
+				}
+			}
+		}
+
+		// Drop additions that have been removed, and elements
+		// that no longer have any live addition
+		let removes = &self.removes;
+		self.adds.retain_mut(|(_, tags)| {
+			tags.retain(|tag| removes.binary_search(tag).is_err());
+			!tags.is_empty()
+		});
+	}
+}
+
+impl<T> Default for OrSet<T>
+where
+	T: Clone + Ord,
+{
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_or_set_concurrent_add_remove() {
+		let mut a = OrSet::new();
+		a.add("key1".to_string());
+
+		// b has observed the first addition and removes the element,
+		// while a concurrently adds it again
+		let mut b = a.clone();
+		b.remove(&"key1".to_string());
+		a.add("key1".to_string());
+
+		let mut ab = a.clone();
+		ab.merge(&b);
+		let mut ba = b.clone();
+		ba.merge(&a);
+		assert_eq!(ab, ba);
+		assert!(ab.contains(&"key1".to_string()));
+
+		// a removal that has observed all additions wins
+		ab.remove(&"key1".to_string());
+		ab.merge(&a);
+		assert!(!ab.contains(&"key1".to_string()));
+		assert!(ab.is_empty());
+	}
+}
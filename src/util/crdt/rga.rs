@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::crdt::*;
+
+/// Identifier of an element of an `Rga`
+///
+/// Identifiers are ordered first by their logical timestamp, then by a random
+/// discriminator that makes identifiers generated concurrently on different nodes
+/// distinct from one another.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RgaId {
+	timestamp: u64,
+	discriminator: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct RgaNode<T> {
+	id: RgaId,
+	/// The element after which this element was inserted, `None` if it
+	/// was inserted at the beginning of the sequence
+	after: Option<RgaId>,
+	value: T,
+	deleted: bool,
+}
+
+/// Replicated Growable Array CRDT
+///
+/// This type defines a CRDT for an ordered sequence of values, in which values can be
+/// inserted and deleted at any position. Concurrent insertions always converge to the same
+/// order on all replicas, and never reorder elements that were already in the sequence.
+///
+/// Each element is identified by a unique `RgaId`, and remembers the element after which
+/// it was inserted. The order of the sequence is that of a depth-first traversal of the
+/// tree formed by these links, where the elements inserted after a same element are visited
+/// by descending identifier, i.e. most recent insertion first. Deleted elements are kept as
+/// tombstones so that elements inserted after them can still be placed.
+///
+/// Internally, elements are stored in a vector sorted by ascending identifier, so merging
+/// two sequences takes `O(n)` time and computing the order of the sequence takes
+/// `O(n log n)` time. `Rga` should be used for reasonably small sequences.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Rga<T> {
+	nodes: Vec<RgaNode<T>>,
+}
+
+impl<T> Rga<T>
+where
+	T: Clone + PartialEq,
+{
+	/// Create a new empty sequence CRDT
+	pub fn new() -> Self {
+		Self { nodes: vec![] }
+	}
+
+	/// Returns a sequence that contains only the insertion of `value` at position `pos`
+	/// of this sequence (counting only elements that are not deleted).
+	/// This can be used to build a delta-mutator.
+	///
+	/// Panics if `pos` is greater than the length of the sequence.
+	#[must_use = "CRDT mutators are meant to be merged into a CRDT and not ignored."]
+	pub fn insert_mutator(&self, pos: usize, value: T) -> Self {
+		let order = self.visible_order();
+		assert!(pos <= order.len(), "insertion position out of bounds");
+
+		let after = match pos {
+			0 => None,
+			_ => Some(self.nodes[order[pos - 1]].id),
+		};
+		let id = RgaId {
+			timestamp: self.nodes.iter().map(|n| n.id.timestamp).max().unwrap_or(0) + 1,
+			discriminator: thread_rng().gen(),
+		};
+
+		Self {
+			nodes: vec![RgaNode {
+				id,
+				after,
+				value,
+				deleted: false,
+			}],
+		}
+	}
+
+	/// Returns a sequence that contains only the deletion of the element at position `pos`
+	/// of this sequence (counting only elements that are not deleted).
+	/// This can be used to build a delta-mutator.
+	///
+	/// Panics if `pos` is not the position of an element of the sequence.
+	#[must_use = "CRDT mutators are meant to be merged into a CRDT and not ignored."]
+	pub fn remove_mutator(&self, pos: usize) -> Self {
+		let order = self.visible_order();
+		let mut node = self.nodes[order[pos]].clone();
+		node.deleted = true;
+		Self { nodes: vec![node] }
+	}
+
+	/// Insert a value at position `pos` of the sequence
+	pub fn insert(&mut self, pos: usize, value: T) {
+		let mutator = self.insert_mutator(pos, value);
+		self.merge(&mutator);
+	}
+
+	/// Append a value at the end of the sequence
+	pub fn push(&mut self, value: T) {
+		self.insert(self.len(), value);
+	}
+
+	/// Delete the value at position `pos` of the sequence
+	pub fn remove(&mut self, pos: usize) {
+		let mutator = self.remove_mutator(pos);
+		self.merge(&mutator);
+	}
+
+	/// Get a reference to the value at position `pos` of the sequence
+	pub fn get(&self, pos: usize) -> Option<&T> {
+		self.visible_order().get(pos).map(|i| &self.nodes[*i].value)
+	}
+
+	/// Returns the number of values in the sequence
+	pub fn len(&self) -> usize {
+		self.nodes.iter().filter(|n| !n.deleted).count()
+	}
+
+	/// Returns true if the sequence is empty
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Returns the values of the sequence, in order
+	pub fn items(&self) -> Vec<&T> {
+		self.visible_order()
+			.into_iter()
+			.map(|i| &self.nodes[i].value)
+			.collect()
+	}
+
+	/// Returns the indices in `self.nodes` of the elements that are not deleted,
+	/// in the order of the sequence
+	fn visible_order(&self) -> Vec<usize> {
+		let mut children: HashMap<Option<RgaId>, Vec<usize>> = HashMap::new();
+		for (i, node) in self.nodes.iter().enumerate() {
+			children.entry(node.after).or_default().push(i);
+		}
+
+		// Nodes are sorted by ascending id, so children are also sorted by ascending id.
+		// We push them in that order on the stack so that they are popped by descending id.
+		let mut ret = Vec::with_capacity(self.nodes.len());
+		let mut stack = children.get(&None).cloned().unwrap_or_default();
+		while let Some(i) = stack.pop() {
+			let node = &self.nodes[i];
+			if !node.deleted {
+				ret.push(i);
+			}
+			if let Some(c) = children.get(&Some(node.id)) {
+				stack.extend_from_slice(c);
+			}
+		}
+		ret
+	}
+}
+
+impl<T> Crdt for Rga<T>
+where
+	T: Clone + PartialEq,
+{
+	fn merge(&mut self, other: &Self) {
+		for node in other.nodes.iter() {
+			match self.nodes.binary_search_by(|n| n.id.cmp(&node.id)) {
+				Ok(i) => {
+					self.nodes[i].deleted |= node.deleted;
+				}
+				Err(i) => {
+					self.nodes.insert(i, node.clone());
+				}
+			}
+		}
+	}
+}
+
+impl<T> Default for Rga<T>
+where
+	T: Clone + PartialEq,
+{
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn values(rga: &Rga<&'static str>) -> Vec<&'static str> {
+		rga.items().into_iter().copied().collect()
+	}
+
+	#[test]
+	fn test_rga_sequential() {
+		let mut a = Rga::new();
+		a.push("b");
+		a.push("d");
+		a.insert(0, "a");
+		a.insert(2, "c");
+		assert_eq!(values(&a), vec!["a", "b", "c", "d"]);
+
+		a.remove(1);
+		assert_eq!(values(&a), vec!["a", "c", "d"]);
+		assert_eq!(a.get(1), Some(&"c"));
+		assert_eq!(a.len(), 3);
+	}
+
+	#[test]
+	fn test_rga_concurrent_inserts() {
+		let mut base = Rga::new();
+		base.push("start");
+		base.push("end");
+
+		// Three replicas insert concurrently at the same position,
+		// and keep on appending after their own insertion
+		let mut replicas = [base.clone(), base.clone(), base.clone()];
+		for (r, words) in replicas
+			.iter_mut()
+			.zip([["a1", "a2"], ["b1", "b2"], ["c1", "c2"]])
+		{
+			r.insert(1, words[0]);
+			r.insert(2, words[1]);
+		}
+
+		// Merging in any order gives the same sequence
+		let mut merged = vec![];
+		for perm in [[0, 1, 2], [2, 1, 0], [1, 0, 2], [2, 0, 1]] {
+			let mut m = base.clone();
+			for i in perm {
+				m.merge(&replicas[i]);
+			}
+			merged.push(m);
+		}
+		for m in merged.iter() {
+			assert_eq!(m, &merged[0]);
+			assert_eq!(values(m), values(&merged[0]));
+		}
+
+		// The insertions of each replica are not interleaved with the others,
+		// and don't move the elements that were already in the sequence
+		let v = values(&merged[0]);
+		assert_eq!(v.len(), 8);
+		assert_eq!(v[0], "start");
+		assert_eq!(v[7], "end");
+		for w in ["a1", "b1", "c1"] {
+			let i = v.iter().position(|x| *x == w).unwrap();
+			assert_eq!(v[i + 1].chars().next(), w.chars().next());
+		}
+	}
+
+	#[test]
+	fn test_rga_concurrent_insert_remove() {
+		let mut a = Rga::new();
+		a.push("x");
+		a.push("y");
+
+		// b deletes "x" while a inserts after it
+		let mut b = a.clone();
+		b.remove(0);
+		a.insert(1, "z");
+
+		let mut ab = a.clone();
+		ab.merge(&b);
+		let mut ba = b.clone();
+		ba.merge(&a);
+		assert_eq!(ab, ba);
+		assert_eq!(values(&ab), vec!["z", "y"]);
+	}
+}
@@ -128,13 +128,31 @@ pub fn sha256sum(data: &[u8]) -> Hash {
 
 /// Compute the blake2 of a slice
 pub fn blake2sum(data: &[u8]) -> Hash {
-	use blake2::{Blake2b512, Digest};
-
-	let mut hasher = Blake2b512::new();
+	let mut hasher = Blake2Hasher::new();
 	hasher.update(data);
-	let mut hash = [0u8; 32];
-	hash.copy_from_slice(&hasher.finalize()[..32]);
-	hash.into()
+	hasher.finalize()
+}
+
+/// Incremental computation of the blake2 of data that arrives in several slices
+#[derive(Default)]
+pub struct Blake2Hasher(blake2::Blake2b512);
+
+impl Blake2Hasher {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn update(&mut self, data: &[u8]) {
+		use blake2::Digest;
+		self.0.update(data);
+	}
+
+	pub fn finalize(self) -> Hash {
+		use blake2::Digest;
+		let mut hash = [0u8; 32];
+		hash.copy_from_slice(&self.0.finalize()[..32]);
+		hash.into()
+	}
 }
 
 /// A 64 bit non cryptographic hash
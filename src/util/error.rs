@@ -3,6 +3,7 @@ use std::fmt;
 use std::io;
 
 use err_derive::Error;
+use http::StatusCode;
 
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
 
@@ -73,6 +74,17 @@ pub enum Error {
 	#[error(display = "Missing block {:?}: no node returned a valid block", _0)]
 	MissingBlock(Hash),
 
+	#[error(display = "Not enough free space: {}", _0)]
+	InsufficientSpace(String),
+
+	#[error(
+		display = "Block too large: {:?} is {} bytes, which exceeds max_block_size ({} bytes)",
+		_0,
+		_1,
+		_2
+	)]
+	BlockTooLarge(Hash, usize, usize),
+
 	#[error(display = "{}", _0)]
 	Message(String),
 }
@@ -81,6 +93,102 @@ impl Error {
 	pub fn unexpected_rpc_message<T: Serialize>(v: T) -> Self {
 		Self::UnexpectedRpcMessage(debug_serialize(&v))
 	}
+
+	/// Returns true if this error is likely to be transient (network hiccup,
+	/// node temporarily unavailable, timeout...), in which case retrying the
+	/// same operation later has a chance of succeeding. Errors caused by
+	/// invalid data or programming mistakes are never considered retryable.
+	///
+	/// Errors returned by remote nodes are only known by their message. They
+	/// are considered retryable, unless they are recognized as permanent errors
+	/// (see `is_block_too_large`).
+	pub fn is_retryable(&self) -> bool {
+		match self {
+			Error::Io(e) => is_transient_io_error_kind(e.kind()),
+			Error::Hyper(e) => !e.is_user() && !e.is_parse(),
+			Error::Net(e) => match e {
+				garage_net::error::Error::Io(e) => is_transient_io_error_kind(e.kind()),
+				garage_net::error::Error::Remote(kind, _) => is_transient_io_error_kind(*kind),
+				garage_net::error::Error::Framing
+				| garage_net::error::Error::ConnectionClosed
+				| garage_net::error::Error::NoHandler
+				| garage_net::error::Error::OneshotRecv(_)
+				| garage_net::error::Error::Handshake(_) => true,
+				_ => false,
+			},
+			Error::TokioJoin(e) => e.is_cancelled(),
+			Error::RemoteError(_) | Error::Quorum(..) => !self.is_block_too_large(),
+			Error::Timeout | Error::MissingBlock(_) | Error::InsufficientSpace(_) => true,
+			_ => false,
+		}
+	}
+
+	/// Returns true if this error, or an error returned by one of the remote
+	/// nodes involved, is a block being rejected because it exceeds the
+	/// `max_block_size` of the node that should have stored it.
+	/// Remote errors are only known by their message, which is why
+	/// they are matched on the display prefix of `BlockTooLarge`.
+	pub fn is_block_too_large(&self) -> bool {
+		match self {
+			Error::BlockTooLarge(..) => true,
+			Error::RemoteError(msg) => msg.starts_with(BLOCK_TOO_LARGE_PREFIX),
+			Error::Quorum(_, _, _, _, errors) => {
+				!errors.is_empty() && errors.iter().all(|e| e.contains(BLOCK_TOO_LARGE_PREFIX))
+			}
+			_ => false,
+		}
+	}
+}
+
+const BLOCK_TOO_LARGE_PREFIX: &str = "Block too large: ";
+
+impl Error {
+	/// HTTP status code to return to a client when a request fails with
+	/// this error. All HTTP-facing endpoints (S3, K2V, web, admin) share
+	/// this mapping so that a given internal error is always reported
+	/// the same way.
+	pub fn http_status_code(&self) -> StatusCode {
+		// No wildcard here: adding a variant must force a decision.
+		match self {
+			Error::Timeout
+			| Error::RemoteError(_)
+			| Error::Quorum(..)
+			| Error::InsufficientSpace(_) => StatusCode::SERVICE_UNAVAILABLE,
+			Error::HttpHeader(_) => StatusCode::BAD_REQUEST,
+			Error::BlockTooLarge(..) => StatusCode::PAYLOAD_TOO_LARGE,
+			Error::Io(_)
+			| Error::Hyper(_)
+			| Error::Http(_)
+			| Error::Net(_)
+			| Error::Db(_)
+			| Error::RmpEncode(_)
+			| Error::RmpDecode(_)
+			| Error::Json(_)
+			| Error::TomlDecode(_)
+			| Error::TokioJoin(_)
+			| Error::TokioSemAcquire(_)
+			| Error::TokioBcastRecv(_)
+			| Error::UnexpectedRpcMessage(_)
+			| Error::CorruptData(_)
+			| Error::MissingBlock(_)
+			| Error::Message(_) => StatusCode::INTERNAL_SERVER_ERROR,
+		}
+	}
+}
+
+fn is_transient_io_error_kind(kind: io::ErrorKind) -> bool {
+	matches!(
+		kind,
+		io::ErrorKind::TimedOut
+			| io::ErrorKind::Interrupted
+			| io::ErrorKind::WouldBlock
+			| io::ErrorKind::ConnectionReset
+			| io::ErrorKind::ConnectionAborted
+			| io::ErrorKind::ConnectionRefused
+			| io::ErrorKind::NotConnected
+			| io::ErrorKind::BrokenPipe
+			| io::ErrorKind::UnexpectedEof
+	)
 }
 
 impl From<garage_db::TxError<Error>> for Error {
@@ -202,3 +310,185 @@ impl<'de> Visitor<'de> for ErrorVisitor {
 		Ok(Error::RemoteError(error_msg))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_is_retryable() {
+		let transient = [
+			Error::Io(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
+			Error::Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset")),
+			Error::Net(garage_net::error::Error::ConnectionClosed),
+			Error::Net(garage_net::error::Error::Remote(
+				io::ErrorKind::ConnectionRefused,
+				"refused".into(),
+			)),
+			Error::RemoteError("node is busy".into()),
+			Error::Timeout,
+			Error::Quorum(2, None, 1, 3, vec!["timeout".into()]),
+			Error::MissingBlock(Hash::from([0u8; 32])),
+			Error::InsufficientSpace("data directory is full".into()),
+		];
+		for e in transient.iter() {
+			assert!(e.is_retryable(), "should be retryable: {}", e);
+		}
+
+		let permanent = [
+			Error::Io(io::Error::new(io::ErrorKind::NotFound, "not found")),
+			Error::Io(io::Error::new(io::ErrorKind::PermissionDenied, "denied")),
+			Error::Net(garage_net::error::Error::IdCollision),
+			Error::Net(garage_net::error::Error::VersionMismatch("v1".into())),
+			Error::Json(serde_json::from_str::<u64>("nope").unwrap_err()),
+			Error::RmpDecode(rmp_serde::decode::from_slice::<u64>(&[0xc1]).unwrap_err()),
+			Error::CorruptData(Hash::from([0u8; 32])),
+			Error::UnexpectedRpcMessage("Ok".into()),
+			Error::BlockTooLarge(Hash::from([0u8; 32]), 2048, 1024),
+			Error::RemoteError(Error::BlockTooLarge(Hash::from([0u8; 32]), 2048, 1024).to_string()),
+			Error::Message("invalid configuration".into()),
+		];
+		for e in permanent.iter() {
+			assert!(!e.is_retryable(), "should not be retryable: {}", e);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_http_status_code() {
+		let sem = tokio::sync::Semaphore::new(1);
+		sem.close();
+		let acquire_err = sem.acquire().await.unwrap_err();
+		let join_err = tokio::spawn(async { panic!("test") }).await.unwrap_err();
+		let header_err = http::HeaderValue::from_bytes(&[0xff])
+			.unwrap()
+			.to_str()
+			.unwrap_err();
+
+		// Every variant except Hyper, whose errors cannot be built outside of hyper
+		let cases = vec![
+			(
+				Error::Io(io::Error::other("io")),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::Http(http::Request::builder().uri("\n").body(()).unwrap_err()),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(Error::HttpHeader(header_err), StatusCode::BAD_REQUEST),
+			(
+				Error::Net(garage_net::error::Error::ConnectionClosed),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::Db(garage_db::Error("db".into())),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::RmpEncode(rmp_serde::encode::Error::Syntax("rmp".into())),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::RmpDecode(rmp_serde::decode::from_slice::<u64>(&[0xc1]).unwrap_err()),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::Json(serde_json::from_str::<u64>("nope").unwrap_err()),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::TomlDecode(toml::from_str::<u64>("nope").unwrap_err()),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::TokioJoin(join_err),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::TokioSemAcquire(acquire_err),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::TokioBcastRecv(tokio::sync::broadcast::error::RecvError::Closed),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::RemoteError("remote".into()),
+				StatusCode::SERVICE_UNAVAILABLE,
+			),
+			(Error::Timeout, StatusCode::SERVICE_UNAVAILABLE),
+			(
+				Error::Quorum(2, None, 1, 3, vec![]),
+				StatusCode::SERVICE_UNAVAILABLE,
+			),
+			(
+				Error::UnexpectedRpcMessage("Ok".into()),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::CorruptData(Hash::from([0u8; 32])),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::MissingBlock(Hash::from([0u8; 32])),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+			(
+				Error::InsufficientSpace("full".into()),
+				StatusCode::SERVICE_UNAVAILABLE,
+			),
+			(
+				Error::BlockTooLarge(Hash::from([0u8; 32]), 2048, 1024),
+				StatusCode::PAYLOAD_TOO_LARGE,
+			),
+			(
+				Error::Message("message".into()),
+				StatusCode::INTERNAL_SERVER_ERROR,
+			),
+		];
+		for (e, code) in cases {
+			assert_eq!(e.http_status_code(), code, "wrong status code for {}", e);
+		}
+	}
+
+	#[test]
+	fn test_is_block_too_large() {
+		let local = Error::BlockTooLarge(Hash::from([0u8; 32]), 2048, 1024);
+		assert!(local.is_block_too_large());
+
+		// As received from a remote node, and as aggregated by the RPC helper
+		let remote = Error::RemoteError(local.to_string());
+		assert!(remote.is_block_too_large());
+		assert!(
+			Error::Quorum(2, None, 0, 2, vec![remote.to_string(), remote.to_string()])
+				.is_block_too_large()
+		);
+
+		// Not all nodes rejected the block for its size: retrying might help
+		assert!(
+			!Error::Quorum(2, None, 0, 2, vec![remote.to_string(), "Timeout".into()])
+				.is_block_too_large()
+		);
+		assert!(!Error::RemoteError("Timeout".into()).is_block_too_large());
+		assert!(!Error::Message(local.to_string()).is_block_too_large());
+	}
+
+	#[test]
+	fn test_source_chain() {
+		use std::error::Error as StdError;
+
+		let e = Error::from(io::Error::new(io::ErrorKind::TimedOut, "disk timed out"));
+		let source = e.source().expect("io error should be kept as source");
+		let io_err = source
+			.downcast_ref::<io::Error>()
+			.expect("source should downcast to io::Error");
+		assert_eq!(io_err.kind(), io::ErrorKind::TimedOut);
+
+		let e = Error::from(serde_json::from_str::<u64>("nope").unwrap_err());
+		assert!(e
+			.source()
+			.and_then(|s| s.downcast_ref::<serde_json::Error>())
+			.is_some());
+
+		assert!(Error::Message("no source".into()).source().is_none());
+	}
+}
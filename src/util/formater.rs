@@ -0,0 +1,85 @@
+//! Module containing helper functions to format values for humans
+use std::time::Duration;
+
+use crate::time::now_msec;
+
+/// Format a duration in a human-readable way, using its two largest
+/// units, such as "3m 20s" or "2h 5m". Durations under one second are
+/// formatted in milliseconds, such as "250ms".
+pub fn format_duration(d: Duration) -> String {
+	let secs = d.as_secs();
+	if secs == 0 {
+		return match d.as_millis() {
+			0 => "0s".into(),
+			ms => format!("{}ms", ms),
+		};
+	}
+
+	let units = [
+		(secs / 86400, "d"),
+		(secs / 3600 % 24, "h"),
+		(secs / 60 % 60, "m"),
+		(secs % 60, "s"),
+	];
+	let first = units.iter().position(|(n, _)| *n > 0).unwrap();
+	units[first..]
+		.iter()
+		.take(2)
+		.filter(|(n, _)| *n > 0)
+		.map(|(n, unit)| format!("{}{}", n, unit))
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+/// Format a timestamp represented as milliseconds since UNIX Epoch
+/// relatively to the current time, such as "in 12s" or "4m ago"
+pub fn format_relative(ts_msec: u64) -> String {
+	format_relative_to(ts_msec, now_msec())
+}
+
+fn format_relative_to(ts_msec: u64, now: u64) -> String {
+	match ts_msec.cmp(&now) {
+		std::cmp::Ordering::Greater => {
+			format!(
+				"in {}",
+				format_duration(Duration::from_millis(ts_msec - now))
+			)
+		}
+		std::cmp::Ordering::Less => {
+			format!(
+				"{} ago",
+				format_duration(Duration::from_millis(now - ts_msec))
+			)
+		}
+		std::cmp::Ordering::Equal => "now".into(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_format_duration() {
+		let secs = Duration::from_secs;
+		assert_eq!(format_duration(Duration::ZERO), "0s");
+		assert_eq!(format_duration(Duration::from_millis(250)), "250ms");
+		assert_eq!(format_duration(Duration::from_millis(1999)), "1s");
+		assert_eq!(format_duration(secs(45)), "45s");
+		assert_eq!(format_duration(secs(200)), "3m 20s");
+		assert_eq!(format_duration(secs(180)), "3m");
+		assert_eq!(format_duration(secs(2 * 3600 + 5 * 60 + 30)), "2h 5m");
+		assert_eq!(format_duration(secs(2 * 3600 + 30)), "2h");
+		assert_eq!(format_duration(secs(3 * 86400 + 4 * 3600)), "3d 4h");
+	}
+
+	#[test]
+	fn test_format_relative() {
+		let now = 1_700_000_000_000;
+		assert_eq!(format_relative_to(now, now), "now");
+		assert_eq!(format_relative_to(now + 12_000, now), "in 12s");
+		assert_eq!(format_relative_to(now - 240_000, now), "4m ago");
+		assert_eq!(format_relative_to(now - 500, now), "500ms ago");
+		assert_eq!(format_relative_to(0, 3_600_000), "1h ago");
+	}
+}
@@ -9,11 +9,13 @@ pub mod crdt;
 pub mod data;
 pub mod encode;
 pub mod error;
+pub mod formater;
 pub mod forwarded_headers;
 pub mod metrics;
 pub mod migrate;
 pub mod persister;
 pub mod socket_address;
 pub mod time;
+pub mod token_bucket;
 pub mod tranquilizer;
 pub mod version;
@@ -103,6 +103,24 @@ mod test {
 		}
 	}
 
+	#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+	struct V3 {
+		a: u64,
+		b: Vec<String>,
+	}
+	impl Migrate for V3 {
+		const VERSION_MARKER: &'static [u8] = b"GtestV3";
+		type Previous = V2;
+		fn migrate(prev: V2) -> V3 {
+			let mut b = prev.b;
+			b.push(prev.c);
+			V3 {
+				a: prev.a as u64,
+				b,
+			}
+		}
+	}
+
 	#[test]
 	fn test_v1() {
 		let x = V1 {
@@ -154,4 +172,38 @@ mod test {
 		let z = V2::decode(&y_enc).unwrap();
 		assert_eq!(y, z);
 	}
+
+	#[test]
+	fn test_migrate_chain() {
+		// Values of any previous version are migrated through all
+		// intermediate versions
+		let x = V1 {
+			a: 12,
+			b: "hello".into(),
+		};
+		let z = V3::decode(&x.encode().unwrap()).unwrap();
+		assert_eq!(
+			z,
+			V3 {
+				a: 12,
+				b: vec!["hello".into(), "".into()],
+			}
+		);
+
+		let y = V2 {
+			a: 12,
+			b: vec!["hello".into()],
+			c: "world".into(),
+		};
+		let z = V3::decode(&y.encode().unwrap()).unwrap();
+		assert_eq!(z.b, vec!["hello".to_string(), "world".to_string()]);
+
+		// Values of the current version are not migrated
+		let z_enc = z.encode().unwrap();
+		assert_eq!(&z_enc[..V3::VERSION_MARKER.len()], V3::VERSION_MARKER);
+		assert_eq!(V3::decode(&z_enc).unwrap(), z);
+
+		// Values that are not valid in any version are rejected
+		assert!(V3::decode(b"GtestV3 garbage").is_none());
+	}
 }
@@ -1,88 +1,301 @@
+use std::collections::HashMap;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
+use async_trait::async_trait;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use crate::data::blake2sum;
 use crate::error::Error;
 use crate::migrate::Migrate;
 
+/// Marker at the beginning of persisted files that are followed by a checksum
+/// of their content. Files written by older versions of Garage don't have it.
+const CHECKSUM_MARKER: &[u8] = b"GprsB2\x00\x01";
+const CHECKSUM_LEN: usize = 32;
+
+/// Storage used by a `Persister` to store its values
+#[async_trait]
+pub trait PersistBackend: Send + Sync + 'static {
+	/// Read the content stored under `name`
+	fn read(&self, name: &str) -> Result<Vec<u8>, Error>;
+	/// Atomically replace the content stored under `name` by `bytes`,
+	/// and keep its previous content, if any, under `backup_name`
+	fn write(&self, name: &str, backup_name: &str, bytes: &[u8]) -> Result<(), Error>;
+
+	async fn read_async(&self, name: &str) -> Result<Vec<u8>, Error>;
+	async fn write_async(&self, name: &str, backup_name: &str, bytes: &[u8]) -> Result<(), Error>;
+
+	/// Human-readable location of the content stored under `name`, for error messages
+	fn location(&self, name: &str) -> String {
+		name.to_string()
+	}
+}
+
+/// Backend that stores values in files of a directory
+///
+/// A file is written to a temporary file which is then renamed over the
+/// previous one, so that it is never left half-written. The directory is
+/// synced after the rename so that the new file survives a crash.
+pub struct FileBackend {
+	base_dir: PathBuf,
+}
+
+impl FileBackend {
+	pub fn new(base_dir: &Path) -> Self {
+		Self {
+			base_dir: base_dir.to_path_buf(),
+		}
+	}
+
+	fn tmp_path(&self, name: &str) -> PathBuf {
+		self.base_dir.join(format!("{}.tmp", name))
+	}
+}
+
+#[async_trait]
+impl PersistBackend for FileBackend {
+	fn read(&self, name: &str) -> Result<Vec<u8>, Error> {
+		let mut file = std::fs::OpenOptions::new()
+			.read(true)
+			.open(self.base_dir.join(name))?;
+
+		let mut bytes = vec![];
+		file.read_to_end(&mut bytes)?;
+		Ok(bytes)
+	}
+
+	fn write(&self, name: &str, backup_name: &str, bytes: &[u8]) -> Result<(), Error> {
+		let path = self.base_dir.join(name);
+		let tmp_path = self.tmp_path(name);
+
+		let mut file = std::fs::OpenOptions::new()
+			.write(true)
+			.create(true)
+			.truncate(true)
+			.open(&tmp_path)?;
+		file.write_all(bytes)?;
+		file.sync_all()?;
+		drop(file);
+
+		if path.exists() {
+			std::fs::rename(&path, self.base_dir.join(backup_name))?;
+		}
+		std::fs::rename(&tmp_path, &path)?;
+		std::fs::File::open(&self.base_dir)?.sync_all()?;
+
+		Ok(())
+	}
+
+	async fn read_async(&self, name: &str) -> Result<Vec<u8>, Error> {
+		let mut file = tokio::fs::File::open(self.base_dir.join(name)).await?;
+
+		let mut bytes = vec![];
+		file.read_to_end(&mut bytes).await?;
+		Ok(bytes)
+	}
+
+	async fn write_async(&self, name: &str, backup_name: &str, bytes: &[u8]) -> Result<(), Error> {
+		let path = self.base_dir.join(name);
+		let tmp_path = self.tmp_path(name);
+
+		let mut file = tokio::fs::File::create(&tmp_path).await?;
+		file.write_all(bytes).await?;
+		file.sync_all().await?;
+		drop(file);
+
+		if tokio::fs::metadata(&path).await.is_ok() {
+			tokio::fs::rename(&path, self.base_dir.join(backup_name)).await?;
+		}
+		tokio::fs::rename(&tmp_path, &path).await?;
+		tokio::fs::File::open(&self.base_dir)
+			.await?
+			.sync_all()
+			.await?;
+
+		Ok(())
+	}
+
+	fn location(&self, name: &str) -> String {
+		self.base_dir.join(name).display().to_string()
+	}
+}
+
+/// Backend that stores values in memory, for tests.
+/// Clones of a memory backend share the same content.
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+	values: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl MemoryBackend {
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+#[async_trait]
+impl PersistBackend for MemoryBackend {
+	fn read(&self, name: &str) -> Result<Vec<u8>, Error> {
+		self.values
+			.lock()
+			.unwrap()
+			.get(name)
+			.cloned()
+			.ok_or_else(|| Error::Message(format!("{} not found", name)))
+	}
+
+	fn write(&self, name: &str, backup_name: &str, bytes: &[u8]) -> Result<(), Error> {
+		let mut values = self.values.lock().unwrap();
+		if let Some(previous) = values.insert(name.to_string(), bytes.to_vec()) {
+			values.insert(backup_name.to_string(), previous);
+		}
+		Ok(())
+	}
+
+	async fn read_async(&self, name: &str) -> Result<Vec<u8>, Error> {
+		self.read(name)
+	}
+
+	async fn write_async(&self, name: &str, backup_name: &str, bytes: &[u8]) -> Result<(), Error> {
+		self.write(name, backup_name, bytes)
+	}
+}
+
+/// A persister stores a value in a file of the metadata directory,
+/// or in another `PersistBackend`.
+///
+/// The previous version of the value is kept as a `.bak` copy. The content of
+/// the file is prefixed by a checksum: if the file is found corrupted when
+/// loading it, the backup copy is loaded instead.
 pub struct Persister<T: Migrate> {
-	path: PathBuf,
+	backend: Arc<dyn PersistBackend>,
+	name: String,
+	bak_name: String,
 
 	_marker: std::marker::PhantomData<T>,
 }
 
 impl<T: Migrate> Persister<T> {
 	pub fn new(base_dir: &Path, file_name: &str) -> Self {
-		let mut path = base_dir.to_path_buf();
-		path.push(file_name);
+		Self::with_backend(Arc::new(FileBackend::new(base_dir)), file_name)
+	}
+
+	pub fn with_backend(backend: Arc<dyn PersistBackend>, name: &str) -> Self {
 		Self {
-			path,
+			backend,
+			name: name.to_string(),
+			bak_name: format!("{}.bak", name),
 			_marker: Default::default(),
 		}
 	}
 
-	fn decode(&self, bytes: &[u8]) -> Result<T, Error> {
+	fn decode(&self, name: &str, bytes: &[u8]) -> Result<T, Error> {
+		let location = self.backend.location(name);
+		let bytes = match bytes.strip_prefix(CHECKSUM_MARKER) {
+			Some(rest) if rest.len() >= CHECKSUM_LEN => {
+				let (checksum, data) = rest.split_at(CHECKSUM_LEN);
+				if blake2sum(data).as_slice() != checksum {
+					error!("Invalid checksum in persisted data file {}", location);
+					return Err(Error::Message(format!(
+						"Invalid checksum in persisted data file {}",
+						location
+					)));
+				}
+				data
+			}
+			Some(_) => {
+				error!("Truncated persisted data file {}", location);
+				return Err(Error::Message(format!(
+					"Truncated persisted data file {}",
+					location
+				)));
+			}
+			None => bytes,
+		};
+
 		match T::decode(bytes) {
 			Some(v) => Ok(v),
 			None => {
-				error!(
-					"Unable to decode persisted data file {}",
-					self.path.display()
-				);
+				error!("Unable to decode persisted data file {}", location);
 				for line in hexdump::hexdump_iter(bytes) {
 					debug!("{}", line);
 				}
 				Err(Error::Message(format!(
 					"Unable to decode persisted data file {}",
-					self.path.display()
+					location
 				)))
 			}
 		}
 	}
 
-	pub fn load(&self) -> Result<T, Error> {
-		let mut file = std::fs::OpenOptions::new().read(true).open(&self.path)?;
-
-		let mut bytes = vec![];
-		file.read_to_end(&mut bytes)?;
+	fn encode(&self, t: &T) -> Result<Vec<u8>, Error> {
+		let data = t.encode()?;
+		let mut bytes = Vec::with_capacity(CHECKSUM_MARKER.len() + CHECKSUM_LEN + data.len());
+		bytes.extend_from_slice(CHECKSUM_MARKER);
+		bytes.extend_from_slice(blake2sum(&data).as_slice());
+		bytes.extend_from_slice(&data);
+		Ok(bytes)
+	}
 
-		let value = self.decode(&bytes[..])?;
-		Ok(value)
+	/// Choose between the result of loading the file and of loading its backup copy
+	fn load_or_backup(
+		&self,
+		res: Result<T, Error>,
+		bak_res: impl FnOnce() -> Result<T, Error>,
+	) -> Result<T, Error> {
+		match res {
+			Ok(v) => Ok(v),
+			Err(e) => match bak_res() {
+				Ok(v) => {
+					warn!(
+						"Could not load {} ({}), loaded backup copy {} instead",
+						self.backend.location(&self.name),
+						e,
+						self.backend.location(&self.bak_name)
+					);
+					Ok(v)
+				}
+				Err(_) => Err(e),
+			},
+		}
 	}
 
-	pub fn save(&self, t: &T) -> Result<(), Error> {
-		let bytes = t.encode()?;
+	fn load_file(&self, name: &str) -> Result<T, Error> {
+		let bytes = self.backend.read(name)?;
+		self.decode(name, &bytes[..])
+	}
 
-		let mut file = std::fs::OpenOptions::new()
-			.write(true)
-			.create(true)
-			.truncate(true)
-			.open(&self.path)?;
+	pub fn load(&self) -> Result<T, Error> {
+		let res = self.load_file(&self.name);
+		self.load_or_backup(res, || self.load_file(&self.bak_name))
+	}
 
-		file.write_all(&bytes[..])?;
+	pub fn save(&self, t: &T) -> Result<(), Error> {
+		let bytes = self.encode(t)?;
+		self.backend.write(&self.name, &self.bak_name, &bytes)
+	}
 
-		Ok(())
+	async fn load_file_async(&self, name: &str) -> Result<T, Error> {
+		let bytes = self.backend.read_async(name).await?;
+		self.decode(name, &bytes[..])
 	}
 
 	pub async fn load_async(&self) -> Result<T, Error> {
-		let mut file = tokio::fs::File::open(&self.path).await?;
-
-		let mut bytes = vec![];
-		file.read_to_end(&mut bytes).await?;
-
-		let value = self.decode(&bytes[..])?;
-		Ok(value)
+		let res = self.load_file_async(&self.name).await;
+		let bak_res = match res {
+			Ok(_) => None,
+			Err(_) => Some(self.load_file_async(&self.bak_name).await),
+		};
+		self.load_or_backup(res, || bak_res.unwrap())
 	}
 
 	pub async fn save_async(&self, t: &T) -> Result<(), Error> {
-		let bytes = t.encode()?;
-
-		let mut file = tokio::fs::File::create(&self.path).await?;
-		file.write_all(&bytes[..]).await?;
-
-		Ok(())
+		let bytes = self.encode(t)?;
+		self.backend
+			.write_async(&self.name, &self.bak_name, &bytes)
+			.await
 	}
 }
 
@@ -96,7 +309,14 @@ impl<V: Migrate + Default> Clone for PersisterShared<V> {
 
 impl<V: Migrate + Default> PersisterShared<V> {
 	pub fn new(base_dir: &Path, file_name: &str) -> Self {
-		let persister = Persister::new(base_dir, file_name);
+		Self::from_persister(Persister::new(base_dir, file_name))
+	}
+
+	pub fn with_backend(backend: Arc<dyn PersistBackend>, name: &str) -> Self {
+		Self::from_persister(Persister::with_backend(backend, name))
+	}
+
+	fn from_persister(persister: Persister<V>) -> Self {
 		let value = persister.load().unwrap_or_default();
 		Self(Arc::new((persister, RwLock::new(value))))
 	}
@@ -118,3 +338,89 @@ impl<V: Migrate + Default> PersisterShared<V> {
 		self.0 .0.save(&value)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use serde::{Deserialize, Serialize};
+
+	use super::*;
+
+	#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+	struct TestState {
+		counter: u64,
+	}
+
+	impl crate::migrate::InitialFormat for TestState {
+		const VERSION_MARKER: &'static [u8] = b"Gtest01";
+	}
+
+	#[test]
+	fn test_persister_backup() -> Result<(), Error> {
+		let dir = mktemp::Temp::new_dir()?;
+		let persister = Persister::<TestState>::new(dir.as_path(), "test_state");
+		let path = dir.as_path().join("test_state");
+
+		assert!(persister.load().is_err());
+
+		persister.save(&TestState { counter: 1 })?;
+		persister.save(&TestState { counter: 2 })?;
+		assert_eq!(persister.load()?, TestState { counter: 2 });
+		assert!(!dir.as_path().join("test_state.tmp").exists());
+
+		// Truncated file: the backup copy is loaded
+		let bytes = std::fs::read(&path)?;
+		std::fs::write(&path, &bytes[..bytes.len() - 1])?;
+		assert_eq!(persister.load()?, TestState { counter: 1 });
+		std::fs::write(&path, &bytes[..CHECKSUM_MARKER.len() + 4])?;
+		assert_eq!(persister.load()?, TestState { counter: 1 });
+
+		// Corrupted file: the backup copy is loaded
+		let mut corrupted = bytes.clone();
+		*corrupted.last_mut().unwrap() ^= 0x01;
+		std::fs::write(&path, &corrupted)?;
+		assert_eq!(persister.load()?, TestState { counter: 1 });
+
+		// Both copies corrupted: a clear error is returned
+		std::fs::write(dir.as_path().join("test_state.bak"), &corrupted)?;
+		let err = persister.load().unwrap_err();
+		assert!(err.to_string().contains("Invalid checksum"));
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_persister_legacy_format() -> Result<(), Error> {
+		use crate::migrate::Migrate;
+
+		let dir = mktemp::Temp::new_dir()?;
+		let persister = Persister::<TestState>::new(dir.as_path(), "test_state");
+
+		// Files written before checksums were added can still be loaded
+		std::fs::write(
+			dir.as_path().join("test_state"),
+			TestState { counter: 3 }.encode()?,
+		)?;
+		assert_eq!(persister.load()?, TestState { counter: 3 });
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_persister_memory_backend() -> Result<(), Error> {
+		let backend = MemoryBackend::new();
+		let shared = PersisterShared::<TestState>::with_backend(Arc::new(backend.clone()), "state");
+		assert_eq!(shared.get_with(|s| s.counter), 0);
+		shared.set_with(|s| s.counter = 1)?;
+		shared.set_with(|s| s.counter = 2)?;
+
+		// A new persister on the same backend reloads the value
+		let persister = Persister::<TestState>::with_backend(Arc::new(backend.clone()), "state");
+		assert_eq!(persister.load()?, TestState { counter: 2 });
+
+		// Corrupted value: the backup copy is loaded
+		backend.write("state", "state.old", b"garbage")?;
+		assert_eq!(persister.load()?, TestState { counter: 1 });
+
+		Ok(())
+	}
+}
@@ -1,13 +1,104 @@
 //! Module containing helper functions to manipulate time
 use chrono::{SecondsFormat, TimeZone, Utc};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Returns milliseconds since UNIX Epoch
 pub fn now_msec() -> u64 {
-	SystemTime::now()
-		.duration_since(UNIX_EPOCH)
-		.expect("Fix your clock :o")
-		.as_millis() as u64
+	RealClock.now_msec()
+}
+
+/// A source of the current time. Components that schedule things in time
+/// can take an `Arc<dyn Clock>` instead of calling `now_msec()`, so that
+/// they can be tested deterministically with a `MockClock`.
+pub trait Clock: Send + Sync {
+	/// Returns milliseconds since UNIX Epoch
+	fn now_msec(&self) -> u64;
+}
+
+/// Clock that reads the system clock, but never goes backwards
+/// (see `MonotonicClock`). All instances share the same floor.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+	fn now_msec(&self) -> u64 {
+		SYSTEM_CLOCK.now_msec()
+	}
+}
+
+static SYSTEM_CLOCK: MonotonicClock<SystemClock> = MonotonicClock::new(SystemClock);
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now_msec(&self) -> u64 {
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.expect("Fix your clock :o")
+			.as_millis() as u64
+	}
+}
+
+/// Clock that never returns a time smaller than one it has already returned,
+/// even if its source goes backwards (e.g. when the system clock is stepped
+/// back by NTP, or after a VM is resumed). This is needed because time is used
+/// to order things, such as the keys of the block resync queue.
+///
+/// This trades absolute accuracy for monotonicity: when the source goes back
+/// in time, this clock stays at the largest time it has returned until its
+/// source catches up, so the times it returns are in the future during that
+/// interval.
+#[derive(Debug)]
+pub struct MonotonicClock<C> {
+	source: C,
+	floor: AtomicU64,
+}
+
+impl<C: Clock> MonotonicClock<C> {
+	pub const fn new(source: C) -> Self {
+		Self {
+			source,
+			floor: AtomicU64::new(0),
+		}
+	}
+}
+
+impl<C: Clock> Clock for MonotonicClock<C> {
+	fn now_msec(&self) -> u64 {
+		let now = self.source.now_msec();
+		let floor = self.floor.fetch_max(now, Ordering::Relaxed);
+		std::cmp::max(floor, now)
+	}
+}
+
+/// Clock whose time only changes when it is set or advanced manually, for tests.
+/// Clones of a `MockClock` share the same time.
+#[derive(Clone, Debug, Default)]
+pub struct MockClock(Arc<AtomicU64>);
+
+impl MockClock {
+	/// Create a mock clock, whose time is initially `now_msec`
+	pub fn new(now_msec: u64) -> Self {
+		Self(Arc::new(AtomicU64::new(now_msec)))
+	}
+
+	/// Set the time of the clock, which can go backwards
+	pub fn set(&self, now_msec: u64) {
+		self.0.store(now_msec, Ordering::Relaxed);
+	}
+
+	/// Move the time of the clock forward by `d`
+	pub fn advance(&self, d: Duration) {
+		self.0.fetch_add(d.as_millis() as u64, Ordering::Relaxed);
+	}
+}
+
+impl Clock for MockClock {
+	fn now_msec(&self) -> u64 {
+		self.0.load(Ordering::Relaxed)
+	}
 }
 
 /// Increment logical clock
@@ -28,3 +119,43 @@ pub fn msec_to_rfc3339(msecs: u64) -> String {
 	let timestamp = Utc.timestamp_opt(secs, nanos).unwrap();
 	timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_mock_clock() {
+		let clock = MockClock::new(1000);
+		let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+		assert_eq!(shared.now_msec(), 1000);
+
+		clock.advance(Duration::from_secs(2));
+		assert_eq!(shared.now_msec(), 3000);
+		clock.set(500);
+		assert_eq!(shared.now_msec(), 500);
+
+		assert!(RealClock.now_msec() > 1_600_000_000_000);
+	}
+
+	#[test]
+	fn test_monotonic_clock() {
+		let source = MockClock::new(10_000);
+		let clock = MonotonicClock::new(source.clone());
+		assert_eq!(clock.now_msec(), 10_000);
+
+		// The source jumps backwards: time stays at the floor
+		source.set(4_000);
+		assert_eq!(clock.now_msec(), 10_000);
+		source.advance(Duration::from_secs(3));
+		assert_eq!(clock.now_msec(), 10_000);
+
+		// Until the source catches up
+		source.advance(Duration::from_secs(4));
+		assert_eq!(clock.now_msec(), 11_000);
+
+		let t1 = now_msec();
+		let t2 = RealClock.now_msec();
+		assert!(t2 >= t1);
+	}
+}
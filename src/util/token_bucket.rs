@@ -0,0 +1,300 @@
+use std::time::{Duration, Instant};
+
+use tokio::time::sleep;
+
+use crate::error::Error;
+
+/// A token bucket is a helper object that is used to limit the rate at which
+/// some resource is consumed (e.g. a number of bytes read from disk per second).
+///
+/// The bucket contains at most `capacity` tokens, and is refilled continuously
+/// at a rate of `refill_rate` tokens per second. Consuming a resource requires
+/// taking the corresponding number of tokens from the bucket, either with
+/// `try_take`, which does not take anything if not enough tokens are available,
+/// or with `take`, which waits until the tokens have been refilled.
+///
+/// Token buckets are built using `TokenBucketBuilder`.
+pub struct TokenBucket {
+	capacity: u64,
+	refill_rate: u64,
+	tokens: f64,
+	last_refill: Instant,
+}
+
+/// Builder for a `TokenBucket`
+#[derive(Default)]
+pub struct TokenBucketBuilder {
+	capacity: Option<u64>,
+	refill_rate: u64,
+	initial_tokens: Option<u64>,
+}
+
+impl TokenBucketBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Maximum number of tokens in the bucket, i.e. the maximum burst size.
+	/// Defaults to the refill rate, i.e. one second worth of tokens.
+	pub fn capacity(mut self, capacity: u64) -> Self {
+		self.capacity = Some(capacity);
+		self
+	}
+
+	/// Number of tokens added to the bucket per second, must not be zero
+	pub fn refill_rate(mut self, refill_rate: u64) -> Self {
+		self.refill_rate = refill_rate;
+		self
+	}
+
+	/// Number of tokens in the bucket when it is created.
+	/// Defaults to the capacity of the bucket.
+	pub fn initial_tokens(mut self, initial_tokens: u64) -> Self {
+		self.initial_tokens = Some(initial_tokens);
+		self
+	}
+
+	pub fn build(self) -> Result<TokenBucket, Error> {
+		if self.refill_rate == 0 {
+			return Err(Error::Message(
+				"Token bucket refill rate must not be zero".into(),
+			));
+		}
+		let capacity = self.capacity.unwrap_or(self.refill_rate);
+		if capacity == 0 {
+			return Err(Error::Message(
+				"Token bucket capacity must not be zero".into(),
+			));
+		}
+		let initial_tokens = self.initial_tokens.unwrap_or(capacity);
+		if initial_tokens > capacity {
+			return Err(Error::Message(
+				"Token bucket initial tokens must not exceed its capacity".into(),
+			));
+		}
+		Ok(TokenBucket {
+			capacity,
+			refill_rate: self.refill_rate,
+			tokens: initial_tokens as f64,
+			last_refill: Instant::now(),
+		})
+	}
+}
+
+impl TokenBucket {
+	/// Take `n` tokens from the bucket if they are available, and return true.
+	/// Return false without taking anything otherwise.
+	pub fn try_take(&mut self, n: u64) -> bool {
+		self.try_take_at(n, Instant::now())
+	}
+
+	/// Take `n` tokens from the bucket, and return how long to wait
+	/// before the bucket is no longer in debt because of this.
+	/// This allows taking more tokens than the capacity of the bucket.
+	#[must_use]
+	pub fn take_delay(&mut self, n: u64) -> Duration {
+		self.take_delay_at(n, Instant::now())
+	}
+
+	/// Take `n` tokens from the bucket, waiting for them to be available
+	pub async fn take(&mut self, n: u64) {
+		let delay = self.take_delay(n);
+		if !delay.is_zero() {
+			sleep(delay).await;
+		}
+	}
+
+	fn try_take_at(&mut self, n: u64, now: Instant) -> bool {
+		self.refill(now);
+		if self.tokens >= n as f64 {
+			self.tokens -= n as f64;
+			true
+		} else {
+			false
+		}
+	}
+
+	fn take_delay_at(&mut self, n: u64, now: Instant) -> Duration {
+		self.refill(now);
+		self.tokens -= n as f64;
+		self.delay()
+	}
+
+	/// How long to wait until the bucket is no longer in debt
+	fn delay(&self) -> Duration {
+		if self.tokens >= 0. {
+			Duration::ZERO
+		} else {
+			Duration::from_secs_f64(-self.tokens / self.refill_rate as f64)
+		}
+	}
+
+	fn refill(&mut self, now: Instant) {
+		let elapsed = now.saturating_duration_since(self.last_refill);
+		self.tokens = f64::min(
+			self.capacity as f64,
+			self.tokens + elapsed.as_secs_f64() * self.refill_rate as f64,
+		);
+		self.last_refill = std::cmp::max(self.last_refill, now);
+	}
+}
+
+/// A pair of token buckets limiting both the number of bytes and the number
+/// of operations per second, e.g. for disk IO where many small operations
+/// can saturate the disk without reaching the limit on bytes, and vice versa.
+/// An operation is admitted only when both buckets have enough tokens.
+pub struct DualTokenBucket {
+	bytes: Option<TokenBucket>,
+	ops: Option<TokenBucket>,
+}
+
+impl DualTokenBucket {
+	/// Create a rate limiter for `bytes_rate` bytes per second and
+	/// `ops_rate` operations per second, 0 meaning no limit
+	pub fn new(bytes_rate: u64, ops_rate: u64) -> Self {
+		// Building a token bucket only fails for a zero refill rate
+		let bucket = |rate| match rate {
+			0 => None,
+			r => Some(TokenBucketBuilder::new().refill_rate(r).build().unwrap()),
+		};
+		Self {
+			bytes: bucket(bytes_rate),
+			ops: bucket(ops_rate),
+		}
+	}
+
+	/// Take `bytes` bytes and one operation if both are available,
+	/// and return true. Return false without taking anything otherwise.
+	pub fn try_take(&mut self, bytes: u64) -> bool {
+		self.try_take_at(bytes, Instant::now())
+	}
+
+	/// Take `bytes` bytes and one operation, and return how long to wait
+	/// before both limits are respected again
+	#[must_use]
+	pub fn take_delay(&mut self, bytes: u64) -> Duration {
+		self.take_delay_at(bytes, Instant::now())
+	}
+
+	/// Take `bytes` bytes and one operation, waiting for them to be available
+	pub async fn take(&mut self, bytes: u64) {
+		let delay = self.take_delay(bytes);
+		if !delay.is_zero() {
+			sleep(delay).await;
+		}
+	}
+
+	fn try_take_at(&mut self, bytes: u64, now: Instant) -> bool {
+		if let Some(b) = &mut self.bytes {
+			b.refill(now);
+			if b.tokens < bytes as f64 {
+				return false;
+			}
+		}
+		if let Some(o) = &mut self.ops {
+			if !o.try_take_at(1, now) {
+				return false;
+			}
+		}
+		if let Some(b) = &mut self.bytes {
+			b.tokens -= bytes as f64;
+		}
+		true
+	}
+
+	fn take_delay_at(&mut self, bytes: u64, now: Instant) -> Duration {
+		let bytes_delay = match &mut self.bytes {
+			Some(b) => b.take_delay_at(bytes, now),
+			None => Duration::ZERO,
+		};
+		let ops_delay = match &mut self.ops {
+			Some(o) => o.take_delay_at(1, now),
+			None => Duration::ZERO,
+		};
+		std::cmp::max(bytes_delay, ops_delay)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_token_bucket_builder() {
+		assert!(TokenBucketBuilder::new().build().is_err());
+		assert!(TokenBucketBuilder::new()
+			.refill_rate(10)
+			.capacity(0)
+			.build()
+			.is_err());
+		assert!(TokenBucketBuilder::new()
+			.refill_rate(10)
+			.initial_tokens(11)
+			.build()
+			.is_err());
+
+		let b = TokenBucketBuilder::new().refill_rate(10).build().unwrap();
+		assert_eq!(b.capacity, 10);
+		assert_eq!(b.tokens, 10.);
+	}
+
+	#[test]
+	fn test_token_bucket_refill() {
+		let mut b = TokenBucketBuilder::new()
+			.refill_rate(100)
+			.capacity(200)
+			.initial_tokens(50)
+			.build()
+			.unwrap();
+		let t0 = b.last_refill;
+		let at = |ms| t0 + Duration::from_millis(ms);
+
+		assert!(b.try_take_at(50, t0));
+		assert!(!b.try_take_at(1, t0));
+
+		// 100 tokens per second = 1 token every 10ms
+		assert!(!b.try_take_at(10, at(50)));
+		assert!(b.try_take_at(10, at(110)));
+
+		// The bucket does not fill up above its capacity
+		assert!(!b.try_take_at(201, at(10_000)));
+		assert!(b.try_take_at(200, at(10_000)));
+
+		// Taking more tokens than available puts the bucket in debt
+		assert_eq!(b.take_delay_at(50, at(10_000)), Duration::from_millis(500));
+		let delay = b.take_delay_at(0, at(10_200));
+		assert!(delay > Duration::from_millis(299) && delay <= Duration::from_millis(300));
+		assert!(!b.try_take_at(1, at(10_400)));
+		assert!(b.try_take_at(1, at(10_520)));
+		assert_eq!(b.take_delay_at(0, at(10_520)), Duration::ZERO);
+	}
+
+	#[test]
+	fn test_dual_token_bucket() {
+		// 1000 bytes/s and 10 ops/s: with small operations,
+		// the limit on operations is the one that applies
+		let mut d = DualTokenBucket::new(1000, 10);
+		let t0 = d.bytes.as_ref().unwrap().last_refill;
+		for _ in 0..10 {
+			assert!(d.try_take_at(10, t0));
+		}
+		assert!(!d.try_take_at(10, t0));
+		assert_eq!(d.take_delay_at(10, t0), Duration::from_millis(100));
+
+		// With large operations, the limit on bytes is the one that applies
+		let mut d = DualTokenBucket::new(1000, 10);
+		let t0 = d.bytes.as_ref().unwrap().last_refill;
+		let at = |ms| t0 + Duration::from_millis(ms);
+		assert!(d.try_take_at(1000, t0));
+		assert!(!d.try_take_at(500, at(200)));
+		assert_eq!(d.take_delay_at(2000, at(1000)), Duration::from_secs(1));
+
+		// A zero rate means no limit on that resource
+		let mut d = DualTokenBucket::new(0, 10);
+		assert!(d.try_take_at(u64::MAX, t0));
+		let mut d = DualTokenBucket::new(1000, 0);
+		for _ in 0..100 {
+			assert!(d.try_take_at(1, t0));
+		}
+	}
+}
@@ -18,11 +18,23 @@ use crate::background::WorkerState;
 /// the tranquility factor. For instance with a tranquility of 2,
 /// the tranquilizer will sleep on average 2 units of time for every
 /// 1 unit of time spent doing the background task.
+///
+/// Instead of a tranquility factor, the fraction of time to spend doing the
+/// background task (the load) can be given, in which case the tranquilizer
+/// sleeps (1 - load) / load units of time for every unit of time of work.
+///
+/// A target step duration can also be set, which replaces the tranquility
+/// factor or the load: the tranquilizer then sleeps so that each step (work
+/// and sleep) lasts that duration on average. The faster the steps, the longer
+/// the sleep, and the slower the steps, the shorter the sleep, down to not
+/// sleeping at all when the steps take longer than the target.
 pub struct Tranquilizer {
 	n_observations: usize,
 	observations: VecDeque<Duration>,
 	sum_observations: Duration,
 	last_step_begin: Instant,
+	last_delay: Duration,
+	target_duration: Option<Duration>,
 }
 
 impl Tranquilizer {
@@ -32,12 +44,54 @@ impl Tranquilizer {
 			observations: VecDeque::with_capacity(n_observations + 1),
 			sum_observations: Duration::ZERO,
 			last_step_begin: Instant::now(),
+			last_delay: Duration::ZERO,
+			target_duration: None,
+		}
+	}
+
+	/// Set the duration that each step (work and sleep) should last on average,
+	/// or `None` to sleep according to the tranquility factor or the load
+	pub fn set_target_duration(&mut self, target_duration: Option<Duration>) {
+		self.target_duration = target_duration;
+	}
+
+	/// Returns the number of steps per second currently achieved,
+	/// taking into account the time spent sleeping between steps
+	pub fn current_rate(&self) -> Option<f64> {
+		let avg = self.average_observation()?;
+		let cycle = (avg + self.last_delay).as_secs_f64();
+		if cycle > 0. {
+			Some(1. / cycle)
+		} else {
+			None
+		}
+	}
+
+	fn average_observation(&self) -> Option<Duration> {
+		if !self.observations.is_empty() {
+			Some(self.sum_observations / (self.observations.len() as u32))
+		} else {
+			None
 		}
 	}
 
 	fn tranquilize_internal(&mut self, tranquility: u32) -> Option<Duration> {
 		let observation = Instant::now().saturating_duration_since(self.last_step_begin);
+		self.observe(observation, tranquility)
+	}
+
+	fn observe(&mut self, observation: Duration, tranquility: u32) -> Option<Duration> {
+		let avg = self.record(observation)?;
+		Some(self.delay_after(avg, tranquility * avg))
+	}
 
+	fn observe_load(&mut self, observation: Duration, load: f64) -> Option<Duration> {
+		let avg = self.record(observation)?;
+		let load = load.clamp(0.01, 1.);
+		Some(self.delay_after(avg, avg.mul_f64((1. - load) / load)))
+	}
+
+	fn record(&mut self, observation: Duration) -> Option<Duration> {
 		self.observations.push_back(observation);
 		self.sum_observations += observation;
 
@@ -45,12 +99,16 @@ impl Tranquilizer {
 			self.sum_observations -= self.observations.pop_front().unwrap();
 		}
 
-		if !self.observations.is_empty() {
-			let delay = (tranquility * self.sum_observations) / (self.observations.len() as u32);
-			Some(delay)
-		} else {
-			None
-		}
+		self.average_observation()
+	}
+
+	fn delay_after(&mut self, avg: Duration, delay: Duration) -> Duration {
+		let delay = match self.target_duration {
+			Some(target) => target.saturating_sub(avg),
+			None => delay,
+		};
+		self.last_delay = delay;
+		delay
 	}
 
 	pub async fn tranquilize(&mut self, tranquility: u32) {
@@ -68,11 +126,112 @@ impl Tranquilizer {
 		}
 	}
 
+	/// Like `tranquilize_worker`, but sleeping so that the time spent working is
+	/// the fraction `load` (between 0.01 and 1) of the total time
+	#[must_use]
+	pub fn tranquilize_worker_load(&mut self, load: f64) -> WorkerState {
+		let observation = Instant::now().saturating_duration_since(self.last_step_begin);
+		match self.observe_load(observation, load) {
+			Some(delay) => WorkerState::Throttled(delay.as_secs_f32()),
+			None => WorkerState::Busy,
+		}
+	}
+
 	pub fn reset(&mut self) {
 		self.last_step_begin = Instant::now();
 	}
 
 	pub fn clear(&mut self) {
 		self.observations.clear();
+		self.sum_observations = Duration::ZERO;
+		self.last_delay = Duration::ZERO;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_tranquilizer_tranquility() {
+		let mut t = Tranquilizer::new(2);
+		assert_eq!(t.current_rate(), None);
+
+		let ms = Duration::from_millis;
+		assert_eq!(t.observe(ms(100), 2), Some(ms(200)));
+		assert_eq!(t.observe(ms(300), 2), Some(ms(400)));
+		// Only the last 2 observations are kept
+		assert_eq!(t.observe(ms(300), 2), Some(ms(600)));
+		// Steps last 300ms of work and 600ms of sleep
+		let rate = t.current_rate().unwrap();
+		assert!((rate - 1. / 0.9).abs() < 1e-6);
+	}
+
+	#[test]
+	fn test_tranquilizer_load() {
+		let ms = Duration::from_millis;
+		let mut t = Tranquilizer::new(2);
+
+		// Working a quarter of the time: sleep 3 times the work time
+		assert_eq!(t.observe_load(ms(100), 0.25), Some(ms(300)));
+		// Averaged over the observations
+		assert_eq!(t.observe_load(ms(300), 0.5), Some(ms(200)));
+		// Full load: no sleep
+		assert_eq!(t.observe_load(ms(200), 1.), Some(ms(0)));
+		// Loads are bounded to 1% of the time
+		let delay = t.observe_load(ms(200), 0.).unwrap();
+		assert!((delay.as_secs_f64() - 19.8).abs() < 1e-6);
+	}
+
+	#[test]
+	fn test_tranquilizer_target_duration() {
+		let ms = Duration::from_millis;
+		let mut t = Tranquilizer::new(1);
+		t.set_target_duration(Some(ms(1000)));
+
+		// Fast steps: sleep for the rest of the target duration
+		assert_eq!(t.observe(ms(100), 1), Some(ms(900)));
+		assert!((t.current_rate().unwrap() - 1.).abs() < 1e-6);
+		assert_eq!(t.observe(ms(10), 1), Some(ms(990)));
+
+		// Slower steps: shorter sleeps, whatever the tranquility or the load
+		assert_eq!(t.observe(ms(800), 1), Some(ms(200)));
+		assert_eq!(t.observe_load(ms(900), 0.1), Some(ms(100)));
+
+		// Steps longer than the target: no sleep
+		assert_eq!(t.observe(ms(2000), 0), Some(ms(0)));
+		assert!((t.current_rate().unwrap() - 0.5).abs() < 1e-6);
+
+		// Without a target, the tranquility factor applies again
+		t.set_target_duration(None);
+		assert_eq!(t.observe(ms(100), 2), Some(ms(200)));
+	}
+
+	#[test]
+	fn test_tranquilizer_target_duration_adapts() {
+		let ms = Duration::from_millis;
+		let mut t = Tranquilizer::new(4);
+		t.set_target_duration(Some(ms(500)));
+
+		// Steps get faster: the sleep gets longer at each step
+		let fast = [ms(400), ms(300), ms(200), ms(100)]
+			.iter()
+			.map(|obs| t.observe(*obs, 1).unwrap())
+			.collect::<Vec<_>>();
+		assert!(fast.windows(2).all(|w| w[0] < w[1]), "{:?}", fast);
+
+		// Steps get slower: the sleep gets shorter at each step
+		let slow = [ms(300), ms(500), ms(700), ms(900)]
+			.iter()
+			.map(|obs| t.observe(*obs, 1).unwrap())
+			.collect::<Vec<_>>();
+		assert!(slow.windows(2).all(|w| w[0] > w[1]), "{:?}", slow);
+
+		// Once observations are stable, each step lasts the target duration
+		for _ in 0..4 {
+			t.observe(ms(200), 1);
+		}
+		assert_eq!(t.observe(ms(200), 1), Some(ms(300)));
+		assert!((t.current_rate().unwrap() - 2.).abs() < 1e-6);
 	}
 }
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::sync::Arc;
 
 use arc_swap::ArcSwapOption;
@@ -26,3 +27,162 @@ pub fn init_features(features: &'static [&'static str]) {
 pub fn rust_version() -> &'static str {
 	env!("RUSTC_VERSION")
 }
+
+/// Version of the protocol used between Garage nodes, i.e. of the RPC messages
+/// they exchange and of the serialization format of the data in them. It is
+/// encoded in the 16 lowest bits of the version tag that nodes announce when
+/// they connect to each other: 12 bits for the major version, and 4 bits for
+/// the minor version (e.g. `0x0010` is version 1.0).
+///
+/// Two nodes can communicate if they have the same major version and their
+/// minor versions differ by at most one: a new minor version must stay able to
+/// exchange messages with the previous one, so that a cluster can go through
+/// a rolling upgrade. Changes that break compatibility with the previous minor
+/// version require a new major version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProtocolVersion {
+	pub major: u16,
+	pub minor: u8,
+}
+
+impl ProtocolVersion {
+	/// Prefix of the version tags of Garage nodes ("garage" in ASCII)
+	const TAG_PREFIX: u64 = 0x6761726167650000;
+
+	pub const fn new(major: u16, minor: u8) -> Self {
+		Self { major, minor }
+	}
+
+	/// Returns the version tag that announces this version
+	pub const fn version_tag(&self) -> u64 {
+		Self::TAG_PREFIX | ((self.major as u64 & 0xfff) << 4) | (self.minor as u64 & 0xf)
+	}
+
+	/// Decode the version announced in a version tag,
+	/// returns None if this is not a Garage version tag
+	pub fn from_version_tag(tag: u64) -> Option<Self> {
+		if tag & !0xffff != Self::TAG_PREFIX {
+			return None;
+		}
+		Some(Self {
+			major: ((tag >> 4) & 0xfff) as u16,
+			minor: (tag & 0xf) as u8,
+		})
+	}
+
+	/// Returns true if a node with this version can communicate
+	/// with a node with version `other`
+	pub fn is_compatible_with(&self, other: &Self) -> bool {
+		self.major == other.major && self.minor.abs_diff(other.minor) <= 1
+	}
+}
+
+impl fmt::Display for ProtocolVersion {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}.{}", self.major, self.minor)
+	}
+}
+
+/// Information about the build of Garage that is running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+	/// Version of Garage, as set by `init_version`
+	pub version: &'static str,
+	/// Git commit from which Garage was built, or "unknown"
+	pub git_commit: &'static str,
+	/// Version of the Rust compiler used to build Garage
+	pub rust_version: &'static str,
+	/// Cargo features enabled in this build, as set by `init_features`
+	pub features: Vec<&'static str>,
+}
+
+/// Returns information about the build of Garage that is running
+pub fn build_info() -> BuildInfo {
+	BuildInfo {
+		version: VERSION
+			.load()
+			.as_ref()
+			.map(|v| **v)
+			.unwrap_or(env!("CARGO_PKG_VERSION")),
+		git_commit: option_env!("GARAGE_GIT_COMMIT").unwrap_or("unknown"),
+		rust_version: rust_version(),
+		features: garage_features().map(|f| f.to_vec()).unwrap_or_default(),
+	}
+}
+
+impl fmt::Display for BuildInfo {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"Garage {} (commit {}, rustc {}) [features: {}]",
+			self.version,
+			self.git_commit,
+			self.rust_version,
+			self.features.join(", ")
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_protocol_version_tag() {
+		let v = ProtocolVersion::new(1, 0);
+		assert_eq!(v.version_tag(), 0x6761726167650010);
+		assert_eq!(
+			ProtocolVersion::from_version_tag(0x6761726167650010),
+			Some(v)
+		);
+		assert_eq!(
+			ProtocolVersion::from_version_tag(0x6761726167650123),
+			Some(ProtocolVersion::new(0x12, 3))
+		);
+		assert_eq!(ProtocolVersion::from_version_tag(0x6772676e65740010), None);
+		assert_eq!(v.to_string(), "1.0");
+	}
+
+	#[test]
+	fn test_protocol_version_compatibility() {
+		let v = ProtocolVersion::new;
+		let matrix = [
+			// Same version
+			(v(1, 0), v(1, 0), true),
+			(v(2, 5), v(2, 5), true),
+			// Adjacent minor versions
+			(v(1, 0), v(1, 1), true),
+			(v(1, 1), v(1, 0), true),
+			(v(1, 14), v(1, 15), true),
+			// Minor versions that are too far apart
+			(v(1, 0), v(1, 2), false),
+			(v(1, 3), v(1, 1), false),
+			// Different major versions
+			(v(1, 0), v(2, 0), false),
+			(v(1, 15), v(2, 0), false),
+			(v(0, 9), v(1, 0), false),
+		];
+		for (a, b, compatible) in matrix {
+			assert_eq!(a.is_compatible_with(&b), compatible, "{} vs. {}", a, b);
+			assert_eq!(b.is_compatible_with(&a), compatible, "{} vs. {}", b, a);
+		}
+	}
+
+	#[test]
+	fn test_build_info_display() {
+		let info = BuildInfo {
+			version: "v1.0.1",
+			git_commit: "0123456789ab",
+			rust_version: "1.80.0",
+			features: vec!["k2v", "lmdb"],
+		};
+		assert_eq!(
+			info.to_string(),
+			"Garage v1.0.1 (commit 0123456789ab, rustc 1.80.0) [features: k2v, lmdb]"
+		);
+
+		let info = build_info();
+		assert!(!info.git_commit.is_empty());
+		assert_eq!(info.rust_version, rust_version());
+	}
+}
@@ -1,5 +1,5 @@
 use err_derive::Error;
-use hyper::header::HeaderValue;
+use hyper::header::{HeaderValue, ALLOW};
 use hyper::{HeaderMap, StatusCode};
 
 use garage_api_common::generic_server::ApiError;
@@ -15,9 +15,13 @@ pub enum Error {
 	#[error(display = "Not found")]
 	NotFound,
 
-	/// The client sent a request without host, or with unsupported method
+	/// The client sent a request without host
 	#[error(display = "Bad request: {}", _0)]
 	BadRequest(String),
+
+	/// The client sent a request with a method other than GET, HEAD or OPTIONS
+	#[error(display = "Method not allowed")]
+	MethodNotAllowed,
 }
 
 impl<T> From<T> for Error
@@ -36,14 +40,44 @@ impl Error {
 			Error::NotFound => StatusCode::NOT_FOUND,
 			Error::ApiError(e) => e.http_status_code(),
 			Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+			Error::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
 		}
 	}
 
 	pub fn add_headers(&self, header_map: &mut HeaderMap<HeaderValue>) {
-		#[allow(clippy::single_match)]
 		match self {
 			Error::ApiError(e) => e.add_http_headers(header_map),
+			Error::MethodNotAllowed => {
+				header_map.insert(ALLOW, HeaderValue::from_static("GET, HEAD, OPTIONS"));
+			}
 			_ => (),
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_api_s3::error::Error as S3Error;
+
+	#[test]
+	fn test_http_status_code() {
+		assert_eq!(Error::NotFound.http_status_code(), StatusCode::NOT_FOUND);
+		assert_eq!(
+			Error::BadRequest("no host".into()).http_status_code(),
+			StatusCode::BAD_REQUEST
+		);
+		assert_eq!(
+			Error::MethodNotAllowed.http_status_code(),
+			StatusCode::METHOD_NOT_ALLOWED
+		);
+		assert_eq!(
+			Error::ApiError(S3Error::NoSuchKey).http_status_code(),
+			StatusCode::NOT_FOUND
+		);
+		assert_eq!(
+			Error::ApiError(S3Error::NotImplemented("website".into())).http_status_code(),
+			StatusCode::NOT_IMPLEMENTED
+		);
+	}
+}
@@ -1,14 +1,21 @@
 use std::fs::{self, Permissions};
 use std::os::unix::prelude::PermissionsExt;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant};
 
 use tokio::net::{TcpListener, UnixListener};
 use tokio::sync::watch;
+use tracing::Instrument;
 
 use hyper::{
-	body::Incoming as IncomingBody,
-	header::{HeaderValue, HOST, LOCATION},
-	Method, Request, Response, StatusCode,
+	body::{Body, Bytes, Frame, Incoming as IncomingBody, SizeHint},
+	header::{
+		HeaderValue, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, HOST,
+		LOCATION, RANGE, VARY,
+	},
+	HeaderMap, Method, Request, Response, StatusCode, Uri, Version,
 };
 
 use opentelemetry::{
@@ -20,23 +27,23 @@ use opentelemetry::{
 
 use crate::error::*;
 
+use garage_api_common::common_error::CommonErrorDerivative;
 use garage_api_common::cors::{
 	add_cors_headers, find_matching_cors_rule, handle_options_for_bucket,
 };
 use garage_api_common::generic_server::{server_loop, UnixListenerOn};
 use garage_api_common::helpers::*;
-use garage_api_s3::error::{
-	CommonErrorDerivative, Error as ApiError, OkOrBadRequest, OkOrInternalError,
-};
+use garage_api_s3::error::{Error as ApiError, OkOrBadRequest, OkOrInternalError};
 use garage_api_s3::get::{handle_get_without_ctx, handle_head_without_ctx};
 use garage_api_s3::website::X_AMZ_WEBSITE_REDIRECT_LOCATION;
 
 use garage_model::garage::Garage;
+use garage_net::request_id::RequestId;
 
 use garage_table::*;
 use garage_util::config::WebConfig;
 use garage_util::data::Uuid;
-use garage_util::error::Error as GarageError;
+use garage_util::error::{Error as GarageError, OkOrMessage};
 use garage_util::forwarded_headers;
 use garage_util::metrics::{gen_trace_id, RecordDuration};
 use garage_util::socket_address::UnixOrTCPSocketAddress;
@@ -71,19 +78,33 @@ pub struct WebServer {
 	garage: Arc<Garage>,
 	metrics: Arc<WebMetrics>,
 	root_domain: String,
+	path_style: bool,
+	precompressed: bool,
 	add_host_to_metrics: bool,
+	cache_control: Option<HeaderValue>,
+	shutdown_grace_period: Duration,
 }
 
 impl WebServer {
 	/// Run a web server
-	pub fn new(garage: Arc<Garage>, config: &WebConfig) -> Arc<Self> {
+	pub fn new(garage: Arc<Garage>, config: &WebConfig) -> Result<Arc<Self>, GarageError> {
 		let metrics = Arc::new(WebMetrics::new());
-		Arc::new(WebServer {
+		let cache_control = config
+			.cache_control
+			.as_deref()
+			.map(HeaderValue::from_str)
+			.transpose()
+			.ok_or_message("Invalid value for s3_web.cache_control")?;
+		Ok(Arc::new(WebServer {
 			garage,
 			metrics,
 			root_domain: config.root_domain.clone(),
+			path_style: config.path_style,
+			precompressed: config.precompressed,
 			add_host_to_metrics: config.add_host_to_metrics,
-		})
+			cache_control,
+			shutdown_grace_period: Duration::from_secs(config.shutdown_grace_period_secs),
+		}))
 	}
 
 	pub async fn run(
@@ -93,6 +114,7 @@ impl WebServer {
 	) -> Result<(), GarageError> {
 		let server_name = "Web".into();
 		info!("Web server listening on {}", bind_addr);
+		let grace_period = self.shutdown_grace_period;
 
 		match bind_addr {
 			UnixOrTCPSocketAddress::TCPSocket(addr) => {
@@ -100,7 +122,7 @@ impl WebServer {
 
 				let handler =
 					move |stream, socketaddr| self.clone().handle_request(stream, socketaddr);
-				server_loop(server_name, listener, handler, must_exit).await
+				server_loop(server_name, listener, handler, must_exit, grace_period).await
 			}
 			UnixOrTCPSocketAddress::UnixSocket(ref path) => {
 				if path.exists() {
@@ -114,7 +136,7 @@ impl WebServer {
 
 				let handler =
 					move |stream, socketaddr| self.clone().handle_request(stream, socketaddr);
-				server_loop(server_name, listener, handler, must_exit).await
+				server_loop(server_name, listener, handler, must_exit, grace_period).await
 			}
 		}
 	}
@@ -131,22 +153,64 @@ impl WebServer {
 			.unwrap_or("<unknown>")
 			.to_string();
 
-		if let Ok(forwarded_for_ip_addr) =
-			forwarded_headers::handle_forwarded_for_headers(req.headers())
-		{
-			// uri() below has a preceding '/', so no space with host
-			info!(
-				"{} (via {}) {} {}{}",
-				forwarded_for_ip_addr,
+		// All log lines of the request are in this span, within the span of the
+		// request ID, and the key is recorded by serve_file once it is known
+		let span = info_span!(
+			"web",
+			%addr,
+			method = %req.method(),
+			host = %host_header,
+			key = tracing::field::Empty,
+		);
+		let handler = async move {
+			let start = Instant::now();
+			let forwarded_for = forwarded_headers::handle_forwarded_for_headers(req.headers()).ok();
+			let method = req.method().clone();
+			let uri = req.uri().clone();
+			let version = req.version();
+
+			let res = self.handle_request_inner(req, host_header.clone()).await;
+
+			let mut entry = AccessLogEntry {
 				addr,
-				req.method(),
-				host_header,
-				req.uri()
-			);
-		} else {
-			info!("{} {} {}{}", addr, req.method(), host_header, req.uri());
-		}
+				forwarded_for,
+				host: host_header,
+				method,
+				uri,
+				version,
+				status: StatusCode::INTERNAL_SERVER_ERROR,
+				bytes: None,
+				duration: Duration::ZERO,
+			};
+			match res {
+				Ok(res) => {
+					// The access log line is written once the body has been sent
+					entry.status = res.status();
+					entry.bytes = Some(0);
+					Ok(res.map(|body| {
+						BoxBody::new(AccessLogBody {
+							inner: body,
+							entry,
+							start,
+							span: tracing::Span::current(),
+						})
+					}))
+				}
+				Err(e) => {
+					entry.duration = start.elapsed();
+					entry.log();
+					Err(e)
+				}
+			}
+		};
+		with_request_id(handler.instrument(span)).await
+	}
 
+	async fn handle_request_inner(
+		self: Arc<Self>,
+		req: Request<IncomingBody>,
+		host_header: String,
+	) -> Result<Response<BoxBody<Error>>, http::Error> {
 		// Lots of instrumentation
 		let tracer = opentelemetry::global::tracer("garage");
 		let span = tracer
@@ -208,7 +272,20 @@ impl WebServer {
 		}
 	}
 
-	async fn check_key_exists(self: &Arc<Self>, bucket_id: Uuid, key: &str) -> Result<bool, Error> {
+	async fn get_bucket_id(&self, bucket_name: &BucketName) -> Result<Option<Uuid>, Error> {
+		Ok(self
+			.garage
+			.bucket_alias_table
+			.get(&EmptyKey, &bucket_name.to_string())
+			.await?
+			.and_then(|x| x.state.take()))
+	}
+
+	async fn check_key_exists(
+		self: &Arc<Self>,
+		bucket_id: Uuid,
+		key: &ObjectKey,
+	) -> Result<bool, Error> {
 		let exists = self
 			.garage
 			.object_table
@@ -219,10 +296,41 @@ impl WebServer {
 		Ok(exists)
 	}
 
+	async fn get_or_head(
+		self: &Arc<Self>,
+		req: &Request<()>,
+		bucket_id: Uuid,
+		key: &ObjectKey,
+	) -> Result<Response<BoxBody<ApiError>>, ApiError> {
+		match *req.method() {
+			Method::HEAD => {
+				handle_head_without_ctx(self.garage.clone(), req, bucket_id, key.as_str(), None)
+					.await
+			}
+			Method::GET => {
+				handle_get_without_ctx(
+					self.garage.clone(),
+					req,
+					bucket_id,
+					key.as_str(),
+					None,
+					Default::default(),
+				)
+				.await
+			}
+			ref method => Err(ApiError::bad_request(format!(
+				"Unsupported method for an object: {}",
+				method
+			))),
+		}
+	}
+
 	async fn serve_file(
 		self: &Arc<Self>,
 		req: &Request<()>,
 	) -> Result<Response<BoxBody<ApiError>>, Error> {
+		check_method(req.method())?;
+
 		// Get http authority string (eg. [::1]:3902 or garage.tld:80)
 		let authority = req
 			.headers()
@@ -233,14 +341,36 @@ impl WebServer {
 		// Get bucket
 		let host = authority_to_host(authority)?;
 
-		let bucket_name = host_to_bucket(&host, &self.root_domain).unwrap_or(&host);
-		let bucket_id = self
-			.garage
-			.bucket_alias_table
-			.get(&EmptyKey, &bucket_name.to_string())
-			.await?
-			.and_then(|x| x.state.take())
-			.ok_or(Error::NotFound)?;
+		let full_path = req.uri().path();
+
+		// Bucket designated by the host of the request. Hosts outside of the root
+		// domain are looked up as bucket names, for buckets whose name is a domain
+		// name. This includes the root domain itself, which is only used for
+		// path-style requests if no bucket is named after it.
+		let host_bucket = match self.path_style && is_ip_literal(&host) {
+			true => None,
+			false => match host_to_bucket(&host, &self.root_domain)
+				.unwrap_or_else(|| BucketName::new(&host))
+			{
+				Ok(name) => self.get_bucket_id(&name).await?.map(|id| (name, id)),
+				Err(_) => None,
+			},
+		};
+		let is_path_style = host_bucket.is_none()
+			&& self.path_style
+			&& (is_ip_literal(&host) || is_root_domain(&host, &self.root_domain));
+
+		let (bucket_name, bucket_id, path) = match host_bucket {
+			Some((name, id)) => (name, id, Some(full_path)),
+			None if is_path_style => {
+				// Path-style requests, to the root domain or to an IP address,
+				// with the bucket as the first segment of the path
+				let (name, path) = split_path_style(full_path)?;
+				let id = self.get_bucket_id(&name).await?.ok_or(Error::NotFound)?;
+				(name, id, path)
+			}
+			None => return Err(bucket_not_found(&host, &self.root_domain)),
+		};
 
 		// Check bucket isn't deleted and has website access enabled
 		let bucket = self
@@ -257,41 +387,71 @@ impl WebServer {
 			.as_ref()
 			.ok_or(Error::NotFound)?;
 
+		// The root of a bucket in a path-style request is `/<bucket>/`, so that
+		// relative links in its index document stay in the bucket
+		let path = match path {
+			Some(path) => path,
+			None if *req.method() == Method::OPTIONS => "/",
+			None => {
+				let query = req.uri().query().map(|q| format!("?{}", q));
+				return Ok(Response::builder()
+					.status(StatusCode::FOUND)
+					.header(
+						LOCATION,
+						format!("/{}/{}", bucket_name, query.unwrap_or_default()),
+					)
+					.body(empty_body())
+					.unwrap());
+			}
+		};
+
 		// Get path
-		let path = req.uri().path().to_string();
 		let index = &website_config.index_document;
-		let (key, may_redirect) = path_to_keys(&path, index)?;
+		let (key, may_redirect) = match path_to_keys(path, index)? {
+			// Redirect URLs of path-style requests keep the bucket in the path
+			(key, ImplicitRedirect::To { key: rkey, url }) if is_path_style => (
+				key,
+				ImplicitRedirect::To {
+					key: rkey,
+					url: format!("/{}{}", bucket_name, url),
+				},
+			),
+			x => x,
+		};
+		tracing::Span::current().record("key", key.as_str());
 
 		debug!(
 			"Selected bucket: \"{}\" {:?}, target key: \"{}\", may redirect to: {:?}",
 			bucket_name, bucket_id, key, may_redirect
 		);
 
-		let ret_doc = match *req.method() {
-			Method::OPTIONS => handle_options_for_bucket(req, &bucket_params)
+		// The gzip-compressed variant of the object, stored as `<key>.gz`,
+		// is served instead of it to clients that accept gzip
+		let gzip_content_type = match self.precompressed && *req.method() != Method::OPTIONS {
+			true => gzip_variant_content_type(key.as_str(), req.headers()),
+			false => None,
+		};
+		let gzip_variant = gzip_content_type
+			.filter(|_| accepts_gzip(req.headers()))
+			.and_then(|ct| Some((ObjectKey::new(format!("{}.gz", key.as_str())).ok()?, ct)));
+
+		let ret_doc = match (req.method(), &gzip_variant) {
+			(&Method::OPTIONS, _) => handle_options_for_bucket(req, &bucket_params)
 				.map_err(ApiError::from)
 				.map(|res| res.map(|_empty_body: EmptyBody| empty_body())),
-			Method::HEAD => {
-				handle_head_without_ctx(self.garage.clone(), req, bucket_id, &key, None).await
-			}
-			Method::GET => {
-				handle_get_without_ctx(
-					self.garage.clone(),
-					req,
-					bucket_id,
-					&key,
-					None,
-					Default::default(),
-				)
-				.await
+			(_, Some((gzip_key, content_type))) => {
+				match self.get_or_head(req, bucket_id, gzip_key).await {
+					Err(ApiError::NoSuchKey) => self.get_or_head(req, bucket_id, &key).await,
+					res => res.map(|resp| gzip_encoded(resp, content_type)),
+				}
 			}
-			_ => Err(ApiError::bad_request("HTTP method not supported")),
+			_ => self.get_or_head(req, bucket_id, &key).await,
 		};
 
 		// Try implicit redirect on error
 		let ret_doc_with_redir = match (&ret_doc, may_redirect) {
 			(Err(ApiError::NoSuchKey), ImplicitRedirect::To { key, url })
-				if self.check_key_exists(bucket_id, key.as_str()).await? =>
+				if self.check_key_exists(bucket_id, &key).await? =>
 			{
 				Ok(Response::builder()
 					.status(StatusCode::FOUND)
@@ -378,6 +538,37 @@ impl WebServer {
 				}
 			}
 			Ok(mut resp) => {
+				// Objects uploaded without a content type are served with
+				// a content type guessed from the extension of their key
+				let has_content_type = resp
+					.headers()
+					.get(CONTENT_TYPE)
+					.map(|v| !is_default_content_type(v))
+					.unwrap_or(false);
+				if resp.status().is_success() && !has_content_type {
+					if let Some(ct) = content_type_from_key(key.as_str()) {
+						resp.headers_mut()
+							.insert(CONTENT_TYPE, HeaderValue::from_static(ct));
+					}
+				}
+
+				// Objects uploaded without a Cache-Control header are served
+				// with the one set in the configuration, if any
+				if let Some(cc) = &self.cache_control {
+					if resp.status().is_success() || resp.status() == StatusCode::NOT_MODIFIED {
+						resp.headers_mut()
+							.entry(CACHE_CONTROL)
+							.or_insert_with(|| cc.clone());
+					}
+				}
+
+				// The response depends on the Accept-Encoding header if the
+				// object may have a gzip-compressed variant
+				if gzip_content_type.is_some() {
+					resp.headers_mut()
+						.append(VARY, HeaderValue::from_static("Accept-Encoding"));
+				}
+
 				// Maybe add CORS headers
 				if let Some(rule) = find_matching_cors_rule(&bucket_params, req)? {
 					add_cors_headers(&mut resp, rule)
@@ -389,6 +580,106 @@ impl WebServer {
 	}
 }
 
+/// The fields of a line of the access log, which is written when a response
+/// is sent. `bytes` is the number of bytes of the body that were actually sent,
+/// or `None` when no response could be built. `addr` is the address of the
+/// peer of the connection, and `forwarded_for` the client address given by
+/// a reverse proxy in the `X-Forwarded-For` header, if any.
+struct AccessLogEntry {
+	addr: String,
+	forwarded_for: Option<String>,
+	host: String,
+	method: Method,
+	uri: Uri,
+	version: Version,
+	status: StatusCode,
+	bytes: Option<u64>,
+	duration: Duration,
+}
+
+impl AccessLogEntry {
+	/// Write the access log line, with each value in a separate field
+	/// so that it can be processed by structured log collectors
+	fn log(&self) {
+		info!(
+			target: "garage_web::access",
+			addr = %self.addr,
+			forwarded_for = self.forwarded_for.as_ref().map(tracing::field::display),
+			host = %self.host,
+			method = %self.method,
+			uri = %self.uri,
+			version = ?self.version,
+			status = self.status.as_u16(),
+			bytes = self.bytes,
+			duration_ms = self.duration.as_millis() as u64,
+			"{} {} {}",
+			self.method,
+			self.uri,
+			self.status.as_u16(),
+		);
+	}
+}
+
+/// Body of a response, that counts the bytes of the body that are sent and
+/// writes the access log line once the body has been sent entirely, or
+/// when the connection is closed before that
+struct AccessLogBody<B> {
+	inner: B,
+	entry: AccessLogEntry,
+	start: Instant,
+	span: tracing::Span,
+}
+
+impl<B: Body<Data = Bytes> + Unpin> Body for AccessLogBody<B> {
+	type Data = Bytes;
+	type Error = B::Error;
+
+	fn poll_frame(
+		mut self: Pin<&mut Self>,
+		cx: &mut TaskContext<'_>,
+	) -> Poll<Option<Result<Frame<Bytes>, B::Error>>> {
+		let res = Pin::new(&mut self.inner).poll_frame(cx);
+		if let Poll::Ready(Some(Ok(frame))) = &res {
+			if let Some(data) = frame.data_ref() {
+				let len = data.len() as u64;
+				*self.entry.bytes.get_or_insert(0) += len;
+			}
+		}
+		res
+	}
+
+	fn is_end_stream(&self) -> bool {
+		self.inner.is_end_stream()
+	}
+
+	fn size_hint(&self) -> SizeHint {
+		self.inner.size_hint()
+	}
+}
+
+impl<B> Drop for AccessLogBody<B> {
+	fn drop(&mut self) {
+		let _enter = self.span.enter();
+		self.entry.duration = self.start.elapsed();
+		self.entry.log();
+	}
+}
+
+/// Handle a request in the scope of a new request ID, which is
+/// returned to the client in the `x-amz-request-id` header
+async fn with_request_id<F, B>(handler: F) -> Result<Response<B>, http::Error>
+where
+	F: std::future::Future<Output = Result<Response<B>, http::Error>>,
+{
+	let request_id = RequestId::new();
+	let mut res = request_id.scope(handler).await?;
+	res.headers_mut().insert(
+		"x-amz-request-id",
+		HeaderValue::from_str(&request_id.to_string()).expect("request id is valid ascii"),
+	);
+	Ok(res)
+}
+
 fn error_to_res(e: Error) -> Response<BoxBody<Error>> {
 	// If we are here, it is either that:
 	// - there was an error before trying to get the requested URL
@@ -404,10 +695,208 @@ fn error_to_res(e: Error) -> Response<BoxBody<Error>> {
 	http_error
 }
 
+/// Error to return when no bucket matches the host of a request. Requests
+/// to the root domain itself (unless a bucket is named after it) get an explanation,
+/// as they are usually a misconfiguration or a path-style request.
+fn bucket_not_found(host: &str, root_domain: &str) -> Error {
+	if is_root_domain(host, root_domain) {
+		let root = root_domain.trim_start_matches('.');
+		let root = root.strip_suffix('.').unwrap_or(root);
+		let host = host.strip_suffix('.').unwrap_or(host);
+		Error::BadRequest(format!(
+			"No bucket specified: {} is the root domain of the web endpoint, \
+			websites are served on <bucket>.{}",
+			host, root
+		))
+	} else {
+		Error::NotFound
+	}
+}
+
+/// Whether `host` is the root domain of the web endpoint itself
+fn is_root_domain(host: &str, root_domain: &str) -> bool {
+	let root = root_domain.trim_start_matches('.');
+	let root = root.strip_suffix('.').unwrap_or(root);
+	let host = host.strip_suffix('.').unwrap_or(host);
+	!root.is_empty() && host.eq_ignore_ascii_case(root)
+}
+
+/// Whether `host`, as returned by `authority_to_host`, is an IP address
+fn is_ip_literal(host: &str) -> bool {
+	host.starts_with('[') || host.parse::<std::net::IpAddr>().is_ok()
+}
+
+/// Split the path of a path-style request, `/<bucket>/<key>`, into the bucket name
+/// and the path of the key in the bucket. The path of the key keeps its leading slash,
+/// and is `None` when the path is only `/<bucket>`, without a trailing slash.
+fn split_path_style(path: &str) -> Result<(BucketName, Option<&str>), Error> {
+	let rest = path
+		.strip_prefix('/')
+		.ok_or_else(|| Error::BadRequest("Path must start with a / (slash)".into()))?;
+	let (bucket, key_path) = match rest.find('/') {
+		Some(i) => (&rest[..i], Some(&rest[i..])),
+		None => (rest, None),
+	};
+	if bucket.is_empty() {
+		return Err(Error::BadRequest(
+			"No bucket specified: the bucket must be the first segment of the path".into(),
+		));
+	}
+	let bucket = BucketName::new(bucket).map_err(|_| Error::NotFound)?;
+	Ok((bucket, key_path))
+}
+
+/// Check that the method of a request is one that the web server handles
+fn check_method(method: &Method) -> Result<(), Error> {
+	match *method {
+		Method::GET | Method::HEAD | Method::OPTIONS => Ok(()),
+		_ => Err(Error::MethodNotAllowed),
+	}
+}
+
+/// Returns true for the content types that S3 clients set when they don't know
+/// the actual content type of an object
+fn is_default_content_type(v: &HeaderValue) -> bool {
+	matches!(
+		v.to_str(),
+		Ok("application/octet-stream") | Ok("binary/octet-stream")
+	)
+}
+
+/// Guess the content type of an object from the extension of its key
+fn content_type_from_key(key: &str) -> Option<&'static str> {
+	let file_name = key.rsplit('/').next().unwrap_or(key);
+	let (_, ext) = file_name.rsplit_once('.')?;
+	let ct = match ext.to_ascii_lowercase().as_str() {
+		"html" | "htm" => "text/html; charset=utf-8",
+		"css" => "text/css; charset=utf-8",
+		"js" | "mjs" => "text/javascript; charset=utf-8",
+		"json" => "application/json",
+		"map" => "application/json",
+		"xml" => "application/xml",
+		"txt" => "text/plain; charset=utf-8",
+		"md" => "text/markdown; charset=utf-8",
+		"csv" => "text/csv; charset=utf-8",
+		"wasm" => "application/wasm",
+		"pdf" => "application/pdf",
+		"png" => "image/png",
+		"jpg" | "jpeg" => "image/jpeg",
+		"gif" => "image/gif",
+		"webp" => "image/webp",
+		"avif" => "image/avif",
+		"svg" => "image/svg+xml",
+		"ico" => "image/vnd.microsoft.icon",
+		"woff" => "font/woff",
+		"woff2" => "font/woff2",
+		"ttf" => "font/ttf",
+		"otf" => "font/otf",
+		"mp4" => "video/mp4",
+		"webm" => "video/webm",
+		"mp3" => "audio/mpeg",
+		"ogg" => "audio/ogg",
+		_ => return None,
+	};
+	Some(ct)
+}
+
+/// Whether a response of this content type is worth compressing: text formats
+/// are, while most images, videos and archives are already compressed
+fn is_compressible_content_type(content_type: &str) -> bool {
+	let mime = content_type
+		.split(';')
+		.next()
+		.unwrap_or("")
+		.trim()
+		.to_ascii_lowercase();
+	mime.starts_with("text/")
+		|| mime.ends_with("+json")
+		|| mime.ends_with("+xml")
+		|| matches!(
+			mime.as_str(),
+			"application/json"
+				| "application/javascript"
+				| "application/xml"
+				| "application/wasm"
+				| "font/ttf" | "font/otf"
+		)
+}
+
+/// If the object at this key may have a gzip-compressed variant that can be
+/// served instead of it, return the content type of the object. Range requests
+/// are always served from the object itself, as ranges of the compressed
+/// variant would not match the ranges of the object.
+fn gzip_variant_content_type(key: &str, headers: &HeaderMap) -> Option<&'static str> {
+	if headers.contains_key(RANGE) {
+		return None;
+	}
+	content_type_from_key(key).filter(|ct| is_compressible_content_type(ct))
+}
+
+/// Whether the client accepts a gzip-encoded response, and prefers it to the
+/// uncompressed response, according to the quality values of its Accept-Encoding
+/// header. Without a quality value, gzip is preferred to the identity encoding.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+	let mut gzip = None;
+	let mut identity = None;
+	let mut any = None;
+	for value in headers.get_all(ACCEPT_ENCODING) {
+		let value = match value.to_str() {
+			Ok(v) => v,
+			Err(_) => continue,
+		};
+		for item in value.split(',') {
+			let mut params = item.split(';');
+			let coding = params.next().unwrap_or("").trim().to_ascii_lowercase();
+			let q = params
+				.find_map(|p| {
+					let p = p.trim();
+					p.strip_prefix("q=").or_else(|| p.strip_prefix("Q="))
+				})
+				.map(|q| q.trim().parse::<f32>().unwrap_or(0.))
+				.unwrap_or(1.);
+			match coding.as_str() {
+				"gzip" | "x-gzip" => gzip = Some(q),
+				"identity" => identity = Some(q),
+				"*" => any = Some(q),
+				_ => (),
+			}
+		}
+	}
+	let gzip = gzip.or(any).unwrap_or(0.);
+	let identity = identity.or(any).unwrap_or(1.);
+	gzip > 0. && gzip >= identity
+}
+
+/// Mark a response for the gzip-compressed variant of an object as the
+/// gzip-encoded object, with the content type of the object itself
+fn gzip_encoded(
+	mut resp: Response<BoxBody<ApiError>>,
+	content_type: &'static str,
+) -> Response<BoxBody<ApiError>> {
+	if resp.status().is_success() || resp.status() == StatusCode::NOT_MODIFIED {
+		let headers = resp.headers_mut();
+		headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+		let has_content_type = headers
+			.get(CONTENT_TYPE)
+			.map(|v| {
+				!is_default_content_type(v)
+					&& !matches!(
+						v.to_str(),
+						Ok("application/gzip") | Ok("application/x-gzip")
+					)
+			})
+			.unwrap_or(false);
+		if !has_content_type {
+			headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+		}
+	}
+	resp
+}
+
 #[derive(Debug, PartialEq)]
 enum ImplicitRedirect {
 	No,
-	To { key: String, url: String },
+	To { key: ObjectKey, url: String },
 }
 
 /// Path to key
@@ -417,71 +906,402 @@ enum ImplicitRedirect {
 /// which is also AWS S3 behavior.
 ///
 /// Check: https://docs.aws.amazon.com/AmazonS3/latest/userguide/IndexDocumentSupport.html
-fn path_to_keys<'a>(path: &'a str, index: &str) -> Result<(String, ImplicitRedirect), Error> {
-	let path_utf8 = percent_encoding::percent_decode_str(path).decode_utf8()?;
-
-	let base_key = match path_utf8.strip_prefix("/") {
+fn path_to_keys(path: &str, index: &str) -> Result<(ObjectKey, ImplicitRedirect), Error> {
+	let base_key = match path.strip_prefix('/') {
 		Some(bk) => bk,
 		None => return Err(Error::BadRequest("Path must start with a / (slash)".into())),
 	};
-	let is_bucket_root = base_key.len() == 0;
-	let is_trailing_slash = path_utf8.ends_with("/");
 
-	match (is_bucket_root, is_trailing_slash) {
-		// It is not possible to store something at the root of the bucket (ie. empty key),
-		// the only option is to fetch the index
-		(true, _) => Ok((index.to_string(), ImplicitRedirect::No)),
+	// It is not possible to store something at the root of the bucket (ie. empty key),
+	// the only option is to fetch the index
+	if base_key.is_empty() {
+		return Ok((ObjectKey::new(index)?, ImplicitRedirect::No));
+	}
 
+	let base_key = ObjectKey::from_percent_encoded(base_key)?;
+	if base_key.as_str().ends_with('/') {
 		// "If you create a folder structure in your bucket, you must have an index document at each level. In each folder, the index document must have the same name, for example, index.html. When a user specifies a URL that resembles a folder lookup, the presence or absence of a trailing slash determines the behavior of the website. For example, the following URL, with a trailing slash, returns the photos/index.html index document."
-		(false, true) => Ok((format!("{base_key}{index}"), ImplicitRedirect::No)),
-
+		Ok((
+			ObjectKey::new(format!("{base_key}{index}"))?,
+			ImplicitRedirect::No,
+		))
+	} else {
 		// "However, if you exclude the trailing slash from the preceding URL, Amazon S3 first looks for an object photos in the bucket. If the photos object is not found, it searches for an index document, photos/index.html. If that document is found, Amazon S3 returns a 302 Found message and points to the photos/ key. For subsequent requests to photos/, Amazon S3 returns photos/index.html. If the index document is not found, Amazon S3 returns an error."
-		(false, false) => Ok((
-			base_key.to_string(),
-			ImplicitRedirect::To {
-				key: format!("{base_key}/{index}"),
+		// No redirect if the key of the index document would be too long
+		let redirect = match ObjectKey::new(format!("{base_key}/{index}")) {
+			Ok(key) => ImplicitRedirect::To {
+				key,
 				url: format!("{path}/"),
 			},
-		)),
+			Err(_) => ImplicitRedirect::No,
+		};
+		Ok((base_key, redirect))
 	}
 }
 
 #[cfg(test)]
 mod tests {
+	use std::collections::BTreeMap;
+
 	use super::*;
 
+	/// Collects the fields of the access log lines
+	#[derive(Clone, Default)]
+	struct AccessLogRecorder(Arc<std::sync::Mutex<Vec<BTreeMap<String, String>>>>);
+
+	impl tracing::Subscriber for AccessLogRecorder {
+		fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+			true
+		}
+		fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+			tracing::span::Id::from_u64(1)
+		}
+		fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+		fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+		fn event(&self, event: &tracing::Event<'_>) {
+			if event.metadata().target() == "garage_web::access" {
+				let mut fields = BTreeMap::new();
+				event.record(
+					&mut |field: &tracing::field::Field, value: &dyn std::fmt::Debug| {
+						fields.insert(field.name().to_string(), format!("{:?}", value));
+					},
+				);
+				self.0.lock().unwrap().push(fields);
+			}
+		}
+		fn enter(&self, _: &tracing::span::Id) {}
+		fn exit(&self, _: &tracing::span::Id) {}
+	}
+
+	#[test]
+	fn test_access_log_fields() {
+		let uri = "/dir/file%20name.html?x=1".parse::<Uri>().unwrap();
+		let mut entry = AccessLogEntry {
+			addr: "198.51.100.7:4321".into(),
+			forwarded_for: Some("192.0.2.1".into()),
+			host: "bucket.garage.tld".into(),
+			method: Method::GET,
+			uri,
+			version: Version::HTTP_11,
+			status: StatusCode::OK,
+			bytes: Some(1234),
+			duration: Duration::from_micros(12_345),
+		};
+		let recorder = AccessLogRecorder::default();
+		tracing::subscriber::with_default(recorder.clone(), || {
+			entry.log();
+			entry.method = Method::HEAD;
+			entry.status = StatusCode::NOT_FOUND;
+			entry.bytes = None;
+			entry.forwarded_for = None;
+			entry.log();
+		});
+
+		let lines = recorder.0.lock().unwrap();
+		let expected = [
+			("addr", "198.51.100.7:4321"),
+			("forwarded_for", "192.0.2.1"),
+			("host", "bucket.garage.tld"),
+			("method", "GET"),
+			("uri", "/dir/file%20name.html?x=1"),
+			("version", "HTTP/1.1"),
+			("status", "200"),
+			("bytes", "1234"),
+			("duration_ms", "12"),
+			("message", "GET /dir/file%20name.html?x=1 200"),
+		];
+		assert_eq!(
+			lines[0],
+			expected
+				.iter()
+				.map(|(k, v)| (k.to_string(), v.to_string()))
+				.collect::<BTreeMap<_, _>>()
+		);
+
+		// No bytes field when no response could be built
+		assert_eq!(lines[1]["method"], "HEAD");
+		assert_eq!(lines[1]["status"], "404");
+		assert!(!lines[1].contains_key("bytes"));
+		assert!(!lines[1].contains_key("forwarded_for"));
+	}
+
+	#[tokio::test]
+	async fn test_access_log_bytes_sent() {
+		use http_body_util::{BodyExt, Full};
+
+		let log_body = |data: &'static str| AccessLogBody {
+			inner: Full::new(Bytes::from(data)),
+			entry: AccessLogEntry {
+				addr: "192.0.2.1:4321".into(),
+				forwarded_for: None,
+				host: "bucket.garage.tld".into(),
+				method: Method::GET,
+				uri: Uri::from_static("/"),
+				version: Version::HTTP_11,
+				status: StatusCode::OK,
+				bytes: Some(0),
+				duration: Duration::ZERO,
+			},
+			start: Instant::now(),
+			span: tracing::Span::none(),
+		};
+
+		// Only the bytes that are actually sent are counted,
+		// whatever the Content-Length of the response
+		let mut body = log_body("hello garage");
+		assert_eq!(body.entry.bytes, Some(0));
+		while body.frame().await.is_some() {}
+		assert_eq!(body.entry.bytes, Some(12));
+
+		let mut body = log_body("");
+		while body.frame().await.is_some() {}
+		assert_eq!(body.entry.bytes, Some(0));
+	}
+
+	#[tokio::test]
+	async fn test_request_id_header() {
+		let res = with_request_id(async {
+			let request_id = RequestId::current().expect("handler runs with a request id");
+			let mut res = error_to_res(Error::NotFound);
+			res.headers_mut().insert(
+				"x-handler-request-id",
+				HeaderValue::from_str(&request_id.to_string()).unwrap(),
+			);
+			Ok(res)
+		})
+		.await
+		.unwrap();
+
+		assert_eq!(res.status(), StatusCode::NOT_FOUND);
+		let header = res.headers().get("x-amz-request-id").unwrap();
+		assert_eq!(header.len(), 32);
+		assert_eq!(header, res.headers().get("x-handler-request-id").unwrap());
+	}
+
+	#[test]
+	fn test_bucket_not_found_at_root_domain() {
+		for (host, root) in [
+			("garage.tld", ".garage.tld"),
+			("garage.tld", "garage.tld"),
+			("garage.tld.", ".garage.tld"),
+			("Garage.TLD", ".garage.tld"),
+		] {
+			let err = bucket_not_found(host, root);
+			assert_eq!(err.http_status_code(), StatusCode::BAD_REQUEST);
+			assert!(err.to_string().contains("No bucket specified"));
+			assert!(err.to_string().contains("<bucket>.garage.tld"));
+		}
+
+		for (host, root) in [
+			("example.com", ".garage.tld"),
+			("unknown.garage.tld", ".garage.tld"),
+			("not-garage.tld", ".garage.tld"),
+		] {
+			assert!(matches!(bucket_not_found(host, root), Error::NotFound));
+		}
+	}
+
 	#[test]
 	fn path_to_keys_test() -> Result<(), Error> {
+		let key = |k: &str| ObjectKey::new(k).unwrap();
 		assert_eq!(
 			path_to_keys("/file%20.jpg", "index.html")?,
 			(
-				"file .jpg".to_string(),
+				key("file .jpg"),
 				ImplicitRedirect::To {
-					key: "file .jpg/index.html".to_string(),
+					key: key("file .jpg/index.html"),
 					url: "/file%20.jpg/".to_string()
 				}
 			)
 		);
 		assert_eq!(
 			path_to_keys("/%20t/", "index.html")?,
-			(" t/index.html".to_string(), ImplicitRedirect::No)
+			(key(" t/index.html"), ImplicitRedirect::No)
 		);
 		assert_eq!(
 			path_to_keys("/", "index.html")?,
-			("index.html".to_string(), ImplicitRedirect::No)
+			(key("index.html"), ImplicitRedirect::No)
 		);
 		assert_eq!(
 			path_to_keys("/hello", "index.html")?,
 			(
-				"hello".to_string(),
+				key("hello"),
 				ImplicitRedirect::To {
-					key: "hello/index.html".to_string(),
+					key: key("hello/index.html"),
 					url: "/hello/".to_string()
 				}
 			)
 		);
+		assert_eq!(
+			path_to_keys("/docs/api/", "index.html")?,
+			(key("docs/api/index.html"), ImplicitRedirect::No)
+		);
+		assert_eq!(
+			path_to_keys("/docs/api", "default.htm")?,
+			(
+				key("docs/api"),
+				ImplicitRedirect::To {
+					key: key("docs/api/default.htm"),
+					url: "/docs/api/".to_string()
+				}
+			)
+		);
 		assert!(path_to_keys("", "index.html").is_err());
 		assert!(path_to_keys("i/am/relative", "index.html").is_err());
+		assert!(path_to_keys("/%FF", "index.html").is_err());
+		let long_key = "a".repeat(1020);
+		assert_eq!(
+			path_to_keys(&format!("/{}", long_key), "index.html")?,
+			(key(&long_key), ImplicitRedirect::No)
+		);
+		assert!(path_to_keys(&format!("/{}", "a".repeat(1025)), "index.html").is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn split_path_style_test() -> Result<(), Error> {
+		let (bucket, path) = split_path_style("/my-bucket/a/super/file.jpg")?;
+		let path = path.unwrap();
+		assert_eq!(bucket.as_str(), "my-bucket");
+		assert_eq!(path, "/a/super/file.jpg");
+		assert_eq!(
+			path_to_keys(path, "index.html")?.0,
+			ObjectKey::new("a/super/file.jpg").unwrap()
+		);
+
+		let (bucket, path) = split_path_style("/my-bucket/docs/api/")?;
+		let path = path.unwrap();
+		assert_eq!(bucket.as_str(), "my-bucket");
+		assert_eq!(
+			path_to_keys(path, "index.html")?,
+			(
+				ObjectKey::new("docs/api/index.html").unwrap(),
+				ImplicitRedirect::No
+			)
+		);
+
+		let (bucket, path) = split_path_style("/john.doe//double%20slash")?;
+		let path = path.unwrap();
+		assert_eq!(bucket.as_str(), "john.doe");
+		assert_eq!(path, "//double%20slash");
+		assert_eq!(
+			path_to_keys(path, "index.html")?.0,
+			ObjectKey::new("/double slash").unwrap()
+		);
+
+		let (bucket, path) = split_path_style("/my-bucket/")?;
+		assert_eq!(bucket.as_str(), "my-bucket");
+		assert_eq!(path, Some("/"));
+
+		// Without a trailing slash, the request is redirected to the root of the bucket
+		let (bucket, path) = split_path_style("/my-bucket")?;
+		assert_eq!(bucket.as_str(), "my-bucket");
+		assert_eq!(path, None);
+
+		for p in ["", "/", "//key"] {
+			assert_eq!(
+				split_path_style(p).unwrap_err().http_status_code(),
+				StatusCode::BAD_REQUEST
+			);
+		}
+		assert!(matches!(
+			split_path_style("/My_Bucket/key"),
+			Err(Error::NotFound)
+		));
 		Ok(())
 	}
+
+	#[test]
+	fn is_ip_literal_test() {
+		assert!(is_ip_literal("127.0.0.1"));
+		assert!(is_ip_literal("[::1]"));
+		assert!(!is_ip_literal("garage.tld"));
+		assert!(!is_ip_literal("127.0.0.1.garage.tld"));
+	}
+
+	#[test]
+	fn check_method_test() {
+		for method in [Method::GET, Method::HEAD, Method::OPTIONS] {
+			assert!(check_method(&method).is_ok());
+		}
+		for method in [Method::POST, Method::PUT, Method::DELETE, Method::PATCH] {
+			let err = check_method(&method).unwrap_err();
+			assert_eq!(err.http_status_code(), StatusCode::METHOD_NOT_ALLOWED);
+			let mut headers = hyper::HeaderMap::new();
+			err.add_headers(&mut headers);
+			assert_eq!(
+				headers.get(hyper::header::ALLOW).unwrap(),
+				"GET, HEAD, OPTIONS"
+			);
+		}
+	}
+
+	#[test]
+	fn content_type_from_key_test() {
+		assert_eq!(
+			content_type_from_key("index.html"),
+			Some("text/html; charset=utf-8")
+		);
+		assert_eq!(
+			content_type_from_key("assets/style.min.CSS"),
+			Some("text/css; charset=utf-8")
+		);
+		assert_eq!(
+			content_type_from_key("fonts/inter.woff2"),
+			Some("font/woff2")
+		);
+		assert_eq!(content_type_from_key("img/logo.svg"), Some("image/svg+xml"));
+		assert_eq!(content_type_from_key("archive.tar.zst"), None);
+		assert_eq!(content_type_from_key("README"), None);
+		assert_eq!(content_type_from_key("dir.html/README"), None);
+	}
+
+	#[test]
+	fn is_compressible_content_type_test() {
+		assert!(is_compressible_content_type("text/html; charset=utf-8"));
+		assert!(is_compressible_content_type("application/json"));
+		assert!(is_compressible_content_type("image/svg+xml"));
+		assert!(is_compressible_content_type("Application/WASM"));
+		assert!(!is_compressible_content_type("image/png"));
+		assert!(!is_compressible_content_type("font/woff2"));
+		assert!(!is_compressible_content_type("application/zip"));
+		assert!(!is_compressible_content_type("application/gzip"));
+		assert!(!is_compressible_content_type("video/mp4"));
+
+		let mut headers = HeaderMap::new();
+		assert_eq!(
+			gzip_variant_content_type("index.html", &headers),
+			Some("text/html; charset=utf-8")
+		);
+		assert_eq!(gzip_variant_content_type("img/logo.png", &headers), None);
+		assert_eq!(gzip_variant_content_type("README", &headers), None);
+		headers.insert(RANGE, HeaderValue::from_static("bytes=0-99"));
+		assert_eq!(gzip_variant_content_type("index.html", &headers), None);
+	}
+
+	#[test]
+	fn accepts_gzip_test() {
+		let accepts = |values: &[&'static str]| {
+			let mut headers = HeaderMap::new();
+			for v in values {
+				headers.append(ACCEPT_ENCODING, HeaderValue::from_static(v));
+			}
+			accepts_gzip(&headers)
+		};
+		assert!(!accepts(&[]));
+		assert!(accepts(&["gzip"]));
+		assert!(accepts(&["gzip, deflate, br"]));
+		assert!(accepts(&["br", "GZIP"]));
+		assert!(accepts(&["x-gzip"]));
+		assert!(accepts(&["*"]));
+		assert!(!accepts(&["deflate, br"]));
+		assert!(!accepts(&["gzip;q=0"]));
+		assert!(!accepts(&["*, gzip;q=0"]));
+		assert!(!accepts(&["identity"]));
+		// The identity encoding is preferred when it has a higher quality value
+		assert!(!accepts(&["gzip;q=0.5, identity"]));
+		assert!(accepts(&["gzip;q=0.5, identity;q=0.2"]));
+		assert!(accepts(&["gzip, identity;q=0"]));
+		assert!(!accepts(&["*;q=0.5, identity"]));
+	}
 }